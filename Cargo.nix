@@ -7967,12 +7967,13 @@ rec {
           }
         ];
         features = {
-          "default" = [ "otlp" ];
+          "default" = [ "otlp" "prometheus" ];
           "otlp" = [ "tvix-tracing/otlp" "tower-otel-http-metrics" ];
+          "prometheus" = [ "tvix-tracing/prometheus" ];
           "tower-otel-http-metrics" = [ "dep:tower-otel-http-metrics" ];
           "xp-store-composition-cli" = [ "tvix-store/xp-composition-cli" ];
         };
-        resolvedDefaultFeatures = [ "default" "otlp" "tower-otel-http-metrics" "xp-store-composition-cli" ];
+        resolvedDefaultFeatures = [ "default" "otlp" "prometheus" "tower-otel-http-metrics" "xp-store-composition-cli" ];
       };
       "nibble_vec" = rec {
         crateName = "nibble_vec";
@@ -8172,6 +8173,11 @@ rec {
         version = "0.1.0";
         edition = "2021";
         crateBin = [
+          {
+            name = "drvdiff";
+            path = "src/bin/drvdiff.rs";
+            requiredFeatures = [ ];
+          }
           {
             name = "drvfmt";
             path = "src/bin/drvfmt.rs";
@@ -8507,10 +8513,11 @@ rec {
           }
         ];
         features = {
-          "default" = [ "otlp" ];
+          "default" = [ "otlp" "prometheus" ];
           "otlp" = [ "tvix-tracing/otlp" ];
+          "prometheus" = [ "tvix-tracing/prometheus" ];
         };
-        resolvedDefaultFeatures = [ "default" "otlp" ];
+        resolvedDefaultFeatures = [ "default" "otlp" "prometheus" ];
       };
       "nohash-hasher" = rec {
         crateName = "nohash-hasher";
@@ -16456,6 +16463,11 @@ rec {
             name = "prost";
             packageId = "prost";
           }
+          {
+            name = "serde";
+            packageId = "serde";
+            features = [ "derive" ];
+          }
           {
             name = "serde_json";
             packageId = "serde_json";
@@ -16464,6 +16476,10 @@ rec {
             name = "thiserror";
             packageId = "thiserror 1.0.69";
           }
+          {
+            name = "toml";
+            packageId = "toml 0.8.19";
+          }
           {
             name = "tokio";
             packageId = "tokio";
@@ -16772,6 +16788,15 @@ rec {
             name = "async-process";
             packageId = "async-process";
           }
+          {
+            name = "opentelemetry";
+            packageId = "opentelemetry";
+          }
+          {
+            name = "opentelemetry_sdk";
+            packageId = "opentelemetry_sdk";
+            features = [ "rt-tokio" "testing" ];
+          }
           {
             name = "hex-literal";
             packageId = "hex-literal";
@@ -16825,6 +16850,11 @@ rec {
             path = "src/main.rs";
             requiredFeatures = [ ];
           }
+          {
+            name = "tvix-lsp";
+            path = "src/bin/tvix-lsp.rs";
+            requiredFeatures = [ ];
+          }
         ];
         src = lib.cleanSourceWith { filter = sourceFilter; src = ./cli; };
         libName = "tvix_cli";
@@ -16838,6 +16868,10 @@ rec {
             packageId = "clap";
             features = [ "derive" "env" ];
           }
+          {
+            name = "codemap";
+            packageId = "codemap";
+          }
           {
             name = "dirs";
             packageId = "dirs";
@@ -16862,6 +16896,10 @@ rec {
             name = "rustyline";
             packageId = "rustyline";
           }
+          {
+            name = "serde_json";
+            packageId = "serde_json";
+          }
           {
             name = "smol_str";
             packageId = "smol_str";
@@ -17083,6 +17121,7 @@ rec {
         features = {
           "arbitrary" = [ "proptest" "test-strategy" ];
           "default" = [ "impure" "arbitrary" "nix_tests" ];
+          "gc" = [ "no_leak" ];
           "proptest" = [ "dep:proptest" ];
           "test-strategy" = [ "dep:test-strategy" ];
         };
@@ -17191,6 +17230,10 @@ rec {
             name = "sha2";
             packageId = "sha2";
           }
+          {
+            name = "tempfile";
+            packageId = "tempfile";
+          }
           {
             name = "thiserror";
             packageId = "thiserror 1.0.69";
@@ -17198,6 +17241,7 @@ rec {
           {
             name = "tokio";
             packageId = "tokio";
+            features = [ "process" ];
           }
           {
             name = "tokio-tar";
@@ -17274,10 +17318,6 @@ rec {
             name = "rstest";
             packageId = "rstest";
           }
-          {
-            name = "tempfile";
-            packageId = "tempfile";
-          }
         ];
         features = {
           "default" = [ "nix_tests" ];
@@ -17296,6 +17336,18 @@ rec {
             packageId = "bstr";
             features = [ "serde" ];
           }
+          {
+            name = "bytes";
+            packageId = "bytes";
+          }
+          {
+            name = "rnix";
+            packageId = "rnix";
+          }
+          {
+            name = "rowan";
+            packageId = "rowan";
+          }
           {
             name = "serde";
             packageId = "serde";
@@ -17483,7 +17535,6 @@ rec {
           {
             name = "toml";
             packageId = "toml 0.8.19";
-            optional = true;
           }
           {
             name = "tonic";
@@ -17573,16 +17624,16 @@ rec {
         ];
         features = {
           "cloud" = [ "dep:bigtable_rs" "tvix-castore/cloud" ];
-          "default" = [ "cloud" "fuse" "otlp" "tonic-reflection" ];
+          "default" = [ "cloud" "fuse" "otlp" "prometheus" "tonic-reflection" ];
           "fuse" = [ "tvix-castore/fuse" ];
           "otlp" = [ "tvix-tracing/otlp" ];
-          "toml" = [ "dep:toml" ];
+          "prometheus" = [ "tvix-tracing/prometheus" ];
           "tonic-reflection" = [ "dep:tonic-reflection" "tvix-castore/tonic-reflection" ];
           "tracy" = [ "tvix-tracing/tracy" ];
           "virtiofs" = [ "tvix-castore/virtiofs" ];
-          "xp-composition-cli" = [ "toml" "tvix-castore/xp-composition-url-refs" ];
+          "xp-composition-cli" = [ "tvix-castore/xp-composition-url-refs" ];
         };
-        resolvedDefaultFeatures = [ "cloud" "default" "fuse" "integration" "otlp" "toml" "tonic-reflection" "tracy" "virtiofs" "xp-composition-cli" ];
+        resolvedDefaultFeatures = [ "cloud" "default" "fuse" "integration" "otlp" "prometheus" "tonic-reflection" "tracy" "virtiofs" "xp-composition-cli" ];
       };
       "tvix-tracing" = rec {
         crateName = "tvix-tracing";
@@ -17591,6 +17642,11 @@ rec {
         src = lib.cleanSourceWith { filter = sourceFilter; src = ./tracing; };
         libName = "tvix_tracing";
         dependencies = [
+          {
+            name = "async-trait";
+            packageId = "async-trait";
+            optional = true;
+          }
           {
             name = "axum";
             packageId = "axum";
@@ -17605,10 +17661,14 @@ rec {
             name = "indicatif";
             packageId = "indicatif";
           }
+          {
+            name = "libc";
+            packageId = "libc";
+            optional = true;
+          }
           {
             name = "opentelemetry";
             packageId = "opentelemetry";
-            optional = true;
           }
           {
             name = "opentelemetry-http";
@@ -17637,6 +17697,10 @@ rec {
             optional = true;
             usesDefaultFeatures = false;
           }
+          {
+            name = "serde_json";
+            packageId = "serde_json";
+          }
           {
             name = "thiserror";
             packageId = "thiserror 1.0.69";
@@ -17677,14 +17741,27 @@ rec {
             features = [ "flush-on-exit" ];
           }
         ];
+        devDependencies = [
+          {
+            name = "opentelemetry_sdk";
+            packageId = "opentelemetry_sdk";
+            features = [ "rt-tokio" "testing" ];
+          }
+          {
+            name = "tokio";
+            packageId = "tokio";
+            features = [ "macros" "rt-multi-thread" ];
+          }
+        ];
         features = {
           "axum" = [ "dep:axum" ];
-          "otlp" = [ "dep:tracing-opentelemetry" "dep:opentelemetry" "dep:opentelemetry-otlp" "dep:opentelemetry_sdk" "dep:opentelemetry-http" "dep:opentelemetry-semantic-conventions" "reqwest-tracing?/opentelemetry_0_27" ];
+          "otlp" = [ "dep:tracing-opentelemetry" "dep:opentelemetry-otlp" "dep:opentelemetry_sdk" "dep:opentelemetry-http" "dep:opentelemetry-semantic-conventions" "reqwest-tracing?/opentelemetry_0_27" ];
+          "prometheus" = [ "otlp" "dep:async-trait" "dep:axum" "dep:libc" "tokio/net" ];
           "reqwest" = [ "dep:reqwest-tracing" ];
           "tonic" = [ "dep:tonic" "dep:http" ];
           "tracy" = [ "dep:tracing-tracy" ];
         };
-        resolvedDefaultFeatures = [ "axum" "default" "otlp" "reqwest" "tonic" "tracy" ];
+        resolvedDefaultFeatures = [ "axum" "default" "otlp" "prometheus" "reqwest" "tonic" "tracy" ];
       };
       "typeid" = rec {
         crateName = "typeid";
@@ -1,3 +1,7 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use clap::Parser;
 use clap::Subcommand;
 use tokio_listener::Listener;
@@ -9,8 +13,11 @@ use tvix_build::{
     buildservice,
     proto::{build_service_server::BuildServiceServer, GRPCBuildServiceWrapper},
 };
-use tvix_castore::blobservice;
-use tvix_castore::directoryservice;
+use tvix_castore::blobservice::{self, BlobService};
+use tvix_castore::composition::{
+    with_registry, Composition, DeserializeWithRegistry, ServiceBuilder, REG,
+};
+use tvix_castore::directoryservice::{self, DirectoryService};
 
 #[cfg(feature = "tonic-reflection")]
 use tvix_build::proto::FILE_DESCRIPTOR_SET;
@@ -25,6 +32,10 @@ static GLOBAL: MiMalloc = MiMalloc;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// The format of log lines written to stderr, "text" or "json".
+    #[arg(long, env, default_value_t)]
+    log_format: tvix_tracing::LogFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -43,14 +54,61 @@ enum Commands {
 
         #[arg(long, env, default_value = "dummy://")]
         build_service_addr: String,
+
+        /// Path to a TOML file describing how the blob and directory services
+        /// are composed, in the format also used by tvix-store.
+        /// The "root" services are used. If set, the service addrs are ignored.
+        #[arg(long, env, alias = "experimental-store-composition")]
+        store_config: Option<PathBuf>,
     },
 }
 
+/// The parts of a store config file relevant for tvix-build.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoreConfig {
+    #[serde(default)]
+    blobservices:
+        HashMap<String, DeserializeWithRegistry<Box<dyn ServiceBuilder<Output = dyn BlobService>>>>,
+    #[serde(default)]
+    directoryservices: HashMap<
+        String,
+        DeserializeWithRegistry<Box<dyn ServiceBuilder<Output = dyn DirectoryService>>>,
+    >,
+    /// PathInfoServices are not used by tvix-build, but accepted so the same
+    /// file can be shared with tvix-store.
+    #[serde(default, rename = "pathinfoservices")]
+    _pathinfoservices: serde::de::IgnoredAny,
+}
+
+/// Builds the "root" blob and directory services from the store config file
+/// at `path`.
+async fn services_from_store_config(
+    path: &Path,
+) -> Result<
+    (Arc<dyn BlobService>, Arc<dyn DirectoryService>),
+    Box<dyn std::error::Error + Send + Sync>,
+> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    let config: StoreConfig = with_registry(&REG, || toml::from_str(&text))
+        .map_err(|e| format!("invalid store config {}: {e}", path.display()))?;
+
+    let mut comp = Composition::new(&REG);
+    comp.extend(config.blobservices);
+    comp.extend(config.directoryservices);
+    comp.validate()?;
+
+    Ok((comp.build("root").await?, comp.build("root").await?))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cli = Cli::parse();
 
     tvix_tracing::TracingBuilder::default()
+        .log_format(cli.log_format)
         .enable_progressbar()
         .build()?;
 
@@ -60,10 +118,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             blob_service_addr,
             directory_service_addr,
             build_service_addr,
+            store_config,
         } => {
             // initialize stores
-            let blob_service = blobservice::from_addr(&blob_service_addr).await?;
-            let directory_service = directoryservice::from_addr(&directory_service_addr).await?;
+            let (blob_service, directory_service) = match store_config {
+                Some(path) => services_from_store_config(&path).await?,
+                None => (
+                    blobservice::from_addr(&blob_service_addr).await?,
+                    directoryservice::from_addr(&directory_service_addr).await?,
+                ),
+            };
 
             let build_service =
                 buildservice::from_addr(&build_service_addr, blob_service, directory_service)
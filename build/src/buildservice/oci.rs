@@ -24,6 +24,9 @@ use super::BuildService;
 
 const SANDBOX_SHELL: &str = env!("TVIX_BUILD_SANDBOX_SHELL");
 const MAX_CONCURRENT_BUILDS: usize = 2; // TODO: make configurable
+/// Number of trailing builder log lines included in errors for failed builds,
+/// same as the `log-lines` default in Nix.
+const LOG_TAIL_LINES: usize = 25;
 
 pub struct OCIBuildService<BS, DS> {
     /// Root path in which all bundles are created in
@@ -176,7 +179,12 @@ where
 
             return Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
-                "nonzero exit code".to_string(),
+                format!(
+                    "builder failed with {}, last {} log lines:\n{}",
+                    child_output.status,
+                    LOG_TAIL_LINES,
+                    log_tail(&child_output.stderr, LOG_TAIL_LINES)
+                ),
             ));
         }
 
@@ -242,6 +250,14 @@ where
     }
 }
 
+/// Returns the last `n` lines of the given builder log, lossily decoded.
+fn log_tail(log: &[u8], n: usize) -> String {
+    let log = String::from_utf8_lossy(log);
+    let lines: Vec<&str> = log.lines().collect();
+
+    lines[lines.len().saturating_sub(n)..].join("\n")
+}
+
 /// Spawns runc with the bundle at bundle_path.
 /// On success, returns the child.
 #[instrument(err)]
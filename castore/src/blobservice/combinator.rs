@@ -2,8 +2,9 @@ use std::sync::Arc;
 
 use tonic::async_trait;
 use tracing::instrument;
+use tvix_tracing::metrics::InstanceMetrics;
 
-use crate::composition::{CompositionContext, ServiceBuilder};
+use crate::composition::{CompositionContext, Dependency, ServiceBuilder};
 use crate::{B3Digest, Error};
 
 use super::{BlobReader, BlobService, BlobWriter, ChunkedReader};
@@ -17,6 +18,7 @@ use super::{BlobReader, BlobService, BlobWriter, ChunkedReader};
 /// The far BlobService is never written to.
 pub struct CombinedBlobService<BL, BR> {
     instance_name: String,
+    metrics: InstanceMetrics,
     near: BL,
     far: BR,
 }
@@ -29,6 +31,7 @@ where
     fn clone(&self) -> Self {
         Self {
             instance_name: self.instance_name.clone(),
+            metrics: self.metrics.clone(),
             near: self.near.clone(),
             far: self.far.clone(),
         }
@@ -43,12 +46,16 @@ where
 {
     #[instrument(skip(self, digest), fields(blob.digest=%digest, instance_name=%self.instance_name))]
     async fn has(&self, digest: &B3Digest) -> std::io::Result<bool> {
-        Ok(self.near.as_ref().has(digest).await? || self.far.as_ref().has(digest).await?)
+        let in_near = self.near.as_ref().has(digest).await?;
+        self.metrics.record_cache("has", in_near);
+        Ok(in_near || self.far.as_ref().has(digest).await?)
     }
 
     #[instrument(skip(self, digest), fields(blob.digest=%digest, instance_name=%self.instance_name), err)]
     async fn open_read(&self, digest: &B3Digest) -> std::io::Result<Option<Box<dyn BlobReader>>> {
-        if self.near.as_ref().has(digest).await? {
+        let in_near = self.near.as_ref().has(digest).await?;
+        self.metrics.record_cache("open_read", in_near);
+        if in_near {
             // near store has the blob, so we can assume it also has all chunks.
             self.near.as_ref().open_read(digest).await
         } else {
@@ -123,9 +130,17 @@ impl ServiceBuilder for CombinedBlobServiceConfig {
             context.resolve(self.far.clone())
         );
         Ok(Arc::new(CombinedBlobService {
+            metrics: InstanceMetrics::new("blobservice", instance_name),
             instance_name: instance_name.to_string(),
             near: local?,
             far: remote?,
         }))
     }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::new::<dyn BlobService>(&self.near),
+            Dependency::new::<dyn BlobService>(&self.far),
+        ]
+    }
 }
@@ -0,0 +1,144 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tonic::async_trait;
+use tvix_tracing::metrics::InstanceMetrics;
+
+use super::{BlobReader, BlobService, BlobWriter};
+use crate::proto::stat_blob_response::ChunkMeta;
+use crate::B3Digest;
+
+/// Wraps a [BlobService], recording all requests sent to it, as well as the
+/// bytes read and written, in [InstanceMetrics].
+/// [crate::composition::add_default_services] registers this to be applied to
+/// every BlobService in a composition.
+pub struct MetricsBlobService<BS> {
+    metrics: InstanceMetrics,
+    inner: BS,
+}
+
+impl<BS> MetricsBlobService<BS> {
+    pub fn new(instance_name: &str, inner: BS) -> Self {
+        Self {
+            metrics: InstanceMetrics::new("blobservice", instance_name),
+            inner,
+        }
+    }
+}
+
+#[async_trait]
+impl<BS> BlobService for MetricsBlobService<BS>
+where
+    BS: BlobService,
+{
+    async fn has(&self, digest: &B3Digest) -> io::Result<bool> {
+        self.metrics.observe("has", self.inner.has(digest)).await
+    }
+
+    async fn open_read(&self, digest: &B3Digest) -> io::Result<Option<Box<dyn BlobReader>>> {
+        let reader = self
+            .metrics
+            .observe("open_read", self.inner.open_read(digest))
+            .await?;
+
+        Ok(reader.map(|inner| {
+            Box::new(MetricsBlobReader {
+                metrics: self.metrics.clone(),
+                inner,
+            }) as Box<dyn BlobReader>
+        }))
+    }
+
+    async fn open_write(&self) -> Box<dyn BlobWriter> {
+        Box::new(MetricsBlobWriter {
+            metrics: self.metrics.clone(),
+            start: Some(Instant::now()),
+            inner: self.inner.open_write().await,
+        })
+    }
+
+    async fn chunks(&self, digest: &B3Digest) -> io::Result<Option<Vec<ChunkMeta>>> {
+        self.metrics
+            .observe("chunks", self.inner.chunks(digest))
+            .await
+    }
+}
+
+/// Records the bytes read from the wrapped [BlobReader].
+struct MetricsBlobReader {
+    metrics: InstanceMetrics,
+    inner: Box<dyn BlobReader>,
+}
+
+impl BlobReader for MetricsBlobReader {}
+
+impl AsyncRead for MetricsBlobReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            self.metrics
+                .record_bytes("open_read", (buf.filled().len() - filled) as u64);
+        }
+        res
+    }
+}
+
+impl AsyncSeek for MetricsBlobReader {
+    fn start_seek(mut self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut self.inner).poll_complete(cx)
+    }
+}
+
+/// Records the bytes written to the wrapped [BlobWriter], and the write as a
+/// request once it's closed.
+struct MetricsBlobWriter {
+    metrics: InstanceMetrics,
+    /// When the writer was opened, taken when it's closed.
+    start: Option<Instant>,
+    inner: Box<dyn BlobWriter>,
+}
+
+#[async_trait]
+impl BlobWriter for MetricsBlobWriter {
+    async fn close(&mut self) -> io::Result<B3Digest> {
+        let result = self.inner.close().await;
+        if let Some(start) = self.start.take() {
+            self.metrics.record_request("open_write", start, &result);
+        }
+        result
+    }
+}
+
+impl AsyncWrite for MetricsBlobWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.metrics.record_bytes("open_write", n as u64);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::sync::Arc;
 
 use auto_impl::auto_impl;
 use tonic::async_trait;
@@ -12,6 +13,7 @@ mod combinator;
 mod from_addr;
 mod grpc;
 mod memory;
+mod metrics;
 mod object_store;
 
 #[cfg(test)]
@@ -22,6 +24,7 @@ pub use self::combinator::{CombinedBlobService, CombinedBlobServiceConfig};
 pub use self::from_addr::from_addr;
 pub use self::grpc::{GRPCBlobService, GRPCBlobServiceConfig};
 pub use self::memory::{MemoryBlobService, MemoryBlobServiceConfig};
+pub use self::metrics::MetricsBlobService;
 pub use self::object_store::{ObjectStoreBlobService, ObjectStoreBlobServiceConfig};
 
 /// The base trait all BlobService services need to implement.
@@ -83,10 +86,14 @@ impl BlobReader for io::Cursor<Vec<u8>> {}
 impl BlobReader for io::Cursor<bytes::Bytes> {}
 impl BlobReader for tokio::fs::File {}
 
-/// Registers the builtin BlobService implementations with the registry
+/// Registers the builtin BlobService implementations with the registry,
+/// and records metrics of each of them.
 pub(crate) fn register_blob_services(reg: &mut Registry) {
     reg.register::<Box<dyn ServiceBuilder<Output = dyn BlobService>>, super::blobservice::ObjectStoreBlobServiceConfig>("objectstore");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn BlobService>>, super::blobservice::MemoryBlobServiceConfig>("memory");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn BlobService>>, super::blobservice::CombinedBlobServiceConfig>("combined");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn BlobService>>, super::blobservice::GRPCBlobServiceConfig>("grpc");
+    reg.register_wrapper::<dyn BlobService>(|instance_name, service| {
+        Arc::new(MetricsBlobService::new(instance_name, service))
+    });
 }
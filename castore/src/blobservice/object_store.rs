@@ -21,6 +21,7 @@ use url::Url;
 use crate::{
     composition::{CompositionContext, ServiceBuilder},
     proto::{stat_blob_response::ChunkMeta, StatBlobResponse},
+    traced_object_store::TracedObjectStore,
     B3Digest, B3HashingReader, Error,
 };
 
@@ -318,7 +319,11 @@ impl ServiceBuilder for ObjectStoreBlobServiceConfig {
             object_store::parse_url_opts(&self.object_store_url.parse()?, opts)?;
         Ok(Arc::new(ObjectStoreBlobService {
             instance_name: instance_name.to_string(),
-            object_store: Arc::new(object_store),
+            object_store: Arc::new(TracedObjectStore::new(
+                Arc::new(object_store),
+                "blobservice",
+                instance_name,
+            )),
             base_path: path,
             avg_chunk_size: self.avg_chunk_size,
         }))
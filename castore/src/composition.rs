@@ -130,9 +130,16 @@ use tonic::async_trait;
 // lookup.
 // I said it was ugly...
 #[derive(Default)]
-pub struct Registry(BTreeMap<(TypeId, &'static str), Box<dyn Any + Sync>>);
+pub struct Registry {
+    entries: BTreeMap<(TypeId, &'static str), Box<dyn Any + Sync>>,
+    // Same trick as above, this holds a `Wrapper<T>` per TypeId of `T`.
+    wrappers: BTreeMap<TypeId, Box<dyn Any + Sync>>,
+}
 pub type FromUrlSeed<T> =
     Box<dyn Fn(url::Url) -> Result<T, Box<dyn std::error::Error + Send + Sync>> + Sync>;
+/// Applied to each service of type `T` instantiated in a [Composition],
+/// together with its instance name, see [Registry::register_wrapper].
+pub type Wrapper<T> = Box<dyn Fn(&str, Arc<T>) -> Arc<T> + Send + Sync>;
 pub struct RegistryEntry<T> {
     serde_deserialize_seed: BoxFnSeed<DeserializeWithRegistry<T>>,
     from_url_seed: FromUrlSeed<DeserializeWithRegistry<T>>,
@@ -152,7 +159,7 @@ impl<'r, 'de: 'r, T: 'static> SeedFactory<'de, TagString<'de>> for RegistryWithF
         // using find() and not get() because of https://github.com/rust-lang/rust/issues/80389
         let seed: &Box<dyn Any + Sync> = self
             .0
-             .0
+            .entries
             .iter()
             .find(|(k, _)| *k == &(TypeId::of::<T>(), tag.as_ref()))
             .ok_or_else(|| serde::de::Error::custom(format!("Unknown type: {}", tag)))?
@@ -188,7 +195,7 @@ impl Registry {
         &mut self,
         type_name: &'static str,
     ) {
-        self.0.insert(
+        self.entries.insert(
             (TypeId::of::<T>(), type_name),
             Box::new(RegistryEntry {
                 serde_deserialize_seed: BoxFnSeed::new(|x| {
@@ -204,6 +211,31 @@ impl Registry {
             }),
         );
     }
+
+    /// Registers a wrapper for services of type `T`, e.g. `dyn BlobService`.
+    /// Every service of that type instantiated in a [Composition] is passed
+    /// through it, together with its instance name, and the returned service
+    /// is used in its place, both by the caller and by other services
+    /// referring to it.
+    /// Registering another wrapper for the same type replaces the previous one.
+    pub fn register_wrapper<T: ?Sized + 'static>(
+        &mut self,
+        wrapper: impl Fn(&str, Arc<T>) -> Arc<T> + Send + Sync + 'static,
+    ) {
+        self.wrappers
+            .insert(TypeId::of::<T>(), Box::new(Box::new(wrapper) as Wrapper<T>));
+    }
+
+    /// Passes the service through the wrapper registered for `T`, if any.
+    fn wrap<T: ?Sized + 'static>(&self, instance_name: &str, service: Arc<T>) -> Arc<T> {
+        match self.wrappers.get(&TypeId::of::<T>()) {
+            Some(wrapper) => {
+                let wrapper: &Wrapper<T> = <dyn Any>::downcast_ref(&**wrapper).unwrap();
+                wrapper(instance_name, service)
+            }
+            None => service,
+        }
+    }
 }
 
 impl<'de, T: 'static> serde::Deserialize<'de> for DeserializeWithRegistry<T> {
@@ -233,7 +265,7 @@ impl<T: 'static> TryFrom<url::Url> for DeserializeWithRegistry<T> {
         let seed = ACTIVE_REG
             .get()
             .unwrap()
-            .0
+            .entries
             .iter()
             .find(|(k, _)| *k == &(TypeId::of::<T>(), tag))
             .ok_or_else(|| Box::new(TryFromUrlError::UnknownTag(tag.into())))?
@@ -324,7 +356,8 @@ impl<'a> CompositionContext<'a> {
         let url = url::Url::parse(&entrypoint)?;
         let config: DeserializeWithRegistry<Box<dyn ServiceBuilder<Output = T>>> =
             with_registry(self.registry, || url.try_into())?;
-        config.0.build("anonymous", self).await
+        let service = config.0.build("anonymous", self).await?;
+        Ok(self.registry.wrap("anonymous", service))
     }
 
     fn build_internal<T: ?Sized + Send + Sync + 'static>(
@@ -377,12 +410,13 @@ impl<'a> CompositionContext<'a> {
                         new_context
                             .stack
                             .push((TypeId::of::<T>(), entrypoint.clone()));
-                        let res =
-                            config.build(&entrypoint, &new_context).await.map_err(|e| {
-                                match e.downcast() {
-                                    Ok(e) => *e,
-                                    Err(e) => CompositionError::Failed(entrypoint, e.into()),
-                                }
+                        let res = config
+                            .build(&entrypoint, &new_context)
+                            .await
+                            .map(|service| self.registry.wrap(&entrypoint, service))
+                            .map_err(|e| match e.downcast() {
+                                Ok(e) => *e,
+                                Err(e) => CompositionError::Failed(entrypoint, e.into()),
                             });
                         tx.send(Some(res.clone())).unwrap();
                         res
@@ -423,6 +457,34 @@ pub trait ServiceBuilder: Send + Sync {
         instance_name: &str,
         context: &CompositionContext,
     ) -> Result<Arc<Self::Output>, Box<dyn std::error::Error + Send + Sync + 'static>>;
+
+    /// Returns the other services in the composition this one refers to.
+    /// This is used to detect dangling references before instantiating
+    /// anything, see [Composition::validate].
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![]
+    }
+}
+
+/// A reference from a service config to another service (of type `T`) in the
+/// same composition, see [ServiceBuilder::dependencies].
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    type_id: TypeId,
+    name: String,
+}
+
+impl Dependency {
+    pub fn new<T: ?Sized + 'static>(name: impl Into<String>) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            name: name.into(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl<T: ?Sized, S: ServiceBuilder<Output = T> + 'static> From<S>
@@ -442,6 +504,7 @@ enum InstantiationState<T: ?Sized> {
 pub struct Composition {
     registry: &'static Registry,
     stores: std::sync::Mutex<HashMap<(TypeId, String), Box<dyn Any + Send + Sync>>>,
+    dependencies: HashMap<(TypeId, String), Vec<Dependency>>,
 }
 
 #[derive(thiserror::Error, Clone, Debug)]
@@ -454,6 +517,8 @@ pub enum CompositionError {
     Poisoned(String),
     #[error("instantiation of service {0} failed: {1}")]
     Failed(String, Arc<dyn std::error::Error + Send + Sync>),
+    #[error("service {0} refers to {1}, which does not exist")]
+    DanglingReference(String, String),
 }
 
 impl<T: ?Sized + Send + Sync + 'static>
@@ -471,15 +536,15 @@ impl<T: ?Sized + Send + Sync + 'static>
             ),
         >,
     {
-        self.stores
-            .lock()
-            .unwrap()
-            .extend(configs.into_iter().map(|(k, v)| {
-                (
-                    (TypeId::of::<T>(), k),
-                    Box::new(InstantiationState::Config(v.0)) as Box<dyn Any + Send + Sync>,
-                )
-            }))
+        let mut stores = self.stores.lock().unwrap();
+        for (k, v) in configs {
+            self.dependencies
+                .insert((TypeId::of::<T>(), k.clone()), v.0.dependencies());
+            stores.insert(
+                (TypeId::of::<T>(), k),
+                Box::new(InstantiationState::Config(v.0)) as Box<dyn Any + Send + Sync>,
+            );
+        }
     }
 }
 
@@ -489,7 +554,51 @@ impl Composition {
         Self {
             registry,
             stores: Default::default(),
+            dependencies: Default::default(),
+        }
+    }
+
+    /// Checks that services only refer to services which exist in the
+    /// composition, without instantiating any of them.
+    pub fn validate(&self) -> Result<(), CompositionError> {
+        let stores = self.stores.lock().unwrap();
+
+        // sort, so the same error is reported every time.
+        let mut dependencies: Vec<_> = self.dependencies.iter().collect();
+        dependencies.sort_by(|((_, a), _), ((_, b), _)| a.cmp(b));
+
+        for ((_, name), dependencies) in dependencies {
+            for dependency in dependencies {
+                #[cfg(feature = "xp-composition-url-refs")]
+                if dependency.name.contains("://") {
+                    continue;
+                }
+
+                if !stores.contains_key(&(dependency.type_id, dependency.name.clone())) {
+                    return Err(CompositionError::DanglingReference(
+                        name.clone(),
+                        dependency.name.clone(),
+                    ));
+                }
+            }
         }
+
+        Ok(())
+    }
+
+    /// Returns the (sorted) names of all services of type `T` in the
+    /// composition.
+    pub fn names<T: ?Sized + 'static>(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .stores
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(type_id, _)| *type_id == TypeId::of::<T>())
+            .map(|(_, name)| name.clone())
+            .collect();
+        names.sort();
+        names
     }
 
     pub fn extend_with_configs<T: ?Sized + Send + Sync + 'static>(
@@ -549,6 +658,51 @@ mod test {
         ));
     }
 
+    /// Test that the registered wrapper is applied to every instantiated
+    /// service, including the ones only referred to by other services.
+    #[tokio::test]
+    async fn wrapper() {
+        let wrapped = Arc::new(std::sync::Mutex::new(vec![]));
+
+        let mut reg = Registry::default();
+        add_default_services(&mut reg);
+        reg.register_wrapper::<dyn BlobService>({
+            let wrapped = wrapped.clone();
+            move |instance_name, service| {
+                wrapped.lock().unwrap().push(instance_name.to_string());
+                service
+            }
+        });
+        let reg: &'static Registry = Box::leak(Box::new(reg));
+
+        let blob_services_configs_json = serde_json::json!({
+            "root": {
+                "type": "combined",
+                "near": "near",
+                "far": "far"
+            },
+            "near": {
+                "type": "memory"
+            },
+            "far": {
+                "type": "memory"
+            }
+        });
+
+        let blob_services_configs =
+            with_registry(reg, || serde_json::from_value(blob_services_configs_json)).unwrap();
+        let mut blob_service_composition = Composition::new(reg);
+        blob_service_composition.extend_with_configs::<dyn BlobService>(blob_services_configs);
+        blob_service_composition
+            .build::<dyn BlobService>("root")
+            .await
+            .expect("must build");
+
+        let mut wrapped = wrapped.lock().unwrap().clone();
+        wrapped.sort();
+        assert_eq!(wrapped, vec!["far", "near", "root"]);
+    }
+
     /// Test that we throw the correct error when an instantiation would recurse (deadlock)
     #[tokio::test]
     async fn reject_recursion() {
@@ -579,4 +733,35 @@ mod test {
             other => panic!("should have returned an error, returned: {:?}", other.err()),
         }
     }
+
+    /// Test that dangling references are reported by name, without instantiating anything.
+    #[test]
+    fn reject_dangling_reference() {
+        let blob_services_configs_json = serde_json::json!({
+            "root": {
+                "type": "combined",
+                "near": "near",
+                "far": "far"
+            },
+            "near": {
+                "type": "memory"
+            }
+        });
+
+        let blob_services_configs =
+            with_registry(&REG, || serde_json::from_value(blob_services_configs_json)).unwrap();
+        let mut blob_service_composition = Composition::new(&REG);
+        blob_service_composition.extend_with_configs::<dyn BlobService>(blob_services_configs);
+
+        assert_eq!(
+            blob_service_composition.names::<dyn BlobService>(),
+            vec!["near".to_string(), "root".to_string()]
+        );
+        match blob_service_composition.validate() {
+            Err(CompositionError::DanglingReference(from, to)) => {
+                assert_eq!((from.as_str(), to.as_str()), ("root", "far"))
+            }
+            other => panic!("should have returned an error, returned: {:?}", other),
+        }
+    }
 }
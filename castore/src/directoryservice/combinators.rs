@@ -6,9 +6,10 @@ use futures::TryFutureExt;
 use futures::TryStreamExt;
 use tonic::async_trait;
 use tracing::{instrument, trace};
+use tvix_tracing::metrics::InstanceMetrics;
 
 use super::{Directory, DirectoryGraph, DirectoryService, RootToLeavesValidator, SimplePutter};
-use crate::composition::{CompositionContext, ServiceBuilder};
+use crate::composition::{CompositionContext, Dependency, ServiceBuilder};
 use crate::directoryservice::DirectoryPutter;
 use crate::B3Digest;
 use crate::Error;
@@ -23,6 +24,7 @@ use crate::Error;
 #[derive(Clone)]
 pub struct Cache<DS1, DS2> {
     instance_name: String,
+    metrics: InstanceMetrics,
     near: DS1,
     far: DS2,
 }
@@ -30,6 +32,7 @@ pub struct Cache<DS1, DS2> {
 impl<DS1, DS2> Cache<DS1, DS2> {
     pub fn new(instance_name: String, near: DS1, far: DS2) -> Self {
         Self {
+            metrics: InstanceMetrics::new("directoryservice", &instance_name),
             instance_name,
             near,
             far,
@@ -48,10 +51,12 @@ where
         match self.near.get(digest).await? {
             Some(directory) => {
                 trace!("serving from cache");
+                self.metrics.record_cache("get", true);
                 Ok(Some(directory))
             }
             None => {
                 trace!("not found in near, asking remote…");
+                self.metrics.record_cache("get", false);
 
                 let mut copy = DirectoryGraph::with_order(
                     RootToLeavesValidator::new_with_root_digest(digest.clone()),
@@ -97,11 +102,14 @@ where
     ) -> BoxStream<'static, Result<Directory, Error>> {
         let near = self.near.clone();
         let far = self.far.clone();
+        let metrics = self.metrics.clone();
         let digest = root_directory_digest.clone();
         Box::pin(
             (async move {
                 let mut stream = near.get_recursive(&digest);
-                match stream.try_next().await? {
+                let first = stream.try_next().await?;
+                metrics.record_cache("get_recursive", first.is_some());
+                match first {
                     Some(first) => {
                         trace!("serving from cache");
                         Ok(futures::stream::once(async { Ok(first) })
@@ -178,10 +186,13 @@ impl ServiceBuilder for CacheConfig {
             context.resolve::<Self::Output>(self.near.clone()),
             context.resolve::<Self::Output>(self.far.clone())
         );
-        Ok(Arc::new(Cache {
-            instance_name: instance_name.to_string(),
-            near: near?,
-            far: far?,
-        }))
+        Ok(Arc::new(Cache::new(instance_name.to_string(), near?, far?)))
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::new::<dyn DirectoryService>(&self.near),
+            Dependency::new::<dyn DirectoryService>(&self.far),
+        ]
     }
 }
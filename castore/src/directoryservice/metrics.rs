@@ -0,0 +1,99 @@
+use std::time::Instant;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tonic::async_trait;
+use tvix_tracing::metrics::InstanceMetrics;
+
+use super::{Directory, DirectoryPutter, DirectoryService};
+use crate::{B3Digest, Error};
+
+/// Wraps a [DirectoryService], recording all requests sent to it in
+/// [InstanceMetrics].
+/// [crate::composition::add_default_services] registers this to be applied to
+/// every DirectoryService in a composition.
+pub struct MetricsDirectoryService<DS> {
+    metrics: InstanceMetrics,
+    inner: DS,
+}
+
+impl<DS> MetricsDirectoryService<DS> {
+    pub fn new(instance_name: &str, inner: DS) -> Self {
+        Self {
+            metrics: InstanceMetrics::new("directoryservice", instance_name),
+            inner,
+        }
+    }
+}
+
+#[async_trait]
+impl<DS> DirectoryService for MetricsDirectoryService<DS>
+where
+    DS: DirectoryService,
+{
+    async fn get(&self, digest: &B3Digest) -> Result<Option<Directory>, Error> {
+        self.metrics.observe("get", self.inner.get(digest)).await
+    }
+
+    async fn put(&self, directory: Directory) -> Result<B3Digest, Error> {
+        self.metrics.observe("put", self.inner.put(directory)).await
+    }
+
+    /// The request is recorded once the stream is exhausted, as failed if any
+    /// of its elements was an error.
+    fn get_recursive(
+        &self,
+        root_directory_digest: &B3Digest,
+    ) -> BoxStream<'static, Result<Directory, Error>> {
+        let metrics = self.metrics.clone();
+        let mut stream = self.inner.get_recursive(root_directory_digest);
+
+        Box::pin(async_stream::stream! {
+            let start = Instant::now();
+            let mut failed = false;
+            while let Some(item) = stream.next().await {
+                failed |= item.is_err();
+                yield item;
+            }
+            metrics.record("get_recursive", start, failed);
+        })
+    }
+
+    fn put_multiple_start(&self) -> Box<dyn DirectoryPutter> {
+        Box::new(MetricsDirectoryPutter {
+            metrics: self.metrics.clone(),
+            start: Some(Instant::now()),
+            inner: self.inner.put_multiple_start(),
+        })
+    }
+}
+
+/// Records the upload as a request once the wrapped [DirectoryPutter] is
+/// closed, or failed.
+struct MetricsDirectoryPutter {
+    metrics: InstanceMetrics,
+    /// When the putter was created, taken when the request is recorded.
+    start: Option<Instant>,
+    inner: Box<dyn DirectoryPutter>,
+}
+
+#[async_trait]
+impl DirectoryPutter for MetricsDirectoryPutter {
+    async fn put(&mut self, directory: Directory) -> Result<(), Error> {
+        let result = self.inner.put(directory).await;
+        if result.is_err() {
+            if let Some(start) = self.start.take() {
+                self.metrics.record_request("put_multiple", start, &result);
+            }
+        }
+        result
+    }
+
+    async fn close(&mut self) -> Result<B3Digest, Error> {
+        let result = self.inner.close().await;
+        if let Some(start) = self.start.take() {
+            self.metrics.record_request("put_multiple", start, &result);
+        }
+        result
+    }
+}
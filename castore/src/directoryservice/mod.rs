@@ -1,6 +1,8 @@
 use crate::composition::{Registry, ServiceBuilder};
 use crate::{B3Digest, Directory, Error};
 
+use std::sync::Arc;
+
 use auto_impl::auto_impl;
 use futures::stream::BoxStream;
 use tonic::async_trait;
@@ -9,6 +11,7 @@ mod directory_graph;
 mod from_addr;
 mod grpc;
 mod memory;
+mod metrics;
 mod object_store;
 mod order_validator;
 mod redb;
@@ -23,6 +26,7 @@ pub use self::directory_graph::{DirectoryGraph, ValidatedDirectoryGraph};
 pub use self::from_addr::from_addr;
 pub use self::grpc::{GRPCDirectoryService, GRPCDirectoryServiceConfig};
 pub use self::memory::{MemoryDirectoryService, MemoryDirectoryServiceConfig};
+pub use self::metrics::MetricsDirectoryService;
 pub use self::object_store::{ObjectStoreDirectoryService, ObjectStoreDirectoryServiceConfig};
 pub use self::order_validator::{LeavesToRootValidator, OrderValidator, RootToLeavesValidator};
 pub use self::redb::{RedbDirectoryService, RedbDirectoryServiceConfig};
@@ -107,7 +111,8 @@ pub trait DirectoryPutter: Send {
     async fn close(&mut self) -> Result<B3Digest, Error>;
 }
 
-/// Registers the builtin DirectoryService implementations with the registry
+/// Registers the builtin DirectoryService implementations with the registry,
+/// and records metrics of each of them.
 pub(crate) fn register_directory_services(reg: &mut Registry) {
     reg.register::<Box<dyn ServiceBuilder<Output = dyn DirectoryService>>, super::directoryservice::ObjectStoreDirectoryServiceConfig>("objectstore");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn DirectoryService>>, super::directoryservice::MemoryDirectoryServiceConfig>("memory");
@@ -118,4 +123,7 @@ pub(crate) fn register_directory_services(reg: &mut Registry) {
     {
         reg.register::<Box<dyn ServiceBuilder<Output = dyn DirectoryService>>, super::directoryservice::BigtableParameters>("bigtable");
     }
+    reg.register_wrapper::<dyn DirectoryService>(|instance_name, service| {
+        Arc::new(MetricsDirectoryService::new(instance_name, service))
+    });
 }
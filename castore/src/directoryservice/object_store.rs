@@ -22,6 +22,7 @@ use super::{
     RootToLeavesValidator,
 };
 use crate::composition::{CompositionContext, ServiceBuilder};
+use crate::traced_object_store::TracedObjectStore;
 use crate::{proto, B3Digest, Error, Node};
 
 /// Stores directory closures in an object store.
@@ -64,11 +65,7 @@ impl ObjectStoreDirectoryService {
     {
         let (object_store, path) = object_store::parse_url_opts(url, options)?;
 
-        Ok(Self {
-            instance_name: "root".into(),
-            object_store: Arc::new(object_store),
-            base_path: path,
-        })
+        Ok(Self::new("root".into(), Arc::new(object_store), path))
     }
 
     /// Like [Self::parse_url_opts], except without the options.
@@ -76,10 +73,16 @@ impl ObjectStoreDirectoryService {
         Self::parse_url_opts(url, Vec::<(String, String)>::new())
     }
 
+    /// Requests to the object store are traced, and recorded in the metrics of
+    /// this instance.
     pub fn new(instance_name: String, object_store: Arc<dyn ObjectStore>, base_path: Path) -> Self {
         Self {
+            object_store: Arc::new(TracedObjectStore::new(
+                object_store,
+                "directoryservice",
+                &instance_name,
+            )),
             instance_name,
-            object_store,
             base_path,
         }
     }
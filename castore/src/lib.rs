@@ -1,6 +1,7 @@
 mod digests;
 mod errors;
 mod hashing_reader;
+mod traced_object_store;
 
 pub mod blobservice;
 pub mod composition;
@@ -0,0 +1,427 @@
+//! A wrapper around an [ObjectStore], tracing and measuring all requests
+//! sent to it.
+
+use std::{fmt, future::Future, ops::Range, sync::Arc, time::Instant};
+
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use object_store::{
+    path::Path, GetOptions, GetResult, GetResultPayload, ListResult, MultipartUpload, ObjectMeta,
+    ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult, Result,
+};
+use tonic::async_trait;
+use tracing::instrument;
+use tvix_tracing::metrics::InstanceMetrics;
+
+/// Wraps an [ObjectStore], emitting a span for every request sent to it, and
+/// recording them in the [InstanceMetrics] of the service using it.
+/// This makes slow requests to S3, GCS etc. show up inside the trace of the
+/// operation that caused them.
+///
+/// Multipart uploads are only traced until the upload is started, listing is
+/// not traced at all, as it returns a stream.
+pub(crate) struct TracedObjectStore {
+    inner: Arc<dyn ObjectStore>,
+    metrics: InstanceMetrics,
+}
+
+impl TracedObjectStore {
+    pub(crate) fn new(
+        inner: Arc<dyn ObjectStore>,
+        service: &'static str,
+        instance_name: &str,
+    ) -> Self {
+        Self {
+            inner,
+            metrics: InstanceMetrics::new(service, instance_name),
+        }
+    }
+
+    /// Runs the future, recording it as a request.
+    async fn observe<T>(
+        &self,
+        operation: &'static str,
+        fut: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let start = Instant::now();
+        let result = fut.await;
+
+        // NotFound is an expected answer (we check for existence using head
+        // requests), not a failed request.
+        let outcome = match &result {
+            Err(object_store::Error::NotFound { .. }) | Ok(_) => Ok(()),
+            Err(_) => Err(()),
+        };
+        self.metrics.record_request(operation, start, &outcome);
+
+        result
+    }
+}
+
+impl fmt::Display for TracedObjectStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Traced({})", self.inner)
+    }
+}
+
+impl fmt::Debug for TracedObjectStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TracedObjectStore")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for TracedObjectStore {
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location, object_store.size = payload.content_length()))]
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        let size = payload.content_length() as u64;
+        self.observe("object_store.put", async {
+            let result = self.inner.put_opts(location, payload, opts).await?;
+            self.metrics.record_bytes("object_store.put", size);
+            Ok(result)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location))]
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        self.observe(
+            "object_store.put_multipart",
+            self.inner.put_multipart_opts(location, opts),
+        )
+        .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location, object_store.head = options.head))]
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let mut result = self
+            .observe("object_store.get", self.inner.get_opts(location, options))
+            .await?;
+
+        // The body is streamed afterwards, count the bytes as they arrive.
+        // Local files are not transferred, so not counted.
+        if let GetResultPayload::Stream(stream) = &mut result.payload {
+            let metrics = self.metrics.clone();
+            *stream = std::mem::replace(stream, futures::stream::empty().boxed())
+                .inspect_ok(move |bytes| {
+                    metrics.record_bytes("object_store.get", bytes.len() as u64)
+                })
+                .boxed();
+        }
+
+        Ok(result)
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location, object_store.range = ?range))]
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.observe("object_store.get_range", async {
+            let bytes = self.inner.get_range(location, range).await?;
+            self.metrics
+                .record_bytes("object_store.get_range", bytes.len() as u64);
+            Ok(bytes)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location, object_store.ranges = ranges.len()))]
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.observe("object_store.get_ranges", async {
+            let bytes = self.inner.get_ranges(location, ranges).await?;
+            self.metrics.record_bytes(
+                "object_store.get_ranges",
+                bytes.iter().map(|b| b.len() as u64).sum(),
+            );
+            Ok(bytes)
+        })
+        .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location))]
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.observe("object_store.head", self.inner.head(location))
+            .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.path = %location))]
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.observe("object_store.delete", self.inner.delete(location))
+            .await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.prefix = ?prefix))]
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.observe(
+            "object_store.list_with_delimiter",
+            self.inner.list_with_delimiter(prefix),
+        )
+        .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.from = %from, object_store.to = %to))]
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.observe("object_store.copy", self.inner.copy(from, to))
+            .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.from = %from, object_store.to = %to))]
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.observe("object_store.rename", self.inner.rename(from, to))
+            .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.from = %from, object_store.to = %to))]
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.observe(
+            "object_store.copy_if_not_exists",
+            self.inner.copy_if_not_exists(from, to),
+        )
+        .await
+    }
+
+    #[instrument(skip_all, fields(otel.kind = "client", object_store.from = %from, object_store.to = %to))]
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.observe(
+            "object_store.rename_if_not_exists",
+            self.inner.rename_if_not_exists(from, to),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::{
+        metrics::{
+            data::{ResourceMetrics, Sum},
+            PeriodicReader, SdkMeterProvider,
+        },
+        runtime,
+        testing::metrics::InMemoryMetricExporter,
+    };
+    use tvix_tracing::metrics::{InstanceMetrics, METER_NAME};
+
+    use super::TracedObjectStore;
+
+    /// Returns the data points of the metric with the given name, as
+    /// (operation, value) tuples.
+    fn sum_points(metrics: &ResourceMetrics, name: &str) -> Vec<(String, u64)> {
+        let mut points: Vec<_> = metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+            .filter(|metric| metric.name == name)
+            .flat_map(|metric| {
+                metric
+                    .data
+                    .as_any()
+                    .downcast_ref::<Sum<u64>>()
+                    .expect("must be a u64 sum")
+                    .data_points
+                    .iter()
+            })
+            .map(|point| {
+                let operation = point
+                    .attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == "operation")
+                    .map(|kv| kv.value.to_string())
+                    .unwrap_or_default();
+                (operation, point.value)
+            })
+            .collect();
+        points.sort();
+        points
+    }
+
+    /// Requests are recorded as object_store.* operations, NotFound is not
+    /// counted as an error, and bytes of a get are counted as the body is
+    /// read.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn records_metrics() {
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone(), runtime::Tokio).build())
+            .build();
+        let store = TracedObjectStore {
+            inner: Arc::new(InMemory::new()),
+            metrics: InstanceMetrics::with_meter(
+                &provider.meter(METER_NAME),
+                "blobservice",
+                "test",
+            ),
+        };
+        let path = Path::from("foo/bar");
+
+        let collect = || {
+            provider.force_flush().expect("flush must succeed");
+            exporter
+                .get_finished_metrics()
+                .expect("must have metrics")
+                .pop()
+                .expect("must have metrics")
+        };
+        let op = |operation: &str, value| (operation.to_string(), value);
+
+        assert!(matches!(
+            store.head(&path).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+        store
+            .put(&path, b"hello world".to_vec().into())
+            .await
+            .expect("put must succeed");
+        store.head(&path).await.expect("must exist");
+        store.get_range(&path, 6..11).await.expect("must succeed");
+        let get = store.get(&path).await.expect("must exist");
+
+        let metrics = collect();
+        assert_eq!(
+            vec![
+                op("object_store.get", 1),
+                op("object_store.get_range", 1),
+                op("object_store.head", 2),
+                op("object_store.put", 1),
+            ],
+            sum_points(&metrics, "tvix.store.requests")
+        );
+        assert_eq!(
+            Vec::<(String, u64)>::new(),
+            sum_points(&metrics, "tvix.store.errors")
+        );
+        // nothing of the body has been read yet.
+        assert_eq!(
+            vec![op("object_store.get_range", 5), op("object_store.put", 11)],
+            sum_points(&metrics, "tvix.store.bytes")
+        );
+
+        get.bytes().await.expect("must succeed");
+
+        assert_eq!(
+            vec![
+                op("object_store.get", 11),
+                op("object_store.get_range", 5),
+                op("object_store.put", 11),
+            ],
+            sum_points(&collect(), "tvix.store.bytes")
+        );
+    }
+
+    /// Records the names and fields of all spans created.
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<Mutex<Vec<(&'static str, Vec<(String, String)>)>>>);
+
+    impl tracing::field::Visit for SpanRecorder {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if let Some((_, fields)) = self.0.lock().unwrap().last_mut() {
+                fields.push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let id = {
+                let mut spans = self.0.lock().unwrap();
+                spans.push((span.metadata().name(), vec![]));
+                spans.len() as u64
+            };
+            span.record(&mut self.clone());
+            tracing::span::Id::from_u64(id)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+        fn event(&self, _event: &tracing::Event<'_>) {}
+        fn enter(&self, _span: &tracing::span::Id) {}
+        fn exit(&self, _span: &tracing::span::Id) {}
+    }
+
+    /// Each request is traced in a client span, carrying the path.
+    #[tokio::test]
+    async fn emits_spans() {
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let store = TracedObjectStore::new(Arc::new(InMemory::new()), "blobservice", "test");
+        let path = Path::from("foo/bar");
+        let _ = store.head(&path).await;
+
+        let spans = recorder.0.lock().unwrap().clone();
+        assert_eq!(
+            vec![(
+                "head",
+                vec![
+                    ("otel.kind".to_string(), "\"client\"".to_string()),
+                    ("object_store.path".to_string(), "foo/bar".to_string()),
+                ]
+            )],
+            spans
+        );
+    }
+
+    /// Requests are passed through to the wrapped store, including NotFound
+    /// errors, which the services rely on.
+    #[tokio::test]
+    async fn passes_through() {
+        let store = TracedObjectStore::new(Arc::new(InMemory::new()), "blobservice", "test");
+        let path = Path::from("foo/bar");
+
+        assert!(matches!(
+            store.head(&path).await,
+            Err(object_store::Error::NotFound { .. })
+        ));
+
+        store
+            .put(&path, b"hello world".to_vec().into())
+            .await
+            .expect("put must succeed");
+
+        assert_eq!(11, store.head(&path).await.expect("must exist").size);
+        assert_eq!(
+            b"world".as_slice(),
+            store.get_range(&path, 6..11).await.expect("must succeed")
+        );
+        assert_eq!(
+            b"hello world".as_slice(),
+            store
+                .get(&path)
+                .await
+                .expect("must exist")
+                .bytes()
+                .await
+                .expect("must succeed")
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use tvix_store::utils::ServiceUrlsMemory;
 
+use crate::attr_path::AttrPath;
+
 /// Provides a CLI interface to trigger evaluation using tvix-eval.
 ///
 /// Uses configured tvix-[ca]store and tvix-build components,
@@ -19,6 +21,25 @@ pub struct Args {
     #[clap(long, short = 'E')]
     pub expr: Option<String>,
 
+    /// Pass the value of the Nix expression EXPR as argument NAME when
+    /// auto-calling functions. Can be specified multiple times.
+    #[clap(long, num_args = 2, value_names = ["NAME", "EXPR"])]
+    pub arg: Vec<Vec<String>>,
+
+    /// Pass the string VALUE as argument NAME when auto-calling
+    /// functions. Can be specified multiple times.
+    #[clap(long, num_args = 2, value_names = ["NAME", "VALUE"])]
+    pub argstr: Vec<Vec<String>>,
+
+    /// Select an attribute from the result of the evaluation, e.g.
+    /// `-A foo.bar."baz.qux".0`. Numeric path components index into lists.
+    ///
+    /// Functions encountered along the attribute path, as well as the final
+    /// result, are called with the arguments passed in via `--arg` and
+    /// `--argstr` (like `nix-instantiate` does).
+    #[clap(long, short = 'A')]
+    pub attr: Option<AttrPath>,
+
     /// Dump the raw AST to stdout before interpreting
     #[clap(long, env = "TVIX_DISPLAY_AST")]
     pub display_ast: bool,
@@ -27,6 +48,19 @@ pub struct Args {
     #[clap(long, env = "TVIX_DUMP_BYTECODE")]
     pub dump_bytecode: bool,
 
+    /// Show the evaluation trace of errors, i.e. the call sites, forced
+    /// values and `builtins.addErrorContext` messages leading up to them.
+    #[clap(long, env = "TVIX_SHOW_TRACE")]
+    pub show_trace: bool,
+
+    /// The format in which errors and warnings are printed to stderr.
+    #[clap(long, value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+
+    /// The format of log lines written to stderr, "text" or "json".
+    #[clap(long, env, default_value_t)]
+    pub log_format: tvix_tracing::LogFormat,
+
     /// Trace the runtime of the VM
     #[clap(long, env = "TVIX_TRACE_RUNTIME")]
     pub trace_runtime: bool,
@@ -36,11 +70,49 @@ pub struct Args {
     #[clap(long, env = "TVIX_TRACE_RUNTIME_TIMING", requires("trace_runtime"))]
     pub trace_runtime_timing: bool,
 
+    /// Profile the evaluation, and write the time spent in (and the number
+    /// of values allocated by) each stack of lambdas and builtins to the
+    /// given file, in the format selected by `--profile-format`.
+    ///
+    /// The file is overwritten after every evaluation (e.g. every line in the
+    /// REPL).
+    #[clap(long, conflicts_with = "trace_runtime")]
+    pub profile: Option<PathBuf>,
+
+    /// The format of the profile written with `--profile`.
+    #[clap(long, value_enum, default_value_t = ProfileFormat::Folded, requires = "profile")]
+    pub profile_format: ProfileFormat,
+
+    /// Instantiate the derivations in the result of the evaluation, like
+    /// `nix-instantiate` does: write the `.drv` files of all derivations
+    /// encountered during evaluation into the store, and print the paths of
+    /// the `.drv` files of the derivations in the result.
+    ///
+    /// The result can be a derivation, or an attribute set or list of them.
+    #[clap(long, conflicts_with = "compile_only")]
+    pub instantiate: bool,
+
     /// Only compile, but do not execute code. This will make Tvix act
     /// sort of like a linter.
     #[clap(long)]
     pub compile_only: bool,
 
+    /// Run a Debug Adapter Protocol server on stdin/stdout, instead of
+    /// evaluating a script or expression passed on the command line.
+    ///
+    /// The code to debug is passed in the `program` (path to a file) or
+    /// `expression` argument of the `launch` request. Evaluation can then
+    /// be paused at breakpoints, stepped through, and inspected.
+    #[clap(long, conflicts_with_all = ["script", "expr", "compile_only", "instantiate"])]
+    pub dap: bool,
+
+    /// A directory in which the bytecode compiled from imported files is
+    /// cached, keyed by the contents of the files. Subsequent evaluations
+    /// importing the same files load their bytecode from there instead of
+    /// compiling them again.
+    #[clap(long, env = "TVIX_BYTECODE_CACHE_DIR")]
+    pub bytecode_cache_dir: Option<PathBuf>,
+
     /// Don't print warnings.
     #[clap(long)]
     pub no_warnings: bool,
@@ -62,6 +134,18 @@ pub struct Args {
     #[clap(flatten)]
     pub service_addrs: ServiceUrlsMemory,
 
+    /// Don't access the network for fetches.
+    /// Fetches from `file://` URLs, as well as fetches whose output is already
+    /// present in the store, still succeed; all others fail immediately.
+    #[clap(long, env = "TVIX_OFFLINE")]
+    pub offline: bool,
+
+    /// The build service used to realise derivation outputs that are accessed
+    /// during evaluation (import-from-derivation), e.g. by `builtins.readFile`
+    /// or `import`. Inputs are built recursively, in dependency order.
+    ///
+    /// The default `dummy://` build service refuses all builds. Failed builds
+    /// are surfaced as catchable evaluation errors.
     #[arg(long, env, default_value = "dummy://")]
     pub build_service_addr: String,
 
@@ -76,3 +160,36 @@ pub struct Args {
     #[clap(long)]
     pub drv_dumpdir: Option<PathBuf>,
 }
+
+/// Output formats of the profiler enabled with `--profile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProfileFormat {
+    /// Folded stacks with the time spent in them (in nanoseconds), as
+    /// consumed by `flamegraph.pl` or `inferno-flamegraph`.
+    Folded,
+
+    /// JSON profile for <https://www.speedscope.app>, containing both the
+    /// time spent in each stack and the number of values allocated.
+    Speedscope,
+}
+
+/// Output formats of errors and warnings, selected with `--error-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Human-readable output, annotating the affected source code.
+    Human,
+
+    /// One JSON object per line, containing the code, message and
+    /// locations (with 1-indexed lines and columns) of each error or
+    /// warning, along with the nested causes and trace of errors.
+    Json,
+}
+
+impl Args {
+    /// Returns true if any of `--arg`, `--argstr` or `--attr` were passed,
+    /// in which case the result of the evaluation needs to be auto-called
+    /// and the attribute path selected.
+    pub fn has_auto_args(&self) -> bool {
+        !self.arg.is_empty() || !self.argstr.is_empty() || self.attr.is_some()
+    }
+}
@@ -0,0 +1,275 @@
+//! Support for `nix-instantiate`-style auto-calling of evaluation results with
+//! the arguments passed via `--arg` and `--argstr`, and selection of
+//! attributes via `--attr`.
+
+use std::{rc::Rc, str::FromStr};
+
+use tvix_eval::builtin_macros::builtins;
+use tvix_eval::{ErrorKind, GlobalsMap, NixAttrs, NixList, SourceCode, Value};
+use tvix_glue::tvix_store_io::TvixStoreIO;
+
+use crate::Args;
+
+/// Nix code applied to the result of the evaluation when `--arg`, `--argstr`
+/// or `--attr` are passed.
+///
+/// `root` is the (lazily evaluated) result of the evaluation, `autoArgs` the
+/// attribute set of arguments and `attrPath` the list of parsed attribute
+/// path components, where integers index into lists.
+///
+/// Like in Nix, only functions taking an attribute set with formal arguments
+/// (even `{ }:`) and functors are auto-called, at every step of the attribute
+/// path as well as on the final result. They get the subset of `autoArgs` they
+/// accept, or all of them if their formals have an ellipsis.
+///
+/// `hasFormals` and `hasEllipsis` are the builtins from [auto_call_builtins].
+pub(crate) const SELECT_ATTR_PATH: &str = r#"
+let
+  autoCall = f:
+    if builtins.isAttrs f && f ? __functor
+    then autoCall (f.__functor f)
+    else if hasFormals f
+    then f (if hasEllipsis f then autoArgs else builtins.intersectAttrs (builtins.functionArgs f) autoArgs)
+    else f;
+
+  select = v: component:
+    let v' = autoCall v; in
+    if builtins.isInt component
+    then builtins.elemAt v' component
+    else v'.${component};
+in
+autoCall (builtins.foldl' select root attrPath)
+"#;
+
+/// Builtins passed to [SELECT_ATTR_PATH], to inspect the formal arguments of
+/// functions, which `builtins.functionArgs` does not fully expose.
+#[builtins]
+pub(crate) mod auto_call_builtins {
+    use tvix_eval::generators::{Gen, GenCo};
+    use tvix_eval::{ErrorKind, Value};
+
+    /// Returns true if the value is a function taking an attribute set with
+    /// formal arguments.
+    #[builtin("hasFormals")]
+    async fn builtin_has_formals(co: GenCo, f: Value) -> Result<Value, ErrorKind> {
+        Ok(Value::Bool(match f {
+            Value::Closure(closure) => closure.has_formals(),
+            _ => false,
+        }))
+    }
+
+    /// Returns true if the value is a function whose formal arguments end in
+    /// an ellipsis.
+    #[builtin("hasEllipsis")]
+    async fn builtin_has_ellipsis(co: GenCo, f: Value) -> Result<Value, ErrorKind> {
+        Ok(Value::Bool(match f {
+            Value::Closure(closure) => closure.has_ellipsis(),
+            _ => false,
+        }))
+    }
+}
+
+/// A single component of an attribute path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrPathComponent {
+    /// Selects the attribute with the given name from an attribute set.
+    Name(String),
+
+    /// Selects the element at the given index from a list.
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AttrPathError {
+    #[error("missing closing quote in selection path '{0}'")]
+    MissingClosingQuote(String),
+
+    #[error("empty attribute name in selection path '{0}'")]
+    EmptyAttrName(String),
+}
+
+/// An attribute path like `foo.bar."baz.qux".0`, as passed to `--attr`.
+///
+/// It is parsed the same way `nix-instantiate` does: Components are separated
+/// by dots, and can be quoted to contain dots. Purely numeric components index
+/// into lists. An empty attribute path selects the result itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttrPath(pub Vec<AttrPathComponent>);
+
+impl FromStr for AttrPath {
+    type Err = AttrPathError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let mut names = vec![];
+        let mut current = String::new();
+        let mut quoted = false;
+
+        for c in path.chars() {
+            match c {
+                '"' => quoted = !quoted,
+                '.' if !quoted => names.push(std::mem::take(&mut current)),
+                c => current.push(c),
+            }
+        }
+
+        if quoted {
+            return Err(AttrPathError::MissingClosingQuote(path.to_string()));
+        }
+
+        if !current.is_empty() {
+            names.push(current);
+        }
+
+        names
+            .into_iter()
+            .map(|name| {
+                if name.is_empty() {
+                    Err(AttrPathError::EmptyAttrName(path.to_string()))
+                } else if let Ok(index) = name.parse::<usize>() {
+                    Ok(AttrPathComponent::Index(index))
+                } else {
+                    Ok(AttrPathComponent::Name(name))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(AttrPath)
+    }
+}
+
+/// Converts the attribute path into the list passed as `attrPath` to
+/// [SELECT_ATTR_PATH].
+pub(crate) fn attr_path_value(attr_path: &AttrPath) -> Value {
+    Value::List(NixList::from(
+        attr_path
+            .0
+            .iter()
+            .map(|component| match component {
+                AttrPathComponent::Name(name) => Value::from(name.as_str()),
+                AttrPathComponent::Index(index) => Value::Integer(*index as i64),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Returns the attribute set of arguments passed via `--arg` and `--argstr`.
+///
+/// Each expression passed via `--arg` is evaluated on its own, and lazily, so
+/// arguments that are not accepted by any of the auto-called functions are
+/// never evaluated.
+pub(crate) fn auto_args(
+    tvix_store_io: Rc<TvixStoreIO>,
+    args: &Args,
+    globals: Rc<GlobalsMap>,
+    source_map: SourceCode,
+) -> Value {
+    let arg_values = args.arg.iter().map(|arg| {
+        let [name, expr] = arg.as_slice() else {
+            unreachable!("clap ensures --arg takes exactly two values");
+        };
+
+        let tvix_store_io = Rc::clone(&tvix_store_io);
+        let args = args.clone();
+        let globals = Rc::clone(&globals);
+        let source_map = source_map.clone();
+        let expr = expr.clone();
+
+        let value = Value::suspended_native_thunk(Box::new(move || {
+            let result = crate::evaluation_builder(
+                Rc::clone(&tvix_store_io),
+                &args,
+                None,
+                Some(Rc::clone(&globals)),
+            )
+            .with_source_map(source_map.clone())
+            .build()
+            .evaluate(&expr, None);
+
+            match (result.value, result.errors.into_iter().next()) {
+                (_, Some(err)) => Err(ErrorKind::NativeError {
+                    gen_type: "--arg",
+                    err: Box::new(err),
+                }),
+                (Some(value), None) => Ok(value),
+                (None, None) => unreachable!("evaluation without errors must return a value"),
+            }
+        }));
+
+        (name.clone(), value)
+    });
+
+    let argstr_values = args.argstr.iter().map(|arg| {
+        let [name, value] = arg.as_slice() else {
+            unreachable!("clap ensures --argstr takes exactly two values");
+        };
+        (name.clone(), Value::from(value.as_str()))
+    });
+
+    Value::attrs(arg_values.chain(argstr_values).collect::<NixAttrs>())
+}
+
+/// Returns an empty attribute set, for use as `autoArgs` if no arguments
+/// have been passed.
+pub(crate) fn empty_auto_args() -> Value {
+    Value::attrs(NixAttrs::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_attr_paths() {
+        assert_eq!("".parse::<AttrPath>().unwrap(), AttrPath::default());
+        assert_eq!(
+            "foo.bar".parse::<AttrPath>().unwrap(),
+            AttrPath(vec![
+                AttrPathComponent::Name("foo".into()),
+                AttrPathComponent::Name("bar".into())
+            ])
+        );
+        // A trailing dot is ignored, like in Nix.
+        assert_eq!(
+            "foo.".parse::<AttrPath>().unwrap(),
+            AttrPath(vec![AttrPathComponent::Name("foo".into())])
+        );
+    }
+
+    #[test]
+    fn quoted_attr_paths() {
+        assert_eq!(
+            r#"foo."bar.baz".qux"#.parse::<AttrPath>().unwrap(),
+            AttrPath(vec![
+                AttrPathComponent::Name("foo".into()),
+                AttrPathComponent::Name("bar.baz".into()),
+                AttrPathComponent::Name("qux".into())
+            ])
+        );
+        assert_eq!(
+            r#"foo."bar"#.parse::<AttrPath>(),
+            Err(AttrPathError::MissingClosingQuote(r#"foo."bar"#.into()))
+        );
+    }
+
+    #[test]
+    fn list_indices() {
+        assert_eq!(
+            "foo.0.bar".parse::<AttrPath>().unwrap(),
+            AttrPath(vec![
+                AttrPathComponent::Name("foo".into()),
+                AttrPathComponent::Index(0),
+                AttrPathComponent::Name("bar".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn empty_attr_names() {
+        for path in [".foo", "foo..bar", r#"foo."".bar"#] {
+            assert_eq!(
+                path.parse::<AttrPath>(),
+                Err(AttrPathError::EmptyAttrName(path.into())),
+                "{path:?}"
+            );
+        }
+    }
+}
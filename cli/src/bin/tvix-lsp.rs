@@ -0,0 +1,29 @@
+use clap::Parser;
+use mimalloc::MiMalloc;
+use std::io::{stdin, stdout, BufReader};
+use tvix_cli::args::Args;
+use tvix_cli::init_io_handle;
+
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+/// Language server for Nix code, using the Tvix compiler for analysis.
+///
+/// Accepts the same options as `tvix` (e.g. `-I` for the Nix search
+/// path), and communicates with editors over stdin/stdout.
+fn main() {
+    let args = Args::parse();
+
+    // Logs are written to stderr, stdout is reserved for the protocol.
+    tvix_tracing::TracingBuilder::default()
+        .log_format(args.log_format)
+        .build()
+        .expect("unable to set up tracing subscriber");
+    let tokio_runtime = tokio::runtime::Runtime::new().expect("failed to setup tokio runtime");
+
+    let io_handle = init_io_handle(&tokio_runtime, &args);
+
+    if !tvix_cli::lsp::run(io_handle, &args, BufReader::new(stdin()), stdout()) {
+        std::process::exit(1);
+    }
+}
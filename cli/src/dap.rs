@@ -0,0 +1,658 @@
+//! Implements a [Debug Adapter Protocol][dap] server for stepping
+//! through Nix evaluation (`tvix --dap`).
+//!
+//! Messages are exchanged over stdin/stdout. The client starts the
+//! evaluation of a file (`program`) or an `expression` with a `launch`
+//! request, after which evaluation can be paused at breakpoints, stepped
+//! through with `next`, `stepIn` and `stepOut`, and inspected with the
+//! `stackTrace`, `scopes`, `variables` and `evaluate` requests.
+//!
+//! Nix evaluation is single-threaded, so the server reports a single
+//! thread. Forcing a thunk or calling a function pushes a new stack
+//! frame, which is what `stepIn` steps into.
+//!
+//! [dap]: https://microsoft.github.io/debug-adapter-protocol/
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+
+use serde_json::{json, Value as Json};
+use smol_str::SmolStr;
+use tvix_eval::debugger::{
+    Breakpoints, DebugFrontend, DebuggingObserver, PausedState, ResumeMode, StopReason,
+};
+use tvix_eval::{EvalIO, Value};
+use tvix_glue::{tvix_io::TvixIO, tvix_store_io::TvixStoreIO};
+
+use crate::{evaluation_builder, Args};
+
+/// The only thread reported to clients.
+const THREAD_ID: u64 = 1;
+
+/// Read a single message, framed by a `Content-Length` header, from the
+/// given reader. Returns `None` once the reader is exhausted.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Json>> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse::<usize>().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length")
+                })?);
+            }
+        }
+    }
+
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write a single message, framed by a `Content-Length` header, to the
+/// given writer.
+pub fn write_message<W: Write>(writer: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Client connection, reading requests from a background thread.
+struct Connection<W: Write> {
+    requests: Receiver<Json>,
+
+    /// Requests that were received while evaluation was running, but
+    /// can only be handled while it is paused.
+    deferred: VecDeque<Json>,
+    output: W,
+    seq: u64,
+}
+
+impl<W: Write> Connection<W> {
+    fn new<R: BufRead + Send + 'static>(mut input: R, output: W) -> Self {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            requests: rx,
+            deferred: VecDeque::new(),
+            output,
+            seq: 0,
+        }
+    }
+
+    /// Wait for the next request. Returns `None` once the client is gone.
+    fn next_request(&mut self) -> Option<Json> {
+        self.deferred
+            .pop_front()
+            .or_else(|| self.requests.recv().ok())
+    }
+
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+
+        // There is nobody left to report errors to if the output is gone.
+        let _ = write_message(&mut self.output, &message);
+    }
+
+    fn respond(&mut self, request: &Json, body: Json) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn respond_error(&mut self, request: &Json, message: impl Into<String>) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message.into(),
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+}
+
+/// Values which can be expanded by a `variables` request, referenced by
+/// their (1-based) position in [`Session::handles`].
+enum Handle {
+    Locals(usize),
+    Upvalues(usize),
+    Value(Value),
+}
+
+/// What to evaluate, as passed to the `launch` request.
+struct Launch {
+    code: String,
+    path: Option<PathBuf>,
+    stop_on_entry: bool,
+}
+
+/// Debugging session with a single client.
+struct Session<W: Write> {
+    conn: Connection<W>,
+    handles: Vec<Handle>,
+    disconnected: bool,
+}
+
+/// Return the name of the command of the given request.
+fn command(request: &Json) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+/// Return the path of the file referenced by a `source` argument, in
+/// the form used for it in the source map.
+fn source_path(source: &Json) -> Option<String> {
+    let path = PathBuf::from(source["path"].as_str()?);
+    let path = path.canonicalize().unwrap_or(path);
+    Some(path.to_string_lossy().into_owned())
+}
+
+/// Handle a `setBreakpoints` request.
+fn set_breakpoints<W: Write>(
+    conn: &mut Connection<W>,
+    breakpoints: &mut Breakpoints,
+    request: &Json,
+) {
+    let args = &request["arguments"];
+    let Some(path) = source_path(&args["source"]) else {
+        conn.respond_error(request, "breakpoints require a source path");
+        return;
+    };
+
+    let lines: Vec<usize> = match args["breakpoints"].as_array() {
+        Some(breakpoints) => breakpoints
+            .iter()
+            .filter_map(|bp| bp["line"].as_u64())
+            .map(|line| line as usize)
+            .collect(),
+        None => args["lines"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Json::as_u64)
+            .map(|line| line as usize)
+            .collect(),
+    };
+
+    breakpoints.set(path, lines.iter().copied());
+    let verified: Vec<Json> = lines
+        .iter()
+        .map(|line| json!({ "verified": true, "line": line }))
+        .collect();
+    conn.respond(request, json!({ "breakpoints": verified }));
+}
+
+/// Describe a value for display in the client, returning its
+/// representation, type and whether it can be expanded.
+fn describe(value: &Value) -> (String, Option<&'static str>, bool) {
+    match value {
+        Value::Thunk(thunk) if thunk.is_evaluated() => describe(&thunk.value()),
+        Value::Thunk(_) => ("<thunk>".to_string(), None, false),
+        Value::Attrs(attrs) => (
+            format!("{{ {} attributes }}", attrs.len()),
+            Some(value.type_of()),
+            !attrs.is_empty(),
+        ),
+        Value::List(list) => (
+            format!("[ {} elements ]", list.len()),
+            Some(value.type_of()),
+            !list.is_empty(),
+        ),
+        _ => (value.to_string(), Some(value.type_of()), false),
+    }
+}
+
+/// Return the children of an expandable value.
+fn children(value: &Value) -> Vec<(SmolStr, Value)> {
+    match value {
+        Value::Thunk(thunk) if thunk.is_evaluated() => children(&thunk.value()),
+        Value::Attrs(attrs) => attrs
+            .iter_sorted()
+            .map(|(name, value)| (SmolStr::new(name.to_string()), value.clone()))
+            .collect(),
+        Value::List(list) => list
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| (SmolStr::new(idx.to_string()), value.clone()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+impl<W: Write> Session<W> {
+    fn handle(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    /// Render a variable (or the result of an evaluation), allocating a
+    /// handle for its children if it has any.
+    fn variable(&mut self, name: &str, value: Value) -> Json {
+        let (repr, type_of, expandable) = describe(&value);
+        let reference = if expandable {
+            self.handle(Handle::Value(value))
+        } else {
+            0
+        };
+
+        json!({
+            "name": name,
+            "value": repr,
+            "type": type_of,
+            "variablesReference": reference,
+        })
+    }
+
+    fn disconnect(&mut self, request: Option<&Json>, breakpoints: &mut Breakpoints) {
+        if let Some(request) = request {
+            self.conn.respond(request, json!({}));
+        }
+
+        // Evaluation can not be aborted from within, so it runs to
+        // completion without stopping again.
+        *breakpoints = Breakpoints::default();
+        self.disconnected = true;
+    }
+
+    /// Handle a request while evaluation is paused. Returns how to
+    /// resume evaluation if the request resumes it.
+    fn handle_paused(&mut self, request: &Json, state: &mut PausedState) -> Option<ResumeMode> {
+        let args = &request["arguments"];
+        let frame_id = args["frameId"].as_u64().unwrap_or(0) as usize;
+
+        match command(request) {
+            "threads" => self.respond_threads(request),
+
+            "stackTrace" => {
+                let frames: Vec<Json> = state
+                    .stack_frames()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, frame)| {
+                        let mut json = json!({
+                            "id": id,
+                            "name": frame.name,
+                            "line": frame.line,
+                            "column": frame.column,
+                        });
+
+                        if let Some(file) = frame.file {
+                            let name = file.rsplit('/').next().unwrap_or_default().to_string();
+                            json["source"] = json!({ "name": name, "path": file });
+                        }
+
+                        json
+                    })
+                    .collect();
+
+                let total = frames.len();
+                self.conn.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": total }),
+                );
+            }
+
+            "scopes" => {
+                let locals = self.handle(Handle::Locals(frame_id));
+                let upvalues = self.handle(Handle::Upvalues(frame_id));
+                self.conn.respond(
+                    request,
+                    json!({ "scopes": [
+                        { "name": "Locals", "variablesReference": locals, "expensive": false },
+                        { "name": "Upvalues", "variablesReference": upvalues, "expensive": false },
+                    ]}),
+                );
+            }
+
+            "variables" => {
+                let reference = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let variables = match reference
+                    .checked_sub(1)
+                    .and_then(|idx| self.handles.get(idx))
+                {
+                    Some(Handle::Locals(frame)) => state.locals(*frame),
+                    Some(Handle::Upvalues(frame)) => state.upvalues(*frame),
+                    Some(Handle::Value(value)) => children(value),
+                    None => {
+                        self.conn
+                            .respond_error(request, "unknown variables reference");
+                        return None;
+                    }
+                };
+
+                let variables: Vec<Json> = variables
+                    .into_iter()
+                    .map(|(name, value)| self.variable(&name, value))
+                    .collect();
+                self.conn
+                    .respond(request, json!({ "variables": variables }));
+            }
+
+            "evaluate" => {
+                let expression = args["expression"].as_str().unwrap_or_default();
+                match state.evaluate(frame_id, expression) {
+                    Ok(value) => {
+                        let variable = self.variable("", value);
+                        self.conn.respond(
+                            request,
+                            json!({
+                                "result": variable["value"],
+                                "type": variable["type"],
+                                "variablesReference": variable["variablesReference"],
+                            }),
+                        );
+                    }
+                    Err(err) => self.conn.respond_error(request, err),
+                }
+            }
+
+            "setBreakpoints" => set_breakpoints(&mut self.conn, state.breakpoints(), request),
+
+            "continue" => {
+                self.conn
+                    .respond(request, json!({ "allThreadsContinued": true }));
+                return Some(ResumeMode::Continue);
+            }
+
+            "next" => {
+                self.conn.respond(request, json!({}));
+                return Some(ResumeMode::StepOver);
+            }
+
+            "stepIn" => {
+                self.conn.respond(request, json!({}));
+                return Some(ResumeMode::StepIn);
+            }
+
+            "stepOut" => {
+                self.conn.respond(request, json!({}));
+                return Some(ResumeMode::StepOut);
+            }
+
+            // Evaluation is already paused.
+            "pause" => self.conn.respond(request, json!({})),
+
+            "disconnect" => {
+                self.disconnect(Some(request), state.breakpoints());
+                return Some(ResumeMode::Continue);
+            }
+
+            other => self
+                .conn
+                .respond_error(request, format!("unsupported request '{other}'")),
+        }
+
+        None
+    }
+
+    fn respond_threads(&mut self, request: &Json) {
+        self.conn.respond(
+            request,
+            json!({ "threads": [{ "id": THREAD_ID, "name": "evaluation" }] }),
+        );
+    }
+}
+
+impl<W: Write> DebugFrontend for Session<W> {
+    fn poll(&mut self, breakpoints: &mut Breakpoints) -> bool {
+        let mut pause = false;
+
+        loop {
+            // A client that went away is noticed once evaluation
+            // pauses, after handling its remaining requests.
+            let Ok(request) = self.conn.requests.try_recv() else {
+                break;
+            };
+
+            // Keep requests in order if earlier ones are waiting for
+            // evaluation to pause.
+            if !self.conn.deferred.is_empty() {
+                self.conn.deferred.push_back(request);
+                continue;
+            }
+
+            match command(&request) {
+                "pause" => {
+                    self.conn.respond(&request, json!({}));
+                    pause = true;
+                }
+                "setBreakpoints" => set_breakpoints(&mut self.conn, breakpoints, &request),
+                "threads" => self.respond_threads(&request),
+                "disconnect" => self.disconnect(Some(&request), breakpoints),
+
+                // Everything else inspects or resumes a paused
+                // evaluation, and is handled once it pauses.
+                _ => self.conn.deferred.push_back(request),
+            }
+        }
+
+        pause && !self.disconnected
+    }
+
+    fn paused(&mut self, reason: StopReason, state: &mut PausedState) -> ResumeMode {
+        if self.disconnected {
+            return ResumeMode::Continue;
+        }
+
+        let reason = match reason {
+            StopReason::Entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+            StopReason::Pause => "pause",
+        };
+
+        self.conn.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+
+        // Variable references are only valid while paused.
+        self.handles.clear();
+
+        loop {
+            let Some(request) = self.conn.next_request() else {
+                self.disconnect(None, state.breakpoints());
+                return ResumeMode::Continue;
+            };
+
+            if let Some(mode) = self.handle_paused(&request, state) {
+                return mode;
+            }
+        }
+    }
+}
+
+/// Parse the arguments of a `launch` request.
+fn parse_launch(request: &Json) -> Result<Launch, String> {
+    let args = &request["arguments"];
+    let stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+    if let Some(expression) = args["expression"].as_str() {
+        return Ok(Launch {
+            code: expression.to_string(),
+            path: None,
+            stop_on_entry,
+        });
+    }
+
+    let Some(program) = args["program"].as_str() else {
+        return Err("either 'program' or 'expression' must be set".to_string());
+    };
+
+    let mut path = PathBuf::from(program);
+    if path.is_dir() {
+        path.push("default.nix");
+    }
+
+    // Breakpoints are set on absolute paths.
+    let path = path.canonicalize().unwrap_or(path);
+    let code = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+
+    Ok(Launch {
+        code,
+        path: Some(path),
+        stop_on_entry,
+    })
+}
+
+/// Run a debugging session, reading requests from `input` and writing
+/// responses and events to `output`, until the client disconnects.
+pub fn run<R, W>(tvix_store_io: Rc<TvixStoreIO>, args: &Args, input: R, output: W)
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let mut conn = Connection::new(input, output);
+    let mut breakpoints = Breakpoints::default();
+    let mut launched = None;
+    let mut configured = false;
+
+    // Handle the initial configuration of the session, until the client
+    // has both launched the evaluation and finished setting breakpoints.
+    let launch = loop {
+        if configured {
+            if let Some(launch) = launched.take() {
+                break launch;
+            }
+        }
+
+        let Some(request) = conn.next_request() else {
+            return;
+        };
+
+        match command(&request) {
+            "initialize" => {
+                conn.respond(
+                    &request,
+                    json!({ "supportsConfigurationDoneRequest": true }),
+                );
+                conn.event("initialized", json!({}));
+            }
+
+            "launch" => match parse_launch(&request) {
+                Ok(launch) => {
+                    conn.respond(&request, json!({}));
+                    launched = Some(launch);
+                }
+                Err(err) => conn.respond_error(&request, err),
+            },
+
+            "setBreakpoints" => set_breakpoints(&mut conn, &mut breakpoints, &request),
+
+            "configurationDone" => {
+                conn.respond(&request, json!({}));
+                configured = true;
+            }
+
+            "threads" => conn.respond(
+                &request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "evaluation" }] }),
+            ),
+
+            "disconnect" => {
+                conn.respond(&request, json!({}));
+                return;
+            }
+
+            other => conn.respond_error(&request, format!("unsupported request '{other}'")),
+        }
+    };
+
+    let mut eval_builder = evaluation_builder(Rc::clone(&tvix_store_io), args, None, None);
+    let source_map = eval_builder.source_map().clone();
+    let io: Rc<dyn EvalIO> = Rc::new(TvixIO::new(tvix_store_io as Rc<dyn EvalIO>));
+
+    let session = Session {
+        conn,
+        handles: vec![],
+        disconnected: false,
+    };
+
+    let mut observer =
+        DebuggingObserver::new(source_map, io, session).stop_on_entry(launch.stop_on_entry);
+    *observer.breakpoints() = breakpoints;
+
+    eval_builder.set_runtime_observer(Some(&mut observer));
+    let result = eval_builder.build().evaluate(&launch.code, launch.path);
+
+    let session = observer.frontend();
+    if session.disconnected {
+        return;
+    }
+
+    let conn = &mut session.conn;
+    for error in &result.errors {
+        let output = if args.show_trace {
+            error.fancy_format_str_with_trace()
+        } else {
+            error.fancy_format_str()
+        };
+        conn.event("output", json!({ "category": "stderr", "output": output }));
+    }
+
+    if let Some(value) = &result.value {
+        conn.event(
+            "output",
+            json!({
+                "category": "stdout",
+                "output": format!("=> {} :: {}\n", value, value.type_of()),
+            }),
+        );
+    }
+
+    let exit_code = if result.errors.is_empty() { 0 } else { 1 };
+    conn.event("exited", json!({ "exitCode": exit_code }));
+    conn.event("terminated", json!({}));
+
+    // Wait for the client to disconnect, answering requests which are
+    // no longer meaningful with errors.
+    while let Some(request) = conn.next_request() {
+        match command(&request) {
+            "disconnect" => {
+                conn.respond(&request, json!({}));
+                return;
+            }
+            "threads" => conn.respond(&request, json!({ "threads": [] })),
+            _ => conn.respond_error(&request, "evaluation has finished"),
+        }
+    }
+}
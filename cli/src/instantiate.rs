@@ -0,0 +1,116 @@
+//! Implements `nix-instantiate`-style instantiation of derivations, i.e.
+//! writing `.drv` files for all derivations in the result of an evaluation
+//! into the store.
+
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use smol_str::SmolStr;
+use tvix_eval::{SourceCode, Value};
+use tvix_glue::tvix_store_io::TvixStoreIO;
+
+use crate::{evaluate, evaluate_with_auto_args, AllowIncomplete, Args};
+
+/// Nix code returning the list of `drvPath`s of all derivations in `result`,
+/// the same way `nix-instantiate` collects them (`getDerivations` in Nix):
+///
+/// The result can be a derivation, or an attribute set or list of them. Lists
+/// are traversed at any depth. Of attribute sets, only the members that are
+/// derivations are collected, and nested attribute sets are only traversed if
+/// they set `recurseForDerivations = true`. Lists inside attribute sets are
+/// never traversed.
+///
+/// The same derivation can be returned more than once, [instantiate] removes
+/// the duplicates.
+const COLLECT_DERIVATIONS: &str = r#"
+let
+  isDerivation = v: builtins.isAttrs v && v.type or null == "derivation";
+
+  collect = v:
+    if isDerivation v then [ v.drvPath ]
+    else if builtins.isList v then builtins.concatMap collect v
+    else if builtins.isAttrs v
+    then builtins.concatMap (name: collectMember v.${name}) (builtins.attrNames v)
+    else throw "expression does not evaluate to a derivation (or a set or list of those)";
+
+  collectMember = v:
+    if isDerivation v then [ v.drvPath ]
+    else if builtins.isAttrs v && v.recurseForDerivations or false then collect v
+    else [ ];
+in
+collect result
+"#;
+
+/// Evaluates the given code snippet (applying `--arg`, `--argstr` and
+/// `--attr`), writes all derivations encountered during evaluation into the
+/// store, and returns the paths of the `.drv` files of the derivations in the
+/// result, one per line.
+///
+/// Returns [None] if evaluation failed, or the derivations could not be
+/// written. Errors are printed to stderr.
+pub fn instantiate(
+    tvix_store_io: Rc<TvixStoreIO>,
+    code: &str,
+    path: Option<PathBuf>,
+    args: &Args,
+) -> Option<String> {
+    let source_map = SourceCode::default();
+
+    let result = evaluate_with_auto_args(
+        Rc::clone(&tvix_store_io),
+        code,
+        path,
+        &Args {
+            strict: false,
+            ..args.clone()
+        },
+        source_map.clone(),
+    )
+    .expect("complete input is required");
+
+    let env: FxHashMap<SmolStr, Value> = [("result".into(), result.value?)].into_iter().collect();
+
+    let drv_paths = evaluate(
+        Rc::clone(&tvix_store_io),
+        COLLECT_DERIVATIONS,
+        None,
+        &Args {
+            strict: true,
+            display_ast: false,
+            ..args.clone()
+        },
+        AllowIncomplete::RequireComplete,
+        Some(&env),
+        Some(result.globals),
+        Some(source_map),
+    )
+    .expect("complete input is required")
+    .value?;
+
+    if let Err(e) = tvix_store_io.add_known_derivations_to_store() {
+        eprintln!("error: failed to write derivations to the store: {e}");
+        return None;
+    }
+
+    // Like Nix, print every derivation only once, in the order they were
+    // first encountered.
+    let mut seen = FxHashSet::default();
+    let mut output = String::new();
+    for drv_path in drv_paths
+        .to_list()
+        .expect("Tvix bug: collected derivations must be a list")
+        .iter()
+    {
+        let drv_path = drv_path
+            .to_contextful_str()
+            .expect("Tvix bug: drvPath must be a string");
+        let drv_path = String::from_utf8_lossy(drv_path.as_bytes()).into_owned();
+        if seen.insert(drv_path.clone()) {
+            output.push_str(&drv_path);
+            output.push('\n');
+        }
+    }
+
+    Some(output)
+}
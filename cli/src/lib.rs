@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use rustc_hash::FxHashMap;
@@ -9,8 +9,8 @@ use tracing_indicatif::span_ext::IndicatifSpanExt;
 use tvix_build::buildservice;
 use tvix_eval::{
     builtins::impure_builtins,
-    observer::{DisassemblingObserver, TracingObserver},
-    ErrorKind, EvalIO, EvalMode, GlobalsMap, SourceCode, Value,
+    observer::{DisassemblingObserver, ProfilingObserver, TracingObserver},
+    BytecodeCache, ErrorKind, EvalIO, EvalMode, EvaluationBuilder, GlobalsMap, SourceCode, Value,
 };
 use tvix_glue::{
     builtins::{add_derivation_builtins, add_fetcher_builtins, add_import_builtins},
@@ -21,11 +21,34 @@ use tvix_glue::{
 
 pub mod args;
 pub mod assignment;
+pub mod attr_path;
+pub mod dap;
+pub mod instantiate;
+pub mod lsp;
 pub mod repl;
 
-pub use args::Args;
+pub use args::{Args, ErrorFormat, ProfileFormat};
 pub use repl::Repl;
 
+/// Print the given error to stderr, in the format selected with
+/// `--error-format`.
+pub fn report_error(error: &tvix_eval::Error, args: &Args) {
+    match args.error_format {
+        ErrorFormat::Human if args.show_trace => error.fancy_format_stderr_with_trace(),
+        ErrorFormat::Human => error.fancy_format_stderr(),
+        ErrorFormat::Json => eprintln!("{}", error.to_json()),
+    }
+}
+
+/// Print the given warning to stderr, in the format selected with
+/// `--error-format`.
+pub fn report_warning(warning: &tvix_eval::EvalWarning, source: &SourceCode, args: &Args) {
+    match args.error_format {
+        ErrorFormat::Human => warning.fancy_format_stderr(source),
+        ErrorFormat::Json => eprintln!("{}", warning.to_json(source)),
+    }
+}
+
 pub fn init_io_handle(tokio_runtime: &tokio::runtime::Runtime, args: &Args) -> Rc<TvixStoreIO> {
     let (blob_service, directory_service, path_info_service, nar_calculation_service) =
         tokio_runtime
@@ -49,14 +72,17 @@ pub fn init_io_handle(tokio_runtime: &tokio::runtime::Runtime, args: &Args) -> R
         })
         .expect("unable to setup buildservice before interpreter setup");
 
-    Rc::new(TvixStoreIO::new(
-        blob_service.clone(),
-        directory_service.clone(),
-        path_info_service,
-        nar_calculation_service.into(),
-        build_service.into(),
-        tokio_runtime.handle().clone(),
-    ))
+    Rc::new(
+        TvixStoreIO::new(
+            blob_service.clone(),
+            directory_service.clone(),
+            path_info_service,
+            nar_calculation_service.into(),
+            build_service.into(),
+            tokio_runtime.handle().clone(),
+        )
+        .with_offline(args.offline),
+    )
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -80,23 +106,16 @@ pub struct EvalResult {
     globals: Rc<GlobalsMap>,
 }
 
-/// Interprets the given code snippet, printing out warnings and errors and returning the result
-#[allow(clippy::too_many_arguments)]
-pub fn evaluate(
+/// Sets up an [EvaluationBuilder] using the given IO handle, with the
+/// builtins, search path and evaluation mode configured by `args`.
+/// If `globals` are passed, they are used instead of setting up new
+/// builtins.
+fn evaluation_builder<'co, 'ro, 'env>(
     tvix_store_io: Rc<TvixStoreIO>,
-    code: &str,
-    path: Option<PathBuf>,
     args: &Args,
-    allow_incomplete: AllowIncomplete,
-    env: Option<&FxHashMap<SmolStr, Value>>,
+    env: Option<&'env FxHashMap<SmolStr, Value>>,
     globals: Option<Rc<GlobalsMap>>,
-    source_map: Option<SourceCode>,
-) -> Result<EvalResult, IncompleteInput> {
-    let span = Span::current();
-    span.pb_start();
-    span.pb_set_style(&tvix_tracing::PB_SPINNER_STYLE);
-    span.pb_set_message("Setting up evaluator…");
-
+) -> EvaluationBuilder<'co, 'ro, 'env, Box<dyn EvalIO>> {
     let mut eval_builder = tvix_eval::Evaluation::builder(Box::new(TvixIO::new(
         tvix_store_io.clone() as Rc<dyn EvalIO>,
     )) as Box<dyn EvalIO>)
@@ -119,6 +138,27 @@ pub fn evaluate(
         }
     };
     eval_builder = configure_nix_path(eval_builder, &args.nix_search_path);
+    eval_builder.bytecode_cache(args.bytecode_cache_dir.as_ref().map(BytecodeCache::new))
+}
+
+/// Interprets the given code snippet, printing out warnings and errors and returning the result
+#[allow(clippy::too_many_arguments)]
+pub fn evaluate(
+    tvix_store_io: Rc<TvixStoreIO>,
+    code: &str,
+    path: Option<PathBuf>,
+    args: &Args,
+    allow_incomplete: AllowIncomplete,
+    env: Option<&FxHashMap<SmolStr, Value>>,
+    globals: Option<Rc<GlobalsMap>>,
+    source_map: Option<SourceCode>,
+) -> Result<EvalResult, IncompleteInput> {
+    let span = Span::current();
+    span.pb_start();
+    span.pb_set_style(&tvix_tracing::PB_SPINNER_STYLE);
+    span.pb_set_message("Setting up evaluator…");
+
+    let mut eval_builder = evaluation_builder(Rc::clone(&tvix_store_io), args, env, globals);
 
     if let Some(source_map) = source_map {
         eval_builder = eval_builder.with_source_map(source_map);
@@ -133,11 +173,14 @@ pub fn evaluate(
         }
 
         let mut runtime_observer = TracingObserver::new(std::io::stderr());
+        let mut profiling_observer = ProfilingObserver::new(source_map.clone());
         if args.trace_runtime {
             if args.trace_runtime_timing {
                 runtime_observer.enable_timing()
             }
             eval_builder.set_runtime_observer(Some(&mut runtime_observer));
+        } else if args.profile.is_some() {
+            eval_builder.set_runtime_observer(Some(&mut profiling_observer));
         }
 
         span.pb_set_message("Evaluating…");
@@ -145,6 +188,16 @@ pub fn evaluate(
         let eval = eval_builder.build();
         let globals = eval.globals();
         let result = eval.evaluate(code, path);
+
+        if let Some(profile) = &args.profile {
+            if let Err(e) = write_profile(&profiling_observer, profile, args.profile_format) {
+                eprintln!(
+                    "warning: failed to write profile to {}: {e}",
+                    profile.display()
+                );
+            }
+        }
+
         (result, globals)
     };
 
@@ -167,12 +220,12 @@ pub fn evaluate(
     }
 
     for error in &result.errors {
-        error.fancy_format_stderr();
+        report_error(error, args);
     }
 
     if !args.no_warnings {
         for warning in &result.warnings {
-            warning.fancy_format_stderr(&source_map);
+            report_warning(warning, &source_map, args);
         }
     }
 
@@ -226,6 +279,21 @@ impl InterpretResult {
     }
 }
 
+/// Writes the profile collected during an evaluation to `path`.
+fn write_profile(
+    observer: &ProfilingObserver,
+    path: &Path,
+    format: ProfileFormat,
+) -> std::io::Result<()> {
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+    match format {
+        ProfileFormat::Folded => observer.write_folded(&mut writer)?,
+        ProfileFormat::Speedscope => observer.write_speedscope(&mut writer)?,
+    }
+
+    std::io::Write::flush(&mut writer)
+}
+
 /// Interprets the given code snippet, printing out warnings, errors
 /// and the result itself. The return value indicates whether
 /// evaluation succeeded.
@@ -242,7 +310,6 @@ pub fn interpret(
     globals: Option<Rc<GlobalsMap>>,
     source_map: Option<SourceCode>,
 ) -> Result<InterpretResult, IncompleteInput> {
-    let mut output = String::new();
     let result = evaluate(
         tvix_store_io,
         code,
@@ -254,6 +321,13 @@ pub fn interpret(
         source_map,
     )?;
 
+    Ok(render_result(result, args, explain))
+}
+
+/// Renders the value of the given [EvalResult] for display.
+fn render_result(result: EvalResult, args: &Args, explain: bool) -> InterpretResult {
+    let mut output = String::new();
+
     if let Some(value) = result.value.as_ref() {
         if explain {
             writeln!(&mut output, "=> {}", value.explain()).unwrap();
@@ -265,9 +339,106 @@ pub fn interpret(
     }
 
     // inform the caller about any errors
-    Ok(InterpretResult {
+    InterpretResult {
         output,
         success: result.value.is_some(),
         globals: Some(result.globals),
-    })
+    }
+}
+
+/// Evaluates the given code snippet like [evaluate], but applies the
+/// `--arg`, `--argstr` and `--attr` flags to the result, the same way
+/// `nix-instantiate` does:
+///
+/// The result is auto-called with the passed arguments, then the attribute
+/// path is selected from it, auto-calling functions encountered along the way.
+///
+/// Values shared between the evaluations are tracked in the given source map,
+/// which needs to be passed to further evaluations using the result.
+pub fn evaluate_with_auto_args(
+    tvix_store_io: Rc<TvixStoreIO>,
+    code: &str,
+    path: Option<PathBuf>,
+    args: &Args,
+    source_map: SourceCode,
+) -> Result<EvalResult, IncompleteInput> {
+    // The code itself is evaluated lazily, as only the selected attribute
+    // should be forced by --strict.
+    let lazy_args = Args {
+        strict: false,
+        ..args.clone()
+    };
+
+    let root = evaluate(
+        Rc::clone(&tvix_store_io),
+        code,
+        path,
+        &lazy_args,
+        AllowIncomplete::RequireComplete,
+        None,
+        None,
+        Some(source_map.clone()),
+    )?;
+
+    let Some(root_value) = root.value else {
+        return Ok(root);
+    };
+
+    // The generated code is not interesting to users of --display-ast.
+    let internal_args = Args {
+        display_ast: false,
+        ..args.clone()
+    };
+
+    let auto_args = if args.arg.is_empty() && args.argstr.is_empty() {
+        attr_path::empty_auto_args()
+    } else {
+        attr_path::auto_args(
+            Rc::clone(&tvix_store_io),
+            &lazy_args,
+            Rc::clone(&root.globals),
+            source_map.clone(),
+        )
+    };
+
+    let env: FxHashMap<SmolStr, Value> = [
+        ("root".into(), root_value),
+        ("autoArgs".into(), auto_args),
+        (
+            "attrPath".into(),
+            attr_path::attr_path_value(&args.attr.clone().unwrap_or_default()),
+        ),
+    ]
+    .into_iter()
+    .chain(
+        attr_path::auto_call_builtins::builtins()
+            .into_iter()
+            .map(|(name, value)| (name.into(), value)),
+    )
+    .collect();
+
+    evaluate(
+        tvix_store_io,
+        attr_path::SELECT_ATTR_PATH,
+        None,
+        &internal_args,
+        AllowIncomplete::RequireComplete,
+        Some(&env),
+        Some(root.globals),
+        Some(source_map),
+    )
+}
+
+/// Interprets the given code snippet like [interpret], but applies the
+/// `--arg`, `--argstr` and `--attr` flags to the result.
+/// See [evaluate_with_auto_args] for details.
+pub fn interpret_with_auto_args(
+    tvix_store_io: Rc<TvixStoreIO>,
+    code: &str,
+    path: Option<PathBuf>,
+    args: &Args,
+) -> Result<InterpretResult, IncompleteInput> {
+    let result = evaluate_with_auto_args(tvix_store_io, code, path, args, SourceCode::default())?;
+
+    Ok(render_result(result, args, false))
 }
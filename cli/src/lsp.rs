@@ -0,0 +1,632 @@
+//! Implements a [Language Server Protocol][lsp] server for Nix code
+//! (`tvix-lsp`), built on the Tvix compiler.
+//!
+//! Open documents are compiled (but not evaluated) whenever they
+//! change, and the errors and warnings emitted by the compiler are
+//! published as diagnostics.
+//!
+//! The scope resolution of the compiler is used to jump to the
+//! definitions of identifiers bound by `let`, `rec`, function arguments
+//! and `with`, and to show the values of bindings to literals on hover.
+//! Path literals (e.g. in `import ./foo.nix`) can be followed to the
+//! file they refer to, and attributes of `builtins` are completed.
+//!
+//! Messages use the same framing as the Debug Adapter Protocol (see
+//! [`crate::dap`]), and are exchanged over stdin/stdout.
+//!
+//! [lsp]: https://microsoft.github.io/language-server-protocol/
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use codemap::Span;
+use rnix::ast;
+use rowan::ast::AstNode;
+use rustc_hash::FxHashMap;
+use serde_json::{json, Value as Json};
+use tvix_eval::observer::{CompilerObserver, Resolution};
+use tvix_eval::{Error, ErrorKind, EvalWarning, GlobalsMap, SourceCode, Value, WarningKind};
+use tvix_glue::tvix_store_io::TvixStoreIO;
+
+use crate::dap::{read_message, write_message};
+use crate::{evaluation_builder, Args};
+
+/// JSON-RPC error code for requests sent after `shutdown`.
+const INVALID_REQUEST: i64 = -32600;
+
+/// JSON-RPC error code for unsupported requests.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP diagnostic severities.
+const SEVERITY_ERROR: u8 = 1;
+const SEVERITY_WARNING: u8 = 2;
+
+/// LSP diagnostic tags.
+const TAG_UNNECESSARY: u8 = 1;
+const TAG_DEPRECATED: u8 = 2;
+
+/// LSP completion item kinds.
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_CONSTANT: u8 = 21;
+
+/// Collects the identifiers resolved by the compiler.
+#[derive(Default)]
+struct IdentifierCollector(Vec<(Span, Resolution)>);
+
+impl CompilerObserver for IdentifierCollector {
+    fn observe_resolved_identifier(&mut self, span: Span, resolution: &Resolution) {
+        self.0.push((span, resolution.clone()));
+    }
+}
+
+/// An open document, together with the results of compiling it.
+struct Document {
+    text: String,
+    path: Option<PathBuf>,
+
+    /// Name of the document in the source map.
+    name: String,
+    source: SourceCode,
+    errors: Vec<Error>,
+    warnings: Vec<EvalWarning>,
+    identifiers: Vec<(Span, Resolution)>,
+}
+
+impl Document {
+    /// Return the byte range of the given span in the text of this
+    /// document, if it is part of it.
+    fn byte_range(&self, span: Span) -> Option<(usize, usize)> {
+        let file = self.source.get_file(span);
+        if file.name() != self.name {
+            return None;
+        }
+
+        Some((
+            (span.low() - file.span.low()) as usize,
+            (span.high() - file.span.low()) as usize,
+        ))
+    }
+
+    /// Return the LSP range of the given span, if it is part of this
+    /// document.
+    fn range(&self, span: Span) -> Option<Json> {
+        let (start, end) = self.byte_range(span)?;
+        Some(json!({
+            "start": position(&self.text, start),
+            "end": position(&self.text, end),
+        }))
+    }
+
+    /// Return the resolution of the innermost identifier at the given
+    /// byte offset.
+    fn identifier_at(&self, offset: usize) -> Option<&Resolution> {
+        self.identifiers
+            .iter()
+            .filter_map(|(span, resolution)| {
+                let (start, end) = self.byte_range(*span)?;
+                (start <= offset && offset <= end).then_some((end - start, resolution))
+            })
+            .min_by_key(|(len, _)| *len)
+            .map(|(_, resolution)| resolution)
+    }
+
+    /// Return the diagnostics of this document.
+    fn diagnostics(&self) -> Vec<Json> {
+        let mut diagnostics = vec![];
+
+        for error in &self.errors {
+            // Parse errors carry one location per error.
+            if let ErrorKind::ParseErrors(_) = error.kind {
+                for (span, label) in error.labelled_spans() {
+                    let message = label.unwrap_or_else(|| error.to_string());
+                    diagnostics.extend(self.diagnostic(
+                        span,
+                        SEVERITY_ERROR,
+                        error.code(),
+                        message,
+                    ));
+                }
+            } else {
+                diagnostics.extend(self.diagnostic(
+                    error.span,
+                    SEVERITY_ERROR,
+                    error.code(),
+                    error.to_string(),
+                ));
+            }
+        }
+
+        for warning in &self.warnings {
+            let message = warning.message(&self.source);
+            let diagnostic = self.diagnostic(
+                warning.span,
+                SEVERITY_WARNING,
+                warning.code(),
+                message.trim_end_matches(':').to_string(),
+            );
+
+            let tags = match warning.kind {
+                WarningKind::UnusedBinding | WarningKind::DeadCode => vec![TAG_UNNECESSARY],
+                WarningKind::DeprecatedLiteralURL | WarningKind::DeprecatedLegacyLet => {
+                    vec![TAG_DEPRECATED]
+                }
+                _ => vec![],
+            };
+
+            diagnostics.extend(diagnostic.map(|mut diagnostic| {
+                if !tags.is_empty() {
+                    diagnostic["tags"] = tags.into();
+                }
+                diagnostic
+            }));
+        }
+
+        diagnostics
+    }
+
+    fn diagnostic(&self, span: Span, severity: u8, code: &str, message: String) -> Option<Json> {
+        Some(json!({
+            "range": self.range(span)?,
+            "severity": severity,
+            "code": code,
+            "source": "tvix",
+            "message": message,
+        }))
+    }
+
+    /// Return the file referenced by the path literal at the given byte
+    /// offset, if it exists.
+    fn path_at(&self, offset: usize) -> Option<PathBuf> {
+        let root = rnix::Root::parse(&self.text).syntax();
+        let literal = root
+            .token_at_offset((offset as u32).into())
+            .find_map(|token| token.parent()?.ancestors().find_map(ast::Path::cast))?;
+
+        let raw = literal.syntax().text().to_string();
+        if raw.contains("${") {
+            return None;
+        }
+
+        let mut path = if let Some(home_relative) = raw.strip_prefix("~/") {
+            dirs::home_dir()?.join(home_relative)
+        } else if raw.starts_with('/') {
+            PathBuf::from(raw)
+        } else if raw.starts_with('<') {
+            // Search path lookups depend on the configuration at runtime.
+            return None;
+        } else {
+            self.path.as_ref()?.parent()?.join(raw)
+        };
+
+        if path.is_dir() {
+            path.push("default.nix");
+        }
+
+        path.exists().then(|| path.canonicalize().unwrap_or(path))
+    }
+
+    /// Return the source of the literal value bound by the attribute at
+    /// the given span (e.g. `1` for the `a` in `let a = 1; in ...`).
+    fn literal_binding(&self, span: Span) -> Option<String> {
+        let (start, _) = self.byte_range(span)?;
+        let root = rnix::Root::parse(&self.text).syntax();
+        let binding = root
+            .token_at_offset((start as u32).into())
+            .right_biased()?
+            .parent()?
+            .ancestors()
+            .find_map(ast::AttrpathValue::cast)?;
+
+        let attrpath = binding.attrpath()?;
+        if usize::from(attrpath.syntax().text_range().start()) != start
+            || attrpath.attrs().count() != 1
+        {
+            return None;
+        }
+
+        let value = binding.value()?;
+        let is_literal = match &value {
+            ast::Expr::Literal(_) => true,
+            ast::Expr::Path(path) => !path.syntax().text().to_string().contains("${"),
+            ast::Expr::Str(s) => s
+                .normalized_parts()
+                .iter()
+                .all(|part| matches!(part, ast::InterpolPart::Literal(_))),
+            _ => false,
+        };
+
+        is_literal.then(|| value.syntax().text().to_string())
+    }
+}
+
+/// Convert a byte offset into `text` into an LSP position, whose
+/// character offset counts UTF-16 code units.
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+
+    json!({
+        "line": before.matches('\n').count(),
+        "character": character,
+    })
+}
+
+/// Convert an LSP position into a byte offset into `text`.
+fn offset(text: &str, position: &Json) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+
+    let line_start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+
+    let mut units = 0;
+    for (idx, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + idx);
+        }
+        units += c.len_utf16();
+    }
+
+    Some(text.len())
+}
+
+/// Return the path of a `file://` URI.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len());
+
+    let mut idx = 0;
+    while idx < encoded.len() {
+        if encoded[idx] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(idx + 1..idx + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            idx += 3;
+        } else {
+            decoded.push(encoded[idx]);
+            idx += 1;
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8(decoded).ok()?))
+}
+
+/// Return the `file://` URI of a path.
+fn path_to_uri(path: &std::path::Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Wrap Nix code for display in a hover.
+fn nix_markdown(code: &str) -> String {
+    format!("```nix\n{}\n```", code)
+}
+
+/// Language server state, shared by all open documents.
+struct Server<'a, W: Write> {
+    tvix_store_io: Rc<TvixStoreIO>,
+    args: &'a Args,
+    globals: Rc<GlobalsMap>,
+    documents: FxHashMap<String, Document>,
+    output: W,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<'_, W> {
+    fn send(&mut self, mut message: Json) {
+        message["jsonrpc"] = "2.0".into();
+
+        // There is nobody left to report errors to if the output is gone.
+        let _ = write_message(&mut self.output, &message);
+    }
+
+    fn respond(&mut self, request: &Json, result: Json) {
+        self.send(json!({ "id": request["id"], "result": result }));
+    }
+
+    fn respond_error(&mut self, request: &Json, code: i64, message: impl Into<String>) {
+        self.send(json!({
+            "id": request["id"],
+            "error": { "code": code, "message": message.into() },
+        }));
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(json!({ "method": method, "params": params }));
+    }
+
+    /// Compile the given text of the document at `uri`, and publish its
+    /// diagnostics.
+    fn update(&mut self, uri: &str, text: String) {
+        let path = uri_to_path(uri);
+
+        let mut observer = IdentifierCollector::default();
+        let mut eval_builder = evaluation_builder(
+            self.tvix_store_io.clone(),
+            self.args,
+            None,
+            Some(self.globals.clone()),
+        );
+        let source = eval_builder.source_map().clone();
+        eval_builder.set_compiler_observer(Some(&mut observer));
+        let result = eval_builder.build().compile_only(&text, path.clone());
+
+        let document = Document {
+            text,
+            name: path
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|| "[code]".into()),
+            path,
+            source,
+            errors: result.errors,
+            warnings: result.warnings,
+            identifiers: observer.0,
+        };
+
+        let diagnostics = document.diagnostics();
+        self.documents.insert(uri.to_string(), document);
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        );
+    }
+
+    /// Return the document and byte offset referenced by the
+    /// `textDocument` and `position` parameters of a request.
+    fn document_position(&self, params: &Json) -> Option<(&str, &Document, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = offset(&document.text, &params["position"])?;
+        Some((uri, document, offset))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, document, offset)) = self.document_position(params) else {
+            return Json::Null;
+        };
+
+        let location = |span: &Span| {
+            let range = document.range(*span)?;
+            Some(json!({ "uri": uri, "range": range }))
+        };
+
+        let locations: Vec<Json> = match document.identifier_at(offset) {
+            Some(Resolution::Local(span)) => location(span).into_iter().collect(),
+            Some(Resolution::With(namespaces)) => namespaces.iter().filter_map(location).collect(),
+            Some(Resolution::Global(_)) | Some(Resolution::Unknown) => vec![],
+            None => match document.path_at(offset) {
+                Some(path) => {
+                    let start = json!({ "line": 0, "character": 0 });
+                    vec![json!({
+                        "uri": path_to_uri(&path),
+                        "range": { "start": start, "end": start },
+                    })]
+                }
+                None => vec![],
+            },
+        };
+
+        Json::Array(locations)
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((_, document, offset)) = self.document_position(params) else {
+            return Json::Null;
+        };
+
+        let contents = match document.identifier_at(offset) {
+            Some(Resolution::Local(span)) => {
+                document.literal_binding(*span).map(|v| nix_markdown(&v))
+            }
+            Some(Resolution::Global(Value::Builtin(builtin))) => {
+                let mut contents = nix_markdown(&format!("builtins.{}", builtin.name()));
+                if let Some(documentation) = builtin.documentation() {
+                    contents.push_str("\n\n");
+                    contents.push_str(documentation);
+                }
+                Some(contents)
+            }
+            Some(Resolution::Global(
+                value @ (Value::Null
+                | Value::Bool(_)
+                | Value::Integer(_)
+                | Value::Float(_)
+                | Value::String(_)
+                | Value::Path(_)),
+            )) => Some(nix_markdown(&value.to_string())),
+            _ => None,
+        };
+
+        match contents {
+            Some(contents) => json!({
+                "contents": { "kind": "markdown", "value": contents },
+            }),
+            None => Json::Null,
+        }
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let Some((_, document, offset)) = self.document_position(params) else {
+            return Json::Null;
+        };
+
+        let line_start = document.text[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+        let before = &document.text[line_start..offset];
+
+        let is_ident_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'');
+        let prefix_start = before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !is_ident_char(*c))
+            .map_or(0, |(idx, c)| idx + c.len_utf8());
+        let (qualifier, prefix) = before.split_at(prefix_start);
+
+        let is_builtins = qualifier
+            .strip_suffix("builtins.")
+            .is_some_and(|rest| !rest.ends_with(|c: char| is_ident_char(c) || c == '.'));
+
+        let items: Vec<Json> = match self.globals.get("builtins") {
+            Some(Value::Attrs(builtins)) if is_builtins => builtins
+                .iter_sorted()
+                .filter_map(|(name, value)| {
+                    let name = name.as_bstr().to_string();
+                    if !name.starts_with(prefix) {
+                        return None;
+                    }
+
+                    let mut item = json!({ "label": name });
+                    if let Value::Builtin(builtin) = value {
+                        item["kind"] = COMPLETION_FUNCTION.into();
+                        if let Some(documentation) = builtin.documentation() {
+                            item["documentation"] =
+                                json!({ "kind": "markdown", "value": documentation });
+                        }
+                    } else {
+                        item["kind"] = COMPLETION_CONSTANT.into();
+                    }
+
+                    Some(item)
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        json!({ "isIncomplete": false, "items": items })
+    }
+
+    /// Handle a single message. Returns `true` once the client asked the
+    /// server to exit.
+    fn handle(&mut self, message: Json) -> bool {
+        let method = message["method"].as_str().unwrap_or_default().to_string();
+        let params = &message["params"];
+        let is_request = message.get("id").is_some();
+
+        if self.shutdown && is_request {
+            self.respond_error(&message, INVALID_REQUEST, "server is shutting down");
+            return method == "exit";
+        }
+
+        match method.as_str() {
+            "initialize" => self.respond(
+                &message,
+                json!({
+                    "capabilities": {
+                        // Documents are always synchronised in full.
+                        "textDocumentSync": 1,
+                        "definitionProvider": true,
+                        "hoverProvider": true,
+                        "completionProvider": { "triggerCharacters": ["."] },
+                    },
+                    "serverInfo": { "name": "tvix-lsp" },
+                }),
+            ),
+
+            "textDocument/didOpen" => {
+                if let (Some(uri), Some(text)) = (
+                    params["textDocument"]["uri"].as_str(),
+                    params["textDocument"]["text"].as_str(),
+                ) {
+                    self.update(uri, text.to_string());
+                }
+            }
+
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+
+                if let (Some(uri), Some(text)) = (params["textDocument"]["uri"].as_str(), text) {
+                    self.update(uri, text.to_string());
+                }
+            }
+
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    self.documents.remove(uri);
+                    self.notify(
+                        "textDocument/publishDiagnostics",
+                        json!({ "uri": uri, "diagnostics": [] }),
+                    );
+                }
+            }
+
+            "textDocument/definition" => {
+                let result = self.definition(params);
+                self.respond(&message, result);
+            }
+
+            "textDocument/hover" => {
+                let result = self.hover(params);
+                self.respond(&message, result);
+            }
+
+            "textDocument/completion" => {
+                let result = self.completion(params);
+                self.respond(&message, result);
+            }
+
+            "shutdown" => {
+                self.shutdown = true;
+                self.respond(&message, Json::Null);
+            }
+
+            "exit" => return true,
+
+            _ if is_request => {
+                self.respond_error(&message, METHOD_NOT_FOUND, format!("unsupported: {method}"))
+            }
+
+            // Other notifications (e.g. `initialized`) need no handling.
+            _ => {}
+        }
+
+        false
+    }
+}
+
+/// Run a language server reading messages from `input` and writing
+/// responses and notifications to `output`, until the client asks it to
+/// exit or disconnects. Returns whether the client shut the server down
+/// before asking it to exit.
+pub fn run<R: BufRead, W: Write>(
+    tvix_store_io: Rc<TvixStoreIO>,
+    args: &Args,
+    mut input: R,
+    output: W,
+) -> bool {
+    // All documents are compiled with the same globals, which are
+    // set up once.
+    let globals = evaluation_builder(tvix_store_io.clone(), args, None, None)
+        .build()
+        .globals();
+
+    let mut server = Server {
+        tvix_store_io,
+        args,
+        globals,
+        documents: FxHashMap::default(),
+        output,
+        shutdown: false,
+    };
+
+    while let Ok(Some(message)) = read_message(&mut input) {
+        if server.handle(message) {
+            break;
+        }
+    }
+
+    server.shutdown
+}
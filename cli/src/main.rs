@@ -3,8 +3,12 @@ use mimalloc::MiMalloc;
 use std::rc::Rc;
 use std::{fs, path::PathBuf};
 use tvix_cli::args::Args;
+use tvix_cli::instantiate::instantiate;
 use tvix_cli::repl::Repl;
-use tvix_cli::{init_io_handle, interpret, AllowIncomplete};
+use tvix_cli::{
+    init_io_handle, interpret, interpret_with_auto_args, report_error, report_warning,
+    AllowIncomplete,
+};
 use tvix_eval::observer::DisassemblingObserver;
 use tvix_eval::EvalMode;
 use tvix_glue::tvix_store_io::TvixStoreIO;
@@ -43,11 +47,11 @@ fn lint(code: &str, path: Option<PathBuf>, args: &Args) -> bool {
     }
 
     for error in &result.errors {
-        error.fancy_format_stderr();
+        report_error(error, args);
     }
 
     for warning in &result.warnings {
-        warning.fancy_format_stderr(&source_map);
+        report_warning(warning, &source_map, args);
     }
 
     // inform the caller about any errors
@@ -58,6 +62,7 @@ fn main() {
     let args = Args::parse();
 
     tvix_tracing::TracingBuilder::default()
+        .log_format(args.log_format)
         .enable_progressbar()
         .build()
         .expect("unable to set up tracing subscriber");
@@ -65,23 +70,13 @@ fn main() {
 
     let io_handle = init_io_handle(&tokio_runtime, &args);
 
-    if let Some(file) = &args.script {
+    if args.dap {
+        let stdin = std::io::BufReader::new(std::io::stdin());
+        tvix_cli::dap::run(io_handle, &args, stdin, std::io::stdout())
+    } else if let Some(file) = &args.script {
         run_file(io_handle, file.clone(), &args)
     } else if let Some(expr) = &args.expr {
-        if !interpret(
-            io_handle,
-            expr,
-            None,
-            &args,
-            false,
-            AllowIncomplete::RequireComplete,
-            None, // TODO(aspen): Pass in --arg/--argstr here
-            None,
-            None,
-        )
-        .unwrap()
-        .finalize()
-        {
+        if !run_code(io_handle, expr, None, &args) {
             std::process::exit(1);
         }
     } else {
@@ -98,11 +93,36 @@ fn run_file(io_handle: Rc<TvixStoreIO>, mut path: PathBuf, args: &Args) {
 
     let success = if args.compile_only {
         lint(&contents, Some(path), args)
+    } else {
+        run_code(io_handle, &contents, Some(path), args)
+    };
+
+    if !success {
+        std::process::exit(1);
+    }
+}
+
+/// Interprets (or instantiates, with `--instantiate`) the given code, applying
+/// `--arg`, `--argstr` and `--attr` if any of them were passed.
+/// Returns whether evaluation succeeded.
+fn run_code(io_handle: Rc<TvixStoreIO>, code: &str, path: Option<PathBuf>, args: &Args) -> bool {
+    if args.instantiate {
+        return match instantiate(io_handle, code, path, args) {
+            Some(output) => {
+                print!("{output}");
+                true
+            }
+            None => false,
+        };
+    }
+
+    if args.has_auto_args() {
+        interpret_with_auto_args(io_handle, code, path, args)
     } else {
         interpret(
             io_handle,
-            &contents,
-            Some(path),
+            code,
+            path,
             args,
             false,
             AllowIncomplete::RequireComplete,
@@ -110,11 +130,7 @@ fn run_file(io_handle: Rc<TvixStoreIO>, mut path: PathBuf, args: &Args) {
             None,
             None,
         )
-        .unwrap()
-        .finalize()
-    };
-
-    if !success {
-        std::process::exit(1);
     }
+    .unwrap()
+    .finalize()
 }
@@ -116,6 +116,10 @@ impl<'a> Repl<'a> {
             eprintln!("warning: `--compile-only` has no effect on REPL usage!");
         }
 
+        if self.args.instantiate {
+            eprintln!("warning: `--instantiate` has no effect on REPL usage!");
+        }
+
         let history_path = match state_dir() {
             // Attempt to set up these paths, but do not hard fail if it
             // doesn't work.
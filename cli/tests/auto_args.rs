@@ -0,0 +1,85 @@
+use std::ffi::OsString;
+use std::rc::Rc;
+
+use clap::Parser;
+use expect_test::expect;
+use tvix_cli::{init_io_handle, interpret_with_auto_args};
+
+macro_rules! test_auto_args {
+    ($name:ident($code:expr, [$($arg:expr),*]) => $expect:expr) => {
+        #[test]
+        fn $name() {
+            let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+            let args = tvix_cli::Args::parse_from(
+                [OsString::from("tvix"), OsString::from("--strict")]
+                    .into_iter()
+                    .chain([$(OsString::from($arg)),*]),
+            );
+            let io_handle = init_io_handle(&tokio_runtime, &args);
+            let result = interpret_with_auto_args(Rc::clone(&io_handle), $code, None, &args).unwrap();
+            $expect.assert_eq(result.output());
+        }
+    };
+}
+
+test_auto_args!(arg("{ x }: x * 2", ["--arg", "x", "1 + 2"]) => expect![[r#"
+    => 6 :: int
+"#]]);
+
+test_auto_args!(argstr("{ x }: x", ["--argstr", "x", "foo \"bar\""]) => expect![[r#"
+    => "foo \"bar\"" :: string
+"#]]);
+
+test_auto_args!(unused_args_are_lazy("{ x ? 1 }: x", ["--arg", "y", "throw \"nope\""]) => expect![[r#"
+    => 1 :: int
+"#]]);
+
+test_auto_args!(attr("{ a.b = [ 1 { c = 2; } ]; }", ["-A", "a.b.1.c"]) => expect![[r#"
+    => 2 :: int
+"#]]);
+
+test_auto_args!(quoted_attr("{ \"a.b\" = 1; }", ["-A", "\"a.b\""]) => expect![[r#"
+    => 1 :: int
+"#]]);
+
+test_auto_args!(auto_call_along_attr_path(
+    "{ x, y ? 2 }: { f = { x }: { g = x + y; }; }",
+    ["--arg", "x", "1", "--argstr", "unused", "", "-A", "f.g"]
+) => expect![[r#"
+    => 3 :: int
+"#]]);
+
+test_auto_args!(missing_attr("{ a = 1; }", ["-A", "b"]) => expect![[""]]);
+
+test_auto_args!(lambda_without_formals_is_not_called("x: x", ["--arg", "x", "1"]) => expect![[r#"
+    => <LAMBDA> :: lambda
+"#]]);
+
+test_auto_args!(ellipsis_without_args_is_called("{ ... }: 1", ["--arg", "x", "1"]) => expect![[r#"
+    => 1 :: int
+"#]]);
+
+test_auto_args!(empty_formals_are_called("{ }: 1", ["--arg", "x", "1"]) => expect![[r#"
+    => 1 :: int
+"#]]);
+
+// Functions with an ellipsis get all arguments, not just the ones they list.
+test_auto_args!(ellipsis_gets_all_args(
+    "args@{ x, ... }: args.y",
+    ["--arg", "x", "1", "--arg", "y", "2"]
+) => expect![[r#"
+    => 2 :: int
+"#]]);
+
+test_auto_args!(functor_along_attr_path(
+    "{ f = { __functor = self: { x }: { g = x + self.y; }; y = 2; }; }",
+    ["--arg", "x", "1", "-A", "f.g"]
+) => expect![[r#"
+    => 3 :: int
+"#]]);
+
+// Each --arg is parsed on its own, and can not inject further arguments.
+test_auto_args!(arg_is_parsed_separately(
+    "{ x, y ? 0 }: x + y",
+    ["--arg", "x", "1); y = (2"]
+) => expect![[""]]);
@@ -0,0 +1,163 @@
+use std::io::Cursor;
+
+use clap::Parser;
+use serde_json::{json, Value};
+use tvix_cli::dap::{read_message, write_message};
+use tvix_cli::init_io_handle;
+
+const CODE: &str = r#"let
+  double = x:
+    let
+      result = x * 2;
+    in
+    result;
+  y = 21;
+in
+double y
+"#;
+
+/// Runs a debugging session of [CODE] (written to a file named after the
+/// test) with the given breakpoints and requests, and returns all messages
+/// sent by the server.
+fn session(name: &str, breakpoints: &[u64], requests: &[Value]) -> Vec<Value> {
+    let path = std::env::temp_dir().join(format!(
+        "tvix-cli-test-dap-{}-{}.nix",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, CODE).unwrap();
+
+    let breakpoints: Vec<Value> = breakpoints
+        .iter()
+        .map(|line| json!({ "line": line }))
+        .collect();
+
+    let mut script = vec![
+        json!({ "command": "initialize", "arguments": { "adapterID": "tvix" } }),
+        json!({ "command": "launch", "arguments": { "program": path } }),
+        json!({
+            "command": "setBreakpoints",
+            "arguments": {
+                "source": { "path": path },
+                "breakpoints": breakpoints,
+            },
+        }),
+        json!({ "command": "configurationDone" }),
+    ];
+    script.extend_from_slice(requests);
+    script.push(json!({ "command": "disconnect" }));
+
+    let mut input = vec![];
+    for (seq, mut request) in script.into_iter().enumerate() {
+        request["seq"] = (seq + 1).into();
+        request["type"] = "request".into();
+        write_message(&mut input, &request).unwrap();
+    }
+
+    let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+    let args = tvix_cli::Args::parse_from(["tvix", "--dap"]);
+    let io_handle = init_io_handle(&tokio_runtime, &args);
+
+    let mut output = vec![];
+    tvix_cli::dap::run(io_handle, &args, Cursor::new(input), &mut output);
+    std::fs::remove_file(path).unwrap();
+
+    let mut output = Cursor::new(output);
+    let mut messages = vec![];
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    messages
+}
+
+fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+    messages
+        .iter()
+        .find(|m| m["type"] == "response" && m["command"] == command)
+        .unwrap_or_else(|| panic!("no response to {command}: {messages:#?}"))
+}
+
+fn events<'a>(messages: &'a [Value], event: &str) -> Vec<&'a Value> {
+    messages
+        .iter()
+        .filter(|m| m["type"] == "event" && m["event"] == event)
+        .collect()
+}
+
+#[test]
+fn run_without_breakpoints() {
+    let messages = session("run", &[], &[]);
+
+    assert_eq!(events(&messages, "initialized").len(), 1);
+    assert!(events(&messages, "stopped").is_empty());
+    assert_eq!(
+        events(&messages, "output")[0]["body"]["output"],
+        "=> 42 :: int\n"
+    );
+    assert_eq!(events(&messages, "exited")[0]["body"]["exitCode"], 0);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+    assert_eq!(response(&messages, "disconnect")["success"], true);
+}
+
+#[test]
+fn inspect_at_breakpoint() {
+    let messages = session(
+        "inspect",
+        &[6],
+        &[
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 0 } }),
+            json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+            json!({ "command": "evaluate", "arguments": { "expression": "x + 1", "frameId": 0 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        ],
+    );
+
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0]["body"]["reason"], "breakpoint");
+
+    let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "double");
+    assert_eq!(frames[0]["line"], 6);
+
+    let variables = &response(&messages, "variables")["body"]["variables"];
+    assert_eq!(variables[0]["name"], "x");
+    assert_eq!(variables[0]["value"], "21");
+    assert_eq!(variables[1]["name"], "result");
+    assert_eq!(variables[1]["value"], "<thunk>");
+
+    assert_eq!(response(&messages, "evaluate")["body"]["result"], "22");
+
+    assert_eq!(
+        events(&messages, "output")[0]["body"]["output"],
+        "=> 42 :: int\n"
+    );
+}
+
+#[test]
+fn step_into_function() {
+    let messages = session(
+        "step",
+        &[9],
+        &[
+            json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        ],
+    );
+
+    let stopped: Vec<_> = events(&messages, "stopped")
+        .iter()
+        .map(|event| event["body"]["reason"].clone())
+        .collect();
+    assert_eq!(stopped, vec!["breakpoint", "step"]);
+
+    let frames = response(&messages, "stackTrace")["body"]["stackFrames"]
+        .as_array()
+        .unwrap();
+    assert_eq!(frames[0]["name"], "double");
+    assert_eq!(frames[0]["line"], 4);
+    assert_eq!(frames[1]["line"], 9);
+}
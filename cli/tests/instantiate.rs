@@ -0,0 +1,73 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::rc::Rc;
+
+use clap::Parser;
+use tvix_cli::{init_io_handle, instantiate::instantiate};
+use tvix_eval::EvalIO;
+
+const DRV: &str =
+    r#"builtins.derivation { name = "foo"; system = ":"; builder = ":"; __ignoreNulls = true; }"#;
+const DRV_PATH: &str = "/nix/store/xa96w6d7fxrlkk60z1fmx2ffp2wzmbqx-foo.drv";
+
+fn run(code: &str, extra_args: &[&str]) -> (Option<String>, bool) {
+    let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+    let args = tvix_cli::Args::parse_from(
+        ["tvix", "--instantiate"]
+            .iter()
+            .chain(extra_args)
+            .map(OsString::from),
+    );
+    let io_handle = init_io_handle(&tokio_runtime, &args);
+    let output = instantiate(Rc::clone(&io_handle), code, None, &args);
+    let exists = io_handle
+        .path_exists(Path::new(DRV_PATH))
+        .expect("must succeed");
+    (output, exists)
+}
+
+#[test]
+fn single_derivation() {
+    let (output, exists) = run(DRV, &[]);
+    assert_eq!(Some(format!("{DRV_PATH}\n")), output);
+    assert!(exists, "{DRV_PATH} must have been written to the store");
+}
+
+#[test]
+fn attrs_of_derivations() {
+    let code = format!(
+        "{{ a = {DRV}; b = {{ c = throw \"not traversed\"; }}; c = 42; d = [ ({DRV}) ]; }}"
+    );
+    let (output, _) = run(&code, &[]);
+    // Lists inside attribute sets are not traversed.
+    assert_eq!(Some(format!("{DRV_PATH}\n")), output);
+}
+
+#[test]
+fn lists_of_derivations() {
+    // Nested lists are traversed, and every derivation is only printed once.
+    let code = format!("[ ({DRV}) [ ({DRV}) {{ a = {DRV}; }} ] ]");
+    let (output, _) = run(&code, &[]);
+    assert_eq!(Some(format!("{DRV_PATH}\n")), output);
+}
+
+#[test]
+fn recurse_for_derivations() {
+    let code = format!("{{ a = {{ recurseForDerivations = true; b = {DRV}; }}; }}");
+    let (output, _) = run(&code, &[]);
+    assert_eq!(Some(format!("{DRV_PATH}\n")), output);
+}
+
+#[test]
+fn attr_path() {
+    let code = format!("{{ name }}: {{ a.b = {DRV}; c = throw \"not selected\"; }}");
+    let (output, _) = run(&code, &["--argstr", "name", "foo", "-A", "a.b"]);
+    assert_eq!(Some(format!("{DRV_PATH}\n")), output);
+}
+
+#[test]
+fn not_a_derivation() {
+    let (output, exists) = run("42", &[]);
+    assert_eq!(None, output);
+    assert!(!exists);
+}
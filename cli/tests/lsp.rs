@@ -0,0 +1,201 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde_json::{json, Value};
+use tvix_cli::dap::{read_message, write_message};
+use tvix_cli::init_io_handle;
+
+const CODE: &str = r#"let
+  greeting = "hello";
+  f = x: x;
+in
+with { y = 1; };
+[
+  (f greeting)
+  y
+  (import ./other.nix)
+  (builtins.to)
+]
+"#;
+
+/// Creates a directory named after the test containing `main.nix` with
+/// the given code and an empty `other.nix`, opens `main.nix` in a
+/// language server, sends it the given requests (numbered by their
+/// position) and returns the directory and all messages sent by the
+/// server.
+fn session(name: &str, code: &str, requests: &[Value]) -> (PathBuf, Vec<Value>) {
+    let dir =
+        std::env::temp_dir().join(format!("tvix-cli-test-lsp-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dir = dir.canonicalize().unwrap();
+    std::fs::write(dir.join("main.nix"), code).unwrap();
+    std::fs::write(dir.join("other.nix"), "{ }").unwrap();
+    let uri = format!("file://{}", dir.join("main.nix").display());
+
+    let mut script = vec![
+        json!({ "id": 0, "method": "initialize", "params": { "capabilities": {} } }),
+        json!({ "method": "initialized", "params": {} }),
+        json!({
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": { "uri": uri, "languageId": "nix", "version": 1, "text": code },
+            },
+        }),
+    ];
+
+    for (id, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["id"] = (id + 1).into();
+        request["params"]["textDocument"] = json!({ "uri": uri });
+        script.push(request);
+    }
+
+    script.push(json!({ "id": requests.len() + 1, "method": "shutdown" }));
+    script.push(json!({ "method": "exit" }));
+
+    let mut input = vec![];
+    for mut message in script {
+        message["jsonrpc"] = "2.0".into();
+        write_message(&mut input, &message).unwrap();
+    }
+
+    let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+    let args = tvix_cli::Args::parse_from(["tvix"]);
+    let io_handle = init_io_handle(&tokio_runtime, &args);
+
+    let mut output = vec![];
+    assert!(tvix_cli::lsp::run(
+        io_handle,
+        &args,
+        Cursor::new(input),
+        &mut output
+    ));
+
+    let mut output = Cursor::new(output);
+    let mut messages = vec![];
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    (dir, messages)
+}
+
+/// Return the result of the request with the given id.
+fn result(messages: &[Value], id: u64) -> &Value {
+    let response = messages
+        .iter()
+        .find(|m| m["id"] == id)
+        .unwrap_or_else(|| panic!("no response to request {id}: {messages:#?}"));
+    &response["result"]
+}
+
+fn position(line: u64, character: u64) -> Value {
+    json!({ "line": line, "character": character })
+}
+
+fn request(method: &str, line: u64, character: u64) -> Value {
+    json!({ "method": method, "params": { "position": position(line, character) } })
+}
+
+#[test]
+fn publishes_diagnostics() {
+    let (_, messages) = session("diagnostics", "let unused = 1; in missing", &[]);
+
+    assert_eq!(
+        result(&messages, 0)["capabilities"]["definitionProvider"],
+        true
+    );
+
+    let notification = messages
+        .iter()
+        .find(|m| m["method"] == "textDocument/publishDiagnostics")
+        .unwrap();
+    let diagnostics = notification["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 2);
+
+    assert_eq!(diagnostics[0]["code"], "E010");
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"]["start"], position(0, 19));
+    assert_eq!(diagnostics[0]["range"]["end"], position(0, 26));
+
+    assert_eq!(diagnostics[1]["code"], "W003");
+    assert_eq!(diagnostics[1]["severity"], 2);
+    assert_eq!(diagnostics[1]["tags"], json!([1]));
+    assert_eq!(diagnostics[1]["range"]["start"], position(0, 4));
+}
+
+#[test]
+fn go_to_definition() {
+    let (dir, messages) = session(
+        "definition",
+        CODE,
+        &[
+            request("textDocument/definition", 6, 3),
+            request("textDocument/definition", 7, 2),
+            request("textDocument/definition", 8, 14),
+            request("textDocument/definition", 10, 0),
+        ],
+    );
+
+    // `f` is bound by the `let`-expression.
+    let locations = result(&messages, 1).as_array().unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0]["range"]["start"], position(2, 2));
+    assert_eq!(locations[0]["range"]["end"], position(2, 3));
+
+    // `y` comes from the namespace of `with`.
+    let locations = result(&messages, 2).as_array().unwrap();
+    assert_eq!(locations.len(), 1);
+    assert_eq!(locations[0]["range"]["start"], position(4, 5));
+    assert_eq!(locations[0]["range"]["end"], position(4, 15));
+
+    // Paths lead to the file they refer to.
+    let locations = result(&messages, 3).as_array().unwrap();
+    assert_eq!(
+        locations[0]["uri"],
+        format!("file://{}", dir.join("other.nix").display())
+    );
+
+    assert_eq!(result(&messages, 4), &json!([]));
+}
+
+#[test]
+fn hover_literal_binding() {
+    let (_, messages) = session(
+        "hover",
+        CODE,
+        &[
+            request("textDocument/hover", 6, 7),
+            request("textDocument/hover", 6, 3),
+        ],
+    );
+
+    assert_eq!(
+        result(&messages, 1)["contents"]["value"],
+        "```nix\n\"hello\"\n```"
+    );
+
+    // `f` is bound to a function, which is not a literal.
+    assert_eq!(result(&messages, 2), &Value::Null);
+}
+
+#[test]
+fn complete_builtins() {
+    let (_, messages) = session(
+        "completion",
+        CODE,
+        &[request("textDocument/completion", 9, 14)],
+    );
+
+    let labels: Vec<&str> = result(&messages, 1)["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+
+    assert!(labels.contains(&"toString"), "{labels:?}");
+    assert!(labels.contains(&"toJSON"), "{labels:?}");
+    assert!(labels.iter().all(|label| label.starts_with("to")));
+}
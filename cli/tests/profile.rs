@@ -0,0 +1,52 @@
+use std::ffi::OsString;
+use std::rc::Rc;
+
+use clap::Parser;
+use tvix_cli::{init_io_handle, interpret_with_auto_args};
+
+const CODE: &str = "let double = x: x * 2; in map double [ 1 2 3 ]";
+
+/// Evaluates [CODE] with profiling enabled, and returns the written profile.
+fn profile(format: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+        "tvix-cli-test-profile-{}-{}",
+        std::process::id(),
+        format
+    ));
+
+    let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+    let args = tvix_cli::Args::parse_from([
+        OsString::from("tvix"),
+        OsString::from("--strict"),
+        OsString::from("--profile"),
+        path.clone().into_os_string(),
+        OsString::from("--profile-format"),
+        OsString::from(format),
+    ]);
+    let io_handle = init_io_handle(&tokio_runtime, &args);
+    let result = interpret_with_auto_args(Rc::clone(&io_handle), CODE, None, &args).unwrap();
+    assert!(result.success(), "{}", result.output());
+
+    let profile = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    profile
+}
+
+#[test]
+fn folded() {
+    let folded = profile("folded");
+    assert!(
+        folded
+            .lines()
+            .any(|line| line.contains("double ([code]:1:")),
+        "{folded}"
+    );
+}
+
+#[test]
+fn speedscope() {
+    let json = profile("speedscope");
+    assert!(json.starts_with('{'), "{json}");
+    assert!(json.contains(r#""name":"double""#), "{json}");
+    assert!(json.contains(r#""unit":"nanoseconds""#), "{json}");
+}
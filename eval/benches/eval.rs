@@ -11,6 +11,58 @@ fn interpret(code: &str) {
         .evaluate(code, None);
 }
 
+/// Returns the peak resident set size of this process in KiB, if it can be
+/// determined on this platform.
+fn peak_rss_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// A package set built like nixpkgs: a fixpoint over a large attribute set
+/// of packages referring to each other, extended by a stack of overlays.
+/// Evaluating this creates lots of reference cycles.
+const NIXPKGS_LIKE: &str = r#"
+let
+  fix = f: let x = f x; in x;
+  extends = overlay: f: final: let prev = f final; in prev // overlay final prev;
+
+  base = final: builtins.listToAttrs (builtins.genList (n: {
+    name = "pkg${toString n}";
+    value = {
+      pname = "pkg${toString n}";
+      version = "1.${toString n}";
+      buildInputs = [ final."pkg${toString (n / 2)}" ];
+      meta = { description = "package number ${toString n}"; };
+    };
+  }) 5000);
+
+  overlays = builtins.genList (i: final: prev: {
+    "pkg${toString i}" = prev."pkg${toString i}" // { patched = true; };
+  }) 50;
+
+  pkgs = fix (builtins.foldl' (f: overlay: extends overlay f) base overlays);
+in
+builtins.foldl' (acc: name: acc + builtins.stringLength pkgs.${name}.pname)
+  0 (builtins.attrNames pkgs)
+"#;
+
+fn eval_nixpkgs_like(c: &mut Criterion) {
+    c.bench_function("nixpkgs-like package set", |b| {
+        b.iter(|| {
+            interpret(black_box(NIXPKGS_LIKE));
+            // Free the cycles left behind by the evaluation, like a
+            // long-running evaluator would. Without the `gc` feature,
+            // this does nothing.
+            tvix_eval::gc::collect();
+        })
+    });
+
+    if let Some(rss) = peak_rss_kib() {
+        println!("peak RSS after nixpkgs-like package set: {} KiB", rss);
+    }
+}
+
 fn eval_literals(c: &mut Criterion) {
     c.bench_function("int", |b| {
         b.iter(|| {
@@ -38,5 +90,5 @@ fn eval_merge_attrs(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, eval_literals, eval_merge_attrs);
+criterion_group!(benches, eval_literals, eval_merge_attrs, eval_nixpkgs_like);
 criterion_main!(benches);
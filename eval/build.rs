@@ -1,4 +1,27 @@
+use std::collections::hash_map::DefaultHasher;
 use std::env;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+/// Sources determining the bytecode emitted by the compiler, and its
+/// serialisation in the bytecode cache.
+const BYTECODE_SOURCES: &[&str] = &[
+    "src/bytecode_cache.rs",
+    "src/chunk.rs",
+    "src/compiler",
+    "src/opcode.rs",
+    "src/value/function.rs",
+];
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path).expect("failed to read source directory") {
+            collect_files(&entry.expect("failed to read entry").path(), files);
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+}
 
 fn main() {
     println!(
@@ -7,6 +30,27 @@ fn main() {
     );
     println!("cargo:rerun-if-changed-env=TARGET");
 
+    // Hash the compiler and opcode definitions, so that the bytecode
+    // cache is invalidated whenever they change.
+    let mut files = vec![];
+    for source in BYTECODE_SOURCES {
+        println!("cargo:rerun-if-changed={source}");
+        collect_files(Path::new(source), &mut files);
+    }
+    files.sort();
+
+    let mut hasher = DefaultHasher::new();
+    for file in files {
+        file.hash(&mut hasher);
+        std::fs::read(&file)
+            .expect("failed to read source file")
+            .hash(&mut hasher);
+    }
+    println!(
+        "cargo:rustc-env=TVIX_BYTECODE_HASH={:016x}",
+        hasher.finish()
+    );
+
     // Pick up new test case files
     // https://github.com/la10736/rstest/issues/256
     println!("cargo:rerun-if-changed=src/tests/nix_tests");
@@ -8,7 +8,8 @@ use std::{
 
 use crate::{
     self as tvix_eval,
-    errors::ErrorKind,
+    errors::{CatchableErrorKind, ErrorKind},
+    nix_search_path::NixSearchPath,
     value::NixAttrs,
     vm::generators::{self, GenCo},
     NixString, Value,
@@ -16,12 +17,67 @@ use crate::{
 
 #[builtins]
 mod impure_builtins {
+    use bstr::ByteSlice;
     use std::ffi::OsStr;
     use std::os::unix::ffi::OsStrExt;
 
     use super::*;
     use crate::builtins::{coerce_value_to_path, hash::hash_nix_string};
 
+    #[builtin("findFile")]
+    async fn builtin_find_file(
+        co: GenCo,
+        search_path: Value,
+        lookup_path: Value,
+    ) -> Result<Value, ErrorKind> {
+        let mut entries = vec![];
+        for entry in search_path.to_list()?.into_iter() {
+            let entry = generators::request_force(&co, entry).await;
+            if entry.is_catchable() {
+                return Ok(entry);
+            }
+            let entry = entry.to_attrs()?;
+
+            let prefix = match entry.select("prefix") {
+                Some(prefix) => {
+                    let prefix = generators::request_force(&co, prefix.clone()).await;
+                    if prefix.is_catchable() {
+                        return Ok(prefix);
+                    }
+                    prefix.to_str()?.to_str()?.to_owned()
+                }
+                None => String::new(),
+            };
+
+            let path =
+                match coerce_value_to_path(&co, entry.select_required("path")?.clone()).await? {
+                    Err(cek) => return Ok(Value::from(cek)),
+                    Ok(path) => path,
+                };
+
+            entries.push((prefix, path));
+        }
+
+        let lookup_path = lookup_path.to_str()?;
+        let lookup_path = lookup_path.to_path()?;
+
+        for candidate in NixSearchPath::from_prefixes_and_paths(entries).candidates(lookup_path)? {
+            match generators::request_path_exists(&co, candidate.clone()).await {
+                Value::Bool(true) => return Ok(Value::Path(Box::new(candidate))),
+                Value::Bool(false) => continue,
+                other => return Ok(other),
+            }
+        }
+
+        Ok(Value::from(CatchableErrorKind::NixPathResolution(
+            format!(
+                "path '{}' was not found in the Nix search path",
+                lookup_path.display()
+            )
+            .into_boxed_str(),
+        )))
+    }
+
     #[builtin("getEnv")]
     async fn builtin_get_env(co: GenCo, var: Value) -> Result<Value, ErrorKind> {
         Ok(env::var(OsStr::from_bytes(&var.to_str()?))
@@ -35,7 +91,10 @@ mod impure_builtins {
             Err(cek) => return Ok(Value::from(cek)),
             Ok(p) => p,
         };
-        let r = generators::request_open_file(&co, path).await;
+        let r = match generators::request_open_file(&co, path).await {
+            Err(cek) => return Ok(Value::from(cek)),
+            Ok(r) => r,
+        };
         hash_nix_string(algo.to_str()?, r).map(Value::from)
     }
 
@@ -52,7 +111,10 @@ mod impure_builtins {
         match coerce_value_to_path(&co, path).await? {
             Err(cek) => Ok(Value::from(cek)),
             Ok(path) => {
-                let dir = generators::request_read_dir(&co, path).await;
+                let dir = match generators::request_read_dir(&co, path).await {
+                    Err(cek) => return Ok(Value::from(cek)),
+                    Ok(dir) => dir,
+                };
                 let res = dir.into_iter().map(|(name, ftype)| {
                     (
                         // TODO: propagate Vec<u8> or bytes::Bytes into NixString.
@@ -74,9 +136,10 @@ mod impure_builtins {
             Err(cek) => Ok(Value::from(cek)),
             Ok(path) => {
                 let mut buf = Vec::new();
-                generators::request_open_file(&co, path)
-                    .await
-                    .read_to_end(&mut buf)?;
+                match generators::request_open_file(&co, path).await {
+                    Err(cek) => return Ok(Value::from(cek)),
+                    Ok(mut r) => r.read_to_end(&mut buf)?,
+                };
                 Ok(Value::from(buf))
             }
         }
@@ -86,11 +149,10 @@ mod impure_builtins {
     async fn builtin_read_file_type(co: GenCo, path: Value) -> Result<Value, ErrorKind> {
         match coerce_value_to_path(&co, path).await? {
             Err(cek) => Ok(Value::from(cek)),
-            Ok(path) => Ok(Value::from(
-                generators::request_read_file_type(&co, path)
-                    .await
-                    .to_string(),
-            )),
+            Ok(path) => match generators::request_read_file_type(&co, path).await {
+                Err(cek) => Ok(Value::from(cek)),
+                Ok(file_type) => Ok(Value::from(file_type.to_string())),
+            },
         }
     }
 }
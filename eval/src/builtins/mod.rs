@@ -1552,6 +1552,44 @@ mod pure_builtins {
 
         Ok(Value::from(x.type_of()))
     }
+
+    #[builtin("zipAttrsWith")]
+    async fn builtin_zip_attrs_with(
+        co: GenCo,
+        #[lazy] f: Value,
+        list: Value,
+    ) -> Result<Value, ErrorKind> {
+        let mut values: BTreeMap<NixString, Vec<Value>> = BTreeMap::new();
+
+        for item in list.to_list()?.into_iter() {
+            let set = generators::request_force(&co, item).await;
+            if set.is_catchable() {
+                return Ok(set);
+            }
+
+            for (key, value) in set.to_attrs()?.into_iter() {
+                values.entry(key).or_default().push(value);
+            }
+        }
+
+        // the best span we can get…
+        let span = generators::request_span(&co).await;
+
+        let out = values.into_iter().map(|(key, values)| {
+            let result = Value::Thunk(Thunk::new_suspended_call(
+                f.clone(),
+                key.clone().into(),
+                span,
+            ));
+            let values = Value::List(NixList::construct(values.len(), values));
+            (
+                key,
+                Value::Thunk(Thunk::new_suspended_call(result, values, span)),
+            )
+        });
+
+        Ok(Value::attrs(NixAttrs::from_iter(out)))
+    }
 }
 
 /// Internal helper function for genericClosure, determining whether a
@@ -1682,11 +1720,16 @@ mod placeholder_builtins {
     #[builtin("addErrorContext")]
     async fn builtin_add_error_context(
         co: GenCo,
-        #[lazy] _context: Value,
+        #[lazy] context: Value,
         #[lazy] val: Value,
     ) -> Result<Value, ErrorKind> {
-        generators::emit_warning_kind(&co, WarningKind::NotImplemented("builtins.addErrorContext"))
-            .await;
+        // Like in C++ Nix, the context is only evaluated if an error
+        // occurs while evaluating the value. A context that fails to
+        // evaluate is ignored.
+        generators::request_push_error_context(&co, context).await;
+        let val = generators::request_force(&co, val).await;
+        generators::request_pop_error_context(&co).await;
+
         Ok(val)
     }
 
@@ -0,0 +1,1047 @@
+//! This module implements an on-disk cache for the bytecode compiled
+//! from imported Nix files.
+//!
+//! Compiling large expression trees (e.g. nixpkgs) means compiling
+//! thousands of files on every evaluation. With a [`BytecodeCache`]
+//! configured, `import` stores the compiled [`Lambda`] of each file
+//! it compiles, and loads it from there instead of invoking the
+//! compiler the next time the same file is imported.
+//!
+//! Entries are keyed by a hash of the sources of the compiler and
+//! the opcode definitions (computed at build time), the path and
+//! contents of the file, and the names of all globals available to
+//! the compiler, as all of these influence the emitted bytecode.
+//!
+//! Spans are stored relative to the start of the file, and rebased
+//! onto the file in the current [`crate::SourceCode`] when loading an
+//! entry. Global values (such as builtins) referenced from the
+//! constants of a chunk are stored by name, and resolved against the
+//! current globals when loading.
+//!
+//! Only the values that the compiler emits as constants can be
+//! serialised. Files containing anything else, as well as files that
+//! compiled with warnings or errors, are simply never cached.
+//!
+//! The bytecode of loaded entries is validated, so that a corrupted
+//! entry is treated like a cache miss instead of crashing the VM.
+
+use codemap::Span;
+use data_encoding::HEXLOWER;
+use sha2::{Digest, Sha256};
+use smol_str::SmolStr;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::chunk::Chunk;
+use crate::compiler::GlobalsMap;
+use crate::opcode::{Op, OpArg, Position, StackIdx};
+use crate::value::{Closure, Formals, Lambda, LocalDebugInfo, NixAttrs, NixString, Thunk};
+use crate::{CatchableErrorKind, Value};
+
+/// Magic bytes at the start of every cache entry.
+const MAGIC: &[u8] = b"tvix-bytecode\0";
+
+/// Version of the serialisation format, checked when loading entries.
+/// Changes to the bytecode emitted by the compiler are covered by the
+/// hash of its sources in the cache key.
+const FORMAT_VERSION: u8 = 3;
+
+/// Maximum size of a u64 encoded in the vu128 varint encoding.
+const U64_VARINT_SIZE: usize = 9;
+
+mod tag {
+    pub const NULL: u8 = 0;
+    pub const BOOL: u8 = 1;
+    pub const INTEGER: u8 = 2;
+    pub const FLOAT: u8 = 3;
+    pub const STRING: u8 = 4;
+    pub const PATH: u8 = 5;
+    pub const UNRESOLVED_PATH: u8 = 6;
+    pub const NIX_PATH_RESOLUTION: u8 = 7;
+    pub const EMPTY_ATTRS: u8 = 8;
+    pub const FINALISE_REQUEST: u8 = 9;
+    pub const THUNK: u8 = 10;
+    pub const CLOSURE: u8 = 11;
+    pub const BLUEPRINT: u8 = 12;
+    pub const GLOBAL: u8 = 13;
+}
+
+/// On-disk cache of compiled bytecode, stored as one file per entry
+/// in the given directory. See the [module-level documentation][self]
+/// for details.
+///
+/// The cache is best-effort: Failures to read or write entries are
+/// treated like cache misses.
+#[derive(Debug, Clone)]
+pub struct BytecodeCache {
+    dir: PathBuf,
+}
+
+impl BytecodeCache {
+    /// Create a cache storing its entries in `dir`. The directory is
+    /// created on the first write, if it doesn't exist yet.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BytecodeCache { dir: dir.into() }
+    }
+
+    /// Compute the cache key for the file at `path` with the given
+    /// `contents`, compiled with the given globals.
+    pub(crate) fn key(globals: &GlobalsMap, path: &Path, contents: &str) -> String {
+        let mut hasher = Sha256::new();
+        let mut field = |data: &[u8]| {
+            hasher.update((data.len() as u64).to_le_bytes());
+            hasher.update(data);
+        };
+
+        field(env!("TVIX_BYTECODE_HASH").as_bytes());
+        field(&[FORMAT_VERSION]);
+        field(path.to_string_lossy().as_bytes());
+        field(contents.as_bytes());
+
+        let mut names: Vec<_> = globals.iter().collect();
+        names.sort_unstable_by_key(|(name, _)| **name);
+        for (name, value) in names {
+            field(name.as_bytes());
+
+            // Scalar globals are inlined into the constants of a chunk
+            // by value, so changing them must invalidate the cache.
+            match value {
+                Value::Null
+                | Value::Bool(_)
+                | Value::Integer(_)
+                | Value::Float(_)
+                | Value::String(_)
+                | Value::Path(_) => field(value.to_string().as_bytes()),
+                _ => field(&[]),
+            }
+        }
+
+        HEXLOWER.encode(&hasher.finalize())
+    }
+
+    /// Load the entry with the given key, rebasing its spans onto
+    /// `file`. Returns [None] if there is no such entry, or it can not
+    /// be decoded.
+    pub(crate) fn lookup(
+        &self,
+        key: &str,
+        file: &codemap::File,
+        globals: &GlobalsMap,
+    ) -> Option<Rc<Lambda>> {
+        let buf = std::fs::read(self.dir.join(key)).ok()?;
+        decode(&buf, file, globals)
+    }
+
+    /// Store `lambda`, compiled from `file`, under the given key. Does
+    /// nothing if the lambda can not be serialised.
+    pub(crate) fn insert(
+        &self,
+        key: &str,
+        file: &codemap::File,
+        globals: &GlobalsMap,
+        lambda: &Lambda,
+    ) {
+        let Some(buf) = encode(lambda, file, globals) else {
+            return;
+        };
+
+        // Entries are written to a temporary file first, so that
+        // concurrent evaluations never observe partially written
+        // entries. Errors are ignored, the entry is simply missing
+        // from the cache then.
+        let tmp = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&tmp, buf))
+            .and_then(|_| std::fs::rename(&tmp, self.dir.join(key)));
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+}
+
+/// Serialise the given lambda (and all lambdas nested in its
+/// constants), compiled from `file`. Returns [None] if it contains
+/// values that can not be serialised.
+pub(crate) fn encode(
+    lambda: &Lambda,
+    file: &codemap::File,
+    globals: &GlobalsMap,
+) -> Option<Vec<u8>> {
+    let mut encoder = Encoder {
+        buf: MAGIC.to_vec(),
+        file_span: file.span,
+        globals,
+    };
+
+    encoder.buf.push(FORMAT_VERSION);
+    encoder.lambda(lambda)?;
+    Some(encoder.buf)
+}
+
+/// Deserialise a lambda previously serialised with [encode], rebasing
+/// its spans onto `file`. Returns [None] if the data is invalid, or
+/// refers to globals that do not exist.
+pub(crate) fn decode(buf: &[u8], file: &codemap::File, globals: &GlobalsMap) -> Option<Rc<Lambda>> {
+    let mut decoder = Decoder {
+        buf: buf.strip_prefix(MAGIC)?,
+        file_span: file.span,
+        globals,
+    };
+
+    if decoder.byte()? != FORMAT_VERSION {
+        return None;
+    }
+
+    let lambda = decoder.lambda()?;
+    decoder.buf.is_empty().then_some(lambda)
+}
+
+struct Encoder<'a> {
+    buf: Vec<u8>,
+    file_span: Span,
+    globals: &'a GlobalsMap,
+}
+
+impl Encoder<'_> {
+    fn uvarint(&mut self, data: u64) {
+        let mut encoded = [0u8; U64_VARINT_SIZE];
+        let bytes_written = vu128::encode_u64(&mut encoded, data);
+        self.buf.extend_from_slice(&encoded[..bytes_written]);
+    }
+
+    fn bool(&mut self, data: bool) {
+        self.buf.push(data as u8);
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        self.uvarint(data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    fn span(&mut self, span: Span) -> Option<()> {
+        if span.low() < self.file_span.low() || span.high() > self.file_span.high() {
+            return None;
+        }
+
+        self.uvarint(span.low() - self.file_span.low());
+        self.uvarint(span.high() - self.file_span.low());
+        Some(())
+    }
+
+    fn lambda(&mut self, lambda: &Lambda) -> Option<()> {
+        match &lambda.name {
+            Some(name) => {
+                self.bool(true);
+                self.bytes(name.as_bytes());
+            }
+            None => self.bool(false),
+        }
+
+        self.uvarint(lambda.upvalue_count as u64);
+
+        match &lambda.formals {
+            Some(formals) => {
+                self.bool(true);
+                self.formals(formals)?;
+            }
+            None => self.bool(false),
+        }
+
+        self.uvarint(lambda.locals.len() as u64);
+        for local in &lambda.locals {
+            self.bytes(local.name.as_bytes());
+            self.uvarint(local.stack_idx.0 as u64);
+            self.uvarint(local.code.start as u64);
+            self.uvarint(local.code.end as u64);
+        }
+
+        self.uvarint(lambda.upvalue_names.len() as u64);
+        for name in &lambda.upvalue_names {
+            match name {
+                Some(name) => {
+                    self.bool(true);
+                    self.bytes(name.as_bytes());
+                }
+                None => self.bool(false),
+            }
+        }
+
+        self.chunk(&lambda.chunk)
+    }
+
+    fn formals(&mut self, formals: &Formals) -> Option<()> {
+        self.uvarint(formals.arguments.len() as u64);
+        for (name, required) in &formals.arguments {
+            self.bytes(name.as_bytes());
+            self.bool(*required);
+        }
+
+        self.bool(formals.ellipsis);
+        self.span(formals.span)?;
+
+        match &formals.name {
+            Some(name) => {
+                self.bool(true);
+                self.bytes(name.as_bytes());
+            }
+            None => self.bool(false),
+        }
+
+        Some(())
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) -> Option<()> {
+        self.bytes(&chunk.code);
+        self.uvarint(chunk.last_op().map_or(0, |(_, idx)| idx) as u64);
+
+        let spans: Vec<_> = chunk.spans().collect();
+        self.uvarint(spans.len() as u64);
+        for (span, start) in spans {
+            self.span(span)?;
+            self.uvarint(start as u64);
+        }
+
+        self.uvarint(chunk.constants.len() as u64);
+        for constant in &chunk.constants {
+            self.value(constant)?;
+        }
+
+        Some(())
+    }
+
+    /// Find the name of the global that the given value was taken from.
+    fn global_name(&self, value: &Value) -> Option<&'static str> {
+        self.globals.iter().find_map(|(name, global)| {
+            let is_global = match (value, global) {
+                (Value::Attrs(a), Value::Attrs(b)) => a.ptr_eq(b),
+                (Value::Thunk(a), Value::Thunk(b)) => a.ptr_eq(b),
+                (Value::Builtin(a), Value::Builtin(b)) => a.name() == b.name(),
+                _ => false,
+            };
+
+            is_global.then_some(*name)
+        })
+    }
+
+    fn value(&mut self, value: &Value) -> Option<()> {
+        if matches!(value, Value::Attrs(_) | Value::Thunk(_) | Value::Builtin(_)) {
+            if let Some(name) = self.global_name(value) {
+                self.buf.push(tag::GLOBAL);
+                self.bytes(name.as_bytes());
+                return Some(());
+            }
+        }
+
+        match value {
+            Value::Null => self.buf.push(tag::NULL),
+
+            Value::Bool(b) => {
+                self.buf.push(tag::BOOL);
+                self.bool(*b);
+            }
+
+            Value::Integer(i) => {
+                self.buf.push(tag::INTEGER);
+                self.uvarint(*i as u64);
+            }
+
+            Value::Float(f) => {
+                self.buf.push(tag::FLOAT);
+                self.buf.extend_from_slice(&f.to_bits().to_le_bytes());
+            }
+
+            Value::String(s) if !s.has_context() => {
+                self.buf.push(tag::STRING);
+                self.bytes(s.as_bytes());
+            }
+
+            Value::Path(p) => {
+                self.buf.push(tag::PATH);
+                self.bytes(p.to_str()?.as_bytes());
+            }
+
+            Value::UnresolvedPath(p) => {
+                self.buf.push(tag::UNRESOLVED_PATH);
+                self.bytes(p.to_str()?.as_bytes());
+            }
+
+            Value::Catchable(c) => match c.as_ref() {
+                CatchableErrorKind::NixPathResolution(msg) => {
+                    self.buf.push(tag::NIX_PATH_RESOLUTION);
+                    self.bytes(msg.as_bytes());
+                }
+                _ => return None,
+            },
+
+            Value::Attrs(attrs) if attrs.is_empty() => self.buf.push(tag::EMPTY_ATTRS),
+
+            Value::FinaliseRequest(b) => {
+                self.buf.push(tag::FINALISE_REQUEST);
+                self.bool(*b);
+            }
+
+            Value::Thunk(thunk) => {
+                let (lambda, span) = thunk.suspended_lambda()?;
+                self.buf.push(tag::THUNK);
+                self.span(span)?;
+                self.lambda(&lambda)?;
+            }
+
+            Value::Closure(closure) => {
+                self.buf.push(tag::CLOSURE);
+                self.lambda(&closure.lambda)?;
+            }
+
+            Value::Blueprint(lambda) => {
+                self.buf.push(tag::BLUEPRINT);
+                self.lambda(lambda)?;
+            }
+
+            _ => return None,
+        }
+
+        Some(())
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+    file_span: Span,
+    globals: &'a GlobalsMap,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (byte, rest) = self.buf.split_first()?;
+        self.buf = rest;
+        Some(*byte)
+    }
+
+    fn uvarint(&mut self) -> Option<u64> {
+        let mut tmp = [0u8; U64_VARINT_SIZE];
+        let len = self.buf.len().min(U64_VARINT_SIZE);
+        tmp[..len].copy_from_slice(&self.buf[..len]);
+
+        let (data, size) = vu128::decode_u64(&tmp);
+        if size > len {
+            return None;
+        }
+
+        self.buf = &self.buf[size..];
+        Some(data)
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.uvarint()?.try_into().ok()
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.byte()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.usize()?;
+        if len > self.buf.len() {
+            return None;
+        }
+
+        let (data, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(data)
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        std::str::from_utf8(self.bytes()?).ok()
+    }
+
+    fn span(&mut self) -> Option<Span> {
+        let low = self.uvarint()?;
+        let high = self.uvarint()?;
+        if low > high || high > self.file_span.len() {
+            return None;
+        }
+
+        Some(self.file_span.subspan(low, high))
+    }
+
+    fn lambda(&mut self) -> Option<Rc<Lambda>> {
+        let name = if self.bool()? {
+            Some(SmolStr::new(self.str()?))
+        } else {
+            None
+        };
+
+        let upvalue_count = self.usize()?;
+
+        let formals = if self.bool()? {
+            Some(self.formals()?)
+        } else {
+            None
+        };
+
+        let mut locals = vec![];
+        for _ in 0..self.usize()? {
+            let name = SmolStr::new(self.str()?);
+            let stack_idx = StackIdx(self.usize()?);
+            let code = self.usize()?..self.usize()?;
+            locals.push(LocalDebugInfo {
+                name,
+                stack_idx,
+                code,
+            });
+        }
+
+        let mut upvalue_names = vec![];
+        for _ in 0..self.usize()? {
+            upvalue_names.push(if self.bool()? {
+                Some(SmolStr::new(self.str()?))
+            } else {
+                None
+            });
+        }
+
+        let lambda = Lambda {
+            chunk: self.chunk()?,
+            name,
+            upvalue_count,
+            formals,
+            locals,
+            upvalue_names,
+        };
+
+        validate(&lambda)?;
+        Some(Rc::new(lambda))
+    }
+
+    fn formals(&mut self) -> Option<Formals> {
+        let mut arguments = BTreeMap::new();
+        for _ in 0..self.usize()? {
+            let name = NixString::from(self.bytes()?);
+            arguments.insert(name, self.bool()?);
+        }
+
+        let ellipsis = self.bool()?;
+        let span = self.span()?;
+
+        let name = if self.bool()? {
+            Some(self.str()?.to_string())
+        } else {
+            None
+        };
+
+        Some(Formals {
+            arguments,
+            ellipsis,
+            span,
+            name,
+        })
+    }
+
+    fn chunk(&mut self) -> Option<Chunk> {
+        let code = self.bytes()?.to_vec();
+        let last_op = self.usize()?;
+        if last_op > code.len() {
+            return None;
+        }
+
+        let mut spans = vec![];
+        for _ in 0..self.usize()? {
+            let span = self.span()?;
+            spans.push((span, self.usize()?));
+        }
+
+        let mut constants = vec![];
+        for _ in 0..self.usize()? {
+            constants.push(self.value()?);
+        }
+
+        Some(Chunk::from_parts(code, constants, spans, last_op))
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        let value = match self.byte()? {
+            tag::NULL => Value::Null,
+            tag::BOOL => Value::Bool(self.bool()?),
+            tag::INTEGER => Value::Integer(self.uvarint()? as i64),
+
+            tag::FLOAT => {
+                if self.buf.len() < 8 {
+                    return None;
+                }
+
+                let (bits, rest) = self.buf.split_at(8);
+                self.buf = rest;
+                Value::Float(f64::from_bits(u64::from_le_bytes(bits.try_into().ok()?)))
+            }
+
+            tag::STRING => Value::String(NixString::from(self.bytes()?)),
+            tag::PATH => Value::Path(Box::new(PathBuf::from(self.str()?))),
+            tag::UNRESOLVED_PATH => Value::UnresolvedPath(Box::new(PathBuf::from(self.str()?))),
+
+            tag::NIX_PATH_RESOLUTION => Value::Catchable(Box::new(
+                CatchableErrorKind::NixPathResolution(self.str()?.into()),
+            )),
+
+            tag::EMPTY_ATTRS => Value::Attrs(Box::new(NixAttrs::empty())),
+            tag::FINALISE_REQUEST => Value::FinaliseRequest(self.bool()?),
+
+            tag::THUNK => {
+                let span = self.span()?;
+                Value::Thunk(Thunk::new_suspended(self.lambda()?, span))
+            }
+
+            tag::CLOSURE => Value::Closure(Rc::new(Closure::new(self.lambda()?))),
+            tag::BLUEPRINT => Value::Blueprint(self.lambda()?),
+            tag::GLOBAL => self.globals.get(self.str()?)?.clone(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+}
+
+/// Check that the bytecode of a decoded lambda only consists of valid
+/// operations, which refer to constants, upvalues and jump targets
+/// that exist. The VM assumes this to hold for compiled code, and
+/// panics otherwise.
+fn validate(lambda: &Lambda) -> Option<()> {
+    let chunk = &lambda.chunk;
+    let code = &chunk.code;
+
+    // The compiler terminates every chunk with a return.
+    if code.last() != Some(&(Op::Return as u8))
+        || chunk.last_op().map(|(_, idx)| idx) != Some(code.len() - 1)
+        || chunk.spans().next().is_none()
+    {
+        return None;
+    }
+
+    let read_uvarint = |idx: &mut usize| -> Option<u64> {
+        if *idx >= code.len() {
+            return None;
+        }
+
+        let (data, size) = chunk.read_uvarint(*idx);
+        *idx += size;
+        (*idx <= code.len()).then_some(data)
+    };
+
+    let mut ops = HashSet::new();
+    let mut jump_targets = vec![];
+    let mut idx = 0;
+
+    while idx < code.len() {
+        ops.insert(idx);
+        let op = Op::from(code[idx]);
+        idx += 1;
+
+        match op.arg_type() {
+            OpArg::None => match op {
+                Op::Invalid => return None,
+                Op::ValidateClosedFormals if lambda.formals.is_none() => return None,
+                _ => {}
+            },
+
+            OpArg::Fixed => {
+                if idx + 2 > code.len() {
+                    return None;
+                }
+
+                let offset = chunk.read_u16(idx) as usize;
+                idx += 2;
+                if offset == 0 {
+                    return None;
+                }
+                jump_targets.push(idx + offset);
+            }
+
+            OpArg::Uvarint => {
+                let arg = read_uvarint(&mut idx)?;
+                match op {
+                    Op::Constant if arg >= chunk.constants.len() as u64 => return None,
+                    Op::GetUpvalue if arg >= lambda.upvalue_count as u64 => return None,
+                    _ => {}
+                }
+            }
+
+            OpArg::Custom => match op {
+                Op::CoerceToString => {
+                    if idx >= code.len() {
+                        return None;
+                    }
+                    idx += 1;
+                }
+
+                Op::Closure | Op::ThunkClosure | Op::ThunkSuspended => {
+                    let bp_idx = usize::try_from(read_uvarint(&mut idx)?).ok()?;
+                    let Some(Value::Blueprint(blueprint)) = chunk.constants.get(bp_idx) else {
+                        return None;
+                    };
+
+                    let count = read_uvarint(&mut idx)? >> 1;
+                    if count != blueprint.upvalue_count as u64 {
+                        return None;
+                    }
+
+                    // Captured upvalues of the enclosing lambda must
+                    // exist, stack slots are only known at runtime.
+                    for _ in 0..count {
+                        let pos = Position(read_uvarint(&mut idx)?);
+                        let valid = match pos.runtime_upvalue_index() {
+                            Some(upvalue) => upvalue.0 < lambda.upvalue_count,
+                            None => {
+                                pos.runtime_stack_index().is_some()
+                                    || pos.runtime_deferred_local().is_some()
+                            }
+                        };
+
+                        if !valid {
+                            return None;
+                        }
+                    }
+                }
+
+                _ => return None,
+            },
+        }
+    }
+
+    jump_targets
+        .into_iter()
+        .all(|target| ops.contains(&target))
+        .then_some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::{CompilerObserver, DisassemblingObserver};
+    use crate::opcode::{CodeIdx, Op};
+    use crate::SourceCode;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    /// Nix code exercising all kinds of constants the compiler emits.
+    const CODE: &str = r#"
+let
+  inherit (builtins) length;
+  float = 1.5;
+  str = "hello ${toString float}";
+  path = ./foo/bar;
+  home = ~/baz;
+  search = <nixpkgs>;
+  empty = <>;
+  f = { a, b ? a + 1, ... }@args: a + b + length (builtins.attrNames args);
+  rec' = rec { x = 1; y = x + 1; z = _: y; };
+  fib = n: if n < 2 then n else fib (n - 1) + fib (n - 2);
+  g = x: y: with x; y + z;
+in
+{
+  inherit str path home search empty rec';
+  a = f { a = 1; };
+  b = map (x: x * 2) [ 1 2 3 ];
+  c = g { z = 1; } (fib 10);
+  d = null;
+  e = true;
+  "quoted attr" = { };
+}
+"#;
+
+    /// Compiles `code`, returning the compiled lambda and the output
+    /// of the [DisassemblingObserver].
+    fn compile(
+        code: &str,
+        globals: Rc<GlobalsMap>,
+        source: &SourceCode,
+    ) -> (Rc<Lambda>, Arc<codemap::File>, String) {
+        let file = source.add_file("/tvix/test.nix".into(), code.into());
+        let parsed = rnix::ast::Root::parse(code);
+        let mut out = vec![];
+        let mut observer = DisassemblingObserver::new(source.clone(), &mut out);
+
+        let result = crate::compiler::compile(
+            &parsed.tree().expr().unwrap(),
+            Some("/tvix/test.nix".into()),
+            globals,
+            None,
+            source,
+            &file,
+            &mut observer,
+        )
+        .expect("compilation should succeed");
+
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        drop(observer);
+        (result.lambda, file, String::from_utf8(out).unwrap())
+    }
+
+    /// Calls the observer for all lambdas nested in the given lambda,
+    /// in the same order as the compiler would have.
+    fn observe_nested(lambda: &Lambda, source: &SourceCode, observer: &mut dyn CompilerObserver) {
+        // Blueprints are observed as thunks if the operation
+        // referencing them creates a suspended thunk.
+        let mut suspended = HashSet::new();
+        let mut idx = 0;
+        while idx < lambda.chunk.code.len() {
+            if lambda.chunk.code[idx] == Op::ThunkSuspended as u8 {
+                suspended.insert(lambda.chunk.read_uvarint(idx + 1).0 as usize);
+            }
+
+            idx += lambda
+                .chunk
+                .disassemble_op(&mut std::io::sink(), source, 0, CodeIdx(idx))
+                .unwrap();
+        }
+
+        for (idx, constant) in lambda.chunk.constants.iter().enumerate() {
+            match constant {
+                Value::Thunk(thunk) => {
+                    if let Some((lambda, _)) = thunk.suspended_lambda() {
+                        observe_nested(&lambda, source, observer);
+                        observer.observe_compiled_thunk(&lambda);
+                    }
+                }
+                Value::Closure(closure) => {
+                    observe_nested(&closure.lambda, source, observer);
+                    observer.observe_compiled_lambda(&closure.lambda);
+                }
+                Value::Blueprint(lambda) => {
+                    observe_nested(lambda, source, observer);
+                    if suspended.contains(&idx) {
+                        observer.observe_compiled_thunk(lambda);
+                    } else {
+                        observer.observe_compiled_lambda(lambda);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the output of the [DisassemblingObserver] for a lambda
+    /// loaded from the cache.
+    fn disassemble_cached(lambda: &Rc<Lambda>, source: &SourceCode) -> String {
+        let mut out = vec![];
+        let mut observer = DisassemblingObserver::new(source.clone(), &mut out);
+        observe_nested(lambda, source, &mut observer);
+        observer.observe_compiled_toplevel(lambda);
+        drop(observer);
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Removes the lambda addresses from the headers printed by the
+    /// [DisassemblingObserver], which differ between the compiled
+    /// and the loaded lambdas.
+    fn strip_addresses(disassembly: &str) -> String {
+        disassembly
+            .lines()
+            .map(|line| match (line.find(" @ 0x"), line.find(" (")) {
+                (Some(start), Some(end)) if line.starts_with("===") => {
+                    format!("{}{}", &line[..start], &line[end..])
+                }
+                _ => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn globals(source: &SourceCode) -> Rc<GlobalsMap> {
+        crate::compiler::prepare_globals(
+            crate::builtins::pure_builtins(),
+            vec![],
+            source.clone(),
+            true,
+            None,
+        )
+    }
+
+    #[test]
+    fn cached_bytecode_matches_compiled() {
+        let source = SourceCode::default();
+        let globals = globals(&source);
+        let (lambda, file, compiled) = compile(CODE, globals.clone(), &source);
+
+        let buf = encode(&lambda, &file, &globals).expect("lambda should be serialisable");
+
+        // Load the entry for a fresh copy of the file, as would
+        // happen in a subsequent evaluation.
+        let file = source.add_file("/tvix/test.nix".into(), CODE.into());
+        let cached = decode(&buf, &file, &globals).expect("entry should be decodable");
+
+        assert_eq!(
+            strip_addresses(&compiled),
+            strip_addresses(&disassemble_cached(&cached, &source))
+        );
+
+        // Debug information survives the roundtrip.
+        assert!(!lambda.locals.is_empty());
+        assert_eq!(lambda.locals, cached.locals);
+        assert_eq!(lambda.upvalue_names, cached.upvalue_names);
+    }
+
+    #[test]
+    fn globals_are_shared() {
+        let source = SourceCode::default();
+        let globals = globals(&source);
+        let (lambda, file, _) = compile("builtins", globals.clone(), &source);
+
+        let buf = encode(&lambda, &file, &globals).unwrap();
+        let cached = decode(&buf, &file, &globals).unwrap();
+
+        match (&cached.chunk.constants[0], &globals["builtins"]) {
+            (Value::Attrs(cached), Value::Attrs(global)) => assert!(cached.ptr_eq(global)),
+            other => panic!("unexpected constants: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let source = SourceCode::default();
+        let globals = globals(&source);
+        let (lambda, file, _) = compile(CODE, globals.clone(), &source);
+        let buf = encode(&lambda, &file, &globals).unwrap();
+
+        for len in 0..buf.len() {
+            assert!(decode(&buf[..len], &file, &globals).is_none(), "{len}");
+        }
+
+        let mut trailing = buf.clone();
+        trailing.push(0);
+        assert!(decode(&trailing, &file, &globals).is_none());
+
+        let mut version = buf.clone();
+        version[MAGIC.len()] += 1;
+        assert!(decode(&version, &file, &globals).is_none());
+
+        // Entries can not be loaded with globals that lack the
+        // referenced builtins.
+        let no_map = crate::compiler::prepare_globals(vec![], vec![], source.clone(), false, None);
+        assert!(decode(&buf, &file, &no_map).is_none());
+    }
+
+    /// Encodes a lambda with the given bytecode and a single constant,
+    /// and decodes it again.
+    fn roundtrip_code(code: &[u8], upvalue_count: usize) -> Option<Rc<Lambda>> {
+        let source = SourceCode::default();
+        let globals = globals(&source);
+        let file = source.add_file("/tvix/test.nix".into(), "null".into());
+
+        let lambda = Lambda {
+            chunk: Chunk::from_parts(
+                code.to_vec(),
+                vec![Value::Null],
+                vec![(file.span, 0)],
+                code.len() - 1,
+            ),
+            upvalue_count,
+            ..Default::default()
+        };
+
+        let buf = encode(&lambda, &file, &globals).unwrap();
+        decode(&buf, &file, &globals)
+    }
+
+    #[test]
+    fn corrupt_bytecode_is_rejected() {
+        let ret = Op::Return as u8;
+        assert!(roundtrip_code(&[Op::Constant as u8, 0, ret], 0).is_some());
+        assert!(roundtrip_code(&[Op::GetUpvalue as u8, 0, ret], 1).is_some());
+
+        // out of bounds constant
+        assert!(roundtrip_code(&[Op::Constant as u8, 1, ret], 0).is_none());
+
+        // out of bounds upvalue
+        assert!(roundtrip_code(&[Op::GetUpvalue as u8, 0, ret], 0).is_none());
+
+        // invalid opcode
+        assert!(roundtrip_code(&[Op::Invalid as u8, ret], 0).is_none());
+        assert!(roundtrip_code(&[0xff, ret], 0).is_none());
+
+        // missing return
+        assert!(roundtrip_code(&[Op::Constant as u8, 0], 0).is_none());
+
+        // missing operand
+        assert!(roundtrip_code(&[Op::Constant as u8], 0).is_none());
+
+        // jump past the end of the chunk, and into an operand
+        assert!(roundtrip_code(&[Op::Jump as u8, 1, 0, ret], 0).is_none());
+        assert!(roundtrip_code(&[Op::Jump as u8, 1, 0, Op::Constant as u8, 0, ret], 0).is_none());
+        assert!(roundtrip_code(&[Op::Jump as u8, 2, 0, Op::Constant as u8, 0, ret], 0).is_some());
+
+        // closure referring to a non-blueprint constant
+        assert!(roundtrip_code(&[Op::Closure as u8, 0, 0, ret], 0).is_none());
+    }
+
+    #[test]
+    fn cache_keys() {
+        let source = SourceCode::default();
+        let globals = globals(&source);
+        let key = BytecodeCache::key(&globals, Path::new("/a.nix"), "1");
+
+        assert_eq!(key, BytecodeCache::key(&globals, Path::new("/a.nix"), "1"));
+        assert_ne!(key, BytecodeCache::key(&globals, Path::new("/b.nix"), "1"));
+        assert_ne!(key, BytecodeCache::key(&globals, Path::new("/a.nix"), "2"));
+
+        let other_globals = crate::compiler::prepare_globals(
+            vec![("foo", Value::Integer(42))],
+            vec![],
+            source.clone(),
+            false,
+            None,
+        );
+        assert_ne!(
+            key,
+            BytecodeCache::key(&other_globals, Path::new("/a.nix"), "1")
+        );
+    }
+
+    #[test]
+    fn cache_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BytecodeCache::new(dir.path().join("cache"));
+        let source = SourceCode::default();
+        let globals = globals(&source);
+        let (lambda, file, compiled) = compile(CODE, globals.clone(), &source);
+
+        let key = BytecodeCache::key(&globals, Path::new("/tvix/test.nix"), CODE);
+        assert!(cache.lookup(&key, &file, &globals).is_none());
+
+        cache.insert(&key, &file, &globals, &lambda);
+        let cached = cache
+            .lookup(&key, &file, &globals)
+            .expect("entry should be cached");
+
+        assert_eq!(
+            strip_addresses(&compiled),
+            strip_addresses(&disassemble_cached(&cached, &source))
+        );
+    }
+
+    #[cfg(feature = "impure")]
+    #[test]
+    fn import_uses_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.nix");
+        std::fs::write(&file, "{ x }: x * 2").unwrap();
+        let cache_dir = dir.path().join("cache");
+
+        let code = format!("import {} {{ x = 21; }}", file.display());
+        for _ in 0..2 {
+            let result = crate::Evaluation::builder_impure()
+                .bytecode_cache(Some(BytecodeCache::new(&cache_dir)))
+                .build()
+                .evaluate(&code, None);
+
+            assert!(result.errors.is_empty(), "{:?}", result.errors);
+            assert!(matches!(result.value, Some(Value::Integer(42))));
+        }
+
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+    }
+}
@@ -121,6 +121,32 @@ impl Chunk {
         self.constants.get(constant.0)
     }
 
+    /// Iterate over the source spans of this chunk, together with the index
+    /// of the first operation covered by each of them.
+    pub(crate) fn spans(&self) -> impl Iterator<Item = (codemap::Span, usize)> + '_ {
+        self.spans.iter().map(|s| (s.span, s.start))
+    }
+
+    /// Reassemble a chunk from its parts, e.g. when loading it from the
+    /// [crate::BytecodeCache]. `spans` must be ordered by the index of
+    /// their first operation.
+    pub(crate) fn from_parts(
+        code: Vec<u8>,
+        constants: Vec<Value>,
+        spans: Vec<(codemap::Span, usize)>,
+        last_op: usize,
+    ) -> Self {
+        Chunk {
+            code,
+            constants,
+            spans: spans
+                .into_iter()
+                .map(|(span, start)| SourceSpan { span, start })
+                .collect(),
+            last_op,
+        }
+    }
+
     fn push_span(&mut self, span: codemap::Span, start: usize) {
         match self.spans.last_mut() {
             // We do not need to insert the same span again, as this
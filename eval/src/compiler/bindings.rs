@@ -383,7 +383,7 @@ impl Compiler<'_, '_> {
                             self.declare_local(&attr, name)
                         };
 
-                        self.scope_mut().mark_initialised(idx);
+                        self.mark_initialised(idx);
                     }
                 }
 
@@ -555,7 +555,7 @@ impl Compiler<'_, '_> {
 
         // Remove the temporary scope, but do not emit any additional cleanup
         // (OpAttrs consumes all of these locals).
-        self.scope_mut().end_scope();
+        self.end_scope();
     }
 
     /// Emit definitions for all variables in the top-level global env passed to the evaluation (eg
@@ -581,12 +581,12 @@ impl Compiler<'_, '_> {
                 KeySlot::Static { slot, name } => {
                     let span = self.scope()[slot].span;
                     self.emit_constant(name.as_str().into(), &OrEntireFile(span));
-                    self.scope_mut().mark_initialised(slot);
+                    self.mark_initialised(slot);
                 }
 
                 KeySlot::Dynamic { slot, attr } => {
                     self.compile_attr(slot, &attr);
-                    self.scope_mut().mark_initialised(slot);
+                    self.mark_initialised(slot);
                 }
             }
 
@@ -618,13 +618,13 @@ impl Compiler<'_, '_> {
                 Binding::Set(set) => self.thunk(binding.value_slot, &set, |c, _| {
                     c.scope_mut().begin_scope();
                     c.compile_bindings(binding.value_slot, set.kind, &set);
-                    c.scope_mut().end_scope();
+                    c.end_scope();
                 }),
             }
 
             // Any code after this point will observe the value in the right
             // stack slot, so mark it as initialised.
-            self.scope_mut().mark_initialised(binding.value_slot);
+            self.mark_initialised(binding.value_slot);
         }
 
         // Final pass to emit finaliser instructions if necessary.
@@ -692,7 +692,7 @@ impl Compiler<'_, '_> {
 
         // Remove the temporary scope, but do not emit any additional cleanup
         // (OpAttrs consumes all of these locals).
-        self.scope_mut().end_scope();
+        self.end_scope();
 
         self.emit_constant("body".into(), node);
         self.push_op(Op::AttrsSelect, node);
@@ -717,6 +717,11 @@ impl Compiler<'_, '_> {
             LocalPosition::Unknown => {
                 // Are we possibly dealing with an upvalue?
                 if let Some(idx) = self.resolve_upvalue_for_use(self.contexts.len() - 1, ident) {
+                    let resolution = self
+                        .local_definition(ident)
+                        .map_or(Resolution::Unknown, Resolution::Local);
+                    self.observe_identifier(node, resolution);
+
                     self.push_op(Op::GetUpvalue, node);
                     self.push_uvarint(idx.0 as u64);
                     return;
@@ -726,8 +731,9 @@ impl Compiler<'_, '_> {
                 // exactly like a `let ... in` prepended to the
                 // program's text, and the global scope is nothing
                 // more than the parent scope of the root scope.
-                if let Some(global) = self.globals.get(ident) {
-                    self.emit_constant(global.clone(), &self.span_for(node));
+                if let Some(global) = self.globals.get(ident).cloned() {
+                    self.observe_identifier(node, Resolution::Global(global.clone()));
+                    self.emit_constant(global, &self.span_for(node));
                     return;
                 }
 
@@ -738,6 +744,9 @@ impl Compiler<'_, '_> {
                 // dynamic resolution without actually using it, this operation
                 // is wrapped in an extra thunk.
                 if self.has_dynamic_ancestor() {
+                    let with_spans = self.scope().with_spans().collect();
+                    self.observe_identifier(node, Resolution::With(with_spans));
+
                     self.thunk(slot, node, |c, _| {
                         c.context_mut().captures_with_stack = true;
                         c.emit_constant(ident.into(), node);
@@ -747,10 +756,12 @@ impl Compiler<'_, '_> {
                 }
 
                 // Otherwise, this variable is missing.
+                self.observe_identifier(node, Resolution::Unknown);
                 self.emit_error(node, ErrorKind::UnknownStaticVariable);
             }
 
             LocalPosition::Known(idx) => {
+                self.observe_local(node, idx);
                 self.scope_mut().mark_used(idx);
 
                 let stack_idx = self.scope().stack_index(idx);
@@ -761,6 +772,7 @@ impl Compiler<'_, '_> {
             // This identifier is referring to a value from the same scope which
             // is not yet defined. This identifier access must be thunked.
             LocalPosition::Recursive(idx) => {
+                self.observe_local(node, idx);
                 self.scope_mut().mark_used(idx);
                 self.thunk(slot, node, move |compiler, _| {
                     let upvalue_idx =
@@ -780,6 +792,33 @@ impl Compiler<'_, '_> {
 
 /// Private compiler helpers related to bindings.
 impl Compiler<'_, '_> {
+    /// Report the resolution of the identifier at `node` to the observer.
+    fn observe_identifier<N: ToSpan>(&mut self, node: &N, resolution: Resolution) {
+        let span = self.span_for(node);
+        self.observer.observe_resolved_identifier(span, &resolution);
+    }
+
+    /// Report the resolution of the identifier at `node` to the given
+    /// local of the current scope to the observer.
+    fn observe_local<N: ToSpan>(&mut self, node: &N, idx: LocalIdx) {
+        let resolution = self.scope()[idx]
+            .span
+            .map_or(Resolution::Unknown, Resolution::Local);
+        self.observe_identifier(node, resolution);
+    }
+
+    /// Find the span at which the local with the given name, as visible
+    /// from the current context, was declared.
+    fn local_definition(&self, name: &str) -> Option<Span> {
+        self.contexts
+            .iter()
+            .rev()
+            .find_map(|ctx| match ctx.scope.resolve_local(name) {
+                LocalPosition::Known(idx) | LocalPosition::Recursive(idx) => ctx.scope[idx].span,
+                LocalPosition::Unknown => None,
+            })
+    }
+
     // ATTN: Also marks local backing the upvalue as used if any
     fn resolve_upvalue_for_use(&mut self, ctx_idx: usize, name: &str) -> Option<UpvalueIdx> {
         if ctx_idx == 0 {
@@ -819,7 +858,19 @@ impl Compiler<'_, '_> {
             }
         }
 
+        // Keep track of the name of the captured variable for debuggers.
+        let name = match &kind {
+            UpvalueKind::Local(local) => self.contexts[ctx_idx - 1].scope[*local].name(),
+            UpvalueKind::Upvalue(upvalue) => self.contexts[ctx_idx - 1]
+                .lambda
+                .upvalue_names
+                .get(upvalue.0)
+                .cloned()
+                .flatten(),
+        };
+
         self.contexts[ctx_idx].scope.upvalues.push(Upvalue { kind });
+        self.contexts[ctx_idx].lambda.upvalue_names.push(name);
 
         let idx = UpvalueIdx(self.contexts[ctx_idx].lambda.upvalue_count);
         self.contexts[ctx_idx].lambda.upvalue_count += 1;
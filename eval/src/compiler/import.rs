@@ -5,27 +5,41 @@
 //! compiler and VM state (such as the [`crate::SourceCode`]
 //! instance, or observers).
 
-use super::GlobalsMap;
+use super::{CompilationOutput, GlobalsMap};
+use bstr::ByteSlice;
 use genawaiter::rc::Gen;
-use std::rc::Weak;
+use rustc_hash::FxHashMap;
+use smol_str::SmolStr;
+use std::path::Path;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
 
 use crate::{
     builtins::coerce_value_to_path,
+    bytecode_cache::BytecodeCache,
     generators::pin_generator,
     observer::NoOpObserver,
     value::{Builtin, Thunk},
     vm::generators::{self, GenCo},
-    ErrorKind, SourceCode, Value,
+    ErrorKind, NixAttrs, SourceCode, Value,
 };
 
+/// Imports the file at `path`. If `scope` is set, its attributes are made
+/// available as variables in the imported file (shadowing any globals), and
+/// the import cache is bypassed, as the result depends on the scope.
+///
+/// Unless `scope` is set, compiled bytecode is stored in (and loaded from)
+/// the `bytecode_cache`, if one is configured.
 async fn import_impl(
     co: GenCo,
     globals: Weak<GlobalsMap>,
     source: SourceCode,
-    mut args: Vec<Value>,
+    scope: Option<NixAttrs>,
+    bytecode_cache: Option<Rc<BytecodeCache>>,
+    path: Value,
 ) -> Result<Value, ErrorKind> {
     // TODO(sterni): canon_path()?
-    let mut path = match coerce_value_to_path(&co, args.pop().unwrap()).await? {
+    let mut path = match coerce_value_to_path(&co, path).await? {
         Err(cek) => return Ok(Value::Catchable(Box::new(cek))),
         Ok(path) => path,
     };
@@ -34,68 +48,129 @@ async fn import_impl(
         path.push("default.nix");
     }
 
-    if let Some(cached) = generators::request_import_cache_lookup(&co, path.clone()).await {
-        return Ok(cached);
+    let env = match scope {
+        Some(scope) => {
+            let mut env = FxHashMap::default();
+            for (name, value) in scope.into_iter() {
+                env.insert(SmolStr::new(name.to_str()?), value);
+            }
+            Some(env)
+        }
+        None => None,
+    };
+
+    if env.is_none() {
+        if let Some(cached) = generators::request_import_cache_lookup(&co, path.clone()).await {
+            return Ok(cached);
+        }
     }
 
-    let mut reader = generators::request_open_file(&co, path.clone()).await;
+    let mut reader = match generators::request_open_file(&co, path.clone()).await {
+        Err(cek) => return Ok(Value::Catchable(Box::new(cek))),
+        Ok(reader) => reader,
+    };
     // We read to a String instead of a Vec<u8> because rnix only supports
     // string source files.
     let mut contents = String::new();
     reader.read_to_string(&mut contents)?;
 
-    let parsed = rnix::ast::Root::parse(&contents);
-    let errors = parsed.errors();
+    // The VM must ensure that a strong reference to the globals outlives
+    // any self-references (which are weak) embedded within the globals. If
+    // the expect() below panics, it means that did not happen.
+    let globals = globals
+        .upgrade()
+        .expect("globals dropped while still in use");
+
     let file = source.add_file(path.to_string_lossy().to_string(), contents.to_owned());
 
+    // Bytecode compiled with a scope depends on that scope, and is not
+    // cached.
+    let bytecode_cache = bytecode_cache.filter(|_| env.is_none()).map(|cache| {
+        let key = BytecodeCache::key(&globals, &path, &contents);
+        (cache, key)
+    });
+
+    let cached = bytecode_cache
+        .as_ref()
+        .and_then(|(cache, key)| cache.lookup(key, &file, &globals));
+
+    let lambda = match cached {
+        Some(lambda) => lambda,
+        None => {
+            let result = compile_file(&path, &contents, &file, &source, &globals, env.as_ref())?;
+
+            // Warnings are only emitted at compile time, so files compiled
+            // with warnings are not cached to ensure they are not lost.
+            if let Some((cache, key)) = &bytecode_cache {
+                if result.warnings.is_empty() {
+                    cache.insert(key, &file, &globals, &result.lambda);
+                }
+            }
+
+            for warning in result.warnings {
+                generators::emit_warning(&co, warning).await;
+            }
+
+            result.lambda
+        }
+    };
+
+    // Compilation succeeded (or the bytecode was cached), we can construct a
+    // thunk from the resulting lambda and return that.
+    let res = Value::Thunk(Thunk::new_suspended(
+        lambda,
+        generators::request_span(&co).await,
+    ));
+
+    if env.is_none() {
+        generators::request_import_cache_put(&co, path, res.clone()).await;
+    }
+
+    Ok(res)
+}
+
+/// Parses and compiles the contents of the imported file at `path`.
+fn compile_file(
+    path: &Path,
+    contents: &str,
+    file: &Arc<codemap::File>,
+    source: &SourceCode,
+    globals: &Rc<GlobalsMap>,
+    env: Option<&FxHashMap<SmolStr, Value>>,
+) -> Result<CompilationOutput, ErrorKind> {
+    let parsed = rnix::ast::Root::parse(contents);
+    let errors = parsed.errors();
+
     if !errors.is_empty() {
         return Err(ErrorKind::ImportParseError {
-            path,
-            file,
+            path: path.to_owned(),
+            file: file.clone(),
             errors: errors.to_vec(),
         });
     }
 
     let result = crate::compiler::compile(
         &parsed.tree().expr().unwrap(),
-        Some(path.clone()),
-        // The VM must ensure that a strong reference to the globals outlives
-        // any self-references (which are weak) embedded within the globals. If
-        // the expect() below panics, it means that did not happen.
-        globals
-            .upgrade()
-            .expect("globals dropped while still in use"),
-        None,
-        &source,
-        &file,
+        Some(path.to_owned()),
+        globals.clone(),
+        env,
+        source,
+        file,
         &mut NoOpObserver::default(),
     )
     .map_err(|err| ErrorKind::ImportCompilerError {
-        path: path.clone(),
+        path: path.to_owned(),
         errors: vec![err],
     })?;
 
     if !result.errors.is_empty() {
         return Err(ErrorKind::ImportCompilerError {
-            path,
+            path: path.to_owned(),
             errors: result.errors,
         });
     }
 
-    for warning in result.warnings {
-        generators::emit_warning(&co, warning).await;
-    }
-
-    // Compilation succeeded, we can construct a thunk from whatever it spat
-    // out and return that.
-    let res = Value::Thunk(Thunk::new_suspended(
-        result.lambda,
-        generators::request_span(&co).await,
-    ));
-
-    generators::request_import_cache_put(&co, path, res.clone()).await;
-
-    Ok(res)
+    Ok(result)
 }
 
 /// Constructs the `import` builtin. This builtin is special in that
@@ -103,7 +178,11 @@ async fn import_impl(
 /// track source code locations while invoking a compiler.
 // TODO: need to be able to pass through a CompilationObserver, too.
 // TODO: can the `SourceCode` come from the compiler?
-pub(super) fn builtins_import(globals: &Weak<GlobalsMap>, source: SourceCode) -> Builtin {
+pub(super) fn builtins_import(
+    globals: &Weak<GlobalsMap>,
+    source: SourceCode,
+    bytecode_cache: Option<Rc<BytecodeCache>>,
+) -> Builtin {
     // This (very cheap, once-per-compiler-startup) clone exists
     // solely in order to keep the borrow checker happy.  It
     // resolves the tension between the requirements of
@@ -114,8 +193,48 @@ pub(super) fn builtins_import(globals: &Weak<GlobalsMap>, source: SourceCode) ->
         "import",
         Some("Import the given file and return the Nix value it evaluates to"),
         1,
-        move |args| {
-            Gen::new(|co| pin_generator(import_impl(co, globals.clone(), source.clone(), args)))
+        move |mut args| {
+            let path = args.pop().unwrap();
+            Gen::new(|co| {
+                pin_generator(import_impl(
+                    co,
+                    globals.clone(),
+                    source.clone(),
+                    None,
+                    bytecode_cache.clone(),
+                    path,
+                ))
+            })
+        },
+    )
+}
+
+/// Constructs the `scopedImport` builtin, which works like `import`, but
+/// additionally makes the attributes of the passed attribute set available
+/// as variables in the imported file.
+pub(super) fn builtins_scoped_import(globals: &Weak<GlobalsMap>, source: SourceCode) -> Builtin {
+    let globals = globals.clone();
+
+    Builtin::new(
+        "scopedImport",
+        Some("Import the given file with the given attribute set in scope"),
+        2,
+        move |mut args| {
+            let path = args.pop().unwrap();
+            let scope = args.pop().unwrap();
+            let globals = globals.clone();
+            let source = source.clone();
+            Gen::new(|co| {
+                pin_generator(async move {
+                    let scope = generators::request_force(&co, scope).await;
+                    if scope.is_catchable() {
+                        return Ok(scope);
+                    }
+
+                    let scope = scope.to_attrs()?;
+                    import_impl(co, globals, source, Some(*scope), None, path).await
+                })
+            })
         },
     )
 }
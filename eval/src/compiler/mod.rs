@@ -26,12 +26,13 @@ use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::rc::{Rc, Weak};
 
+use crate::bytecode_cache::BytecodeCache;
 use crate::chunk::Chunk;
 use crate::errors::{CatchableErrorKind, Error, ErrorKind, EvalResult};
-use crate::observer::CompilerObserver;
+use crate::observer::{CompilerObserver, Resolution};
 use crate::opcode::{CodeIdx, Op, Position, UpvalueIdx};
 use crate::spans::ToSpan;
-use crate::value::{Closure, Formals, Lambda, NixAttrs, Thunk, Value};
+use crate::value::{Closure, Formals, Lambda, LocalDebugInfo, NixAttrs, Thunk, Value};
 use crate::warnings::{EvalWarning, WarningKind};
 use crate::CoercionKind;
 use crate::SourceCode;
@@ -686,12 +687,12 @@ impl Compiler<'_, '_> {
 
             count += 1;
             self.compile(item_slot, item);
-            self.scope_mut().mark_initialised(item_slot);
+            self.mark_initialised(item_slot);
         }
 
         self.push_op(Op::List, node);
         self.push_uvarint(count as u64);
-        self.scope_mut().end_scope();
+        self.end_scope();
     }
 
     fn compile_attr(&mut self, slot: LocalIdx, node: &ast::Attr) {
@@ -960,7 +961,7 @@ impl Compiler<'_, '_> {
         let local_idx = self.scope_mut().declare_phantom(span, true);
         let with_idx = self.scope().stack_index(local_idx);
 
-        self.scope_mut().push_with();
+        self.scope_mut().push_with(span);
 
         self.push_op(Op::PushWith, &node.namespace().unwrap());
         self.push_uvarint(with_idx.0 as u64);
@@ -1025,7 +1026,7 @@ impl Compiler<'_, '_> {
         };
 
         // At call time, the attribute set is already at the top of the stack.
-        self.scope_mut().mark_initialised(set_idx);
+        self.mark_initialised(set_idx);
         self.emit_force(pattern);
         let throw_idx = self.push_op(Op::JumpIfCatchable, pattern);
         self.push_u16(0);
@@ -1128,13 +1129,13 @@ impl Compiler<'_, '_> {
                 }
             }
 
-            self.scope_mut().mark_initialised(idx);
+            self.mark_initialised(idx);
             if let TrackedFormal::WithDefault {
                 finalise_request_idx,
                 ..
             } = tracked_formal
             {
-                self.scope_mut().mark_initialised(*finalise_request_idx);
+                self.mark_initialised(*finalise_request_idx);
             }
         }
 
@@ -1190,7 +1191,7 @@ impl Compiler<'_, '_> {
                     .to_string();
 
                 let idx = self.declare_local(&param, &name);
-                self.scope_mut().mark_initialised(idx);
+                self.mark_initialised(idx);
                 None
             }
         };
@@ -1390,13 +1391,40 @@ impl Compiler<'_, '_> {
         self.chunk().patch_jump(idx.0);
     }
 
+    /// Mark a local as initialised from the next emitted operation
+    /// onwards.
+    fn mark_initialised(&mut self, idx: LocalIdx) {
+        let code_idx = self.chunk().code.len();
+        self.scope_mut().mark_initialised(idx, code_idx);
+    }
+
+    /// Decrease scope depth of the current function and record debug
+    /// information for the named locals that go out of scope.
+    ///
+    /// See [`Scope::end_scope`] for the returned values.
+    fn end_scope(&mut self) -> (usize, Vec<Span>) {
+        let end = self.chunk().code.len();
+        let (popcount, unused_spans, dropped) = self.scope_mut().end_scope();
+
+        let locals = &mut self.context_mut().lambda.locals;
+        for (name, stack_idx, start) in dropped.into_iter().rev() {
+            locals.push(LocalDebugInfo {
+                name,
+                stack_idx,
+                code: start..end,
+            });
+        }
+
+        (popcount, unused_spans)
+    }
+
     /// Decrease scope depth of the current function and emit
     /// instructions to clean up the stack at runtime.
     fn cleanup_scope<N: ToSpan>(&mut self, node: &N) {
         // When ending a scope, all corresponding locals need to be
         // removed, but the value of the body needs to remain on the
         // stack. This is implemented by a separate instruction.
-        let (popcount, unused_spans) = self.scope_mut().end_scope();
+        let (popcount, unused_spans) = self.end_scope();
 
         for span in &unused_spans {
             self.emit_warning(span, WarningKind::UnusedBinding);
@@ -1571,25 +1599,36 @@ fn compile_src_builtin(
 /// A subset of builtins (specified by [`GLOBAL_BUILTINS`]) is
 /// available globally *iff* they are set.
 ///
-/// Optionally adds the `import` feature if desired by the caller.
+/// Optionally adds the `import` and `scopedImport` features if desired by
+/// the caller. Files compiled by `import` are cached in the given
+/// [`BytecodeCache`], if any.
 pub fn prepare_globals(
     builtins: Vec<(&'static str, Value)>,
     src_builtins: Vec<(&'static str, &'static str)>,
     source: SourceCode,
     enable_import: bool,
+    bytecode_cache: Option<BytecodeCache>,
 ) -> Rc<GlobalsMap> {
     Rc::new_cyclic(Box::new(move |weak: &Weak<GlobalsMap>| {
         // First step is to construct the builtins themselves as
         // `NixAttrs`.
         let mut builtins: GlobalsMap = FxHashMap::from_iter(builtins);
 
-        // At this point, optionally insert `import` if enabled. To
-        // "tie the knot" of `import` needing the full set of globals
-        // to instantiate its compiler, the `Weak` reference is passed
-        // here.
+        // At this point, optionally insert `import` and `scopedImport`
+        // if enabled. To "tie the knot" of `import` needing the full set
+        // of globals to instantiate its compiler, the `Weak` reference is
+        // passed here.
         if enable_import {
-            let import = Value::Builtin(import::builtins_import(weak, source.clone()));
+            let import = Value::Builtin(import::builtins_import(
+                weak,
+                source.clone(),
+                bytecode_cache.map(Rc::new),
+            ));
             builtins.insert("import", import);
+
+            let scoped_import =
+                Value::Builtin(import::builtins_scoped_import(weak, source.clone()));
+            builtins.insert("scopedImport", scoped_import);
         }
 
         // Next, the actual map of globals which the compiler will use
@@ -44,6 +44,10 @@ pub struct Local {
     /// Is this local initialised?
     pub initialised: bool,
 
+    /// Index in the bytecode from which on the value of this local is
+    /// available on the stack (used for debug information).
+    pub initialised_at: Option<usize>,
+
     /// Is this local known to have been used at all?
     pub used: bool,
 
@@ -170,8 +174,9 @@ pub struct Scope {
     /// How many scopes "deep" are these locals?
     scope_depth: usize,
 
-    /// Current size of the `with`-stack at runtime.
-    with_stack_size: usize,
+    /// Spans of the namespaces on the `with`-stack at runtime,
+    /// innermost last.
+    with_stack: Vec<codemap::Span>,
 }
 
 impl Index<LocalIdx> for Scope {
@@ -189,25 +194,32 @@ impl Scope {
     pub fn inherit(&self) -> Self {
         Self {
             scope_depth: self.scope_depth + 1,
-            with_stack_size: self.with_stack_size,
+            with_stack: self.with_stack.clone(),
             ..Default::default()
         }
     }
 
-    /// Increase the `with`-stack size of this scope.
-    pub fn push_with(&mut self) {
-        self.with_stack_size += 1;
+    /// Push the namespace of a `with`-expression onto the `with`-stack
+    /// of this scope.
+    pub fn push_with(&mut self, span: codemap::Span) {
+        self.with_stack.push(span);
     }
 
-    /// Decrease the `with`-stack size of this scope.
+    /// Pop the innermost namespace from the `with`-stack of this scope.
     pub fn pop_with(&mut self) {
-        self.with_stack_size -= 1;
+        self.with_stack.pop();
     }
 
     /// Does this scope currently require dynamic runtime resolution
     /// of identifiers that could not be found?
     pub fn has_with(&self) -> bool {
-        self.with_stack_size > 0
+        !self.with_stack.is_empty()
+    }
+
+    /// Spans of the namespaces of all `with`-expressions in this
+    /// scope, innermost first.
+    pub fn with_spans(&self) -> impl Iterator<Item = codemap::Span> + '_ {
+        self.with_stack.iter().rev().copied()
     }
 
     /// Resolve the stack index of a statically known local.
@@ -239,6 +251,7 @@ impl Scope {
             span: Some(span),
             name: LocalName::Phantom,
             depth: self.scope_depth,
+            initialised_at: None,
             needs_finaliser: false,
             must_thunk: false,
             used: true,
@@ -262,6 +275,7 @@ impl Scope {
             span: Some(span),
             depth: self.scope_depth,
             initialised: false,
+            initialised_at: None,
             needs_finaliser: false,
             must_thunk: false,
             used: false,
@@ -289,6 +303,7 @@ impl Scope {
             span: None,
             depth: 0,
             initialised: true,
+            initialised_at: None,
             used: false,
             needs_finaliser: false,
             must_thunk: false,
@@ -299,9 +314,11 @@ impl Scope {
         idx
     }
 
-    /// Mark local as initialised after compiling its expression.
-    pub fn mark_initialised(&mut self, idx: LocalIdx) {
+    /// Mark local as initialised after compiling its expression, with
+    /// its value available from the operation at `code_idx` onwards.
+    pub fn mark_initialised(&mut self, idx: LocalIdx, code_idx: usize) {
         self.locals[idx.0].initialised = true;
+        self.locals[idx.0].initialised_at = Some(code_idx);
     }
 
     pub fn mark_used(&mut self, idx: LocalIdx) {
@@ -342,19 +359,26 @@ impl Scope {
     ///
     /// Returns the count of locals that were dropped while marked as
     /// initialised (used by the compiler to determine whether to emit
-    /// scope cleanup operations), as well as the spans of the
-    /// definitions of unused locals (used by the compiler to emit
-    /// unused binding warnings).
-    pub fn end_scope(&mut self) -> (usize, Vec<codemap::Span>) {
+    /// scope cleanup operations), the spans of the definitions of
+    /// unused locals (used by the compiler to emit unused binding
+    /// warnings), as well as the name, stack index and initialisation
+    /// point of all dropped named locals (used for debug information).
+    pub fn end_scope(&mut self) -> (usize, Vec<codemap::Span>, Vec<(SmolStr, StackIdx, usize)>) {
         debug_assert!(self.scope_depth != 0, "can not end top scope");
 
         let mut pops = 0;
         let mut unused_spans = vec![];
+        let mut dropped = vec![];
 
         // TL;DR - iterate from the back while things belonging to the
         // ended scope still exist.
         while self.locals.last().unwrap().depth == self.scope_depth {
+            let stack_idx = self.stack_index(LocalIdx(self.locals.len() - 1));
             if let Some(local) = self.locals.pop() {
+                if let (Some(name), Some(code_idx)) = (local.name(), local.initialised_at) {
+                    dropped.push((name, stack_idx, code_idx));
+                }
+
                 // pop the local from the stack if it was actually
                 // initialised
                 if local.initialised {
@@ -385,7 +409,7 @@ impl Scope {
 
         self.scope_depth -= 1;
 
-        (pops, unused_spans)
+        (pops, unused_spans, dropped)
     }
 
     /// Access the current scope depth.
@@ -0,0 +1,683 @@
+//! This module implements the runtime side of an interactive debugger
+//! for Nix evaluation.
+//!
+//! [`DebuggingObserver`] is a [`RuntimeObserver`] which follows the
+//! call frames of the VM, and pauses evaluation whenever it reaches a
+//! breakpoint or finishes a step. While paused, control is handed to a
+//! [`DebugFrontend`] (such as the Debug Adapter Protocol server of the
+//! Tvix CLI), which can inspect the paused call stack through
+//! [`PausedState`] before deciding how to resume.
+//!
+//! Execution is tracked at the granularity of source lines: the
+//! debugger only considers pausing when a frame reaches a line it was
+//! not on before. Forcing a thunk or calling a function enters a new
+//! frame, which is what "stepping in" steps into.
+
+use codemap::Span;
+use rustc_hash::FxHashMap;
+use smol_str::SmolStr;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use crate::compiler::GlobalsMap;
+use crate::observer::RuntimeObserver;
+use crate::opcode::{CodeIdx, Op};
+use crate::upvalues::Upvalues;
+use crate::value::Lambda;
+use crate::{EvalIO, Evaluation, SourceCode, Value};
+
+/// Reason for which evaluation was paused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// Evaluation reached its first line, and the debugger was
+    /// configured to stop on entry.
+    Entry,
+
+    /// Evaluation reached a line with a breakpoint.
+    Breakpoint,
+
+    /// A step requested by the frontend finished.
+    Step,
+
+    /// The frontend requested to pause while evaluation was running.
+    Pause,
+}
+
+/// How to resume evaluation after it was paused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResumeMode {
+    /// Run until the next breakpoint.
+    Continue,
+
+    /// Pause at the next line reached, including lines in called
+    /// functions and forced thunks.
+    StepIn,
+
+    /// Pause at the next line reached in the current frame, or in one
+    /// of the frames below it.
+    StepOver,
+
+    /// Pause at the next line reached in one of the frames below the
+    /// current frame.
+    StepOut,
+}
+
+/// Source lines (1-based) at which evaluation should pause, by the
+/// name of the file in the [`SourceCode`] map.
+#[derive(Debug, Default)]
+pub struct Breakpoints(FxHashMap<String, BTreeSet<usize>>);
+
+impl Breakpoints {
+    /// Replace all breakpoints in the given file.
+    pub fn set<I: IntoIterator<Item = usize>>(&mut self, file: String, lines: I) {
+        let lines: BTreeSet<usize> = lines.into_iter().collect();
+        if lines.is_empty() {
+            self.0.remove(&file);
+        } else {
+            self.0.insert(file, lines);
+        }
+    }
+
+    fn contains(&self, file: &str, line: usize) -> bool {
+        self.0
+            .get(file)
+            .map(|lines| lines.contains(&line))
+            .unwrap_or(false)
+    }
+}
+
+/// Implemented by the user-facing part of a debugger.
+pub trait DebugFrontend {
+    /// Called whenever evaluation reaches a new line. Returning `true`
+    /// pauses evaluation at that line.
+    ///
+    /// Frontends use this to process requests (e.g. updated
+    /// breakpoints) arriving while evaluation is running.
+    fn poll(&mut self, _breakpoints: &mut Breakpoints) -> bool {
+        false
+    }
+
+    /// Called when evaluation is paused. Blocks until evaluation should
+    /// resume, and returns how to do so.
+    fn paused(&mut self, reason: StopReason, state: &mut PausedState) -> ResumeMode;
+}
+
+/// A frame on the call stack, as presented to a [`DebugFrontend`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Name of the lambda or generator executing in this frame.
+    pub name: String,
+
+    /// File which the frame is currently executing code from, if any.
+    pub file: Option<String>,
+
+    /// Line (1-based) currently executing in the frame, or 0 if
+    /// unknown.
+    pub line: usize,
+
+    /// Column (1-based) currently executing in the frame, or 0 if
+    /// unknown.
+    pub column: usize,
+}
+
+struct LambdaFrame {
+    lambda: Rc<Lambda>,
+    upvalues: Rc<Upvalues>,
+    stack_offset: usize,
+
+    /// Index of the operation that is executing in this frame.
+    op: usize,
+
+    /// Span of that operation, and the file and line it is on.
+    span: Option<Span>,
+    line: Option<(Span, usize)>,
+
+    /// Whether the frame is calling a function. The VM exits the frame
+    /// of the caller before entering the callee, and resumes it later.
+    calling: bool,
+}
+
+enum Frame {
+    Lambda(LambdaFrame),
+    Generator(String),
+}
+
+/// An observer that implements a debugger, see the module
+/// documentation.
+pub struct DebuggingObserver<F: DebugFrontend> {
+    source: SourceCode,
+    io: Rc<dyn EvalIO>,
+    frontend: F,
+    breakpoints: Breakpoints,
+    globals: Option<Rc<GlobalsMap>>,
+
+    /// Frames currently on the VM's frame stack, indexed by their
+    /// position in it.
+    frames: Vec<Frame>,
+    stop_on_entry: bool,
+    mode: ResumeMode,
+
+    /// Number of frames on the stack when the current step started.
+    step_depth: usize,
+}
+
+impl<F: DebugFrontend> DebuggingObserver<F> {
+    /// Create a debugger for an evaluation using the given source map.
+    /// The IO handle is used when evaluating expressions in paused
+    /// frames.
+    pub fn new(source: SourceCode, io: Rc<dyn EvalIO>, frontend: F) -> Self {
+        Self {
+            source,
+            io,
+            frontend,
+            breakpoints: Breakpoints::default(),
+            globals: None,
+            frames: vec![],
+            stop_on_entry: false,
+            mode: ResumeMode::Continue,
+            step_depth: 0,
+        }
+    }
+
+    /// Pause at the first line of the evaluation.
+    pub fn stop_on_entry(mut self, stop_on_entry: bool) -> Self {
+        self.stop_on_entry = stop_on_entry;
+        self
+    }
+
+    /// Access the breakpoints of this debugger.
+    pub fn breakpoints(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Access the frontend of this debugger.
+    pub fn frontend(&mut self) -> &mut F {
+        &mut self.frontend
+    }
+
+    /// Place the given frame at position `frame_at` of the stack,
+    /// dropping all frames above it. Frames that are resumed after
+    /// being suspended keep their state.
+    fn enter_frame(&mut self, frame_at: usize, frame: Frame) {
+        self.frames.truncate(frame_at + 1);
+
+        let resumed = match (self.frames.get(frame_at), &frame) {
+            (Some(Frame::Lambda(old)), Frame::Lambda(new)) => {
+                Rc::ptr_eq(&old.lambda, &new.lambda)
+                    && Rc::ptr_eq(&old.upvalues, &new.upvalues)
+                    && old.stack_offset == new.stack_offset
+            }
+            (Some(Frame::Generator(old)), Frame::Generator(new)) => old == new,
+            _ => false,
+        };
+
+        if resumed {
+            return;
+        }
+
+        self.frames.truncate(frame_at);
+
+        // Frames below this one which were never entered (which should
+        // not happen) are unknown.
+        while self.frames.len() < frame_at {
+            self.frames.push(Frame::Generator("<unknown>".into()));
+        }
+
+        self.frames.push(frame);
+    }
+
+    /// Determine whether evaluation should pause on reaching a new line
+    /// in the current frame.
+    fn stop_reason(&mut self, file: &str, line: usize) -> Option<StopReason> {
+        if self.stop_on_entry {
+            self.stop_on_entry = false;
+            return Some(StopReason::Entry);
+        }
+
+        if self.frontend.poll(&mut self.breakpoints) {
+            return Some(StopReason::Pause);
+        }
+
+        if self.breakpoints.contains(file, line) {
+            return Some(StopReason::Breakpoint);
+        }
+
+        let depth = self.frames.len();
+        let step_finished = match self.mode {
+            ResumeMode::Continue => false,
+            ResumeMode::StepIn => true,
+            ResumeMode::StepOver => depth <= self.step_depth,
+            ResumeMode::StepOut => depth < self.step_depth,
+        };
+
+        step_finished.then_some(StopReason::Step)
+    }
+}
+
+impl<F: DebugFrontend> RuntimeObserver for DebuggingObserver<F> {
+    fn observe_globals(&mut self, globals: &Rc<GlobalsMap>) {
+        self.globals = Some(globals.clone());
+    }
+
+    fn observe_call_frame_state(
+        &mut self,
+        frame_at: usize,
+        lambda: &Rc<Lambda>,
+        upvalues: &Rc<Upvalues>,
+        stack_offset: usize,
+    ) {
+        let frame = Frame::Lambda(LambdaFrame {
+            lambda: lambda.clone(),
+            upvalues: upvalues.clone(),
+            stack_offset,
+            op: 0,
+            span: None,
+            line: None,
+            calling: false,
+        });
+
+        self.enter_frame(frame_at, frame);
+    }
+
+    fn observe_exit_call_frame(&mut self, frame_at: usize, _: &[Value]) {
+        match self.frames.get(frame_at) {
+            Some(Frame::Lambda(frame)) if frame.calling => self.frames.truncate(frame_at + 1),
+            _ => self.frames.truncate(frame_at),
+        }
+    }
+
+    fn observe_suspend_call_frame(&mut self, frame_at: usize, _: &[Value]) {
+        self.frames.truncate(frame_at + 1);
+    }
+
+    fn observe_enter_generator(&mut self, frame_at: usize, name: &str, _: &[Value]) {
+        self.enter_frame(frame_at, Frame::Generator(name.to_string()));
+    }
+
+    fn observe_exit_generator(&mut self, frame_at: usize, _: &str, _: &[Value]) {
+        self.frames.truncate(frame_at);
+    }
+
+    fn observe_suspend_generator(&mut self, frame_at: usize, _: &str, _: &[Value]) {
+        self.frames.truncate(frame_at + 1);
+    }
+
+    fn observe_execute_op(&mut self, ip: CodeIdx, op: &Op, stack: &[Value]) {
+        let Some(Frame::Lambda(frame)) = self.frames.last_mut() else {
+            return;
+        };
+
+        // The VM reports the instruction pointer *after* reading the
+        // operation.
+        frame.op = ip.0 - 1;
+        frame.calling = *op == Op::Call;
+
+        // Returns carry the span of the whole lambda, which is not a
+        // useful place to stop at.
+        if *op == Op::Return {
+            return;
+        }
+
+        let span = frame.lambda.chunk.get_span(CodeIdx(frame.op));
+        if frame.span == Some(span) {
+            return;
+        }
+        frame.span = Some(span);
+
+        let loc = self.source.codemap().look_up_span(span);
+        let line = (loc.file.span, loc.begin.line);
+        if frame.line == Some(line) {
+            return;
+        }
+        frame.line = Some(line);
+
+        let reason = match self.stop_reason(loc.file.name(), loc.begin.line + 1) {
+            Some(reason) => reason,
+            None => return,
+        };
+
+        let mut state = PausedState {
+            source: &self.source,
+            io: &self.io,
+            globals: self.globals.as_ref(),
+            frames: &self.frames,
+            stack,
+            breakpoints: &mut self.breakpoints,
+        };
+
+        self.mode = self.frontend.paused(reason, &mut state);
+        self.step_depth = self.frames.len();
+    }
+}
+
+/// State of a paused evaluation, as presented to a [`DebugFrontend`].
+///
+/// Frames are identified by their position on the call stack, starting
+/// with 0 for the innermost frame.
+pub struct PausedState<'a> {
+    source: &'a SourceCode,
+    io: &'a Rc<dyn EvalIO>,
+    globals: Option<&'a Rc<GlobalsMap>>,
+    frames: &'a [Frame],
+    stack: &'a [Value],
+    breakpoints: &'a mut Breakpoints,
+}
+
+impl PausedState<'_> {
+    fn frame(&self, frame: usize) -> Option<&Frame> {
+        let idx = self.frames.len().checked_sub(frame + 1)?;
+        self.frames.get(idx)
+    }
+
+    fn lambda_frame(&self, frame: usize) -> Option<&LambdaFrame> {
+        match self.frame(frame)? {
+            Frame::Lambda(frame) => Some(frame),
+            Frame::Generator(_) => None,
+        }
+    }
+
+    /// Access the breakpoints of the debugger, e.g. to update them
+    /// while paused.
+    pub fn breakpoints(&mut self) -> &mut Breakpoints {
+        self.breakpoints
+    }
+
+    /// Return the frames on the call stack, starting with the innermost
+    /// one.
+    pub fn stack_frames(&self) -> Vec<StackFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| match frame {
+                Frame::Generator(name) => StackFrame {
+                    name: name.clone(),
+                    file: None,
+                    line: 0,
+                    column: 0,
+                },
+
+                Frame::Lambda(frame) => {
+                    let span = frame.lambda.chunk.get_span(CodeIdx(frame.op));
+                    let loc = self.source.codemap().look_up_span(span);
+
+                    StackFrame {
+                        name: match &frame.lambda.name {
+                            Some(name) => name.to_string(),
+                            None => "<anonymous>".to_string(),
+                        },
+                        file: Some(loc.file.name().to_string()),
+                        line: loc.begin.line + 1,
+                        column: loc.begin.column + 1,
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Return the named locals in scope at the current position of the
+    /// given frame, in the order in which they were declared.
+    pub fn locals(&self, frame: usize) -> Vec<(SmolStr, Value)> {
+        let Some(frame) = self.lambda_frame(frame) else {
+            return vec![];
+        };
+
+        let mut locals: Vec<_> = frame
+            .lambda
+            .locals
+            .iter()
+            .filter(|local| local.code.contains(&frame.op))
+            .collect();
+        locals.sort_by_key(|local| local.stack_idx.0);
+
+        locals
+            .into_iter()
+            .filter_map(|local| {
+                let value = self.stack.get(frame.stack_offset + local.stack_idx.0)?;
+                is_inspectable(value).then(|| (local.name.clone(), value.clone()))
+            })
+            .collect()
+    }
+
+    /// Return the named upvalues captured by the given frame.
+    pub fn upvalues(&self, frame: usize) -> Vec<(SmolStr, Value)> {
+        let Some(frame) = self.lambda_frame(frame) else {
+            return vec![];
+        };
+
+        frame
+            .lambda
+            .upvalue_names
+            .iter()
+            .zip(frame.upvalues.static_upvalues())
+            .filter_map(|(name, value)| match name {
+                Some(name) if is_inspectable(value) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Evaluate the given Nix expression with the variables visible in
+    /// the given frame in scope.
+    pub fn evaluate(&self, frame: usize, code: &str) -> Result<Value, String> {
+        let globals = self
+            .globals
+            .ok_or_else(|| "evaluation has not started yet".to_string())?;
+
+        // Locals shadow upvalues, and later locals shadow earlier ones.
+        let env: FxHashMap<SmolStr, Value> = self
+            .upvalues(frame)
+            .into_iter()
+            .chain(self.locals(frame))
+            .collect();
+
+        let result = Evaluation::builder(self.io.clone())
+            .with_globals(globals.clone())
+            .with_source_map(self.source.clone())
+            .env(Some(&env))
+            .build()
+            .evaluate(code, None);
+
+        if let Some(err) = result.errors.first() {
+            return Err(err.to_string());
+        }
+
+        result
+            .value
+            .ok_or_else(|| "evaluation did not return a value".to_string())
+    }
+}
+
+/// Internal values that the VM places on the stack or in upvalues are
+/// not useful to users of a debugger.
+fn is_inspectable(value: &Value) -> bool {
+    !matches!(
+        value,
+        Value::AttrNotFound
+            | Value::Blueprint(_)
+            | Value::DeferredUpvalue(_)
+            | Value::UnresolvedPath(_)
+            | Value::FinaliseRequest(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DummyIO;
+
+    const CODE: &str = r#"let
+  double = x:
+    let
+      result = x * 2;
+    in
+    result;
+  y = 21;
+in
+double y
+"#;
+
+    /// A frontend that records every pause, and resumes with the
+    /// given sequence of modes.
+    #[derive(Default)]
+    struct ScriptedFrontend {
+        resume: Vec<ResumeMode>,
+        stops: Vec<(StopReason, StackFrame)>,
+        locals: Vec<Vec<(SmolStr, String)>>,
+        upvalues: Vec<Vec<(SmolStr, String)>>,
+        evaluated: Vec<Result<String, String>>,
+    }
+
+    impl DebugFrontend for ScriptedFrontend {
+        fn paused(&mut self, reason: StopReason, state: &mut PausedState) -> ResumeMode {
+            let frame = state.stack_frames().remove(0);
+            let display = |vars: Vec<(SmolStr, Value)>| {
+                vars.into_iter()
+                    .map(|(name, value)| (name, value.to_string()))
+                    .collect()
+            };
+
+            self.stops.push((reason, frame));
+            self.locals.push(display(state.locals(0)));
+            self.upvalues.push(display(state.upvalues(0)));
+            self.evaluated
+                .push(state.evaluate(0, "y + 1").map(|value| value.to_string()));
+
+            if self.resume.is_empty() {
+                ResumeMode::Continue
+            } else {
+                self.resume.remove(0)
+            }
+        }
+    }
+
+    fn debug(
+        frontend: ScriptedFrontend,
+        breakpoints: &[usize],
+        stop_on_entry: bool,
+    ) -> ScriptedFrontend {
+        let mut eval_builder = Evaluation::builder_pure();
+        let io: Rc<dyn EvalIO> = Rc::new(DummyIO);
+        let mut observer = DebuggingObserver::new(eval_builder.source_map().clone(), io, frontend)
+            .stop_on_entry(stop_on_entry);
+        observer
+            .breakpoints()
+            .set("/test.nix".into(), breakpoints.iter().copied());
+
+        eval_builder.set_runtime_observer(Some(&mut observer));
+        let result = eval_builder
+            .build()
+            .evaluate(CODE, Some("/test.nix".into()));
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        assert_eq!(result.value.unwrap().to_string(), "42");
+
+        observer.frontend
+    }
+
+    #[test]
+    fn breakpoint_locals() {
+        let frontend = debug(ScriptedFrontend::default(), &[6], false);
+
+        assert_eq!(frontend.stops.len(), 1);
+        let (reason, frame) = &frontend.stops[0];
+        assert_eq!(*reason, StopReason::Breakpoint);
+        assert_eq!(frame.name, "double");
+        assert_eq!(frame.file.as_deref(), Some("/test.nix"));
+        assert_eq!(frame.line, 6);
+
+        assert_eq!(
+            frontend.locals[0],
+            vec![
+                ("x".into(), "21".into()),
+                ("result".into(), "<CODE>".into())
+            ]
+        );
+
+        // `y` is not visible in the function.
+        assert!(frontend.evaluated[0].is_err());
+    }
+
+    #[test]
+    fn evaluate_in_scope() {
+        let frontend = debug(ScriptedFrontend::default(), &[9], false);
+
+        assert_eq!(frontend.stops.len(), 1);
+        assert_eq!(frontend.stops[0].1.line, 9);
+        assert!(frontend.locals[0].contains(&("y".into(), "21".into())));
+        assert_eq!(frontend.evaluated[0], Ok("22".to_string()));
+    }
+
+    #[test]
+    fn step_in_and_out() {
+        let frontend = ScriptedFrontend {
+            resume: vec![
+                ResumeMode::StepOver,
+                ResumeMode::StepOver,
+                ResumeMode::StepIn,
+                ResumeMode::StepOut,
+            ],
+            ..Default::default()
+        };
+
+        let frontend = debug(frontend, &[], true);
+        let stops: Vec<_> = frontend
+            .stops
+            .iter()
+            .map(|(reason, frame)| (*reason, frame.name.as_str(), frame.line))
+            .collect();
+
+        // Stepping over the bindings stays in the let-expression,
+        // stepping in at the call enters the function, and stepping out
+        // returns to the let-expression.
+        assert_eq!(
+            stops,
+            vec![
+                (StopReason::Entry, "<anonymous>", 2),
+                (StopReason::Step, "<anonymous>", 7),
+                (StopReason::Step, "<anonymous>", 9),
+                (StopReason::Step, "double", 4),
+                (StopReason::Step, "<anonymous>", 1),
+            ]
+        );
+    }
+
+    /// Compiler observer capturing the compiled top-level lambda.
+    #[derive(Default)]
+    struct ToplevelObserver(Option<Rc<Lambda>>);
+
+    impl crate::observer::CompilerObserver for ToplevelObserver {
+        fn observe_compiled_toplevel(&mut self, lambda: &Rc<Lambda>) {
+            self.0 = Some(lambda.clone());
+        }
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut observer = ToplevelObserver::default();
+        let mut eval_builder = Evaluation::builder_pure();
+        eval_builder.set_compiler_observer(Some(&mut observer));
+        let result = eval_builder
+            .build()
+            .compile_only("let a = 1; b = 2; in (x: a + b + x) 3", None);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+
+        let toplevel = observer.0.expect("toplevel must be compiled");
+        let names: Vec<_> = toplevel.locals.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        let closure = toplevel
+            .chunk
+            .constants
+            .iter()
+            .find_map(|c| match c {
+                Value::Blueprint(lambda) => Some(lambda.clone()),
+                _ => None,
+            })
+            .expect("closure capturing upvalues must be a blueprint");
+
+        assert_eq!(
+            closure.upvalue_names,
+            vec![Some(SmolStr::new("a")), Some(SmolStr::new("b"))]
+        );
+        assert_eq!(closure.locals[0].name, "x");
+    }
+}
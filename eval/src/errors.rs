@@ -49,6 +49,12 @@ pub enum CatchableErrorKind {
     /// Resolving a user-supplied angle brackets path literal failed in some way.
     #[error("Nix path entry could not be resolved: {0}")]
     NixPathResolution(Box<str>),
+
+    /// Realising a store path (e.g. by building the derivation producing it)
+    /// failed. This is raised by [crate::EvalIO] implementations supporting
+    /// import-from-derivation.
+    #[error("{0}")]
+    BuildFailure(Box<str>),
 }
 
 #[derive(thiserror::Error, Clone, Debug)]
@@ -137,7 +143,7 @@ to a missing value in the attribute set(s) included via `with`."#
 
     /// An error occured while executing some native code (e.g. a
     /// builtin), and needs to be chained up.
-    #[error("while evaluating this as native code ({gen_type})")]
+    #[error("{}", native_error_message(gen_type))]
     NativeError {
         gen_type: &'static str,
         err: Box<Error>,
@@ -148,6 +154,12 @@ to a missing value in the attribute set(s) included via `with`."#
     #[error("while evaluating this Nix code")]
     BytecodeError(Box<Error>),
 
+    /// An error occured while evaluating a value wrapped in
+    /// `builtins.addErrorContext`, and needs to be chained up with the
+    /// context message supplied by the user.
+    #[error("{context}")]
+    ErrorContext { context: String, err: Box<Error> },
+
     /// Given type can't be coerced to a string in the respective context
     #[error("cannot ({}) coerce {from} to a string{}", 
         (if .kind.strong { "strongly" } else { "weakly" }),
@@ -275,6 +287,12 @@ to a missing value in the attribute set(s) included via `with`."#
         underlying: Box<ErrorKind>,
     },
 
+    /// Internal variant for `builtins.addErrorContext` messages which have
+    /// not been evaluated yet. The VM replaces it with
+    /// [`ErrorKind::ErrorContext`] before returning the error.
+    #[error("internal ErrorKind::PendingErrorContext variant leaked")]
+    PendingErrorContext { context: Value, err: Box<Error> },
+
     /// Unexpected context string
     #[error("unexpected context string")]
     UnexpectedContext,
@@ -297,7 +315,10 @@ to a missing value in the attribute set(s) included via `with`."#
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match &self.kind {
-            ErrorKind::NativeError { err, .. } | ErrorKind::BytecodeError(err) => err.source(),
+            ErrorKind::NativeError { err, .. }
+            | ErrorKind::BytecodeError(err)
+            | ErrorKind::ErrorContext { err, .. }
+            | ErrorKind::PendingErrorContext { err, .. } => err.source(),
             ErrorKind::ParseErrors(err) => err.first().map(|e| e as &dyn error::Error),
             ErrorKind::ParseIntError(err) => Some(err),
             ErrorKind::ImportParseError { errors, .. } => {
@@ -621,16 +642,81 @@ fn spans_for_parse_errors(file: &File, errors: &[rnix::parser::ParseError]) -> V
 }
 
 impl Error {
+    /// Render a fancy, human-readable output of this error. Only the
+    /// innermost error of an evaluation trace is rendered, see
+    /// [`Error::fancy_format_str_with_trace`] for the full trace.
     pub fn fancy_format_str(&self) -> String {
         let mut out = vec![];
-        Emitter::vec(&mut out, Some(&*self.source.codemap())).emit(&self.diagnostics());
+        Emitter::vec(&mut out, Some(&*self.source.codemap())).emit(&self.diagnostics(false));
+        String::from_utf8_lossy(&out).to_string()
+    }
+
+    /// Render a fancy, human-readable output of this error including
+    /// the evaluation trace leading up to it (the call sites, forced
+    /// thunks and `builtins.addErrorContext` messages on the way).
+    pub fn fancy_format_str_with_trace(&self) -> String {
+        let mut out = vec![];
+        Emitter::vec(&mut out, Some(&*self.source.codemap())).emit(&self.diagnostics(true));
         String::from_utf8_lossy(&out).to_string()
     }
 
     /// Render a fancy, human-readable output of this error and print
     /// it to stderr.
     pub fn fancy_format_stderr(&self) {
-        Emitter::stderr(ColorConfig::Auto, Some(&*self.source.codemap())).emit(&self.diagnostics());
+        Emitter::stderr(ColorConfig::Auto, Some(&*self.source.codemap()))
+            .emit(&self.diagnostics(false));
+    }
+
+    /// Render a fancy, human-readable output of this error including
+    /// its evaluation trace and print it to stderr.
+    pub fn fancy_format_stderr_with_trace(&self) {
+        Emitter::stderr(ColorConfig::Auto, Some(&*self.source.codemap()))
+            .emit(&self.diagnostics(true));
+    }
+
+    /// Render a machine-readable (JSON) representation of this error,
+    /// for consumption by editors, CI systems and similar tools.
+    ///
+    /// The object describes the innermost error (with its code, message
+    /// and spans). Errors nested in it (e.g. compiler errors in an
+    /// imported file) are listed in `causes`, and the evaluation trace
+    /// leading up to it in `trace`, starting at the outermost frame.
+    pub fn to_json(&self) -> serde_json::Value {
+        let codemap = self.source.codemap();
+        let mut trace = vec![];
+        let mut diagnostics = self.diagnostics(true).into_iter();
+
+        let primary = loop {
+            match diagnostics.next() {
+                Some(diagnostic) if matches!(diagnostic.level, Level::Note) => {
+                    trace.push(diagnostic_to_json(&codemap, &diagnostic))
+                }
+                Some(diagnostic) => break diagnostic,
+                None => unreachable!("errors always have a primary diagnostic"),
+            }
+        };
+
+        let mut json = diagnostic_to_json(&codemap, &primary);
+        json["causes"] = diagnostics
+            .map(|diagnostic| diagnostic_to_json(&codemap, &diagnostic))
+            .collect();
+        json["trace"] = trace.into();
+        json
+    }
+
+    /// Return the innermost error of an evaluation trace, i.e. the
+    /// error that actually occured.
+    fn innermost(&self) -> &Error {
+        let mut error = self;
+
+        while let ErrorKind::NativeError { err, .. }
+        | ErrorKind::BytecodeError(err)
+        | ErrorKind::ErrorContext { err, .. } = &error.kind
+        {
+            error = err;
+        }
+
+        error
     }
 
     /// Create the optional span label displayed as an annotation on
@@ -662,6 +748,7 @@ impl Error {
             | ErrorKind::ParseErrors(_)
             | ErrorKind::NativeError { .. }
             | ErrorKind::BytecodeError(_)
+            | ErrorKind::ErrorContext { .. }
             | ErrorKind::NotCoercibleToString { .. }
             | ErrorKind::NotAnAbsolutePath(_)
             | ErrorKind::ParseIntError(_)
@@ -678,6 +765,7 @@ impl Error {
             | ErrorKind::TvixBug { .. }
             | ErrorKind::NotImplemented(_)
             | ErrorKind::WithContext { .. }
+            | ErrorKind::PendingErrorContext { .. }
             | ErrorKind::UnknownHashType(_)
             | ErrorKind::InvalidHash(_)
             | ErrorKind::CatchableError(_) => return None,
@@ -688,7 +776,7 @@ impl Error {
 
     /// Return the unique error code for this variant which can be
     /// used to refer users to documentation.
-    fn code(&self) -> &'static str {
+    pub fn code(&self) -> &'static str {
         match self.kind {
             ErrorKind::CatchableError(CatchableErrorKind::Throw(_)) => "E001",
             ErrorKind::Abort(_) => "E002",
@@ -727,6 +815,7 @@ impl Error {
             ErrorKind::UnknownHashType(_) => "E039",
             ErrorKind::UnexpectedArgumentBuiltin { .. } => "E040",
             ErrorKind::InvalidHash(_) => "E041",
+            ErrorKind::CatchableError(CatchableErrorKind::BuildFailure(_)) => "E042",
 
             // Special error code for errors from other Tvix
             // components. We may want to introduce a code namespacing
@@ -743,13 +832,17 @@ impl Error {
 
             // Chained errors should yield the code of the innermost
             // error.
-            ErrorKind::NativeError { ref err, .. } | ErrorKind::BytecodeError(ref err) => {
-                err.code()
-            }
+            ErrorKind::NativeError { ref err, .. }
+            | ErrorKind::BytecodeError(ref err)
+            | ErrorKind::ErrorContext { ref err, .. } => err.code(),
 
             ErrorKind::WithContext { .. } => {
                 panic!("internal ErrorKind::WithContext variant leaked")
             }
+
+            ErrorKind::PendingErrorContext { .. } => {
+                panic!("internal ErrorKind::PendingErrorContext variant leaked")
+            }
         }
     }
 
@@ -838,6 +931,15 @@ impl Error {
         spans
     }
 
+    /// Return the source locations of this error together with their
+    /// (optional) descriptions, e.g. for reporting errors in editors.
+    pub fn labelled_spans(&self) -> Vec<(Span, Option<String>)> {
+        self.spans()
+            .into_iter()
+            .map(|label| (label.span, label.label))
+            .collect()
+    }
+
     /// Create the primary diagnostic for a given error.
     fn diagnostic(&self) -> Diagnostic {
         Diagnostic {
@@ -849,8 +951,9 @@ impl Error {
     }
 
     /// Return the primary diagnostic and all further associated diagnostics (if
-    /// any) of an error.
-    fn diagnostics(&self) -> Vec<Diagnostic> {
+    /// any) of an error. The evaluation trace of chained errors is only
+    /// included if `show_trace` is set.
+    fn diagnostics(&self, show_trace: bool) -> Vec<Diagnostic> {
         match &self.kind {
             ErrorKind::ImportCompilerError { errors, .. } => {
                 let mut out = vec![self.diagnostic()];
@@ -858,7 +961,15 @@ impl Error {
                 out
             }
 
-            // When encountering either of these error kinds, we are dealing
+            ErrorKind::NativeError { .. }
+            | ErrorKind::BytecodeError(_)
+            | ErrorKind::ErrorContext { .. }
+                if !show_trace =>
+            {
+                self.innermost().diagnostics(false)
+            }
+
+            // When encountering any of these error kinds, we are dealing
             // with the top of an error chain.
             //
             // An error chain creates a list of diagnostics which provide trace
@@ -866,7 +977,9 @@ impl Error {
             //
             // We don't know how deep this chain is, so we avoid recursing in
             // this function while unrolling the chain.
-            ErrorKind::NativeError { err: next, .. } | ErrorKind::BytecodeError(next) => {
+            ErrorKind::NativeError { err: next, .. }
+            | ErrorKind::BytecodeError(next)
+            | ErrorKind::ErrorContext { err: next, .. } => {
                 // Accumulated diagnostics to return.
                 let mut diagnostics: Vec<Diagnostic> = vec![];
 
@@ -902,12 +1015,13 @@ impl Error {
 
                     match next.kind {
                         ErrorKind::NativeError { err: inner, .. }
-                        | ErrorKind::BytecodeError(inner) => {
+                        | ErrorKind::BytecodeError(inner)
+                        | ErrorKind::ErrorContext { err: inner, .. } => {
                             next = *inner;
                             continue;
                         }
                         _ => {
-                            diagnostics.extend(next.diagnostics());
+                            diagnostics.extend(next.diagnostics(true));
                             break;
                         }
                     }
@@ -921,6 +1035,53 @@ impl Error {
     }
 }
 
+/// Convert a diagnostic to its machine-readable (JSON) representation,
+/// with 1-indexed line and column numbers.
+pub(crate) fn diagnostic_to_json(
+    codemap: &codemap::CodeMap,
+    diagnostic: &Diagnostic,
+) -> serde_json::Value {
+    let level = match diagnostic.level {
+        Level::Warning => "warning",
+        Level::Note => "note",
+        Level::Help => "help",
+        _ => "error",
+    };
+
+    let spans: Vec<serde_json::Value> = diagnostic
+        .spans
+        .iter()
+        .map(|label| {
+            let loc = codemap.look_up_span(label.span);
+            serde_json::json!({
+                "file": loc.file.name(),
+                "start": { "line": loc.begin.line + 1, "column": loc.begin.column + 1 },
+                "end": { "line": loc.end.line + 1, "column": loc.end.column + 1 },
+                "label": label.label,
+                "primary": matches!(label.style, SpanStyle::Primary),
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "level": level,
+        "code": diagnostic.code,
+        "message": diagnostic.message,
+        "spans": spans,
+    })
+}
+
+/// Describe what a native code frame (i.e. a generator) of the given type
+/// was doing when an error occured in it, for display in traces.
+fn native_error_message(gen_type: &str) -> String {
+    match gen_type {
+        "force" => "while forcing this value".into(),
+        "deep_force" | "final_deep_force" => "while deeply forcing this value".into(),
+        "__functor call" => "while calling the `__functor` of this attribute set".into(),
+        _ => format!("while evaluating this as native code ({gen_type})"),
+    }
+}
+
 // Check if this error is in a different span from its immediate ancestor.
 fn is_new_span(this_span: Span, parent: Option<&SpanLabel>) -> bool {
     match parent {
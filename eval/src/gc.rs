@@ -0,0 +1,370 @@
+//! This module implements a tracing cycle collector for Nix values.
+//!
+//! Values are reference-counted, which frees most of them as soon as
+//! they become unreachable. Reference cycles however (created for
+//! example by recursive attribute sets, or `let`-bindings referring
+//! to each other) keep themselves alive forever. Every such cycle
+//! contains at least one [`Thunk`], as thunks are the only values
+//! with interior mutability.
+//!
+//! With the `gc` feature enabled, all thunks are registered with the
+//! collector when they are created. A collection then works similar
+//! to the cycle collector in CPython:
+//!
+//! 1. Starting from every live thunk, the heap objects (thunks,
+//!    closures, upvalues, lambdas, attribute sets and lists)
+//!    reachable from it are traced, counting the references between
+//!    them.
+//! 2. Objects with more strong references than were found while
+//!    tracing are referenced from outside of the traced heap (e.g. by
+//!    the VM stack, or by values held by the caller). They are the
+//!    roots, and everything reachable from them is kept alive.
+//! 3. All remaining thunks are only kept alive by reference cycles.
+//!    Their contents are dropped, which breaks the cycles and lets
+//!    reference counting free the rest.
+//!
+//! Anything the collector can not look into (such as the closures of
+//! native thunks or builtins) is treated conservatively, i.e. objects
+//! referenced from there are considered to be roots.
+//!
+//! Collections run automatically whenever the number of tracked
+//! thunks has doubled since the last collection, and can be triggered
+//! explicitly using [`collect`], e.g. by long-running evaluators in
+//! between evaluations. Without the `gc` feature, no thunks are
+//! tracked and [`collect`] does nothing.
+//!
+//! The collector only holds weak references to the thunks it tracks,
+//! so it does not keep their values alive. A weak reference does
+//! however keep the allocation of the thunk itself around, so thunks
+//! freed by reference counting only give their memory back at the
+//! next collection, which drops the registry entries of dead thunks.
+//! As no automatic collection runs before 100,000 thunks are tracked
+//! (and the threshold doubles with the number of surviving thunks
+//! afterwards), up to that many dead thunk allocations may be
+//! retained.
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::value::{Closure, Lambda, Thunk, WeakThunk};
+use crate::Value;
+
+/// Identity of a reference-counted allocation, used to identify
+/// objects in the traced heap.
+pub(crate) type ObjectId = *const ();
+
+/// Number of tracked thunks below which no automatic collection is
+/// triggered.
+#[cfg(not(test))]
+const MIN_COLLECTION_THRESHOLD: usize = 100_000;
+
+/// In tests, collections are triggered early, so they also run in the
+/// middle of the evaluations in the test suite.
+#[cfg(test)]
+const MIN_COLLECTION_THRESHOLD: usize = 64;
+
+/// Statistics about a single collection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollectionStats {
+    /// Number of live thunks at the start of the collection.
+    pub tracked: usize,
+
+    /// Number of thunks that were only kept alive by reference cycles,
+    /// and have been collected.
+    pub collected: usize,
+}
+
+/// Thunks registered with the collector, and the number of tracked
+/// thunks at which the next automatic collection is triggered.
+///
+/// Entries of thunks that have been freed are only removed by the next
+/// collection, and keep the thunk's allocation alive until then.
+struct Heap {
+    thunks: Vec<WeakThunk>,
+    threshold: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = const {
+        RefCell::new(Heap {
+            thunks: Vec::new(),
+            threshold: MIN_COLLECTION_THRESHOLD,
+        })
+    };
+}
+
+/// Register a newly created thunk with the collector.
+#[cfg_attr(not(feature = "gc"), allow(dead_code))]
+pub(crate) fn track(thunk: &Thunk) {
+    let should_collect = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.thunks.push(thunk.downgrade());
+        heap.thunks.len() >= heap.threshold
+    });
+
+    if should_collect {
+        collect();
+    }
+}
+
+/// Returns the number of thunks currently registered with the
+/// collector. This includes thunks that were freed since the last
+/// collection.
+pub fn tracked_thunks() -> usize {
+    HEAP.with(|heap| heap.borrow().thunks.len())
+}
+
+/// Run a collection on the current thread, freeing all values that
+/// are only kept alive by reference cycles.
+pub fn collect() -> CollectionStats {
+    // Every live thunk is kept alive by one additional strong
+    // reference in this vector until the collection is done.
+    let thunks: Vec<Thunk> = HEAP.with(|heap| {
+        heap.borrow_mut()
+            .thunks
+            .drain(..)
+            .filter_map(|thunk| thunk.upgrade())
+            .collect()
+    });
+
+    let mut tracer = Tracer::default();
+    for thunk in &thunks {
+        tracer.objects.insert(
+            thunk.gc_id(),
+            Object {
+                strong: thunk.strong_count() - 1,
+                internal: 0,
+                edges: vec![],
+            },
+        );
+    }
+
+    // Thunks whose contents could not be traced need to be kept, as
+    // it is unknown what they refer to.
+    let mut roots = vec![];
+    for thunk in &thunks {
+        tracer.current = thunk.gc_id();
+        if !thunk.trace_contents(&mut tracer) {
+            roots.push(thunk.gc_id());
+        }
+    }
+
+    roots.extend(
+        tracer
+            .objects
+            .iter()
+            .filter(|(_, object)| object.strong > object.internal)
+            .map(|(id, _)| *id),
+    );
+
+    let mut reachable: FxHashSet<ObjectId> = FxHashSet::default();
+    while let Some(id) = roots.pop() {
+        if reachable.insert(id) {
+            if let Some(object) = tracer.objects.get(&id) {
+                roots.extend(object.edges.iter().copied());
+            }
+        }
+    }
+
+    let mut survivors = Vec::with_capacity(reachable.len());
+    let mut stats = CollectionStats {
+        tracked: thunks.len(),
+        collected: 0,
+    };
+
+    for thunk in &thunks {
+        if !reachable.contains(&thunk.gc_id()) && thunk.clear() {
+            stats.collected += 1;
+        } else {
+            survivors.push(thunk.downgrade());
+        }
+    }
+
+    // Dropping the last references to the cleared thunks frees them.
+    drop(tracer);
+    drop(thunks);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.threshold = MIN_COLLECTION_THRESHOLD.max(survivors.len() * 2);
+        heap.thunks.append(&mut survivors);
+    });
+
+    stats
+}
+
+/// Information about a traced heap object.
+struct Object {
+    /// Number of strong references to the object, not counting the
+    /// ones held by the collector itself.
+    strong: usize,
+
+    /// Number of references to the object found while tracing.
+    internal: usize,
+
+    /// Objects referenced by this object.
+    edges: Vec<ObjectId>,
+}
+
+/// Records the references between the heap objects reachable from the
+/// tracked thunks.
+pub(crate) struct Tracer {
+    objects: FxHashMap<ObjectId, Object>,
+
+    /// The object whose references are currently being traced.
+    current: ObjectId,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer {
+            objects: FxHashMap::default(),
+            current: std::ptr::null(),
+        }
+    }
+}
+
+impl Tracer {
+    /// Record a reference to the object behind the given [`Rc`], and
+    /// trace its references if it has not been seen before.
+    pub(crate) fn visit_rc<T: Trace>(&mut self, rc: &Rc<T>) {
+        let id = Rc::as_ptr(rc) as ObjectId;
+        if self.edge(id, Rc::strong_count(rc)) {
+            let parent = std::mem::replace(&mut self.current, id);
+            (**rc).trace(self);
+            self.current = parent;
+        }
+    }
+
+    /// Record a reference to a thunk. The contents of thunks are traced
+    /// separately, as every tracked thunk is traced on its own.
+    pub(crate) fn visit_thunk(&mut self, id: ObjectId, strong: usize) {
+        self.edge(id, strong);
+    }
+
+    /// Record a reference from the current object to the given one.
+    /// Returns true if the object was seen for the first time.
+    fn edge(&mut self, id: ObjectId, strong: usize) -> bool {
+        let mut new = false;
+        self.objects
+            .entry(id)
+            .or_insert_with(|| {
+                new = true;
+                Object {
+                    strong,
+                    internal: 0,
+                    edges: vec![],
+                }
+            })
+            .internal += 1;
+
+        if let Some(current) = self.objects.get_mut(&self.current) {
+            current.edges.push(id);
+        }
+
+        new
+    }
+}
+
+/// Implemented by all types that can hold strong references to other
+/// heap objects, to make these references visible to the collector.
+pub(crate) trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+impl Trace for Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Attrs(attrs) => attrs.trace(tracer),
+            Value::List(list) => list.trace(tracer),
+            Value::Closure(closure) => tracer.visit_rc(closure),
+            Value::Builtin(builtin) => builtin.trace(tracer),
+            Value::Thunk(thunk) => thunk.trace(tracer),
+            Value::Blueprint(lambda) => tracer.visit_rc(lambda),
+
+            Value::Null
+            | Value::Bool(_)
+            | Value::Integer(_)
+            | Value::Float(_)
+            | Value::String(_)
+            | Value::Path(_)
+            | Value::AttrNotFound
+            | Value::DeferredUpvalue(_)
+            | Value::UnresolvedPath(_)
+            | Value::FinaliseRequest(_)
+            | Value::Catchable(_) => {}
+        }
+    }
+}
+
+impl Trace for Vec<Value> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit_rc(&self.lambda);
+        tracer.visit_rc(&self.upvalues);
+    }
+}
+
+impl Trace for Lambda {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.chunk.constants.trace(tracer);
+    }
+}
+
+#[cfg(all(test, feature = "gc"))]
+mod tests {
+    use super::collect;
+    use crate::{EvalMode, Evaluation};
+
+    #[test]
+    fn collects_cycles() {
+        let result = Evaluation::builder_pure()
+            .build()
+            .evaluate("let x = { inherit x; y = 42; }; in x.x.x.y", None);
+        assert_eq!("42", result.value.as_ref().unwrap().to_string());
+        drop(result);
+
+        let stats = collect();
+        assert!(stats.collected > 0, "stats = {stats:?}");
+        assert_eq!(0, collect().collected, "nothing left to collect");
+    }
+
+    #[test]
+    fn collects_during_evaluation() {
+        // Creates many more thunks than the (test) collection threshold,
+        // so collections are triggered while the evaluation is running.
+        let result = Evaluation::builder_pure().build().evaluate(
+            r#"
+              builtins.foldl'
+                (acc: i: acc + (let x = { inherit x; v = i; }; in x.x.v))
+                0
+                (builtins.genList (i: i) 1000)
+            "#,
+            None,
+        );
+        assert!(result.errors.is_empty(), "errors: {:?}", result.errors);
+        assert_eq!("499500", result.value.as_ref().unwrap().to_string());
+    }
+
+    #[test]
+    fn keeps_reachable_cycles() {
+        let value = Evaluation::builder_pure()
+            .mode(EvalMode::Strict)
+            .build()
+            .evaluate("let x = { inherit x; y = 42; }; in x", None)
+            .value
+            .unwrap();
+        let before = value.to_string();
+
+        collect();
+        assert_eq!(before, value.to_string());
+    }
+}
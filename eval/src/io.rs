@@ -79,6 +79,11 @@ pub trait EvalIO {
     fn path_exists(&self, path: &Path) -> io::Result<bool>;
 
     /// Open the file at the specified path to a `io::Read`.
+    ///
+    /// If the returned [io::Error] wraps a [crate::CatchableErrorKind], the
+    /// error is surfaced as a catchable error to the Nix code (e.g. for failed
+    /// builds when doing import-from-derivation), instead of aborting
+    /// evaluation.
     fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read>>;
 
     /// Return the [FileType] of the given path, or an error if it doesn't
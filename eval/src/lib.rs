@@ -13,9 +13,12 @@
 //! for controlling how they work.
 
 pub mod builtins;
+mod bytecode_cache;
 mod chunk;
 mod compiler;
+pub mod debugger;
 mod errors;
+pub mod gc;
 mod io;
 pub mod observer;
 mod opcode;
@@ -47,6 +50,7 @@ use crate::value::Lambda;
 use crate::vm::run_lambda;
 
 // Re-export the public interface used by other crates.
+pub use crate::bytecode_cache::BytecodeCache;
 pub use crate::compiler::{compile, prepare_globals, CompilationOutput, GlobalsMap};
 pub use crate::errors::{AddContext, CatchableErrorKind, Error, ErrorKind, EvalResult};
 pub use crate::io::{DummyIO, EvalIO, FileType};
@@ -91,6 +95,7 @@ pub struct EvaluationBuilder<'co, 'ro, 'env, IO> {
     enable_import: bool,
     mode: EvalMode,
     nix_path: Option<String>,
+    bytecode_cache: Option<BytecodeCache>,
     compiler_observer: Option<&'co mut dyn CompilerObserver>,
     runtime_observer: Option<&'ro mut dyn RuntimeObserver>,
 }
@@ -104,6 +109,7 @@ where
     /// This:
     ///
     /// - Adds a `"storeDir"` builtin containing the store directory of the configured IO handle
+    /// - Adds a `"nixPath"` builtin containing the configured Nix search path
     /// - Sets up globals based on the configured builtins
     /// - Copies all other configured fields to the [`Evaluation`]
     pub fn build(self) -> Evaluation<'co, 'ro, 'env, IO> {
@@ -120,11 +126,20 @@ where
                     builtins.push(("storeDir", store_dir.into()));
                 }
 
+                // Expose the configured search path as the nixPath builtin.
+                let nix_path: nix_search_path::NixSearchPath = self
+                    .nix_path
+                    .as_deref()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_default();
+                builtins.push(("nixPath", nix_path.to_value()));
+
                 crate::compiler::prepare_globals(
                     builtins,
                     src_builtins,
                     source_map.clone(),
                     self.enable_import,
+                    self.bytecode_cache,
                 )
             }
         };
@@ -160,6 +175,7 @@ impl<'co, 'ro, 'env, IO> EvaluationBuilder<'co, 'ro, 'env, IO> {
             env: None,
             mode: Default::default(),
             nix_path: None,
+            bytecode_cache: None,
             compiler_observer: None,
             runtime_observer: None,
         }
@@ -174,6 +190,7 @@ impl<'co, 'ro, 'env, IO> EvaluationBuilder<'co, 'ro, 'env, IO> {
             enable_import: self.enable_import,
             mode: self.mode,
             nix_path: self.nix_path,
+            bytecode_cache: self.bytecode_cache,
             compiler_observer: self.compiler_observer,
             runtime_observer: self.runtime_observer,
         }
@@ -264,6 +281,19 @@ impl<'co, 'ro, 'env, IO> EvaluationBuilder<'co, 'ro, 'env, IO> {
         Self { env, ..self }
     }
 
+    /// Cache the bytecode of files compiled by `import` in the given
+    /// [`BytecodeCache`], and load it from there on subsequent imports
+    /// of the same files.
+    ///
+    /// Has no effect if this evaluation builder has had globals set via
+    /// [`with_globals`], as the cache is configured along with them.
+    pub fn bytecode_cache(self, bytecode_cache: Option<BytecodeCache>) -> Self {
+        Self {
+            bytecode_cache,
+            ..self
+        }
+    }
+
     pub fn compiler_observer(
         self,
         compiler_observer: Option<&'co mut dyn CompilerObserver>,
@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use crate::errors::{CatchableErrorKind, ErrorKind};
-use crate::EvalIO;
+use crate::{EvalIO, NixAttrs, NixList, Value};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum NixSearchPathEntry {
@@ -72,16 +72,8 @@ impl NixSearchPathEntry {
     where
         IO: AsRef<dyn EvalIO>,
     {
-        let path = match self {
-            NixSearchPathEntry::Path(parent) => canonicalise(parent.join(lookup_path))?,
-
-            NixSearchPathEntry::Prefix { prefix, path } => {
-                if let Ok(child_path) = lookup_path.strip_prefix(prefix) {
-                    canonicalise(path.join(child_path))?
-                } else {
-                    return Ok(None);
-                }
-            }
+        let Some(path) = self.candidate(lookup_path)? else {
+            return Ok(None);
         };
 
         if io.as_ref().path_exists(&path).map_err(|e| ErrorKind::IO {
@@ -95,6 +87,52 @@ impl NixSearchPathEntry {
     }
 }
 
+impl NixSearchPathEntry {
+    /// Construct an entry from the `prefix` and `path` fields used in the
+    /// Nix representation of search paths (see `builtins.nixPath`), where an
+    /// empty prefix denotes a bare path.
+    fn from_prefix_and_path(prefix: &str, path: PathBuf) -> Self {
+        if prefix.is_empty() {
+            NixSearchPathEntry::Path(path)
+        } else {
+            NixSearchPathEntry::Prefix {
+                prefix: prefix.into(),
+                path,
+            }
+        }
+    }
+
+    /// Determine the path the given lookup path would resolve to under this
+    /// entry, without checking whether it exists.
+    fn candidate(&self, lookup_path: &Path) -> Result<Option<PathBuf>, ErrorKind> {
+        match self {
+            NixSearchPathEntry::Path(parent) => Ok(Some(canonicalise(parent.join(lookup_path))?)),
+
+            NixSearchPathEntry::Prefix { prefix, path } => {
+                if let Ok(child_path) = lookup_path.strip_prefix(prefix) {
+                    Ok(Some(canonicalise(path.join(child_path))?))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Returns the Nix representation of this entry, an attribute set with
+    /// `prefix` and `path` fields.
+    fn to_value(&self) -> Value {
+        let (prefix, path) = match self {
+            NixSearchPathEntry::Path(path) => (Path::new(""), path),
+            NixSearchPathEntry::Prefix { prefix, path } => (prefix.as_path(), path),
+        };
+
+        Value::attrs(NixAttrs::from_iter([
+            ("path", Value::from(path.to_string_lossy().as_ref())),
+            ("prefix", Value::from(prefix.to_string_lossy().as_ref())),
+        ]))
+    }
+}
+
 impl FromStr for NixSearchPathEntry {
     type Err = Infallible;
 
@@ -148,6 +186,43 @@ impl NixSearchPath {
     }
 }
 
+impl NixSearchPath {
+    /// Construct a [`NixSearchPath`] from `(prefix, path)` pairs, as found in
+    /// the Nix representation of search paths.
+    pub(crate) fn from_prefixes_and_paths<I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (String, PathBuf)>,
+    {
+        NixSearchPath {
+            entries: entries
+                .into_iter()
+                .map(|(prefix, path)| NixSearchPathEntry::from_prefix_and_path(&prefix, path))
+                .collect(),
+        }
+    }
+
+    /// Returns all paths the given `<...>`-style path could resolve to, in
+    /// the order in which they should be checked for existence.
+    pub(crate) fn candidates<P: AsRef<Path>>(&self, path: P) -> Result<Vec<PathBuf>, ErrorKind> {
+        let mut candidates = vec![];
+        for entry in &self.entries {
+            if let Some(candidate) = entry.candidate(path.as_ref())? {
+                candidates.push(candidate);
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    /// Returns the Nix representation of this search path, as exposed in
+    /// `builtins.nixPath`: a list of attribute sets with `prefix` and `path`
+    /// fields.
+    pub(crate) fn to_value(&self) -> Value {
+        let entries: Vec<Value> = self.entries.iter().map(|e| e.to_value()).collect();
+        Value::List(NixList::construct(entries.len(), entries))
+    }
+}
+
 impl FromStr for NixSearchPath {
     type Err = Infallible;
 
@@ -6,14 +6,20 @@
 //!
 //! All methods are optional, that is, observers can implement only
 /// what they are interested in observing.
+use codemap::Span;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use smol_str::SmolStr;
 use std::io::Write;
 use std::rc::Rc;
 use std::time::Instant;
 use tabwriter::TabWriter;
 
 use crate::chunk::Chunk;
+use crate::compiler::GlobalsMap;
 use crate::generators::VMRequest;
 use crate::opcode::{CodeIdx, Op};
+use crate::upvalues::Upvalues;
 use crate::value::Lambda;
 use crate::SourceCode;
 use crate::Value;
@@ -35,6 +41,30 @@ pub trait CompilerObserver {
 
     /// Called when the compiler finishes compilation of a thunk.
     fn observe_compiled_thunk(&mut self, _: &Rc<Lambda>) {}
+
+    /// Called when the compiler resolves the identifier at the given
+    /// span, e.g. to find the definitions of identifiers in editors.
+    fn observe_resolved_identifier(&mut self, _span: Span, _: &Resolution) {}
+}
+
+/// Describes what an identifier refers to, as determined by the
+/// compiler's scope resolution.
+#[derive(Clone, Debug)]
+pub enum Resolution {
+    /// A local (e.g. a `let`-binding, `rec` attribute or function
+    /// argument) declared at the given span.
+    Local(Span),
+
+    /// A global, such as `builtins` or `true`.
+    Global(Value),
+
+    /// A value looked up at runtime from the namespaces of the
+    /// enclosing `with`-expressions at the given spans, innermost
+    /// first.
+    With(Vec<Span>),
+
+    /// Nothing, which is a compilation error.
+    Unknown,
 }
 
 /// Implemented by types that wish to observe internal happenings of
@@ -43,6 +73,22 @@ pub trait RuntimeObserver {
     /// Called when the runtime enters a new call frame.
     fn observe_enter_call_frame(&mut self, _arg_count: usize, _: &Rc<Lambda>, _call_depth: usize) {}
 
+    /// Called right after [`RuntimeObserver::observe_enter_call_frame`]
+    /// with the upvalues of the entered frame, and the offset of its
+    /// locals on the stack.
+    fn observe_call_frame_state(
+        &mut self,
+        _frame_at: usize,
+        _: &Rc<Lambda>,
+        _upvalues: &Rc<Upvalues>,
+        _stack_offset: usize,
+    ) {
+    }
+
+    /// Called once before the runtime starts executing code, with the
+    /// globals available to it.
+    fn observe_globals(&mut self, _globals: &Rc<GlobalsMap>) {}
+
     /// Called when the runtime exits a call frame.
     fn observe_exit_call_frame(&mut self, _frame_at: usize, _stack: &[Value]) {}
 
@@ -320,3 +366,414 @@ impl<W: Write> Drop for TracingObserver<W> {
         let _ = self.writer.flush();
     }
 }
+
+/// A frame in the stacks recorded by the [ProfilingObserver], i.e. a
+/// lambda (identified by its source location) or a builtin.
+#[derive(Debug, Serialize)]
+struct ProfileFrame {
+    name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    col: Option<usize>,
+}
+
+impl ProfileFrame {
+    /// Label of the frame in folded stacks. Semicolons separate frames
+    /// in that format, and are replaced.
+    fn folded_label(&self) -> String {
+        let label = match (&self.file, self.line, self.col) {
+            (Some(file), Some(line), Some(col)) => {
+                format!("{} ({}:{}:{})", self.name, file, line, col)
+            }
+            _ => self.name.clone(),
+        };
+
+        label.replace(';', ",")
+    }
+}
+
+/// Time spent in, and values allocated by a single stack of frames.
+#[derive(Debug, Default, Clone, Copy)]
+struct ProfileSample {
+    nanos: u64,
+    allocations: u64,
+}
+
+/// An observer that profiles the runtime, aggregating the time spent
+/// in each stack of lambdas and builtins, and the number of values
+/// (attribute sets, lists, strings, closures and thunks) allocated by
+/// the bytecode executed in them.
+///
+/// Stacks follow the frames of the VM, i.e. forcing a thunk pushes the
+/// frame of the thunk on top of the frame that forced it. Lambdas are
+/// identified by their name (if any) and source location.
+///
+/// After evaluation, the profile can be written as folded stacks (for
+/// use with `flamegraph.pl` or `inferno`), or as [speedscope] JSON.
+///
+/// [speedscope]: https://www.speedscope.app
+pub struct ProfilingObserver {
+    source: SourceCode,
+    frames: Vec<ProfileFrame>,
+    lambda_frames: FxHashMap<(Span, Option<SmolStr>), usize>,
+    generator_frames: FxHashMap<String, usize>,
+
+    /// Frames currently on the VM's frame stack, indexed by their
+    /// position in it.
+    stack: Vec<usize>,
+    samples: FxHashMap<Vec<usize>, ProfileSample>,
+    last_event: Instant,
+}
+
+impl ProfilingObserver {
+    pub fn new(source: SourceCode) -> Self {
+        Self {
+            source,
+            frames: vec![],
+            lambda_frames: Default::default(),
+            generator_frames: Default::default(),
+            stack: vec![],
+            samples: Default::default(),
+            last_event: Instant::now(),
+        }
+    }
+
+    /// Attribute the time since the last event to the current stack.
+    fn record_time(&mut self) {
+        let now = Instant::now();
+        let nanos = (now - self.last_event).as_nanos() as u64;
+        self.last_event = now;
+
+        if !self.stack.is_empty() {
+            self.current_sample().nanos += nanos;
+        }
+    }
+
+    fn current_sample(&mut self) -> &mut ProfileSample {
+        if !self.samples.contains_key(self.stack.as_slice()) {
+            self.samples.insert(self.stack.clone(), Default::default());
+        }
+
+        self.samples
+            .get_mut(self.stack.as_slice())
+            .expect("sample was just inserted")
+    }
+
+    /// Place the given frame at position `frame_at` of the stack,
+    /// dropping all frames above it.
+    fn enter_frame(&mut self, frame_at: usize, frame: usize) {
+        self.record_time();
+        self.stack.truncate(frame_at);
+
+        // Frames below this one which were never entered (which should
+        // not happen) are unknown.
+        while self.stack.len() < frame_at {
+            let unknown = self.generator_frame("<unknown>");
+            self.stack.push(unknown);
+        }
+
+        self.stack.push(frame);
+    }
+
+    fn exit_frame(&mut self, frame_at: usize) {
+        self.record_time();
+        self.stack.truncate(frame_at);
+    }
+
+    fn suspend_frame(&mut self, frame_at: usize) {
+        self.record_time();
+        self.stack.truncate(frame_at + 1);
+    }
+
+    fn lambda_frame(&mut self, lambda: &Lambda) -> usize {
+        let span = lambda.chunk.first_span();
+        let key = (span, lambda.name.clone());
+
+        if let Some(idx) = self.lambda_frames.get(&key) {
+            return *idx;
+        }
+
+        let loc = self.source.codemap().look_up_span(span);
+        let idx = self.frames.len();
+        self.frames.push(ProfileFrame {
+            name: match &lambda.name {
+                Some(name) => name.to_string(),
+                None => "<anonymous>".to_string(),
+            },
+            file: Some(loc.file.name().to_string()),
+            line: Some(loc.begin.line + 1),
+            col: Some(loc.begin.column + 1),
+        });
+
+        self.lambda_frames.insert(key, idx);
+        idx
+    }
+
+    fn generator_frame(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.generator_frames.get(name) {
+            return *idx;
+        }
+
+        let idx = self.frames.len();
+        self.frames.push(ProfileFrame {
+            name: name.to_string(),
+            file: None,
+            line: None,
+            col: None,
+        });
+
+        self.generator_frames.insert(name.to_string(), idx);
+        idx
+    }
+
+    /// Return the recorded samples, sorted by their stacks to make the
+    /// output deterministic.
+    fn sorted_samples(&self) -> Vec<(Vec<&ProfileFrame>, ProfileSample)> {
+        let mut samples: Vec<_> = self
+            .samples
+            .iter()
+            .map(|(stack, sample)| {
+                let frames = stack.iter().map(|idx| &self.frames[*idx]).collect();
+                (frames, *sample)
+            })
+            .collect();
+
+        samples.sort_by_cached_key(|(frames, _)| {
+            frames
+                .iter()
+                .map(|frame| frame.folded_label())
+                .collect::<Vec<_>>()
+        });
+
+        samples
+    }
+
+    /// Write the time spent in each stack (in nanoseconds) as folded
+    /// stacks, one stack per line.
+    pub fn write_folded<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        for (frames, sample) in self.sorted_samples() {
+            if sample.nanos == 0 {
+                continue;
+            }
+
+            let labels: Vec<_> = frames.iter().map(|frame| frame.folded_label()).collect();
+            writeln!(writer, "{} {}", labels.join(";"), sample.nanos)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the profile as speedscope JSON, containing one profile for
+    /// the time spent in each stack, and one for the number of values
+    /// allocated.
+    pub fn write_speedscope<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut stacks: Vec<Vec<usize>> = self.samples.keys().cloned().collect();
+        stacks.sort();
+
+        let profile = |name: &str, unit: &str, weight: fn(&ProfileSample) -> u64| {
+            let weights: Vec<u64> = stacks.iter().map(|s| weight(&self.samples[s])).collect();
+
+            serde_json::json!({
+                "type": "sampled",
+                "name": name,
+                "unit": unit,
+                "startValue": 0,
+                "endValue": weights.iter().sum::<u64>(),
+                "samples": stacks,
+                "weights": weights,
+            })
+        };
+
+        let json = serde_json::json!({
+            "$schema": "https://www.speedscope.app/file-format-schema.json",
+            "exporter": "tvix",
+            "name": "tvix evaluation",
+            "activeProfileIndex": 0,
+            "shared": { "frames": self.frames },
+            "profiles": [
+                profile("time", "nanoseconds", |s| s.nanos),
+                profile("allocations", "none", |s| s.allocations),
+            ],
+        });
+
+        serde_json::to_writer(writer, &json).map_err(std::io::Error::from)
+    }
+}
+
+impl RuntimeObserver for ProfilingObserver {
+    fn observe_enter_call_frame(&mut self, _: usize, lambda: &Rc<Lambda>, call_depth: usize) {
+        let frame = self.lambda_frame(lambda);
+        self.enter_frame(call_depth, frame);
+    }
+
+    fn observe_exit_call_frame(&mut self, frame_at: usize, _: &[Value]) {
+        self.exit_frame(frame_at);
+    }
+
+    fn observe_suspend_call_frame(&mut self, frame_at: usize, _: &[Value]) {
+        self.suspend_frame(frame_at);
+    }
+
+    fn observe_enter_generator(&mut self, frame_at: usize, name: &str, _: &[Value]) {
+        let frame = self.generator_frame(name);
+        self.enter_frame(frame_at, frame);
+    }
+
+    fn observe_exit_generator(&mut self, frame_at: usize, _: &str, _: &[Value]) {
+        self.exit_frame(frame_at);
+    }
+
+    fn observe_suspend_generator(&mut self, frame_at: usize, _: &str, _: &[Value]) {
+        self.suspend_frame(frame_at);
+    }
+
+    fn observe_execute_op(&mut self, _: CodeIdx, op: &Op, _: &[Value]) {
+        if matches!(
+            op,
+            Op::Attrs
+                | Op::AttrsUpdate
+                | Op::List
+                | Op::Concat
+                | Op::Interpolate
+                | Op::Closure
+                | Op::ThunkClosure
+                | Op::ThunkSuspended
+        ) && !self.stack.is_empty()
+        {
+            self.current_sample().allocations += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Evaluation;
+
+    const CODE: &str = r#"
+let
+  double = x: x * 2;
+  xs = map double [ 1 2 3 ];
+in
+builtins.foldl' (acc: x: acc + x) 0 xs
+"#;
+
+    fn profile(code: &str) -> ProfilingObserver {
+        let mut eval_builder = Evaluation::builder_pure();
+        let mut observer = ProfilingObserver::new(eval_builder.source_map().clone());
+
+        eval_builder.set_runtime_observer(Some(&mut observer));
+        let result = eval_builder.build().evaluate(code, None);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+
+        observer
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let mut out = vec![];
+        profile(CODE).write_folded(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        // Every line is a stack followed by its weight.
+        for line in out.lines() {
+            let (stack, nanos) = line.rsplit_once(' ').unwrap();
+            assert!(!stack.is_empty());
+            nanos.parse::<u64>().unwrap();
+        }
+
+        assert!(
+            out.lines().any(|line| line.contains(";double ([code]:3:")),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn speedscope() {
+        let mut out = vec![];
+        profile(CODE).write_speedscope(&mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        let frames = json["shared"]["frames"].as_array().unwrap();
+        assert!(frames.iter().any(|frame| frame["name"] == "double"));
+        assert!(frames.iter().any(|frame| frame["name"] == "map"));
+
+        let profiles = json["profiles"].as_array().unwrap();
+        assert_eq!(profiles.len(), 2);
+        for profile in profiles {
+            let samples = profile["samples"].as_array().unwrap();
+            assert_eq!(samples.len(), profile["weights"].as_array().unwrap().len());
+            for sample in samples {
+                for idx in sample.as_array().unwrap() {
+                    assert!((idx.as_u64().unwrap() as usize) < frames.len());
+                }
+            }
+        }
+
+        // Building the list and the closures allocates.
+        assert!(profiles[1]["endValue"].as_u64().unwrap() > 0);
+    }
+
+    #[derive(Default)]
+    struct ResolutionCollector(Vec<(Span, Resolution)>);
+
+    impl CompilerObserver for ResolutionCollector {
+        fn observe_resolved_identifier(&mut self, span: Span, resolution: &Resolution) {
+            self.0.push((span, resolution.clone()));
+        }
+    }
+
+    #[test]
+    fn resolved_identifiers() {
+        let mut eval_builder = Evaluation::builder_pure();
+        let source = eval_builder.source_map().clone();
+        let mut observer = ResolutionCollector::default();
+
+        eval_builder.set_compiler_observer(Some(&mut observer));
+        let result = eval_builder.build().compile_only(
+            "let a = 1; f = x: a + x; in with { b = 2; }; if true then f b else a",
+            None,
+        );
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+
+        let mut resolutions: Vec<String> = observer
+            .0
+            .iter()
+            .map(|(span, resolution)| {
+                let target = match resolution {
+                    Resolution::Local(def) => format!("local {}", source.source_slice(*def)),
+                    Resolution::Global(value) => format!("global {}", value),
+                    Resolution::With(namespaces) => {
+                        let namespaces: Vec<_> = namespaces
+                            .iter()
+                            .map(|ns| source.source_slice(*ns).to_string())
+                            .collect();
+                        format!("with {}", namespaces.join(", "))
+                    }
+                    Resolution::Unknown => "unknown".into(),
+                };
+
+                format!("{} -> {}", source.source_slice(*span), target)
+            })
+            .collect();
+        resolutions.sort();
+
+        assert_eq!(
+            resolutions,
+            vec![
+                "a -> local a",
+                "a -> local a",
+                "b -> with { b = 2; }",
+                "f -> local f",
+                "true -> global true",
+                "x -> local x",
+            ]
+        );
+    }
+}
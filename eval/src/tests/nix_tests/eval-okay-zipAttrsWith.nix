@@ -1,4 +1,4 @@
-with import ./../lib.nix;
+with import ./lib.nix;
 
 let
   str = builtins.hashString "sha256" "test";
@@ -37,3 +37,89 @@ fn skip_broken_bytecode() {
         ErrorKind::UnknownStaticVariable
     ));
 }
+
+#[test]
+fn error_trace_with_context() {
+    let code =
+        r#"builtins.addErrorContext "while checking the answer" (let f = x: x.answer; in f { })"#;
+    let result = Evaluation::builder_pure().build().evaluate(code, None);
+
+    assert_eq!(result.errors.len(), 1);
+    let error = &result.errors[0];
+    assert_eq!(
+        error.code(),
+        "E005",
+        "expected the code of the innermost error"
+    );
+
+    let trace = error.fancy_format_str_with_trace();
+    assert!(trace.contains("while checking the answer"), "{trace}");
+    assert!(trace.contains("attribute with name 'answer' could not be found"));
+
+    let short = error.fancy_format_str();
+    assert!(!short.contains("while checking the answer"), "{short}");
+    assert!(short.contains("attribute with name 'answer' could not be found"));
+}
+
+#[test]
+fn error_context_is_evaluated_on_error() {
+    let code = r#"
+      let context = "while checking " + "the answer";
+      in builtins.addErrorContext context (let f = x: x.answer; in f { })
+    "#;
+    let result = Evaluation::builder_pure().build().evaluate(code, None);
+
+    assert_eq!(result.errors.len(), 1);
+    let trace = result.errors[0].fancy_format_str_with_trace();
+    assert!(trace.contains("while checking the answer"), "{trace}");
+
+    // Messages that fail to evaluate are left out of the trace.
+    let code = r#"builtins.addErrorContext (throw "bad context") { }.answer"#;
+    let result = Evaluation::builder_pure().build().evaluate(code, None);
+
+    assert_eq!(result.errors.len(), 1);
+    let error = &result.errors[0];
+    assert_eq!(error.code(), "E005");
+    let trace = error.fancy_format_str_with_trace();
+    assert!(!trace.contains("bad context"), "{trace}");
+}
+
+#[test]
+fn error_trace_is_bounded() {
+    let code = "let f = n: if n == 0 then { }.x else 1 + f (n - 1); in f 1000";
+    let result = Evaluation::builder_pure().build().evaluate(code, None);
+
+    assert_eq!(result.errors.len(), 1);
+    let trace = result.errors[0].fancy_format_str_with_trace();
+    assert!(trace.contains("outer frames omitted"), "{trace}");
+}
+
+#[test]
+fn error_to_json() {
+    let code = "let f = x: x.answer; in f { }";
+    let result = Evaluation::builder_pure().build().evaluate(code, None);
+
+    assert_eq!(result.errors.len(), 1);
+    let json = result.errors[0].to_json();
+
+    assert_eq!(json["level"], "error");
+    assert_eq!(json["code"], "E005");
+    assert_eq!(
+        json["message"],
+        "attribute with name 'answer' could not be found in the set"
+    );
+
+    let span = &json["spans"][0];
+    assert_eq!(span["file"], "[code]");
+    assert_eq!(
+        span["start"],
+        serde_json::json!({ "line": 1, "column": 12 })
+    );
+    assert_eq!(span["end"], serde_json::json!({ "line": 1, "column": 20 }));
+    assert_eq!(span["primary"], true);
+
+    assert_eq!(json["causes"], serde_json::json!([]));
+    let trace = json["trace"].as_array().unwrap();
+    assert!(!trace.is_empty());
+    assert!(trace.iter().all(|note| note["level"] == "note"));
+}
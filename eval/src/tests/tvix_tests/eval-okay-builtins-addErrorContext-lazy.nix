@@ -0,0 +1,2 @@
+# The message is only evaluated if an error occurs.
+builtins.addErrorContext (abort "unused context") 42
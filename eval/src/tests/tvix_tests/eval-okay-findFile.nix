@@ -0,0 +1,13 @@
+let
+  searchPath = [
+    { prefix = "nope"; path = ./does-not-exist; }
+    { prefix = "tvix"; path = ./directory; }
+    { prefix = ""; path = ./.; }
+  ];
+in
+[
+  (import (builtins.findFile searchPath "tvix"))
+  (import (builtins.findFile searchPath "tvix/default.nix"))
+  (import (builtins.findFile searchPath "directory"))
+  (builtins.tryEval (builtins.findFile searchPath "nope/foo"))
+]
@@ -0,0 +1,6 @@
+# scopedImport makes the passed attributes available in the imported file,
+# shadowing globals, and results must not be shared through the import cache.
+[
+  (scopedImport { x = 1; true = false; } ./scoped-import.nix)
+  (scopedImport { x = "foo"; } ./scoped-import.nix)
+]
@@ -0,0 +1,5 @@
+{
+  inherit x;
+  t = true;
+  type = builtins.typeOf x;
+}
@@ -9,7 +9,11 @@
 
 use std::ops::Index;
 
-use crate::{opcode::UpvalueIdx, Value};
+use crate::{
+    gc::{Trace, Tracer},
+    opcode::UpvalueIdx,
+    Value,
+};
 
 /// Structure for carrying upvalues of an UpvalueCarrier.  The
 /// implementation of this struct encapsulates the logic for
@@ -55,6 +59,12 @@ impl Upvalues {
         self.with_stack = Some(with_stack);
     }
 
+    /// Access the upvalues of static identifiers, in the order of
+    /// their [`UpvalueIdx`].
+    pub fn static_upvalues(&self) -> &[Value] {
+        &self.static_upvalues
+    }
+
     pub fn with_stack(&self) -> Option<&Vec<Value>> {
         self.with_stack.as_ref()
     }
@@ -84,3 +94,12 @@ impl Index<UpvalueIdx> for Upvalues {
         &self.static_upvalues[index.0]
     }
 }
+
+impl Trace for Upvalues {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.static_upvalues.trace(tracer);
+        if let Some(with_stack) = &self.with_stack {
+            with_stack.trace(tracer);
+        }
+    }
+}
@@ -20,6 +20,7 @@ use super::thunk::ThunkSet;
 use super::TotalDisplay;
 use super::Value;
 use crate::errors::ErrorKind;
+use crate::gc::{Trace, Tracer};
 use crate::CatchableErrorKind;
 
 static NAME: LazyLock<NixString> = LazyLock::new(|| "name".into());
@@ -68,10 +69,33 @@ impl AttrsRep {
     }
 }
 
+impl Trace for AttrsRep {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            AttrsRep::Empty => {}
+            AttrsRep::Map(map) => {
+                for value in map.values() {
+                    value.trace(tracer);
+                }
+            }
+            AttrsRep::KV { name, value } => {
+                name.trace(tracer);
+                value.trace(tracer);
+            }
+        }
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Debug, Default)]
 pub struct NixAttrs(pub(super) Rc<AttrsRep>);
 
+impl Trace for NixAttrs {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit_rc(&self.0);
+    }
+}
+
 impl From<AttrsRep> for NixAttrs {
     fn from(rep: AttrsRep) -> Self {
         NixAttrs(Rc::new(rep))
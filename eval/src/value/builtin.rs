@@ -3,6 +3,7 @@
 //!
 //! Builtins are directly backed by Rust code operating on Nix values.
 
+use crate::gc::{Trace, Tracer};
 use crate::vm::generators::Generator;
 
 use super::Value;
@@ -59,6 +60,14 @@ pub enum BuiltinResult {
 #[derive(Clone)]
 pub struct Builtin(Box<BuiltinRepr>);
 
+/// Only the partially applied arguments are visible to the collector,
+/// values captured by the builtin's function itself are not.
+impl Trace for Builtin {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.0.partials.trace(tracer);
+    }
+}
+
 impl From<BuiltinRepr> for Builtin {
     fn from(value: BuiltinRepr) -> Self {
         Builtin(Box::new(value))
@@ -1,10 +1,10 @@
 //! This module implements the runtime representation of functions.
-use std::{collections::BTreeMap, hash::Hash, rc::Rc};
+use std::{collections::BTreeMap, hash::Hash, ops::Range, rc::Rc};
 
 use codemap::Span;
 use smol_str::SmolStr;
 
-use crate::{chunk::Chunk, upvalues::Upvalues};
+use crate::{chunk::Chunk, opcode::StackIdx, upvalues::Upvalues};
 
 use super::NixString;
 
@@ -38,6 +38,21 @@ impl Formals {
     }
 }
 
+/// Debug information about a named local, used to inspect the values of
+/// variables at runtime (e.g. in a debugger).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LocalDebugInfo {
+    /// Name of the local.
+    pub(crate) name: SmolStr,
+
+    /// Position of the local on the stack, relative to the call frame.
+    pub(crate) stack_idx: StackIdx,
+
+    /// Range of the bytecode in which the local is initialised and in
+    /// scope.
+    pub(crate) code: Range<usize>,
+}
+
 /// The opcodes for a thunk or closure, plus the number of
 /// non-executable opcodes which are allowed after an OpThunkClosure or
 /// OpThunkSuspended referencing it.  At runtime `Lambda` is usually wrapped
@@ -65,6 +80,13 @@ pub struct Lambda {
     /// data-carrying opcodes (see [`crate::opcode::OpCode::DataStackIdx`]).
     pub(crate) upvalue_count: usize,
     pub(crate) formals: Option<Formals>,
+
+    /// Debug information about the named locals of this lambda.
+    pub(crate) locals: Vec<LocalDebugInfo>,
+
+    /// Names of the upvalues captured by this lambda, indexed by
+    /// upvalue index (`None` for anonymous captures).
+    pub(crate) upvalue_names: Vec<Option<SmolStr>>,
 }
 
 impl Lambda {
@@ -109,4 +131,20 @@ impl Closure {
     pub fn upvalues(&self) -> Rc<Upvalues> {
         self.upvalues.clone()
     }
+
+    /// Returns true if the function takes an attribute set with formal
+    /// arguments, i.e. has the form `{ ... }: ...`, even if it does not
+    /// list any arguments.
+    pub fn has_formals(&self) -> bool {
+        self.lambda.formals.is_some()
+    }
+
+    /// Returns true if the formal arguments of the function end in an
+    /// ellipsis, i.e. it accepts arguments it does not list.
+    pub fn has_ellipsis(&self) -> bool {
+        self.lambda
+            .formals
+            .as_ref()
+            .is_some_and(|formals| formals.ellipsis)
+    }
 }
@@ -7,6 +7,7 @@ use serde::Deserialize;
 use super::thunk::ThunkSet;
 use super::TotalDisplay;
 use super::Value;
+use crate::gc::{Trace, Tracer};
 
 #[repr(transparent)]
 #[derive(Clone, Debug, Deserialize)]
@@ -25,6 +26,12 @@ impl TotalDisplay for NixList {
     }
 }
 
+impl Trace for NixList {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit_rc(&self.0);
+    }
+}
+
 impl From<Vec<Value>> for NixList {
     fn from(vs: Vec<Value>) -> Self {
         Self(Rc::new(vs))
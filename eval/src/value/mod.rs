@@ -29,12 +29,13 @@ use crate::vm::generators::{self, GenCo};
 use crate::AddContext;
 pub use attrs::NixAttrs;
 pub use builtin::{Builtin, BuiltinResult};
-pub(crate) use function::Formals;
 pub use function::{Closure, Lambda};
+pub(crate) use function::{Formals, LocalDebugInfo};
 pub use list::NixList;
 pub use path::canon_path;
 pub use string::{NixContext, NixContextElement, NixString};
 pub use thunk::Thunk;
+pub(crate) use thunk::WeakThunk;
 
 pub use self::thunk::ThunkSet;
 
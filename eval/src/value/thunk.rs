@@ -22,11 +22,12 @@ use rustc_hash::FxHashSet;
 use std::{
     cell::{Ref, RefCell, RefMut},
     fmt::Debug,
-    rc::Rc,
+    rc::{Rc, Weak},
 };
 
 use crate::{
     errors::ErrorKind,
+    gc::{ObjectId, Trace, Tracer},
     opcode::Op,
     upvalues::Upvalues,
     value::Closure,
@@ -133,27 +134,32 @@ impl ThunkRepr {
 pub struct Thunk(Rc<RefCell<ThunkRepr>>);
 
 impl Thunk {
+    fn from_repr(repr: ThunkRepr) -> Self {
+        let thunk = Thunk(Rc::new(RefCell::new(repr)));
+
+        #[cfg(feature = "gc")]
+        crate::gc::track(&thunk);
+
+        thunk
+    }
+
     pub fn new_closure(lambda: Rc<Lambda>) -> Self {
-        Thunk(Rc::new(RefCell::new(ThunkRepr::Evaluated(Value::Closure(
-            Rc::new(Closure {
-                upvalues: Rc::new(Upvalues::with_capacity(lambda.upvalue_count)),
-                lambda: lambda.clone(),
-            }),
-        )))))
+        Thunk::from_repr(ThunkRepr::Evaluated(Value::Closure(Rc::new(Closure {
+            upvalues: Rc::new(Upvalues::with_capacity(lambda.upvalue_count)),
+            lambda: lambda.clone(),
+        }))))
     }
 
     pub fn new_suspended(lambda: Rc<Lambda>, span: Span) -> Self {
-        Thunk(Rc::new(RefCell::new(ThunkRepr::Suspended {
+        Thunk::from_repr(ThunkRepr::Suspended {
             upvalues: Rc::new(Upvalues::with_capacity(lambda.upvalue_count)),
             lambda: lambda.clone(),
             span,
-        })))
+        })
     }
 
     pub fn new_suspended_native(native: Box<dyn Fn() -> Result<Value, ErrorKind>>) -> Self {
-        Thunk(Rc::new(RefCell::new(ThunkRepr::Native(SuspendedNative(
-            native,
-        )))))
+        Thunk::from_repr(ThunkRepr::Native(SuspendedNative(native)))
     }
 
     /// Helper function to create a [`Thunk`] that calls a function given as the
@@ -180,11 +186,11 @@ impl Thunk {
         // Inform the VM that the chunk has ended
         lambda.chunk.push_op(Op::Return, span);
 
-        Thunk(Rc::new(RefCell::new(ThunkRepr::Suspended {
+        Thunk::from_repr(ThunkRepr::Suspended {
             upvalues: Rc::new(Upvalues::with_capacity(0)),
             lambda: Rc::new(lambda),
             span,
-        })))
+        })
     }
 
     fn prepare_blackhole(&self, forced_at: Span) -> ThunkRepr {
@@ -390,6 +396,85 @@ impl Thunk {
     pub(crate) fn debug_repr(&self) -> String {
         self.0.borrow().debug_repr()
     }
+
+    /// Returns the lambda and span of a suspended thunk, as emitted by the
+    /// compiler into chunk constants. Returns [None] for all other thunks.
+    pub(crate) fn suspended_lambda(&self) -> Option<(Rc<Lambda>, Span)> {
+        match &*self.0.borrow() {
+            ThunkRepr::Suspended { lambda, span, .. } => Some((lambda.clone(), *span)),
+            _ => None,
+        }
+    }
+}
+
+/// Support for the cycle collector, see [`crate::gc`].
+impl Thunk {
+    /// Identity of this thunk in the traced heap.
+    pub(crate) fn gc_id(&self) -> ObjectId {
+        Rc::as_ptr(&self.0) as ObjectId
+    }
+
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakThunk {
+        WeakThunk(Rc::downgrade(&self.0))
+    }
+
+    /// Trace the references held by this thunk. Returns false if the
+    /// thunk is currently borrowed, and could not be traced.
+    pub(crate) fn trace_contents(&self, tracer: &mut Tracer) -> bool {
+        let Ok(repr) = self.0.try_borrow() else {
+            return false;
+        };
+
+        match &*repr {
+            ThunkRepr::Suspended {
+                lambda, upvalues, ..
+            } => {
+                tracer.visit_rc(lambda);
+                tracer.visit_rc(upvalues);
+            }
+            ThunkRepr::Evaluated(value) => value.trace(tracer),
+
+            // The references of native thunks are hidden inside of
+            // their closures. Objects only referenced from there are
+            // treated as roots, as their references are not counted.
+            ThunkRepr::Native(_) | ThunkRepr::Blackhole { .. } => {}
+        }
+
+        true
+    }
+
+    /// Drop the contents of this thunk, which must be unreachable, to
+    /// break the reference cycles it is part of. Returns false if the
+    /// thunk is currently borrowed.
+    pub(crate) fn clear(&self) -> bool {
+        let old = match self.0.try_borrow_mut() {
+            Ok(mut repr) => std::mem::replace(&mut *repr, ThunkRepr::Evaluated(Value::Null)),
+            Err(_) => return false,
+        };
+
+        drop(old);
+        true
+    }
+}
+
+impl Trace for Thunk {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit_thunk(self.gc_id(), self.strong_count());
+    }
+}
+
+/// A weak reference to a [`Thunk`], used by the cycle collector to keep
+/// track of all thunks without keeping them alive.
+pub(crate) struct WeakThunk(Weak<RefCell<ThunkRepr>>);
+
+impl WeakThunk {
+    pub(crate) fn upgrade(&self) -> Option<Thunk> {
+        self.0.upgrade().map(Thunk)
+    }
 }
 
 impl TotalDisplay for Thunk {
@@ -120,6 +120,17 @@ pub enum VMRequest {
 
     /// Request the VM for the file type of the given path.
     ReadFileType(PathBuf),
+
+    /// Add a message to the trace of errors occuring while this generator
+    /// is suspended, until it is removed with `PopErrorContext`. Used by
+    /// `builtins.addErrorContext`.
+    ///
+    /// The VM does not respond to this request, so the next message received
+    /// is `Empty`.
+    PushErrorContext(Value),
+
+    /// Remove the error context most recently added by this generator.
+    PopErrorContext,
 }
 
 /// Human-readable representation of a generator message, used by observers.
@@ -177,6 +188,8 @@ impl Display for VMRequest {
             VMRequest::Span => write!(f, "span"),
             VMRequest::TryForce(v) => write!(f, "try_force({})", v.type_of()),
             VMRequest::ReadFileType(p) => write!(f, "read_file_type({})", p.to_string_lossy()),
+            VMRequest::PushErrorContext(_) => write!(f, "push_error_context"),
+            VMRequest::PopErrorContext => write!(f, "pop_error_context"),
         }
     }
 }
@@ -432,45 +445,45 @@ where
                         }
 
                         VMRequest::OpenFile(path) => {
-                            let reader = self
-                                .io_handle
-                                .as_ref()
-                                .open(&path)
-                                .map_err(|e| ErrorKind::IO {
-                                    path: Some(path),
-                                    error: e.into(),
-                                })
-                                .with_span(span, self)?;
-
-                            message = VMResponse::Reader(reader)
+                            message = match self.io_handle.as_ref().open(&path) {
+                                Ok(reader) => VMResponse::Reader(reader),
+                                Err(e) => match catchable_io_error(&e) {
+                                    Some(cek) => VMResponse::Value(Value::from(cek)),
+                                    None => Err(ErrorKind::IO {
+                                        path: Some(path),
+                                        error: e.into(),
+                                    })
+                                    .with_span(span, self)?,
+                                },
+                            };
                         }
 
                         VMRequest::PathExists(path) => {
-                            let exists = self
-                                .io_handle
-                                .as_ref()
-                                .path_exists(&path)
-                                .map_err(|e| ErrorKind::IO {
-                                    path: Some(path),
-                                    error: e.into(),
-                                })
-                                .map(Value::Bool)
-                                .with_span(span, self)?;
-
-                            message = VMResponse::Value(exists);
+                            message = match self.io_handle.as_ref().path_exists(&path) {
+                                Ok(exists) => VMResponse::Value(Value::Bool(exists)),
+                                Err(e) => match catchable_io_error(&e) {
+                                    Some(cek) => VMResponse::Value(Value::from(cek)),
+                                    None => Err(ErrorKind::IO {
+                                        path: Some(path),
+                                        error: e.into(),
+                                    })
+                                    .with_span(span, self)?,
+                                },
+                            };
                         }
 
                         VMRequest::ReadDir(path) => {
-                            let dir = self
-                                .io_handle
-                                .as_ref()
-                                .read_dir(&path)
-                                .map_err(|e| ErrorKind::IO {
-                                    path: Some(path),
-                                    error: e.into(),
-                                })
-                                .with_span(span, self)?;
-                            message = VMResponse::Directory(dir);
+                            message = match self.io_handle.as_ref().read_dir(&path) {
+                                Ok(dir) => VMResponse::Directory(dir),
+                                Err(e) => match catchable_io_error(&e) {
+                                    Some(cek) => VMResponse::Value(Value::from(cek)),
+                                    None => Err(ErrorKind::IO {
+                                        path: Some(path),
+                                        error: e.into(),
+                                    })
+                                    .with_span(span, self)?,
+                                },
+                            };
                         }
 
                         VMRequest::Span => {
@@ -493,17 +506,31 @@ where
                         }
 
                         VMRequest::ReadFileType(path) => {
-                            let file_type = self
-                                .io_handle
-                                .as_ref()
-                                .file_type(&path)
-                                .map_err(|e| ErrorKind::IO {
-                                    path: Some(path),
-                                    error: e.into(),
-                                })
-                                .with_span(span, self)?;
+                            message = match self.io_handle.as_ref().file_type(&path) {
+                                Ok(file_type) => VMResponse::FileType(file_type),
+                                Err(e) => match catchable_io_error(&e) {
+                                    Some(cek) => VMResponse::Value(Value::from(cek)),
+                                    None => Err(ErrorKind::IO {
+                                        path: Some(path),
+                                        error: e.into(),
+                                    })
+                                    .with_span(span, self)?,
+                                },
+                            };
+                        }
+
+                        VMRequest::PushErrorContext(context) => {
+                            self.error_contexts.push((frame_id, context));
+                            message = VMResponse::Empty;
+                        }
 
-                            message = VMResponse::FileType(file_type);
+                        VMRequest::PopErrorContext => {
+                            debug_assert!(
+                                matches!(self.error_contexts.last(), Some((id, _)) if *id == frame_id),
+                                "generator should only pop its own error contexts"
+                            );
+                            self.error_contexts.pop();
+                            message = VMResponse::Empty;
                         }
                     }
                 }
@@ -743,9 +770,13 @@ pub(crate) async fn request_path_import(co: &GenCo, path: PathBuf) -> PathBuf {
 }
 
 /// Request that the VM open a [std::io::Read] for the specified file.
-pub async fn request_open_file(co: &GenCo, path: PathBuf) -> Box<dyn std::io::Read> {
+pub async fn request_open_file(
+    co: &GenCo,
+    path: PathBuf,
+) -> Result<Box<dyn std::io::Read>, CatchableErrorKind> {
     match co.yield_(VMRequest::OpenFile(path)).await {
-        VMResponse::Reader(value) => value,
+        VMResponse::Reader(value) => Ok(value),
+        VMResponse::Value(Value::Catchable(cek)) => Err(*cek),
         msg => panic!(
             "Tvix bug: VM responded with incorrect generator message: {}",
             msg
@@ -753,6 +784,14 @@ pub async fn request_open_file(co: &GenCo, path: PathBuf) -> Box<dyn std::io::Re
     }
 }
 
+/// Returns the [CatchableErrorKind] wrapped by an IO error returned from
+/// [crate::EvalIO], if any.
+fn catchable_io_error(e: &std::io::Error) -> Option<CatchableErrorKind> {
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<CatchableErrorKind>())
+        .cloned()
+}
+
 #[cfg_attr(not(feature = "impure"), allow(unused))]
 pub(crate) async fn request_path_exists(co: &GenCo, path: PathBuf) -> Value {
     match co.yield_(VMRequest::PathExists(path)).await {
@@ -765,9 +804,13 @@ pub(crate) async fn request_path_exists(co: &GenCo, path: PathBuf) -> Value {
 }
 
 #[cfg_attr(not(feature = "impure"), allow(unused))]
-pub(crate) async fn request_read_dir(co: &GenCo, path: PathBuf) -> Vec<(bytes::Bytes, FileType)> {
+pub(crate) async fn request_read_dir(
+    co: &GenCo,
+    path: PathBuf,
+) -> Result<Vec<(bytes::Bytes, FileType)>, CatchableErrorKind> {
     match co.yield_(VMRequest::ReadDir(path)).await {
-        VMResponse::Directory(dir) => dir,
+        VMResponse::Directory(dir) => Ok(dir),
+        VMResponse::Value(Value::Catchable(cek)) => Err(*cek),
         msg => panic!(
             "Tvix bug: VM responded with incorrect generator message: {}",
             msg
@@ -785,10 +828,34 @@ pub(crate) async fn request_span(co: &GenCo) -> Span {
     }
 }
 
+pub(crate) async fn request_push_error_context(co: &GenCo, context: Value) {
+    match co.yield_(VMRequest::PushErrorContext(context)).await {
+        VMResponse::Empty => {}
+        msg => panic!(
+            "Tvix bug: VM responded with incorrect generator message: {}",
+            msg
+        ),
+    }
+}
+
+pub(crate) async fn request_pop_error_context(co: &GenCo) {
+    match co.yield_(VMRequest::PopErrorContext).await {
+        VMResponse::Empty => {}
+        msg => panic!(
+            "Tvix bug: VM responded with incorrect generator message: {}",
+            msg
+        ),
+    }
+}
+
 #[cfg_attr(not(feature = "impure"), allow(unused))]
-pub(crate) async fn request_read_file_type(co: &GenCo, path: PathBuf) -> FileType {
+pub(crate) async fn request_read_file_type(
+    co: &GenCo,
+    path: PathBuf,
+) -> Result<FileType, CatchableErrorKind> {
     match co.yield_(VMRequest::ReadFileType(path)).await {
-        VMResponse::FileType(file_type) => file_type,
+        VMResponse::FileType(file_type) => Ok(file_type),
+        VMResponse::Value(Value::Catchable(cek)) => Err(*cek),
         msg => panic!(
             "Tvix bug: VM responded with incorrect generator message: {}",
             msg
@@ -43,6 +43,11 @@ use generators::{call_functor, Generator, GeneratorState};
 
 use self::generators::{VMRequest, VMResponse};
 
+/// Maximum number of (innermost) frames included in the trace of an
+/// error. Deeply recursive evaluations would otherwise produce
+/// unbounded traces.
+const MAX_TRACE_FRAMES: usize = 128;
+
 /// Internal helper trait for taking a span from a variety of types, to make use
 /// of `WithSpan` (defined below) more ergonomic at call sites.
 trait GetSpan {
@@ -88,8 +93,11 @@ impl<T, S: GetSpan, IO> WithSpan<T, S, IO> for Result<T, ErrorKind> {
                 let mut error = Error::new(kind, top_span.get_span(), vm.source.clone());
 
                 // Wrap the top-level error in chaining errors for each element
-                // of the frame stack.
-                for frame in vm.frames.iter().rev() {
+                // of the (innermost part of the) frame stack, as well as for
+                // any error contexts added by the frames.
+                let omitted = vm.frames.len().saturating_sub(MAX_TRACE_FRAMES);
+
+                for (idx, frame) in vm.frames.iter().enumerate().skip(omitted).rev() {
                     match frame {
                         Frame::CallFrame { span, .. } => {
                             error = Error::new(
@@ -109,6 +117,32 @@ impl<T, S: GetSpan, IO> WithSpan<T, S, IO> for Result<T, ErrorKind> {
                             );
                         }
                     }
+
+                    // The messages are only evaluated once the error leaves
+                    // the VM, see [VM::resolve_error_contexts].
+                    for (_, context) in vm.error_contexts.iter().rev().filter(|(id, _)| *id == idx)
+                    {
+                        error = Error::new(
+                            ErrorKind::PendingErrorContext {
+                                context: context.clone(),
+                                err: Box::new(error),
+                            },
+                            frame.span(),
+                            vm.source.clone(),
+                        );
+                    }
+                }
+
+                if omitted > 0 {
+                    let span = error.span;
+                    error = Error::new(
+                        ErrorKind::ErrorContext {
+                            context: format!("({omitted} outer frames omitted)"),
+                            err: Box::new(error),
+                        },
+                        span,
+                        vm.source.clone(),
+                    );
                 }
 
                 Err(error)
@@ -346,6 +380,12 @@ struct VM<'o, IO> {
     /// Control is yielded to the outer VM loop, which evaluates the next frame
     /// and returns the result itself to the `builtins.tryEval` frame.
     try_eval_frames: Vec<usize>,
+
+    /// Messages added to errors occuring within `builtins.addErrorContext`,
+    /// along with the index of the frame that added them. These are
+    /// attached to the error trace when wrapping the respective frame,
+    /// and only evaluated if an error actually occurs.
+    error_contexts: Vec<(usize, Value)>,
 }
 
 impl<'o, IO> VM<'o, IO>
@@ -373,6 +413,7 @@ where
             warnings: vec![],
             import_cache: Default::default(),
             try_eval_frames: vec![],
+            error_contexts: vec![],
         }
     }
 
@@ -381,9 +422,29 @@ where
         self.frames.push(Frame::CallFrame { span, call_frame })
     }
 
-    /// Run the VM's primary (outer) execution loop, continuing execution based
-    /// on the current frame at the top of the frame stack.
+    /// Run the VM to completion, and return the value left on the top of the
+    /// stack.
     fn execute(mut self) -> EvalResult<RuntimeResult> {
+        if let Err(err) = self.run() {
+            return Err(self.resolve_error_contexts(err));
+        }
+
+        // Once no more frames are present, return the stack's top value as the
+        // result.
+        let value = self
+            .stack
+            .pop()
+            .expect("tvix bug: runtime stack empty after execution");
+        Ok(RuntimeResult {
+            value,
+            warnings: self.warnings,
+        })
+    }
+
+    /// Run the VM's primary (outer) execution loop, continuing execution based
+    /// on the current frame at the top of the frame stack, until no more
+    /// frames are present.
+    fn run(&mut self) -> EvalResult<()> {
         while let Some(frame) = self.frames.pop() {
             self.reasonable_span = frame.span();
             let frame_id = self.frames.len();
@@ -392,6 +453,12 @@ where
                 Frame::CallFrame { call_frame, span } => {
                     self.observer
                         .observe_enter_call_frame(0, &call_frame.lambda, frame_id);
+                    self.observer.observe_call_frame_state(
+                        frame_id,
+                        &call_frame.lambda,
+                        &call_frame.upvalues,
+                        call_frame.stack_offset,
+                    );
 
                     match self.execute_bytecode(span, call_frame) {
                         Ok(true) => self.observer.observe_exit_call_frame(frame_id, &self.stack),
@@ -430,16 +497,66 @@ where
             }
         }
 
-        // Once no more frames are present, return the stack's top value as the
-        // result.
-        let value = self
-            .stack
-            .pop()
-            .expect("tvix bug: runtime stack empty after execution");
-        Ok(RuntimeResult {
-            value,
-            warnings: self.warnings,
-        })
+        Ok(())
+    }
+
+    /// Evaluate the `builtins.addErrorContext` messages attached to an error
+    /// leaving the VM, innermost first. Messages that fail to evaluate to a
+    /// string are dropped from the trace.
+    fn resolve_error_contexts(&mut self, error: Error) -> Error {
+        let kind = match error.kind {
+            ErrorKind::PendingErrorContext { context, err } => {
+                let err = self.resolve_error_contexts(*err);
+                match self.force_error_context(context) {
+                    Some(context) => ErrorKind::ErrorContext {
+                        context,
+                        err: Box::new(err),
+                    },
+                    None => return err,
+                }
+            }
+
+            ErrorKind::ErrorContext { context, err } => ErrorKind::ErrorContext {
+                context,
+                err: Box::new(self.resolve_error_contexts(*err)),
+            },
+
+            ErrorKind::NativeError { gen_type, err } => ErrorKind::NativeError {
+                gen_type,
+                err: Box::new(self.resolve_error_contexts(*err)),
+            },
+
+            ErrorKind::BytecodeError(err) => {
+                ErrorKind::BytecodeError(Box::new(self.resolve_error_contexts(*err)))
+            }
+
+            kind => kind,
+        };
+
+        Error { kind, ..error }
+    }
+
+    /// Force an error context message after an error has occured.
+    fn force_error_context(&mut self, context: Value) -> Option<String> {
+        // Execution can not continue after the error, so the state it
+        // occured in is discarded.
+        self.frames.clear();
+        self.stack.clear();
+        self.with_stack.clear();
+        self.try_eval_frames.clear();
+        self.error_contexts.clear();
+
+        self.enqueue_generator(
+            "force_error_context",
+            self.reasonable_span,
+            |co| async move { Ok(generators::request_force(&co, context).await) },
+        );
+        self.run().ok()?;
+
+        match self.stack.pop()? {
+            Value::String(s) => Some(s.as_bstr().to_string()),
+            _ => None,
+        }
     }
 
     /// Run the VM's inner execution loop, processing Tvix bytecode from a
@@ -1436,5 +1553,6 @@ where
         },
     });
 
+    vm.observer.observe_globals(&vm.globals);
     vm.execute()
 }
@@ -3,6 +3,7 @@
 
 use codemap_diagnostic::{ColorConfig, Diagnostic, Emitter, Level, SpanLabel, SpanStyle};
 
+use crate::errors::diagnostic_to_json;
 use crate::SourceCode;
 
 #[derive(Debug)]
@@ -62,7 +63,7 @@ impl EvalWarning {
 
     /// Create the primary warning message displayed to users for a
     /// warning.
-    fn message(&self, source: &SourceCode) -> String {
+    pub fn message(&self, source: &SourceCode) -> String {
         match self.kind {
             WarningKind::DeprecatedLiteralURL => {
                 "URL literal syntax is deprecated, use a quoted string instead".to_string()
@@ -116,7 +117,7 @@ impl EvalWarning {
 
     /// Return the unique warning code for this variant which can be
     /// used to refer users to documentation.
-    fn code(&self) -> &'static str {
+    pub fn code(&self) -> &'static str {
         match self.kind {
             WarningKind::DeprecatedLiteralURL => "W001",
             WarningKind::UselessInherit => "W002",
@@ -135,6 +136,12 @@ impl EvalWarning {
         }
     }
 
+    /// Render a machine-readable (JSON) representation of this warning,
+    /// in the same format as [`crate::Error::to_json`].
+    pub fn to_json(&self, source: &SourceCode) -> serde_json::Value {
+        diagnostic_to_json(&source.codemap(), &self.diagnostic(source))
+    }
+
     fn diagnostic(&self, source: &SourceCode) -> Diagnostic {
         let span_label = SpanLabel {
             label: self.span_label(),
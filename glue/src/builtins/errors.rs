@@ -1,7 +1,7 @@
 //! Contains errors that can occur during evaluation of builtins in this crate
 use nix_compat::{
     nixhash::{self, NixHash},
-    store_path::BuildStorePathError,
+    store_path::{BuildStorePathError, StorePath},
 };
 use reqwest::Url;
 use std::{path::PathBuf, rc::Rc};
@@ -40,6 +40,9 @@ pub enum FetcherError {
         got: NixHash,
     },
 
+    #[error("refusing to fetch {0} in offline mode")]
+    Offline(Url),
+
     #[error("Invalid hash type '{0}' for fetcher")]
     InvalidHashType(&'static str),
 
@@ -57,6 +60,28 @@ pub enum FetcherError {
 
     #[error("Error calculating store path for fetcher output: {0}")]
     StorePath(#[from] BuildStorePathError),
+
+    #[error("path '{}' not found in binary cache '{1}'", .0.to_absolute_path())]
+    ClosurePathNotFound(StorePath<String>, Url),
+
+    #[error("attribute 'toPath' is set, but 'inputAddressed' is also set to true; please remove one of them")]
+    ClosureConflictingArgs,
+
+    #[error("rewriting '{}' to content-addressed form yielded '{}', while '{}' was expected", .from.to_absolute_path(), .got.to_absolute_path(), .expected.to_absolute_path())]
+    ClosureRewriteMismatch {
+        from: StorePath<String>,
+        got: StorePath<String>,
+        expected: StorePath<String>,
+    },
+
+    #[error("the 'fromPath' store path '{}' is input-addressed, which is not allowed unless 'inputAddressed = true' is set", .0.to_absolute_path())]
+    ClosureInputAddressed(StorePath<String>),
+
+    #[error("the 'fromPath' store path '{}' is content-addressed, but 'inputAddressed' is set to true", .0.to_absolute_path())]
+    ClosureContentAddressed(StorePath<String>),
+
+    #[error("fetching Mercurial repository failed: {0}")]
+    Mercurial(String),
 }
 
 /// Errors related to `builtins.path` and `builtins.filterSource`,
@@ -2,15 +2,17 @@
 
 use super::utils::select_string;
 use crate::{
-    fetchers::{url_basename, Fetch},
+    fetchers::{url_basename, ClosureFetch, Fetch, MercurialFetch, DEFAULT_MERCURIAL_REV},
     tvix_store_io::TvixStoreIO,
 };
-use nix_compat::nixhash;
+use nix_compat::{nixhash, store_path::StorePath};
 use std::rc::Rc;
 use tvix_eval::builtin_macros::builtins;
-use tvix_eval::generators::Gen;
-use tvix_eval::generators::GenCo;
-use tvix_eval::{CatchableErrorKind, ErrorKind, Value};
+use tvix_eval::builtins::coerce_value_to_path;
+use tvix_eval::generators::{self, Gen, GenCo};
+use tvix_eval::{
+    CatchableErrorKind, ErrorKind, NixAttrs, NixContext, NixContextElement, NixString, Value,
+};
 use url::Url;
 
 // Used as a return type for extract_fetch_args, which is sharing some
@@ -86,6 +88,39 @@ async fn extract_fetch_args(
     Ok(Ok(NixFetchArgs { url, name, sha256 }))
 }
 
+// Coerces the value to a path, and parses it as a store path.
+// Used for the `fromPath` and `toPath` arguments of fetchClosure.
+async fn select_store_path(
+    co: &GenCo,
+    v: &Value,
+) -> Result<Result<StorePath<String>, CatchableErrorKind>, ErrorKind> {
+    let path = match coerce_value_to_path(co, v.clone()).await? {
+        Ok(path) => path,
+        Err(cek) => return Ok(Err(cek)),
+    };
+
+    StorePath::from_absolute_path(path.as_os_str().as_encoded_bytes())
+        .map(Ok)
+        .map_err(|e| ErrorKind::TvixError(Rc::new(e)))
+}
+
+// Returns the repository to fetch for fetchMercurial, which is either a
+// (local) path or a URL. Paths are used as-is, rather than imported.
+async fn select_hg_url(
+    co: &GenCo,
+    v: &Value,
+) -> Result<Result<String, CatchableErrorKind>, ErrorKind> {
+    match generators::request_force(co, v.clone()).await {
+        Value::Catchable(cek) => Ok(Err(*cek)),
+        Value::Path(path) => Ok(Ok(path.to_string_lossy().into_owned())),
+        v => {
+            let url = v.to_str()?;
+            let url = String::from_utf8(url.as_bytes().to_vec()).map_err(|_| ErrorKind::Utf8)?;
+            Ok(Ok(url))
+        }
+    }
+}
+
 #[allow(unused_variables)] // for the `state` arg, for now
 #[builtins(state = "Rc<TvixStoreIO>")]
 pub(crate) mod fetcher_builtins {
@@ -184,6 +219,82 @@ pub(crate) mod fetcher_builtins {
         )
     }
 
+    #[builtin("fetchClosure")]
+    async fn builtin_fetch_closure(
+        state: Rc<TvixStoreIO>,
+        co: GenCo,
+        args: Value,
+    ) -> Result<Value, ErrorKind> {
+        let attrs = args.to_attrs()?;
+
+        // Disallow other attrset keys, to match Nix' behaviour.
+        const VALID_KEYS: [&[u8]; 4] = [b"fromStore", b"fromPath", b"toPath", b"inputAddressed"];
+        if let Some(first_invalid_key) = attrs.keys().find(|k| !&VALID_KEYS.contains(&k.as_bytes()))
+        {
+            return Err(ErrorKind::UnexpectedArgumentBuiltin(
+                first_invalid_key.clone(),
+            ));
+        }
+
+        let from_store = match select_string(&co, &attrs, "fromStore").await? {
+            Ok(s) => s.ok_or_else(|| ErrorKind::AttributeNotFound {
+                name: "fromStore".into(),
+            })?,
+            Err(cek) => return Ok(Value::from(cek)),
+        };
+        let from_store = Url::parse(&from_store).map_err(|e| ErrorKind::TvixError(Rc::new(e)))?;
+
+        let from_path = match select_store_path(&co, attrs.select_required("fromPath")?).await? {
+            Ok(store_path) => store_path,
+            Err(cek) => return Ok(Value::from(cek)),
+        };
+
+        let to_path = match attrs.select("toPath") {
+            Some(to_path) => match select_store_path(&co, to_path).await? {
+                Ok(store_path) => Some(store_path),
+                Err(cek) => return Ok(Value::from(cek)),
+            },
+            None => None,
+        };
+
+        let input_addressed = match attrs.select("inputAddressed") {
+            Some(v) => {
+                let v = generators::request_force(&co, v.clone()).await;
+                if let Value::Catchable(cek) = v {
+                    return Ok(Value::Catchable(cek));
+                }
+                v.as_bool()?
+            }
+            None => false,
+        };
+
+        let closure_fetch = ClosureFetch {
+            from_store,
+            from_path,
+            to_path,
+            input_addressed,
+        };
+
+        // Like Nix, fetch eagerly, so invalid arguments are reported right away.
+        let store_path = state
+            .tokio_handle
+            .block_on(state.fetcher.fetch_closure(&closure_fetch))
+            .map_err(|e| ErrorKind::TvixError(Rc::new(e)))?;
+
+        let sp = state.known_paths.borrow_mut().add_closure(closure_fetch);
+        debug_assert_eq!(
+            sp, store_path,
+            "store path registered in KnownPaths should match the fetched one"
+        );
+
+        // Emit the store path as string with context, so it's not imported
+        // again when interpolated.
+        let abs_path = store_path.to_absolute_path();
+        let context: NixContext = NixContextElement::Plain(abs_path.clone()).into();
+
+        Ok(Value::from(NixString::new_context_from(context, abs_path)))
+    }
+
     #[builtin("fetchGit")]
     async fn builtin_fetch_git(
         state: Rc<TvixStoreIO>,
@@ -192,4 +303,346 @@ pub(crate) mod fetcher_builtins {
     ) -> Result<Value, ErrorKind> {
         Err(ErrorKind::NotImplemented("fetchGit"))
     }
+
+    #[builtin("fetchMercurial")]
+    async fn builtin_fetch_mercurial(
+        state: Rc<TvixStoreIO>,
+        co: GenCo,
+        args: Value,
+    ) -> Result<Value, ErrorKind> {
+        // The argument is either the URL, or an attrset with `url`, and
+        // optionally `rev` and `name`.
+        let (url, rev, name) = match args {
+            Value::Attrs(attrs) => {
+                const VALID_KEYS: [&[u8]; 3] = [b"url", b"rev", b"name"];
+                if let Some(first_invalid_key) =
+                    attrs.keys().find(|k| !&VALID_KEYS.contains(&k.as_bytes()))
+                {
+                    return Err(ErrorKind::UnexpectedArgumentBuiltin(
+                        first_invalid_key.clone(),
+                    ));
+                }
+
+                let url = match select_hg_url(&co, attrs.select_required("url")?).await? {
+                    Ok(url) => url,
+                    Err(cek) => return Ok(Value::from(cek)),
+                };
+                let rev = match select_string(&co, &attrs, "rev").await? {
+                    Ok(rev) => rev,
+                    Err(cek) => return Ok(Value::from(cek)),
+                };
+                let name = match select_string(&co, &attrs, "name").await? {
+                    Ok(name) => name,
+                    Err(cek) => return Ok(Value::from(cek)),
+                };
+
+                (url, rev, name)
+            }
+            v => match select_hg_url(&co, &v).await? {
+                Ok(url) => (url, None, None),
+                Err(cek) => return Ok(Value::from(cek)),
+            },
+        };
+
+        // Name defaults to "source" if not set explicitly.
+        const DEFAULT_NAME_FETCH_MERCURIAL: &str = "source";
+
+        let fetch = MercurialFetch {
+            url,
+            rev: rev.unwrap_or_else(|| DEFAULT_MERCURIAL_REV.to_owned()),
+            name: name.unwrap_or_else(|| DEFAULT_NAME_FETCH_MERCURIAL.to_owned()),
+        };
+
+        // The revision metadata is only known after fetching, so there's no
+        // way to do this lazily.
+        let revision = state
+            .tokio_handle
+            .block_on(state.fetcher.fetch_mercurial(&fetch))
+            .map_err(|e| ErrorKind::TvixError(Rc::new(e)))?;
+
+        let abs_path = revision.store_path.to_absolute_path();
+        let context: NixContext = NixContextElement::Plain(abs_path.clone()).into();
+        let short_rev: String = revision.rev.chars().take(12).collect();
+
+        Ok(Value::attrs(NixAttrs::from_iter([
+            (
+                "outPath",
+                Value::from(NixString::new_context_from(context, abs_path)),
+            ),
+            ("rev", Value::from(revision.rev)),
+            ("revCount", Value::Integer(revision.rev_count as i64)),
+            ("branch", Value::from(revision.branch)),
+            ("shortRev", Value::from(short_rev)),
+        ])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, sync::Arc};
+
+    use clap::Parser;
+    use nix_compat::{
+        nixhash::{CAHash, NixHash},
+        store_path::{build_ca_path, StorePath},
+    };
+    use tokio::io::AsyncWriteExt;
+    use tvix_build::buildservice::DummyBuildService;
+    use tvix_castore::Node;
+    use tvix_eval::{EvalIO, EvaluationResult, Value};
+    use tvix_store::{
+        pathinfoservice::PathInfo,
+        utils::{construct_services, ServiceUrlsMemory},
+    };
+
+    use crate::{builtins::add_fetcher_builtins, tvix_store_io::TvixStoreIO};
+
+    /// An input-addressed store path, present in the local store.
+    const IA_PATH: &str = "/nix/store/00bgd045z0d4icpbc2yyz4gx48ak44la-hello";
+
+    /// Store paths (as strings) of the store set up by [setup].
+    struct Paths {
+        /// A content-addressed store path, present in the local store.
+        ca: String,
+        /// Where [IA_PATH] ends up when rewritten to content-addressed form.
+        rewritten: String,
+    }
+
+    /// Sets up a store containing [IA_PATH] and a content-addressed path, so
+    /// fetchClosure doesn't need to talk to the binary cache.
+    fn setup(runtime: &tokio::runtime::Runtime) -> (Rc<TvixStoreIO>, Paths) {
+        let (blob_service, directory_service, path_info_service, nar_calculation_service) = runtime
+            .block_on(async {
+                construct_services(ServiceUrlsMemory::parse_from(std::iter::empty::<&str>())).await
+            })
+            .expect("Failed to construct store services in memory");
+
+        let paths = runtime.block_on(async {
+            let contents = b"Hello World!";
+            let mut writer = blob_service.open_write().await;
+            writer
+                .write_all(contents)
+                .await
+                .expect("write must succeed");
+            let node = Node::File {
+                digest: writer.close().await.expect("close must succeed"),
+                size: contents.len() as u64,
+                executable: false,
+            };
+            let (nar_size, nar_sha256) = nar_calculation_service
+                .calculate_nar(&node)
+                .await
+                .expect("must calculate nar");
+
+            let ca = CAHash::Nar(NixHash::Sha256(nar_sha256));
+            let ca_path: StorePath<String> =
+                build_ca_path("hello-ca", &ca, Vec::<String>::new(), false).unwrap();
+            let rewritten: StorePath<String> =
+                build_ca_path("hello", &ca, Vec::<String>::new(), false).unwrap();
+
+            for (store_path, ca) in [
+                (
+                    StorePath::from_absolute_path(IA_PATH.as_bytes()).unwrap(),
+                    None,
+                ),
+                (ca_path.clone(), Some(ca)),
+            ] {
+                path_info_service
+                    .put(PathInfo {
+                        store_path,
+                        node: node.clone(),
+                        references: vec![],
+                        nar_size,
+                        nar_sha256,
+                        signatures: vec![],
+                        deriver: None,
+                        ca,
+                    })
+                    .await
+                    .expect("put must succeed");
+            }
+
+            Paths {
+                ca: ca_path.to_absolute_path(),
+                rewritten: rewritten.to_absolute_path(),
+            }
+        });
+
+        let io = Rc::new(TvixStoreIO::new(
+            blob_service,
+            directory_service,
+            path_info_service,
+            nar_calculation_service.into(),
+            Arc::<DummyBuildService>::default(),
+            runtime.handle().clone(),
+        ));
+
+        (io, paths)
+    }
+
+    fn eval(io: &Rc<TvixStoreIO>, code: &str) -> EvaluationResult {
+        let mut eval_builder = tvix_eval::Evaluation::builder(io.clone() as Rc<dyn EvalIO>);
+        eval_builder = add_fetcher_builtins(eval_builder, Rc::clone(io));
+        eval_builder.build().evaluate(code, None)
+    }
+
+    fn fetch_closure(from_path: &str, extra_args: &str) -> String {
+        format!(
+            r#"builtins.fetchClosure {{ fromStore = "https://cache.example.org"; fromPath = {from_path}; {extra_args} }}"#
+        )
+    }
+
+    /// Asserts the evaluation returned the given store path, as string with
+    /// context.
+    fn assert_store_path(result: EvaluationResult, expected: &str) {
+        assert!(result.errors.is_empty(), "errors: {:?}", result.errors);
+        match result.value.expect("must be some") {
+            Value::String(s) => {
+                assert_eq!(expected.as_bytes(), s.as_bytes());
+                assert_eq!(vec![expected], s.iter_ctx_plain().collect::<Vec<_>>());
+            }
+            v => panic!("unexpected value: {v}"),
+        }
+    }
+
+    #[test]
+    fn to_path_rewrites() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (io, paths) = setup(&runtime);
+
+        let code = fetch_closure(IA_PATH, &format!("toPath = {};", paths.rewritten));
+        assert_store_path(eval(&io, &code), &paths.rewritten);
+
+        // the rewritten path was persisted, and is known.
+        let rewritten = StorePath::from_absolute_path(paths.rewritten.as_bytes()).unwrap();
+        assert!(runtime
+            .block_on(io.path_info_service.get(*rewritten.digest()))
+            .unwrap()
+            .is_some());
+        assert!(io
+            .known_paths
+            .borrow()
+            .get_closure_for_output_path(&rewritten)
+            .is_some());
+
+        // Interpolating it must not import it again.
+        assert_store_path(eval(&io, &format!(r#""${{{code}}}""#)), &paths.rewritten);
+    }
+
+    #[test]
+    fn to_path_mismatch() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (io, paths) = setup(&runtime);
+
+        // the CA path is not what IA_PATH is rewritten to.
+        let result = eval(
+            &io,
+            &fetch_closure(IA_PATH, &format!("toPath = {};", paths.ca)),
+        );
+        assert!(!result.errors.is_empty(), "must fail");
+    }
+
+    #[test]
+    fn input_addressed() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (io, _paths) = setup(&runtime);
+
+        let result = eval(&io, &fetch_closure(IA_PATH, ""));
+        assert!(
+            !result.errors.is_empty(),
+            "input-addressed paths need inputAddressed = true"
+        );
+
+        assert_store_path(
+            eval(&io, &fetch_closure(IA_PATH, "inputAddressed = true;")),
+            IA_PATH,
+        );
+    }
+
+    #[test]
+    fn content_addressed() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (io, paths) = setup(&runtime);
+
+        assert_store_path(eval(&io, &fetch_closure(&paths.ca, "")), &paths.ca);
+
+        let result = eval(&io, &fetch_closure(&paths.ca, "inputAddressed = true;"));
+        assert!(
+            !result.errors.is_empty(),
+            "content-addressed paths must not be fetched with inputAddressed = true"
+        );
+    }
+
+    /// Runs `hg` in `dir`, returning its stdout.
+    fn hg(dir: &std::path::Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("hg")
+            .args(args)
+            .current_dir(dir)
+            .env("HGPLAIN", "1")
+            .env("HGRCPATH", "")
+            .env("HGUSER", "tvix <tvix@example.org>")
+            .output()
+            .expect("hg must be in PATH");
+        assert!(output.status.success(), "hg {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn fetch_mercurial() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (io, _paths) = setup(&runtime);
+
+        // Create a repository with two commits on the default branch.
+        let repo = tempfile::tempdir().unwrap();
+        hg(repo.path(), &["init"]);
+        std::fs::write(repo.path().join("hello.txt"), "first").unwrap();
+        hg(repo.path(), &["commit", "--addremove", "-m", "first"]);
+        std::fs::write(repo.path().join("hello.txt"), "second").unwrap();
+        hg(repo.path(), &["commit", "-m", "second"]);
+
+        let first = hg(repo.path(), &["log", "-r", "0", "--template", "{node}"]);
+        let second = hg(repo.path(), &["log", "-r", "1", "--template", "{node}"]);
+
+        let fetch = |args: &str| {
+            let code = format!(
+                r#"let r = builtins.fetchMercurial {args}; in builtins.toJSON {{
+                  inherit (r) rev revCount branch shortRev;
+                  name = builtins.substring 33 100 (baseNameOf r.outPath);
+                  contents = builtins.readFile "${{r}}/hello.txt";
+                  archival = builtins.pathExists "${{r}}/.hg_archival.txt";
+                }}"#
+            );
+            let result = eval(&io, &code);
+            assert!(result.errors.is_empty(), "errors: {:?}", result.errors);
+            match result.value.expect("must be some") {
+                Value::String(s) => s.to_str().unwrap().to_owned(),
+                v => panic!("unexpected value: {v}"),
+            }
+        };
+
+        let expected = |rev: &str, rev_count: u64, name: &str, contents: &str| {
+            format!(
+                r#"{{"archival":false,"branch":"default","contents":"{contents}","name":"{name}","rev":"{rev}","revCount":{rev_count},"shortRev":"{}"}}"#,
+                &rev[..12]
+            )
+        };
+
+        let url = repo.path().to_str().unwrap();
+
+        // Without a revision, the tip of the default branch is fetched.
+        assert_eq!(
+            expected(&second, 1, "source", "second"),
+            fetch(&format!(r#""{url}""#))
+        );
+        assert_eq!(
+            expected(&second, 1, "source", "second"),
+            fetch(&format!(r#"{{ url = {url}; }}"#))
+        );
+        assert_eq!(
+            expected(&first, 0, "foo", "first"),
+            fetch(&format!(
+                r#"{{ url = "{url}"; rev = "{first}"; name = "foo"; }}"#
+            ))
+        );
+    }
 }
@@ -0,0 +1,410 @@
+//! Implements copying store path closures from Nix binary caches, which is
+//! what `builtins.fetchClosure` uses under the hood.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bstr::ByteSlice;
+use nix_compat::{
+    nixbase32,
+    nixhash::{CAHash, NixHash},
+    store_path::{build_ca_path, StorePath},
+};
+use sha2::{Digest, Sha256};
+use tracing::{debug, instrument};
+use tvix_castore::{blobservice::BlobService, directoryservice::DirectoryService};
+use tvix_store::{
+    nar::NarCalculationService,
+    pathinfoservice::{NixHTTPPathInfoService, PathInfo, PathInfoService},
+};
+use url::Url;
+
+use super::{redact_url, Fetcher};
+use crate::builtins::FetcherError;
+
+/// The arguments of a `builtins.fetchClosure` call.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClosureFetch {
+    /// The binary cache to copy the closure from.
+    pub from_store: Url,
+    /// The store path whose closure is copied.
+    pub from_path: StorePath<String>,
+    /// If set, the closure is rewritten to content-addressed form, and
+    /// `from_path` needs to end up at this store path.
+    pub to_path: Option<StorePath<String>>,
+    /// Whether `from_path` is allowed to be input-addressed.
+    pub input_addressed: bool,
+}
+
+impl ClosureFetch {
+    /// The store path the closure is made available at.
+    pub fn store_path(&self) -> &StorePath<String> {
+        self.to_path.as_ref().unwrap_or(&self.from_path)
+    }
+}
+
+impl<BS, DS, PS, NS> Fetcher<BS, DS, PS, NS>
+where
+    BS: BlobService + Clone + 'static,
+    DS: DirectoryService + Clone + 'static,
+    PS: PathInfoService,
+    NS: NarCalculationService,
+{
+    /// Copies the closure of `from_path` from the binary cache at `from_store`
+    /// into the local stores, and returns the store path to use.
+    ///
+    /// If `to_path` is set, the closure is rewritten to content-addressed form,
+    /// and the rewritten `from_path` needs to match `to_path`.
+    /// Otherwise, `from_path` needs to be content-addressed, or input-addressed
+    /// if `input_addressed` is set.
+    #[instrument(skip_all, fields(from_store=%redact_url(&fetch.from_store), from_path=%fetch.from_path), err)]
+    pub async fn fetch_closure(
+        &self,
+        fetch: &ClosureFetch,
+    ) -> Result<StorePath<String>, FetcherError> {
+        let ClosureFetch {
+            from_store,
+            from_path,
+            to_path,
+            input_addressed,
+        } = fetch.clone();
+
+        if to_path.is_some() && input_addressed {
+            return Err(FetcherError::ClosureConflictingArgs);
+        }
+
+        // If the rewritten path is already present, there's nothing to do.
+        if let Some(to_path) = &to_path {
+            if self
+                .path_info_service
+                .get(*to_path.digest())
+                .await
+                .map_err(|e| FetcherError::Io(e.into()))?
+                .is_some()
+            {
+                return Ok(to_path.clone());
+            }
+        }
+
+        let closure = self.copy_closure(&from_store, &from_path).await?;
+
+        if let Some(to_path) = to_path {
+            let remappings = self.make_content_addressed(&closure).await?;
+            let rewritten = remappings
+                .get(&from_path)
+                .expect("Tvix bug: closure must contain fromPath");
+
+            if rewritten != &to_path {
+                return Err(FetcherError::ClosureRewriteMismatch {
+                    from: from_path,
+                    got: rewritten.clone(),
+                    expected: to_path,
+                });
+            }
+
+            return Ok(to_path);
+        }
+
+        let path_info = closure
+            .get(&from_path)
+            .expect("Tvix bug: closure must contain fromPath");
+
+        match (is_content_addressed(path_info), input_addressed) {
+            (true, false) | (false, true) => Ok(from_path),
+            (false, false) => Err(FetcherError::ClosureInputAddressed(from_path)),
+            (true, true) => Err(FetcherError::ClosureContentAddressed(from_path)),
+        }
+    }
+
+    /// Walks the closure of `from_path`, copying all [PathInfo] (and the
+    /// contents they point to) that are not present locally from the binary
+    /// cache at `from_store`.
+    /// Returns the [PathInfo] of all store paths in the closure.
+    async fn copy_closure(
+        &self,
+        from_store: &Url,
+        from_path: &StorePath<String>,
+    ) -> Result<BTreeMap<StorePath<String>, PathInfo>, FetcherError> {
+        let remote = NixHTTPPathInfoService::new(
+            "fetchClosure".to_string(),
+            from_store.clone(),
+            self.blob_service.clone(),
+            self.directory_service.clone(),
+        );
+
+        let mut closure = BTreeMap::new();
+        let mut queue = vec![from_path.clone()];
+
+        while let Some(store_path) = queue.pop() {
+            if closure.contains_key(&store_path) {
+                continue;
+            }
+
+            let path_info = match self
+                .path_info_service
+                .get(*store_path.digest())
+                .await
+                .map_err(|e| FetcherError::Io(e.into()))?
+            {
+                Some(path_info) => path_info,
+                None => {
+                    if self.offline {
+                        return Err(FetcherError::Offline(redact_url(from_store)));
+                    }
+
+                    let path_info = remote
+                        .get(*store_path.digest())
+                        .await
+                        .map_err(|e| FetcherError::Io(e.into()))?
+                        .ok_or_else(|| {
+                            FetcherError::ClosurePathNotFound(
+                                store_path.clone(),
+                                redact_url(from_store),
+                            )
+                        })?;
+
+                    self.path_info_service
+                        .put(path_info)
+                        .await
+                        .map_err(|e| FetcherError::Io(e.into()))?
+                }
+            };
+
+            queue.extend(path_info.references.iter().cloned());
+            closure.insert(store_path, path_info);
+        }
+
+        Ok(closure)
+    }
+
+    /// Rewrites all store paths in the closure to content-addressed form, the
+    /// same way `nix store make-content-addressed` does.
+    /// References to other store paths in the closure are rewritten to their
+    /// new locations, and self-references are handled by hashing modulo the
+    /// store path's own hash.
+    /// Returns a map from the original to the rewritten store paths.
+    async fn make_content_addressed(
+        &self,
+        closure: &BTreeMap<StorePath<String>, PathInfo>,
+    ) -> Result<BTreeMap<StorePath<String>, StorePath<String>>, FetcherError> {
+        let mut remappings: BTreeMap<StorePath<String>, StorePath<String>> = BTreeMap::new();
+
+        for store_path in topo_sort(closure) {
+            let path_info = &closure[store_path];
+
+            let mut nar = Vec::new();
+            tvix_store::nar::write_nar(
+                &mut nar,
+                &path_info.node,
+                self.blob_service.clone(),
+                self.directory_service.clone(),
+            )
+            .await
+            .map_err(|e| FetcherError::Io(std::io::Error::other(e)))?;
+
+            // Point references to their rewritten locations.
+            let mut self_reference = false;
+            let mut references = BTreeSet::new();
+            for reference in &path_info.references {
+                if reference == store_path {
+                    self_reference = true;
+                    continue;
+                }
+
+                let new_reference = remappings.get(reference).unwrap_or(reference);
+                if new_reference != reference {
+                    rewrite_all(
+                        &mut nar,
+                        nixbase32::encode(reference.digest()).as_bytes(),
+                        nixbase32::encode(new_reference.digest()).as_bytes(),
+                    );
+                }
+                references.insert(new_reference.clone());
+            }
+
+            let old_hash_part = nixbase32::encode(store_path.digest());
+            let nar_modulo_sha256 = hash_modulo(&nar, old_hash_part.as_bytes());
+            let ca = CAHash::Nar(NixHash::Sha256(nar_modulo_sha256));
+
+            let new_path: StorePath<String> = build_ca_path(
+                store_path.name(),
+                &ca,
+                references.iter().map(|r| r.to_absolute_path()),
+                self_reference,
+            )?;
+
+            debug!(from=%store_path, to=%new_path, "rewrote store path");
+
+            // Replace self-references with the new hash part.
+            rewrite_all(
+                &mut nar,
+                old_hash_part.as_bytes(),
+                nixbase32::encode(new_path.digest()).as_bytes(),
+            );
+
+            let (node, nar_sha256, nar_size) = tvix_store::nar::ingest_nar_and_hash(
+                self.blob_service.clone(),
+                self.directory_service.clone(),
+                &mut nar.as_slice(),
+                &None,
+            )
+            .await
+            .map_err(|e| FetcherError::Io(std::io::Error::other(e)))?;
+
+            if self_reference {
+                references.insert(new_path.clone());
+            }
+
+            self.path_info_service
+                .put(PathInfo {
+                    store_path: new_path.clone(),
+                    node,
+                    references: references.into_iter().collect(),
+                    nar_size,
+                    nar_sha256,
+                    signatures: vec![],
+                    deriver: None,
+                    ca: Some(ca),
+                })
+                .await
+                .map_err(|e| FetcherError::Io(e.into()))?;
+
+            remappings.insert(store_path.clone(), new_path);
+        }
+
+        Ok(remappings)
+    }
+}
+
+/// Checks whether the [PathInfo] describes a content-addressed store path, by
+/// recalculating the store path from its CA field and references.
+fn is_content_addressed(path_info: &PathInfo) -> bool {
+    let Some(ca) = &path_info.ca else {
+        return false;
+    };
+
+    let mut self_reference = false;
+    let mut references = BTreeSet::new();
+    for reference in &path_info.references {
+        if reference == &path_info.store_path {
+            self_reference = true;
+        } else {
+            references.insert(reference);
+        }
+    }
+
+    build_ca_path::<_, String, _>(
+        path_info.store_path.name(),
+        ca,
+        references.iter().map(|r| r.to_absolute_path()),
+        self_reference,
+    )
+    .is_ok_and(|store_path| store_path == path_info.store_path)
+}
+
+/// Returns the store paths in the closure, ordered so that each store path
+/// comes after all the store paths it refers to.
+fn topo_sort(closure: &BTreeMap<StorePath<String>, PathInfo>) -> Vec<&StorePath<String>> {
+    fn visit<'a>(
+        store_path: &'a StorePath<String>,
+        closure: &'a BTreeMap<StorePath<String>, PathInfo>,
+        visited: &mut BTreeSet<&'a StorePath<String>>,
+        sorted: &mut Vec<&'a StorePath<String>>,
+    ) {
+        if !visited.insert(store_path) {
+            return;
+        }
+
+        for reference in &closure[store_path].references {
+            visit(reference, closure, visited, sorted);
+        }
+
+        sorted.push(store_path);
+    }
+
+    let mut visited = BTreeSet::new();
+    let mut sorted = Vec::with_capacity(closure.len());
+    for store_path in closure.keys() {
+        visit(store_path, closure, &mut visited, &mut sorted);
+    }
+
+    sorted
+}
+
+/// Hashes the NAR with all occurrences of `modulus` replaced by zeroes,
+/// followed by the positions of these occurrences.
+/// This is what `HashModuloSink` does in Nix, and allows calculating a
+/// content address for store paths referring to themselves.
+fn hash_modulo(nar: &[u8], modulus: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    let mut positions = Vec::new();
+    let mut last = 0;
+
+    for pos in nar.find_iter(modulus) {
+        hasher.update(&nar[last..pos]);
+        hasher.update(vec![0u8; modulus.len()]);
+        positions.push(pos);
+        last = pos + modulus.len();
+    }
+    hasher.update(&nar[last..]);
+
+    for pos in positions {
+        hasher.update(format!("|{}", pos));
+    }
+
+    hasher.finalize().into()
+}
+
+/// Replaces all occurrences of `from` in `buf` with `to`.
+/// Both need to have the same length.
+fn rewrite_all(buf: &mut [u8], from: &[u8], to: &[u8]) {
+    debug_assert_eq!(from.len(), to.len());
+
+    let positions: Vec<usize> = buf.find_iter(from).collect();
+    for pos in positions {
+        buf[pos..pos + to.len()].copy_from_slice(to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_modulo, rewrite_all};
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn hash_modulo_no_occurrence() {
+        let nar = b"some contents without the modulus";
+
+        assert_eq!(
+            <[u8; 32]>::from(Sha256::digest(nar)),
+            hash_modulo(nar, b"0123456789abcdfghijklmnpqrsvwxyz")
+        );
+    }
+
+    #[test]
+    fn hash_modulo_occurrences() {
+        let modulus = b"0123456789abcdfghijklmnpqrsvwxyz";
+        let nar = [&b"foo"[..], modulus, b"bar", modulus].concat();
+
+        let mut expected = Sha256::new();
+        expected.update(b"foo");
+        expected.update([0u8; 32]);
+        expected.update(b"bar");
+        expected.update([0u8; 32]);
+        expected.update(b"|3|38");
+
+        assert_eq!(
+            <[u8; 32]>::from(expected.finalize()),
+            hash_modulo(&nar, modulus)
+        );
+
+        // Replacing the modulus must not change the hash, as long as the
+        // positions stay the same.
+        let mut rewritten = nar.clone();
+        rewrite_all(&mut rewritten, modulus, b"zyxwvsrqpnmlkjihgfdcba9876543210");
+        assert_ne!(nar, rewritten);
+        assert_eq!(
+            hash_modulo(&nar, modulus),
+            hash_modulo(&rewritten, b"zyxwvsrqpnmlkjihgfdcba9876543210")
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Implements fetching revisions of Mercurial repositories, which is what
+//! `builtins.fetchMercurial` uses under the hood.
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use nix_compat::{
+    nixhash::{CAHash, NixHash},
+    store_path::{build_ca_path, StorePath},
+};
+use tokio::process::Command;
+use tracing::{debug, instrument};
+use tvix_castore::{
+    blobservice::BlobService, directoryservice::DirectoryService, import::fs::ingest_path,
+};
+use tvix_store::{
+    nar::NarCalculationService,
+    pathinfoservice::{PathInfo, PathInfoService},
+};
+use url::Url;
+
+use super::Fetcher;
+use crate::builtins::FetcherError;
+
+/// The revision `builtins.fetchMercurial` fetches if none is passed, the tip
+/// of the default branch.
+pub const DEFAULT_MERCURIAL_REV: &str = "default";
+
+/// The arguments of a `builtins.fetchMercurial` call.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MercurialFetch {
+    /// The repository to fetch from, as understood by `hg clone`.
+    pub url: String,
+    /// The revision (or branch, tag, …) to fetch.
+    pub rev: String,
+    /// The name of the resulting store path.
+    pub name: String,
+}
+
+/// The result of a [MercurialFetch].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MercurialRevision {
+    /// The store path the contents of the revision are available at.
+    pub store_path: StorePath<String>,
+    /// The full changeset hash of the fetched revision.
+    pub rev: String,
+    /// The local revision number of the fetched revision.
+    pub rev_count: u64,
+    /// The branch the fetched revision is on.
+    pub branch: String,
+}
+
+impl<BS, DS, PS, NS> Fetcher<BS, DS, PS, NS>
+where
+    BS: BlobService + Clone + 'static,
+    DS: DirectoryService + Clone + 'static,
+    PS: PathInfoService,
+    NS: NarCalculationService,
+{
+    /// Clones the repository at `url`, and copies the contents of `rev` into
+    /// the local stores, like `hg archive` would produce them.
+    ///
+    /// This shells out to `hg`, which needs to be in `PATH`.
+    #[instrument(skip_all, fields(url=%fetch.url, rev=%fetch.rev), err)]
+    pub async fn fetch_mercurial(
+        &self,
+        fetch: &MercurialFetch,
+    ) -> Result<MercurialRevision, FetcherError> {
+        // Only local repositories can be fetched in offline mode.
+        if self.offline {
+            if let Ok(url) = Url::parse(&fetch.url) {
+                if url.scheme() != "file" {
+                    return Err(FetcherError::Offline(url));
+                }
+            }
+        }
+
+        let tmpdir = tempfile::tempdir()?;
+        let repo_dir = tmpdir.path().join("repo");
+        let archive_dir = tmpdir.path().join("archive");
+
+        hg([
+            OsStr::new("clone"),
+            OsStr::new("--quiet"),
+            OsStr::new("--noupdate"),
+            OsStr::new("--"),
+            OsStr::new(&fetch.url),
+            repo_dir.as_os_str(),
+        ])
+        .await?;
+
+        let log = hg([
+            OsStr::new("log"),
+            OsStr::new("-R"),
+            repo_dir.as_os_str(),
+            OsStr::new("-r"),
+            OsStr::new(&fetch.rev),
+            OsStr::new("--template"),
+            OsStr::new("{node} {rev} {branch}"),
+        ])
+        .await?;
+
+        let (rev, rev_count, branch) = parse_log(&log).ok_or_else(|| {
+            FetcherError::Mercurial(format!("unexpected output of 'hg log': {log}"))
+        })?;
+        debug!(%rev, rev_count, %branch, "resolved revision");
+
+        hg([
+            OsStr::new("archive"),
+            OsStr::new("-R"),
+            repo_dir.as_os_str(),
+            OsStr::new("-r"),
+            OsStr::new(&rev),
+            archive_dir.as_os_str(),
+        ])
+        .await?;
+
+        // Like Nix, don't include the metadata `hg archive` adds.
+        tokio::fs::remove_file(archive_dir.join(".hg_archival.txt")).await?;
+
+        let store_path = self.ingest_dir(&fetch.name, &archive_dir).await?;
+
+        Ok(MercurialRevision {
+            store_path,
+            rev,
+            rev_count,
+            branch,
+        })
+    }
+
+    /// Ingests the directory at `path` into the local stores, as a
+    /// content-addressed (recursive, sha256) store path with the given name.
+    async fn ingest_dir(&self, name: &str, path: &Path) -> Result<StorePath<String>, FetcherError> {
+        let node = ingest_path::<_, _, _, &[u8]>(
+            self.blob_service.clone(),
+            self.directory_service.clone(),
+            path,
+            None,
+        )
+        .await
+        .map_err(|e| FetcherError::Io(std::io::Error::other(e)))?;
+
+        let (nar_size, nar_sha256) = self
+            .nar_calculation_service
+            .calculate_nar(&node)
+            .await
+            .map_err(|e| FetcherError::Io(e.into()))?;
+
+        let ca = CAHash::Nar(NixHash::Sha256(nar_sha256));
+        let store_path: StorePath<String> = build_ca_path(name, &ca, Vec::<String>::new(), false)?;
+
+        self.path_info_service
+            .put(PathInfo {
+                store_path: store_path.clone(),
+                node,
+                references: vec![],
+                nar_size,
+                nar_sha256,
+                signatures: vec![],
+                deriver: None,
+                ca: Some(ca),
+            })
+            .await
+            .map_err(|e| FetcherError::Io(e.into()))?;
+
+        Ok(store_path)
+    }
+}
+
+/// Runs `hg` with the given arguments, returning its (trimmed) stdout.
+async fn hg<I, S>(args: I) -> Result<String, FetcherError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("hg")
+        .args(args)
+        // Don't let user configuration (extensions, aliases, …) interfere.
+        .env("HGPLAIN", "1")
+        .env("HGRCPATH", "")
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(FetcherError::Mercurial(format!(
+            "'hg' failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Parses the `{node} {rev} {branch}` output of `hg log`.
+fn parse_log(log: &str) -> Option<(String, u64, String)> {
+    let mut it = log.splitn(3, ' ');
+    let rev = it.next()?.to_string();
+    let rev_count = it.next()?.parse().ok()?;
+    let branch = it.next()?.to_string();
+
+    Some((rev, rev_count, branch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_log;
+
+    #[test]
+    fn parse_hg_log() {
+        assert_eq!(
+            Some((
+                "0123456789abcdef0123456789abcdef01234567".to_string(),
+                3,
+                "default".to_string()
+            )),
+            parse_log("0123456789abcdef0123456789abcdef01234567 3 default")
+        );
+        assert_eq!(None, parse_log("0123456789abcdef0123456789abcdef01234567"));
+        assert_eq!(None, parse_log("0123 x default"));
+    }
+}
@@ -19,8 +19,12 @@ use url::Url;
 
 use crate::builtins::FetcherError;
 
+mod closure;
+pub use closure::ClosureFetch;
 mod decompression;
+mod mercurial;
 use decompression::DecompressedReader;
+pub use mercurial::{MercurialFetch, MercurialRevision, DEFAULT_MERCURIAL_REV};
 
 /// Representing options for doing a fetch.
 #[derive(Clone, Eq, PartialEq)]
@@ -176,6 +180,7 @@ pub struct Fetcher<BS, DS, PS, NS> {
     directory_service: DS,
     path_info_service: PS,
     nar_calculation_service: NS,
+    offline: bool,
 }
 
 impl<BS, DS, PS, NS> Fetcher<BS, DS, PS, NS> {
@@ -194,9 +199,19 @@ impl<BS, DS, PS, NS> Fetcher<BS, DS, PS, NS> {
             directory_service,
             path_info_service,
             nar_calculation_service,
+            offline: false,
         }
     }
 
+    /// Puts the fetcher into offline mode.
+    /// In offline mode, all fetches from non-`file://` URLs fail immediately,
+    /// instead of touching the network. Fetches whose output is already present
+    /// in the [PathInfoService] are still served from there.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
     /// Constructs a HTTP request to the passed URL, and returns a AsyncReadBuf to it.
     /// In case the URI uses the file:// scheme, use tokio::fs to open it.
     #[instrument(skip_all, fields(url, indicatif.pb_show=tracing::field::Empty), err)]
@@ -233,6 +248,7 @@ impl<BS, DS, PS, NS> Fetcher<BS, DS, PS, NS> {
                     },
                 ))))
             }
+            _ if self.offline => Err(FetcherError::Offline(redact_url(&url))),
             _ => {
                 let resp = self.http_client.get(url).send().await?;
 
@@ -526,6 +542,19 @@ where
         name: &'a str,
         fetch: Fetch,
     ) -> Result<(StorePathRef<'a>, Node), FetcherError> {
+        // If the store path can be calculated upfront, and there's already a
+        // PathInfo for it, we don't need to fetch again.
+        if let Some(store_path) = fetch.store_path(name)? {
+            if let Some(path_info) = self
+                .path_info_service
+                .get(*store_path.digest())
+                .await
+                .map_err(|e| FetcherError::Io(e.into()))?
+            {
+                return Ok((store_path, path_info.node));
+            }
+        }
+
         // Fetch file, return the (unnamed) (File)Node of its contents, ca hash and filesize.
         let (node, ca_hash, size) = self.ingest(fetch).await?;
 
@@ -685,6 +714,84 @@ mod tests {
         }
     }
 
+    mod offline {
+        use super::super::*;
+        use clap::Parser;
+        use std::path::Path;
+        use tvix_store::utils::{construct_services, ServiceUrlsMemory};
+
+        #[allow(clippy::type_complexity)]
+        async fn offline_fetcher() -> Fetcher<
+            std::sync::Arc<dyn BlobService>,
+            std::sync::Arc<dyn DirectoryService>,
+            std::sync::Arc<dyn PathInfoService>,
+            Box<dyn NarCalculationService>,
+        > {
+            let (blob_service, directory_service, path_info_service, nar_calculation_service) =
+                construct_services(ServiceUrlsMemory::parse_from(std::iter::empty::<&str>()))
+                    .await
+                    .unwrap();
+
+            Fetcher::new(
+                blob_service,
+                directory_service,
+                path_info_service,
+                nar_calculation_service,
+            )
+            .with_offline(true)
+        }
+
+        #[tokio::test]
+        async fn refuses_network() {
+            let fetcher = offline_fetcher().await;
+
+            let err = fetcher
+                .ingest_and_persist(
+                    "source",
+                    Fetch::URL {
+                        url: Url::parse("https://test.example/owo").unwrap(),
+                        exp_hash: None,
+                    },
+                )
+                .await
+                .expect_err("must fail");
+
+            assert!(matches!(err, FetcherError::Offline(_)), "{:?}", err);
+        }
+
+        #[tokio::test]
+        async fn allows_file_urls() {
+            let fetcher = offline_fetcher().await;
+
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/empty-file");
+            let (store_path, _node) = fetcher
+                .ingest_and_persist(
+                    "empty-file",
+                    Fetch::URL {
+                        url: Url::from_file_path(path).unwrap(),
+                        exp_hash: None,
+                    },
+                )
+                .await
+                .expect("must succeed");
+
+            // Fetching the same path with the now-known hash is served from
+            // the PathInfoService.
+            let (cached_store_path, _node) = fetcher
+                .ingest_and_persist(
+                    "empty-file",
+                    Fetch::URL {
+                        url: Url::parse("https://test.example/empty-file").unwrap(),
+                        exp_hash: Some(NixHash::Sha256(Sha256::digest(b"").into())),
+                    },
+                )
+                .await
+                .expect("must succeed");
+
+            assert_eq!(store_path, cached_store_path);
+        }
+    }
+
     mod url_basename {
         use super::super::*;
         use rstest::rstest;
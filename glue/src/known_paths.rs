@@ -14,7 +14,7 @@ use nix_compat::{
 };
 use std::collections::HashMap;
 
-use crate::fetchers::Fetch;
+use crate::fetchers::{ClosureFetch, Fetch};
 
 /// Struct keeping track of all known Derivations in the current evaluation.
 /// This keeps both the Derivation struct, as well as the "Hash derivation
@@ -34,6 +34,10 @@ pub struct KnownPaths {
 
     /// A map from output path to fetches (and their names).
     outputs_to_fetches: HashMap<StorePath<String>, (String, Fetch)>,
+
+    /// A map from store path to the `builtins.fetchClosure` call making it
+    /// available.
+    closures: HashMap<StorePath<String>, ClosureFetch>,
 }
 
 impl KnownPaths {
@@ -136,6 +140,23 @@ impl KnownPaths {
             .map(|(name, fetch)| (name.to_owned(), fetch.to_owned()))
     }
 
+    /// Insert a new [ClosureFetch] into this struct, which has already been
+    /// fetched, and return the store path it makes available.
+    pub fn add_closure(&mut self, closure_fetch: ClosureFetch) -> StorePath<String> {
+        let store_path = closure_fetch.store_path().to_owned();
+        self.closures.insert(store_path.clone(), closure_fetch);
+
+        store_path
+    }
+
+    /// Return the [ClosureFetch] making the passed store path available.
+    pub fn get_closure_for_output_path(
+        &self,
+        output_path: &StorePath<String>,
+    ) -> Option<ClosureFetch> {
+        self.closures.get(output_path).cloned()
+    }
+
     /// Returns an iterator over all known derivations and their store path.
     pub fn get_derivations(&self) -> impl Iterator<Item = (&StorePath<String>, &Derivation)> {
         self.derivations.iter().map(|(k, v)| (k, &v.1))
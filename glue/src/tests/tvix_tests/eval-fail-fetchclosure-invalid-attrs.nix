@@ -0,0 +1,6 @@
+(builtins.fetchClosure {
+  fromStore = "https://cache.nixos.org";
+  fromPath = /nix/store/r2jd6ygnmirm2g803mksqqjm4y39yi6i-git-2.33.1;
+  # Only "fromStore", "fromPath", "toPath" and "inputAddressed" are accepted here.
+  name = "git";
+})
@@ -0,0 +1,15 @@
+# Like eval-okay-ifd-build-failure, but for the other builtins accessing the
+# output of a derivation, which must be catchable the same way.
+let
+  drv = derivation {
+    name = "ifd-build-failure-io";
+    builder = "/bin/sh";
+    system = "x86_64-linux";
+    args = [ "-c" "exit 1" ];
+  };
+in
+map (f: (builtins.tryEval (f drv)).success) [
+  builtins.pathExists
+  builtins.readDir
+  builtins.readFileType
+]
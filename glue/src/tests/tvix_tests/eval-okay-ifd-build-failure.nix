@@ -0,0 +1,11 @@
+# Reading from the output of a derivation triggers a build (import-from-derivation).
+# The tests use DummyBuildService, so the build fails, which must be catchable.
+let
+  drv = derivation {
+    name = "ifd-build-failure";
+    builder = "/bin/sh";
+    system = "x86_64-linux";
+    args = [ "-c" "exit 1" ];
+  };
+in
+builtins.tryEval (builtins.readFile drv)
@@ -1,14 +1,17 @@
 //! This module contains glue code translating from
 //! [nix_compat::derivation::Derivation] to [tvix_build::buildservice::BuildRequest].
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::path::PathBuf;
 
 use bytes::Bytes;
-use nix_compat::{derivation::Derivation, nixbase32, store_path::StorePath};
+use nix_compat::{
+    derivation::Derivation, nixbase32, path_info::ExportedPathInfo, store_path::StorePath,
+};
 use sha2::{Digest, Sha256};
 use tvix_build::buildservice::{AdditionalFile, BuildConstraints, BuildRequest, EnvVar};
 use tvix_castore::Node;
+use tvix_store::pathinfoservice::PathInfo;
 
 /// These are the environment variables that Nix sets in its sandbox for every
 /// build.
@@ -27,6 +30,13 @@ const NIX_ENVIRONMENT_VARS: [(&str, &str); 12] = [
     ("TMPDIR", "/build"),
 ];
 
+/// The path at which structured attrs are provided as JSON inside the build.
+const NIX_ATTRS_JSON_FILE: &str = "/build/.attrs.json";
+
+/// The path at which structured attrs are provided as a source-able bash
+/// script inside the build.
+const NIX_ATTRS_SH_FILE: &str = "/build/.attrs.sh";
+
 /// Get an iterator of store paths whose nixbase32 hashes will be the needles for refscanning
 /// Importantly, the returned order will match the one used by [derivation_to_build_request]
 /// so users may use this function to map back from the found needles to a store path
@@ -41,16 +51,92 @@ pub(crate) fn get_refscan_needles(
         .chain(derivation.input_derivations.keys())
 }
 
+/// Parses the structured attrs of a [Derivation] (the `__json` environment
+/// variable), if it uses them.
+fn get_structured_attrs(
+    derivation: &Derivation,
+) -> std::io::Result<Option<serde_json::Map<String, serde_json::Value>>> {
+    derivation
+        .environment
+        .get("__json")
+        .map(|json| {
+            serde_json::from_slice(json).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unable to parse structured attrs: {}", e),
+                )
+            })
+        })
+        .transpose()
+}
+
+/// Returns all store paths referred to by `exportReferencesGraph` in the
+/// structured attrs of a [Derivation].
+/// [derivation_to_build_request] needs to be passed the [PathInfo] for the
+/// closure of all of these.
+pub(crate) fn get_export_references_graph_paths(
+    derivation: &Derivation,
+) -> std::io::Result<BTreeSet<StorePath<String>>> {
+    let mut paths = BTreeSet::new();
+
+    if let Some(structured_attrs) = get_structured_attrs(derivation)? {
+        for (_, store_paths) in get_export_references_graph(&structured_attrs)? {
+            paths.extend(store_paths);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Parses `exportReferencesGraph` from structured attrs, which is an attrset
+/// from key to a list of store paths.
+/// Paths pointing inside a store path are truncated to the store path.
+fn get_export_references_graph(
+    structured_attrs: &serde_json::Map<String, serde_json::Value>,
+) -> std::io::Result<Vec<(String, Vec<StorePath<String>>)>> {
+    let invalid_data = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+    let Some(export_references_graph) = structured_attrs
+        .get("exportReferencesGraph")
+        .and_then(|v| v.as_object())
+    else {
+        return Ok(vec![]);
+    };
+
+    export_references_graph
+        .iter()
+        .map(|(k, v)| {
+            let store_paths = v
+                .as_array()
+                .ok_or_else(|| invalid_data("exportReferencesGraph values must be lists"))?
+                .iter()
+                .map(|p| {
+                    let p = p.as_str().ok_or_else(|| {
+                        invalid_data("exportReferencesGraph elements must be strings")
+                    })?;
+
+                    StorePath::<String>::from_absolute_path_full(p)
+                        .map(|(store_path, _)| store_path)
+                        .map_err(|_| {
+                            invalid_data("exportReferencesGraph elements must be store paths")
+                        })
+                })
+                .collect::<std::io::Result<_>>()?;
+
+            Ok((k.to_owned(), store_paths))
+        })
+        .collect()
+}
+
 /// Takes a [Derivation] and turns it into a [buildservice::BuildRequest].
 /// It assumes the Derivation has been validated.
-/// It needs two lookup functions:
-/// - one translating input sources to a castore node
-///   (`fn_input_sources_to_node`)
-/// - one translating a tuple of drv path and (a subset of their) output names to
-///   castore nodes of the selected outpus (`fn_input_drvs_to_output_nodes`).
+/// It needs the castore nodes of all inputs (`inputs`), as well as the
+/// [PathInfo] of the closure of all paths returned by
+/// [get_export_references_graph_paths] (`path_infos`).
 pub(crate) fn derivation_to_build_request(
     derivation: &Derivation,
     inputs: BTreeMap<StorePath<String>, Node>,
+    path_infos: &BTreeMap<StorePath<String>, PathInfo>,
 ) -> std::io::Result<BuildRequest> {
     debug_assert!(derivation.validate(true).is_ok(), "drv must validate");
 
@@ -72,19 +158,30 @@ pub(crate) fn derivation_to_build_request(
             .map(|(k, v)| (k.to_string(), Bytes::from_static(v.as_bytes()))),
     );
 
-    // extend / overwrite with the keys set in the derivation environment itself.
-    // TODO: check if this order is correct, and environment vars set in the
-    // *Derivation actually* have priority.
-    environment_vars.extend(
-        derivation
-            .environment
-            .iter()
-            .map(|(k, v)| (k.clone(), Bytes::from(v.to_vec()))),
-    );
-
-    handle_pass_as_file(&mut environment_vars, &mut additional_files)?;
-
-    // TODO: handle __json (structured attrs, provide JSON file and source-able bash script)
+    match get_structured_attrs(derivation)? {
+        // With structured attrs, the derivation environment is not passed as
+        // environment variables, but as a JSON file and source-able bash script.
+        Some(structured_attrs) => handle_structured_attrs(
+            derivation,
+            structured_attrs,
+            path_infos,
+            &mut environment_vars,
+            &mut additional_files,
+        )?,
+        None => {
+            // extend / overwrite with the keys set in the derivation environment itself.
+            // TODO: check if this order is correct, and environment vars set in the
+            // *Derivation actually* have priority.
+            environment_vars.extend(
+                derivation
+                    .environment
+                    .iter()
+                    .map(|(k, v)| (k.clone(), Bytes::from(v.to_vec()))),
+            );
+
+            handle_pass_as_file(&mut environment_vars, &mut additional_files)?;
+        }
+    }
 
     // Produce constraints.
     let mut constraints = HashSet::from([
@@ -198,6 +295,185 @@ fn handle_pass_as_file(
     Ok(())
 }
 
+/// handle structured attrs.
+/// The `outputs` key is replaced with an attrset from output name to output
+/// path, and each key in `exportReferencesGraph` is added to the top-level,
+/// containing the closure of the referenced store paths.
+/// The result is provided as JSON in `$NIX_ATTRS_JSON_FILE`, and as a bash
+/// script declaring all representable values in `$NIX_ATTRS_SH_FILE`.
+fn handle_structured_attrs(
+    derivation: &Derivation,
+    mut structured_attrs: serde_json::Map<String, serde_json::Value>,
+    path_infos: &BTreeMap<StorePath<String>, PathInfo>,
+    environment_vars: &mut BTreeMap<String, Bytes>,
+    additional_files: &mut BTreeMap<String, Bytes>,
+) -> std::io::Result<()> {
+    structured_attrs.insert(
+        "outputs".to_string(),
+        derivation
+            .outputs
+            .iter()
+            .map(|(name, output)| (name.to_owned(), output.path_str().into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    );
+
+    for (k, store_paths) in get_export_references_graph(&structured_attrs)? {
+        let exported_path_infos = export_references(&store_paths, path_infos)?;
+
+        structured_attrs.insert(
+            k,
+            serde_json::to_value(exported_path_infos).map_err(std::io::Error::other)?,
+        );
+    }
+
+    additional_files.insert(
+        NIX_ATTRS_SH_FILE[1..].to_string(),
+        Bytes::from(write_structured_attrs_shell(&structured_attrs)),
+    );
+    environment_vars.insert(
+        "NIX_ATTRS_SH_FILE".to_string(),
+        Bytes::from_static(NIX_ATTRS_SH_FILE.as_bytes()),
+    );
+
+    additional_files.insert(
+        NIX_ATTRS_JSON_FILE[1..].to_string(),
+        Bytes::from(serde_json::to_string(&structured_attrs).map_err(std::io::Error::other)?),
+    );
+    environment_vars.insert(
+        "NIX_ATTRS_JSON_FILE".to_string(),
+        Bytes::from_static(NIX_ATTRS_JSON_FILE.as_bytes()),
+    );
+
+    Ok(())
+}
+
+/// Returns the [ExportedPathInfo] for the closure of the given store paths,
+/// looking up the [PathInfo] in `path_infos`.
+fn export_references<'a>(
+    store_paths: &[StorePath<String>],
+    path_infos: &'a BTreeMap<StorePath<String>, PathInfo>,
+) -> std::io::Result<BTreeSet<ExportedPathInfo<'a>>> {
+    closure(store_paths.iter(), path_infos)?
+        .into_iter()
+        .map(|path_info| {
+            let closure_size = closure(std::iter::once(&path_info.store_path), path_infos)?
+                .iter()
+                .map(|path_info| path_info.nar_size)
+                .sum();
+
+            Ok(ExportedPathInfo {
+                closure_size,
+                nar_sha256: path_info.nar_sha256,
+                nar_size: path_info.nar_size,
+                path: path_info.store_path.as_ref(),
+                deriver: None,
+                references: path_info.references.iter().map(StorePath::as_ref).collect(),
+                signatures: vec![],
+            })
+        })
+        .collect()
+}
+
+/// Returns the [PathInfo] of all store paths in the closure of the given store
+/// paths, looking them up in `path_infos`.
+fn closure<'a>(
+    store_paths: impl Iterator<Item = &'a StorePath<String>>,
+    path_infos: &'a BTreeMap<StorePath<String>, PathInfo>,
+) -> std::io::Result<Vec<&'a PathInfo>> {
+    let mut seen: BTreeSet<&StorePath<String>> = BTreeSet::new();
+    let mut queue: Vec<&StorePath<String>> = store_paths.collect();
+    let mut result = vec![];
+
+    while let Some(store_path) = queue.pop() {
+        if !seen.insert(store_path) {
+            continue;
+        }
+
+        let path_info = path_infos.get(store_path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("missing PathInfo for {}", store_path),
+            )
+        })?;
+
+        queue.extend(path_info.references.iter());
+        result.push(path_info);
+    }
+
+    Ok(result)
+}
+
+/// Renders structured attrs to a bash script, declaring a variable for each
+/// key that is a valid shell variable name, and whose value is representable
+/// as a bash value. This follows `writeStructuredAttrsShell` in Nix.
+fn write_structured_attrs_shell(
+    structured_attrs: &serde_json::Map<String, serde_json::Value>,
+) -> String {
+    let mut out = String::new();
+
+    for (k, v) in structured_attrs {
+        if !is_shell_var_name(k) {
+            continue;
+        }
+
+        if let Some(s) = shell_simple_value(v) {
+            out.push_str(&format!("declare {}={}\n", k, s));
+        } else if let Some(array) = v.as_array() {
+            if let Some(elems) = array
+                .iter()
+                .map(|e| shell_simple_value(e).map(|s| s + " "))
+                .collect::<Option<String>>()
+            {
+                out.push_str(&format!("declare -a {}=({})\n", k, elems));
+            }
+        } else if let Some(object) = v.as_object() {
+            if let Some(elems) = object
+                .iter()
+                .map(|(k2, v2)| {
+                    shell_simple_value(v2).map(|s| format!("[{}]={} ", shell_escape(k2), s))
+                })
+                .collect::<Option<String>>()
+            {
+                out.push_str(&format!("declare -A {}=({})\n", k, elems));
+            }
+        }
+    }
+
+    out
+}
+
+/// Returns the bash representation of strings, integers, null and booleans,
+/// or None for all other values.
+fn shell_simple_value(v: &serde_json::Value) -> Option<String> {
+    match v {
+        serde_json::Value::String(s) => Some(shell_escape(s)),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Some(i.to_string()),
+            None => n
+                .as_f64()
+                .filter(|f| f.fract() == 0.0)
+                .map(|f| (f as i64).to_string()),
+        },
+        serde_json::Value::Null => Some("''".to_string()),
+        serde_json::Value::Bool(b) => Some(if *b { "1" } else { "" }.to_string()),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => None,
+    }
+}
+
+/// Single-quotes the given string for use in bash.
+fn shell_escape(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Checks whether the given string matches `[A-Za-z_][A-Za-z0-9_]*`.
+fn is_shell_var_name(s: &str) -> bool {
+    let mut chars = s.chars();
+
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 /// For a given key k in a derivation environment that's supposed to be passed as file,
 /// calculate the ${k}Path key and filepath value that it's being replaced with
 /// while preparing the build.
@@ -216,12 +492,14 @@ fn calculate_pass_as_file_env(k: &str) -> (String, String) {
 mod test {
     use bytes::Bytes;
     use nix_compat::{derivation::Derivation, store_path::StorePath};
-    use std::collections::{BTreeMap, HashSet};
+    use std::collections::{BTreeMap, BTreeSet, HashSet};
     use std::sync::LazyLock;
     use tvix_castore::fixtures::DUMMY_DIGEST;
     use tvix_castore::{Node, PathComponent};
 
+    use nix_compat::nixbase32;
     use tvix_build::buildservice::{AdditionalFile, BuildConstraints, BuildRequest, EnvVar};
+    use tvix_store::pathinfoservice::PathInfo;
 
     use crate::tvix_build::NIX_ENVIRONMENT_VARS;
 
@@ -247,6 +525,7 @@ mod test {
                 StorePath::<String>::from_bytes(&INPUT_NODE_FOO_NAME.clone()).unwrap(),
                 INPUT_NODE_FOO.clone(),
             )]),
+            &BTreeMap::new(),
         )
         .expect("must succeed");
 
@@ -313,7 +592,8 @@ mod test {
         let derivation = Derivation::from_aterm_bytes(aterm_bytes).expect("must parse");
 
         let build_request =
-            derivation_to_build_request(&derivation, BTreeMap::from([])).expect("must succeed");
+            derivation_to_build_request(&derivation, BTreeMap::from([]), &BTreeMap::new())
+                .expect("must succeed");
 
         let mut expected_environment_vars = vec![
             EnvVar {
@@ -382,7 +662,8 @@ mod test {
         let derivation = Derivation::from_aterm_bytes(aterm_bytes).expect("must parse");
 
         let build_request =
-            derivation_to_build_request(&derivation, BTreeMap::from([])).expect("must succeed");
+            derivation_to_build_request(&derivation, BTreeMap::from([]), &BTreeMap::new())
+                .expect("must succeed");
 
         let mut expected_environment_vars = vec![
             // Note how bar and baz are not present in the env anymore,
@@ -457,4 +738,188 @@ mod test {
             build_request
         );
     }
+
+    #[test]
+    fn test_structured_attrs() {
+        // (builtins.derivation { name = "structured-attrs"; system = ":"; builder = ":"; __structuredAttrs = true; }).drvPath
+        let aterm_bytes = r#"Derive([("out","/nix/store/6a39dl014j57bqka7qx25k0vb20vkqm6-structured-attrs","","")],[],[],":",":",[],[("__json","{\"builder\":\":\",\"name\":\"structured-attrs\",\"system\":\":\"}"),("out","/nix/store/6a39dl014j57bqka7qx25k0vb20vkqm6-structured-attrs")])"#.as_bytes();
+
+        let derivation = Derivation::from_aterm_bytes(aterm_bytes).expect("must parse");
+
+        let build_request =
+            derivation_to_build_request(&derivation, BTreeMap::from([]), &BTreeMap::new())
+                .expect("must succeed");
+
+        // Note how none of the keys in the derivation environment end up in
+        // the environment, only the paths to the structured attrs files.
+        let mut expected_environment_vars = vec![
+            EnvVar {
+                key: "NIX_ATTRS_JSON_FILE".into(),
+                value: "/build/.attrs.json".into(),
+            },
+            EnvVar {
+                key: "NIX_ATTRS_SH_FILE".into(),
+                value: "/build/.attrs.sh".into(),
+            },
+        ];
+
+        expected_environment_vars.extend(NIX_ENVIRONMENT_VARS.iter().map(|(k, v)| EnvVar {
+            key: k.to_string(),
+            value: Bytes::from_static(v.as_bytes()),
+        }));
+
+        expected_environment_vars.sort_unstable_by_key(|e| e.key.to_owned());
+
+        assert_eq!(
+            BuildRequest {
+                command_args: vec![":".to_string()],
+                outputs: vec![
+                    "nix/store/6a39dl014j57bqka7qx25k0vb20vkqm6-structured-attrs".into()
+                ],
+                environment_vars: expected_environment_vars,
+                inputs: BTreeMap::new(),
+                inputs_dir: "nix/store".into(),
+                constraints: HashSet::from([
+                    BuildConstraints::System(derivation.system.clone()),
+                    BuildConstraints::ProvideBinSh,
+                ]),
+                // These are the files Nix writes for this derivation.
+                additional_files: vec![
+                    AdditionalFile {
+                        path: "build/.attrs.json".into(),
+                        contents: r#"{"builder":":","name":"structured-attrs","outputs":{"out":"/nix/store/6a39dl014j57bqka7qx25k0vb20vkqm6-structured-attrs"},"system":":"}"#.into(),
+                    },
+                    AdditionalFile {
+                        path: "build/.attrs.sh".into(),
+                        contents: concat!(
+                            "declare builder=':'\n",
+                            "declare name='structured-attrs'\n",
+                            "declare -A outputs=(['out']='/nix/store/6a39dl014j57bqka7qx25k0vb20vkqm6-structured-attrs' )\n",
+                            "declare system=':'\n",
+                        )
+                        .into(),
+                    },
+                ],
+                working_dir: "build".into(),
+                scratch_paths: vec!["build".into(), "nix/store".into()],
+                refscan_needles: vec!["6a39dl014j57bqka7qx25k0vb20vkqm6".into()],
+            },
+            build_request
+        );
+    }
+
+    #[test]
+    fn test_structured_attrs_export_references_graph() {
+        // A derivation with structured attrs, and exportReferencesGraph.refs
+        // pointing to hello, which refers to itself and libgcc.
+        let aterm_bytes = r#"Derive([("out","/nix/store/pp17lwra2jkx8rha15qabg2q3wij72lj-foo","","")],[],["/nix/store/dbghhbq1x39yxgkv3vkgfwbxrmw9nfzi-hello-2.12.1"],":",":",[],[("__json","{\"builder\":\":\",\"exportReferencesGraph\":{\"refs\":[\"/nix/store/dbghhbq1x39yxgkv3vkgfwbxrmw9nfzi-hello-2.12.1\"]},\"name\":\"foo\",\"system\":\":\"}"),("out","/nix/store/pp17lwra2jkx8rha15qabg2q3wij72lj-foo")])"#.as_bytes();
+
+        let derivation = Derivation::from_aterm_bytes(aterm_bytes).expect("must parse");
+
+        let hello =
+            StorePath::<String>::from_bytes(b"dbghhbq1x39yxgkv3vkgfwbxrmw9nfzi-hello-2.12.1")
+                .unwrap();
+        let libgcc =
+            StorePath::<String>::from_bytes(b"rxganm4ibf31qngal3j3psp20mak37yy-xgcc-13.2.0-libgcc")
+                .unwrap();
+
+        assert_eq!(
+            super::get_export_references_graph_paths(&derivation).expect("must succeed"),
+            BTreeSet::from([hello.clone()]),
+        );
+
+        let path_infos = BTreeMap::from([
+            (
+                hello.clone(),
+                PathInfo {
+                    store_path: hello.clone(),
+                    node: INPUT_NODE_FOO.clone(),
+                    references: vec![hello.clone(), libgcc.clone()],
+                    nar_size: 226560,
+                    nar_sha256: nixbase32::decode_fixed(
+                        "0alzbhjxdcsmr1pk7z0bdh46r2xpq3xs3k9y82bi4bx5pklcvw5x",
+                    )
+                    .unwrap(),
+                    signatures: vec![],
+                    deriver: None,
+                    ca: None,
+                },
+            ),
+            (
+                libgcc.clone(),
+                PathInfo {
+                    store_path: libgcc.clone(),
+                    node: INPUT_NODE_FOO.clone(),
+                    references: vec![],
+                    nar_size: 159560,
+                    nar_sha256: nixbase32::decode_fixed(
+                        "10q8iyvfmpfck3yiisnj1j8vp6lq3km17r26sr95zpdf9mgmk69s",
+                    )
+                    .unwrap(),
+                    signatures: vec![],
+                    deriver: None,
+                    ca: None,
+                },
+            ),
+        ]);
+
+        let build_request = derivation_to_build_request(
+            &derivation,
+            BTreeMap::from([(hello, INPUT_NODE_FOO.clone())]),
+            &path_infos,
+        )
+        .expect("must succeed");
+
+        // The closure is added as a top-level `refs` key to the JSON.
+        // It's not representable in bash, so it's omitted from .attrs.sh.
+        assert_eq!(
+            vec![
+                AdditionalFile {
+                    path: "build/.attrs.json".into(),
+                    contents: concat!(
+                        r#"{"builder":":","#,
+                        r#""exportReferencesGraph":{"refs":["/nix/store/dbghhbq1x39yxgkv3vkgfwbxrmw9nfzi-hello-2.12.1"]},"#,
+                        r#""name":"foo","#,
+                        r#""outputs":{"out":"/nix/store/pp17lwra2jkx8rha15qabg2q3wij72lj-foo"},"#,
+                        r#""refs":["#,
+                        r#"{"closureSize":386120,"narHash":"sha256:0alzbhjxdcsmr1pk7z0bdh46r2xpq3xs3k9y82bi4bx5pklcvw5x","narSize":226560,"path":"/nix/store/dbghhbq1x39yxgkv3vkgfwbxrmw9nfzi-hello-2.12.1","references":["/nix/store/dbghhbq1x39yxgkv3vkgfwbxrmw9nfzi-hello-2.12.1","/nix/store/rxganm4ibf31qngal3j3psp20mak37yy-xgcc-13.2.0-libgcc"]},"#,
+                        r#"{"closureSize":159560,"narHash":"sha256:10q8iyvfmpfck3yiisnj1j8vp6lq3km17r26sr95zpdf9mgmk69s","narSize":159560,"path":"/nix/store/rxganm4ibf31qngal3j3psp20mak37yy-xgcc-13.2.0-libgcc","references":[]}"#,
+                        r#"],"#,
+                        r#""system":":"}"#,
+                    )
+                    .into(),
+                },
+                AdditionalFile {
+                    path: "build/.attrs.sh".into(),
+                    contents: concat!(
+                        "declare builder=':'\n",
+                        "declare name='foo'\n",
+                        "declare -A outputs=(['out']='/nix/store/pp17lwra2jkx8rha15qabg2q3wij72lj-foo' )\n",
+                        "declare system=':'\n",
+                    )
+                    .into(),
+                },
+            ],
+            build_request.additional_files
+        );
+    }
+
+    #[test]
+    fn test_write_structured_attrs_shell() {
+        let structured_attrs: serde_json::Map<String, serde_json::Value> = serde_json::from_str(
+            r#"{"a-b":"skipped","bool":true,"f":false,"float":1.5,"int":42,"list":[1,"it's",null],"mixed":[1,[2]],"n":null}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            concat!(
+                "declare bool=1\n",
+                "declare f=\n",
+                "declare int=42\n",
+                "declare -a list=(1 'it'\\''s' '' )\n",
+                "declare n=''\n",
+            ),
+            super::write_structured_attrs_shell(&structured_attrs)
+        );
+    }
 }
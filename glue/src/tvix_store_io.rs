@@ -1,6 +1,7 @@
 //! This module provides an implementation of EvalIO talking to tvix-store.
 use futures::{StreamExt, TryStreamExt};
 use nix_compat::{nixhash::CAHash, store_path::StorePath};
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 use std::{
     cell::RefCell,
@@ -12,7 +13,7 @@ use tokio_util::io::SyncIoBridge;
 use tracing::{error, instrument, warn, Level, Span};
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 use tvix_build::buildservice::BuildService;
-use tvix_eval::{EvalIO, FileType, StdIO};
+use tvix_eval::{CatchableErrorKind, EvalIO, FileType, StdIO};
 use tvix_store::nar::NarCalculationService;
 
 use tvix_castore::{
@@ -24,7 +25,7 @@ use tvix_store::pathinfoservice::{PathInfo, PathInfoService};
 
 use crate::fetchers::Fetcher;
 use crate::known_paths::KnownPaths;
-use crate::tvix_build::derivation_to_build_request;
+use crate::tvix_build::{derivation_to_build_request, get_export_references_graph_paths};
 
 /// Implements [EvalIO], asking given [PathInfoService], [DirectoryService]
 /// and [BlobService].
@@ -92,6 +93,13 @@ impl TvixStoreIO {
         }
     }
 
+    /// Puts the [Fetcher] used by the fetcher builtins into offline mode.
+    /// See [Fetcher::with_offline] for details.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.fetcher = self.fetcher.with_offline(offline);
+        self
+    }
+
     /// for a given [StorePath] and additional [Path] inside the store path,
     /// look up the [PathInfo], and if it exists, and then use
     /// [directoryservice::descend_to] to return the
@@ -137,9 +145,15 @@ impl TvixStoreIO {
                     .known_paths
                     .borrow()
                     .get_fetch_for_output_path(store_path);
+                // Closures copied by `builtins.fetchClosure` are persisted
+                // right away, but copy them again if they went missing.
+                let maybe_closure = self
+                    .known_paths
+                    .borrow()
+                    .get_closure_for_output_path(store_path);
 
-                match maybe_fetch {
-                    Some((name, fetch)) => {
+                match (maybe_fetch, maybe_closure) {
+                    (Some((name, fetch)), _) => {
                         let (sp, root_node) = self
                             .fetcher
                             .ingest_and_persist(&name, fetch)
@@ -156,7 +170,31 @@ impl TvixStoreIO {
 
                         root_node
                     }
-                    None => {
+                    (None, Some(closure_fetch)) => {
+                        let sp = self
+                            .fetcher
+                            .fetch_closure(&closure_fetch)
+                            .await
+                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+                        debug_assert_eq!(
+                            &sp, store_path,
+                            "store path returned from fetchClosure must match store path we have in KnownPaths"
+                        );
+
+                        self.path_info_service
+                            .as_ref()
+                            .get(*store_path.digest())
+                            .await?
+                            .ok_or_else(|| {
+                                std::io::Error::other(format!(
+                                    "PathInfo for {} missing after fetchClosure",
+                                    store_path
+                                ))
+                            })?
+                            .node
+                    }
+                    (None, None) => {
                         // Look up the derivation for this output path.
                         let (drv_path, drv) = {
                             let known_paths = self.known_paths.borrow();
@@ -267,16 +305,33 @@ impl TvixStoreIO {
                         // I think yes, they must be imported into the store by other
                         // operations, so dealt with in the Some(…) match arm
 
+                        // Look up the PathInfo for the closure of all paths
+                        // referred to by exportReferencesGraph.
+                        let path_infos = self
+                            .closure_path_infos(get_export_references_graph_paths(&drv)?)
+                            .await?;
+
                         // synthesize the build request.
-                        let build_request = derivation_to_build_request(&drv, inputs)?;
+                        let build_request = derivation_to_build_request(&drv, inputs, &path_infos)?;
 
                         // create a build
+                        // Failed builds are surfaced as catchable errors to
+                        // the evaluator, including the builder log (if any).
                         let build_result = self
                             .build_service
                             .as_ref()
                             .do_build(build_request)
                             .await
-                            .map_err(|e| std::io::Error::new(io::ErrorKind::Other, e))?;
+                            .map_err(|e| {
+                                io::Error::other(CatchableErrorKind::BuildFailure(
+                                    format!(
+                                        "build of '{}' failed: {}",
+                                        drv_path.to_absolute_path(),
+                                        e
+                                    )
+                                    .into(),
+                                ))
+                            })?;
 
                         // Maps from the index in refscan_needles to the full store path
                         // Used to map back to the actual store path from the found needles
@@ -284,12 +339,18 @@ impl TvixStoreIO {
                         let refscan_needles =
                             crate::tvix_build::get_refscan_needles(&drv).collect::<Vec<_>>();
 
+                        // The build outputs are sorted by their output path, not
+                        // by their output name, so sort the derivation outputs
+                        // the same way before zipping them together.
+                        let mut drv_outputs: Vec<_> = drv.outputs.values().collect();
+                        drv_outputs.sort_unstable_by(|a, b| a.path_str().cmp(&b.path_str()));
+
                         // For each output, insert a PathInfo.
                         for ((output, output_needles), drv_output) in build_result
                             .outputs
                             .iter()
                             .zip(build_result.outputs_needles.iter())
-                            .zip(drv.outputs.iter())
+                            .zip(drv_outputs.into_iter())
                         {
                             let output_node = output
                                 .clone()
@@ -319,7 +380,6 @@ impl TvixStoreIO {
                             // assemble the PathInfo to persist
                             let path_info = PathInfo {
                                 store_path: drv_output
-                                    .1
                                     .path
                                     .as_ref()
                                     .ok_or(std::io::Error::new(
@@ -383,6 +443,103 @@ impl TvixStoreIO {
             .await
             .map_err(|e| std::io::Error::new(io::ErrorKind::Other, e))
     }
+
+    /// Returns the [PathInfo] of all store paths in the closure of the given
+    /// store paths, as found in the [PathInfoService].
+    async fn closure_path_infos(
+        &self,
+        store_paths: impl IntoIterator<Item = StorePath<String>>,
+    ) -> io::Result<BTreeMap<StorePath<String>, PathInfo>> {
+        let mut path_infos = BTreeMap::new();
+        let mut queue: Vec<StorePath<String>> = store_paths.into_iter().collect();
+
+        while let Some(store_path) = queue.pop() {
+            if path_infos.contains_key(&store_path) {
+                continue;
+            }
+
+            let path_info = self
+                .path_info_service
+                .get(*store_path.digest())
+                .await?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is not present in the store", store_path),
+                    )
+                })?;
+
+            queue.extend(path_info.references.iter().cloned());
+            path_infos.insert(store_path, path_info);
+        }
+
+        Ok(path_infos)
+    }
+
+    /// Writes all derivations known to this instance (see [KnownPaths]) into
+    /// the store as `.drv` files, the same way `nix-instantiate` does.
+    ///
+    /// Each `.drv` file is added as a text-hashed store path, referring to
+    /// its input sources and input derivations.
+    /// Derivations already present in the store are skipped.
+    #[instrument(skip_all, err)]
+    pub fn add_known_derivations_to_store(&self) -> io::Result<()> {
+        let derivations: Vec<(StorePath<String>, Vec<u8>, Vec<StorePath<String>>)> = self
+            .known_paths
+            .borrow()
+            .get_derivations()
+            .map(|(drv_path, drv)| {
+                let references = drv
+                    .input_sources
+                    .iter()
+                    .chain(drv.input_derivations.keys())
+                    .cloned()
+                    .collect();
+                (drv_path.clone(), drv.to_aterm_bytes(), references)
+            })
+            .collect();
+
+        self.tokio_handle.block_on(async {
+            for (drv_path, aterm, references) in derivations {
+                if self
+                    .path_info_service
+                    .get(*drv_path.digest())
+                    .await?
+                    .is_some()
+                {
+                    continue;
+                }
+
+                let mut blob_writer = self.blob_service.open_write().await;
+                let size = tokio::io::copy(&mut aterm.as_slice(), &mut blob_writer).await?;
+                let digest = blob_writer.close().await?;
+
+                let node = Node::File {
+                    digest,
+                    size,
+                    executable: false,
+                };
+
+                let (nar_size, nar_sha256) =
+                    self.nar_calculation_service.calculate_nar(&node).await?;
+
+                self.path_info_service
+                    .put(PathInfo {
+                        store_path: drv_path,
+                        node,
+                        references,
+                        nar_size,
+                        nar_sha256,
+                        signatures: vec![],
+                        deriver: None,
+                        ca: Some(CAHash::Text(Sha256::digest(&aterm).into())),
+                    })
+                    .await?;
+            }
+
+            Ok(())
+        })
+    }
 }
 
 impl EvalIO for TvixStoreIO {
@@ -35,6 +35,16 @@ struct Cli {
     /// Whether to configure OTLP. Set --otlp=false to disable.
     #[arg(long, default_missing_value = "true", default_value = "true", num_args(0..=1), require_equals(true), action(clap::ArgAction::Set))]
     otlp: bool,
+
+    #[cfg(feature = "prometheus")]
+    /// The address to serve Prometheus metrics on, at /metrics.
+    /// Metrics are not served if this is not set.
+    #[arg(long, env)]
+    metrics_listen_address: Option<std::net::SocketAddr>,
+
+    /// The format of log lines written to stderr, "text" or "json".
+    #[arg(long, env, default_value_t)]
+    log_format: tvix_tracing::LogFormat,
 }
 
 #[tokio::main]
@@ -43,13 +53,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     let _tracing_handle = {
         #[allow(unused_mut)]
-        let mut builder = tvix_tracing::TracingBuilder::default();
+        let mut builder = tvix_tracing::TracingBuilder::default().log_format(cli.log_format);
         #[cfg(feature = "otlp")]
         {
             if cli.otlp {
                 builder = builder.enable_otlp("nar-bridge");
             }
         }
+        #[cfg(feature = "prometheus")]
+        if let Some(metrics_listen_address) = cli.metrics_listen_address {
+            builder = builder.enable_prometheus(metrics_listen_address);
+        }
         builder.build()?
     };
 
@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+
+use nix_compat::derivation::{diff::diff, Derivation};
+use nix_compat::store_path::StorePath;
+
+use mimalloc::MiMalloc;
+
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+/// Reads and parses the A-Term of the given derivation from `dir`.
+fn load(dir: &Path, drv_path: &StorePath<String>) -> Result<Derivation, String> {
+    let path = dir.join(drv_path.to_string());
+    let buf = std::fs::read(&path).map_err(|e| format!("unable to read {:?}: {}", path, e))?;
+
+    Derivation::from_aterm_bytes(&buf)
+        .map_err(|e| format!("unable to parse derivation {:?}: {:?}", path, e))
+}
+
+/// Splits the path to a `.drv` file into the directory containing it, and its
+/// store path.
+fn split_drv_path(path: &str) -> Result<(PathBuf, StorePath<String>), String> {
+    let path = Path::new(path);
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let drv_path = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("invalid derivation path: {:?}", path))
+        .and_then(|name| {
+            StorePath::from_bytes(name.as_bytes())
+                .map_err(|e| format!("invalid derivation path {:?}: {}", path, e))
+        })?;
+
+    Ok((dir, drv_path))
+}
+
+/// Compares two graphs of derivations, e.g. one produced by Tvix (using
+/// `--drv-dumpdir`) and one produced by C++ Nix, and prints the derivations
+/// with root-cause differences, suppressing the cascade of changed store
+/// paths in derivations depending on them.
+///
+/// Usage: drvdiff OURS.drv THEIRS.drv
+///
+/// Input derivations are looked up in the directory containing the root
+/// derivation on the respective side (e.g. `/nix/store`).
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} OURS.drv THEIRS.drv", args[0]);
+        std::process::exit(2);
+    }
+
+    let result = split_drv_path(&args[1]).and_then(|(ours_dir, ours)| {
+        let (theirs_dir, theirs) = split_drv_path(&args[2])?;
+
+        diff(
+            &ours,
+            &theirs,
+            |drv_path| load(&ours_dir, drv_path),
+            |drv_path| load(&theirs_dir, drv_path),
+        )
+    });
+
+    match result {
+        Ok(diffs) if diffs.is_empty() => {}
+        Ok(diffs) => {
+            for drv_diff in diffs {
+                print!("{}", drv_diff);
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! Compares two graphs of [Derivation]s, e.g. the ones produced by Tvix and
+//! C++ Nix for the same expression, and reports the root causes of their
+//! differences.
+//!
+//! Any change in a derivation changes its store path and output paths, which
+//! changes all derivations depending on it. When comparing derivations, store
+//! paths of corresponding inputs (and outputs) on both sides are considered to
+//! be equal, so only the derivations that actually differ are reported, not
+//! everything depending on them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use bstr::{BString, ByteSlice};
+
+use super::{Derivation, Output};
+use crate::nixbase32;
+use crate::store_path::StorePath;
+
+/// A single difference between two corresponding derivations.
+/// `ours` and `theirs` refer to the first and second graph passed to [diff].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    Builder {
+        ours: String,
+        theirs: String,
+    },
+    Arguments {
+        ours: Vec<String>,
+        theirs: Vec<String>,
+    },
+    System {
+        ours: String,
+        theirs: String,
+    },
+    /// An environment variable that differs, or is only set on one side.
+    Environment {
+        key: String,
+        ours: Option<BString>,
+        theirs: Option<BString>,
+    },
+    /// An input source that differs, or only exists on one side.
+    /// Input sources are matched by their name.
+    InputSource {
+        ours: Option<StorePath<String>>,
+        theirs: Option<StorePath<String>>,
+    },
+    /// An input derivation that only exists on one side.
+    /// Input derivations are matched by their name.
+    InputDerivation {
+        ours: Option<StorePath<String>>,
+        theirs: Option<StorePath<String>>,
+    },
+    /// The outputs used from an input derivation differ.
+    InputDerivationOutputs {
+        ours: StorePath<String>,
+        theirs: StorePath<String>,
+        ours_outputs: BTreeSet<String>,
+        theirs_outputs: BTreeSet<String>,
+    },
+    /// An output that differs (i.e. is fixed-output on one side, or with a
+    /// different hash), or only exists on one side.
+    Output {
+        name: String,
+        ours: Option<Output>,
+        theirs: Option<Output>,
+    },
+}
+
+/// The root-cause differences between two corresponding derivations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DerivationDiff {
+    pub ours: StorePath<String>,
+    pub theirs: StorePath<String>,
+    pub differences: Vec<Difference>,
+}
+
+/// Walks both derivation graphs from the given roots, and returns the
+/// derivations with root-cause differences, inputs before the derivations
+/// depending on them.
+///
+/// `load_ours` and `load_theirs` are used to look up the derivations of
+/// each graph by their store path.
+pub fn diff<E, LO, LT>(
+    ours: &StorePath<String>,
+    theirs: &StorePath<String>,
+    load_ours: LO,
+    load_theirs: LT,
+) -> Result<Vec<DerivationDiff>, E>
+where
+    LO: FnMut(&StorePath<String>) -> Result<Derivation, E>,
+    LT: FnMut(&StorePath<String>) -> Result<Derivation, E>,
+{
+    let mut differ = Differ {
+        load_ours,
+        load_theirs,
+        visited: BTreeSet::new(),
+        diffs: vec![],
+    };
+
+    differ.visit(ours, theirs)?;
+
+    Ok(differ.diffs)
+}
+
+struct Differ<LO, LT> {
+    load_ours: LO,
+    load_theirs: LT,
+    visited: BTreeSet<(StorePath<String>, StorePath<String>)>,
+    diffs: Vec<DerivationDiff>,
+}
+
+impl<E, LO, LT> Differ<LO, LT>
+where
+    LO: FnMut(&StorePath<String>) -> Result<Derivation, E>,
+    LT: FnMut(&StorePath<String>) -> Result<Derivation, E>,
+{
+    fn visit(&mut self, ours: &StorePath<String>, theirs: &StorePath<String>) -> Result<(), E> {
+        // Identical store paths mean identical derivation graphs.
+        if ours == theirs || !self.visited.insert((ours.clone(), theirs.clone())) {
+            return Ok(());
+        }
+
+        let drv_ours = (self.load_ours)(ours)?;
+        let drv_theirs = (self.load_theirs)(theirs)?;
+
+        let mut differences = vec![];
+
+        // Store path hashes of their side, replaced by the corresponding ones
+        // on our side before comparing.
+        let mut replacements = Replacements::default();
+
+        let (input_drvs, ours_only, theirs_only) = pair_by_name(
+            drv_ours.input_derivations.keys(),
+            drv_theirs.input_derivations.keys(),
+        );
+
+        for (input_ours, input_theirs) in input_drvs {
+            self.visit(input_ours, input_theirs)?;
+
+            replacements.add(input_ours, input_theirs);
+            let input_drv_ours = (self.load_ours)(input_ours)?;
+            let input_drv_theirs = (self.load_theirs)(input_theirs)?;
+            replacements.add_outputs(&input_drv_ours.outputs, &input_drv_theirs.outputs);
+
+            let ours_outputs = &drv_ours.input_derivations[input_ours];
+            let theirs_outputs = &drv_theirs.input_derivations[input_theirs];
+            if ours_outputs != theirs_outputs {
+                differences.push(Difference::InputDerivationOutputs {
+                    ours: input_ours.clone(),
+                    theirs: input_theirs.clone(),
+                    ours_outputs: ours_outputs.clone(),
+                    theirs_outputs: theirs_outputs.clone(),
+                });
+            }
+        }
+
+        differences.extend(
+            ours_only
+                .into_iter()
+                .map(|ours| Difference::InputDerivation {
+                    ours: Some(ours.clone()),
+                    theirs: None,
+                })
+                .chain(
+                    theirs_only
+                        .into_iter()
+                        .map(|theirs| Difference::InputDerivation {
+                            ours: None,
+                            theirs: Some(theirs.clone()),
+                        }),
+                ),
+        );
+
+        let (input_srcs, ours_only, theirs_only) = pair_by_name(
+            drv_ours.input_sources.iter(),
+            drv_theirs.input_sources.iter(),
+        );
+
+        for (ours, theirs) in input_srcs {
+            // Their contents differ, which is a root cause on its own.
+            replacements.add(ours, theirs);
+            differences.push(Difference::InputSource {
+                ours: Some(ours.clone()),
+                theirs: Some(theirs.clone()),
+            });
+        }
+
+        differences.extend(
+            ours_only
+                .into_iter()
+                .map(|ours| Difference::InputSource {
+                    ours: Some(ours.clone()),
+                    theirs: None,
+                })
+                .chain(
+                    theirs_only
+                        .into_iter()
+                        .map(|theirs| Difference::InputSource {
+                            ours: None,
+                            theirs: Some(theirs.clone()),
+                        }),
+                ),
+        );
+
+        replacements.add_outputs(&drv_ours.outputs, &drv_theirs.outputs);
+
+        for name in drv_ours
+            .outputs
+            .keys()
+            .chain(drv_theirs.outputs.keys())
+            .collect::<BTreeSet<_>>()
+        {
+            let ours = drv_ours.outputs.get(name);
+            let theirs = drv_theirs.outputs.get(name);
+
+            if ours.map(|o| &o.ca_hash) != theirs.map(|o| &o.ca_hash) {
+                differences.push(Difference::Output {
+                    name: name.clone(),
+                    ours: ours.cloned(),
+                    theirs: theirs.cloned(),
+                });
+            }
+        }
+
+        let builder_theirs = replacements.apply_str(&drv_theirs.builder);
+        if drv_ours.builder != builder_theirs {
+            differences.push(Difference::Builder {
+                ours: drv_ours.builder.clone(),
+                theirs: builder_theirs,
+            });
+        }
+
+        let arguments_theirs: Vec<String> = drv_theirs
+            .arguments
+            .iter()
+            .map(|arg| replacements.apply_str(arg))
+            .collect();
+        if drv_ours.arguments != arguments_theirs {
+            differences.push(Difference::Arguments {
+                ours: drv_ours.arguments.clone(),
+                theirs: arguments_theirs,
+            });
+        }
+
+        if drv_ours.system != drv_theirs.system {
+            differences.push(Difference::System {
+                ours: drv_ours.system.clone(),
+                theirs: drv_theirs.system.clone(),
+            });
+        }
+
+        for key in drv_ours
+            .environment
+            .keys()
+            .chain(drv_theirs.environment.keys())
+            .collect::<BTreeSet<_>>()
+        {
+            let ours = drv_ours.environment.get(key);
+            let theirs = drv_theirs
+                .environment
+                .get(key)
+                .map(|value| replacements.apply(value));
+
+            if ours != theirs.as_ref() {
+                differences.push(Difference::Environment {
+                    key: key.clone(),
+                    ours: ours.cloned(),
+                    theirs,
+                });
+            }
+        }
+
+        if !differences.is_empty() {
+            self.diffs.push(DerivationDiff {
+                ours: ours.clone(),
+                theirs: theirs.clone(),
+                differences,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps nixbase32-encoded store path hashes on their side to the
+/// corresponding ones on our side.
+#[derive(Default)]
+struct Replacements(BTreeMap<String, String>);
+
+impl Replacements {
+    fn add(&mut self, ours: &StorePath<String>, theirs: &StorePath<String>) {
+        if ours.digest() != theirs.digest() {
+            self.0.insert(
+                nixbase32::encode(theirs.digest()),
+                nixbase32::encode(ours.digest()),
+            );
+        }
+    }
+
+    fn add_outputs(&mut self, ours: &BTreeMap<String, Output>, theirs: &BTreeMap<String, Output>) {
+        for (name, output_ours) in ours {
+            if let (Some(path_ours), Some(path_theirs)) = (
+                &output_ours.path,
+                theirs.get(name).and_then(|o| o.path.as_ref()),
+            ) {
+                self.add(path_ours, path_theirs);
+            }
+        }
+    }
+
+    fn apply(&self, value: &BString) -> BString {
+        let mut value = value.clone();
+        for (from, to) in &self.0 {
+            value = value.replace(from, to).into();
+        }
+        value
+    }
+
+    fn apply_str(&self, value: &str) -> String {
+        let mut value = value.to_string();
+        for (from, to) in &self.0 {
+            value = value.replace(from, to);
+        }
+        value
+    }
+}
+
+/// Matches store paths on both sides by their name.
+/// Identical store paths on both sides are skipped. If there are multiple
+/// store paths with the same name, they are matched in order of their
+/// hashes.
+/// Returns the matched store paths, as well as the ones only present on our
+/// or their side.
+#[allow(clippy::type_complexity)]
+fn pair_by_name<'a>(
+    ours: impl IntoIterator<Item = &'a StorePath<String>>,
+    theirs: impl IntoIterator<Item = &'a StorePath<String>>,
+) -> (
+    Vec<(&'a StorePath<String>, &'a StorePath<String>)>,
+    Vec<&'a StorePath<String>>,
+    Vec<&'a StorePath<String>>,
+) {
+    let ours: BTreeSet<_> = ours.into_iter().collect();
+    let theirs: BTreeSet<_> = theirs.into_iter().collect();
+
+    let mut by_name: BTreeMap<&str, (Vec<_>, Vec<_>)> = BTreeMap::new();
+    for path in ours.difference(&theirs) {
+        by_name
+            .entry(path.name().as_str())
+            .or_default()
+            .0
+            .push(*path);
+    }
+    for path in theirs.difference(&ours) {
+        by_name
+            .entry(path.name().as_str())
+            .or_default()
+            .1
+            .push(*path);
+    }
+
+    let mut pairs = vec![];
+    let mut ours_only = vec![];
+    let mut theirs_only = vec![];
+    for (ours, theirs) in by_name.into_values() {
+        let paired = ours.len().min(theirs.len());
+        pairs.extend(ours.iter().copied().zip(theirs.iter().copied()));
+        ours_only.extend_from_slice(&ours[paired..]);
+        theirs_only.extend_from_slice(&theirs[paired..]);
+    }
+
+    (pairs, ours_only, theirs_only)
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn opt<T: fmt::Display>(value: &Option<T>) -> String {
+            match value {
+                Some(value) => value.to_string(),
+                None => "(missing)".to_string(),
+            }
+        }
+
+        match self {
+            Difference::Builder { ours, theirs } => {
+                write!(f, "builder: {:?} != {:?}", ours, theirs)
+            }
+            Difference::Arguments { ours, theirs } => {
+                write!(f, "args: {:?} != {:?}", ours, theirs)
+            }
+            Difference::System { ours, theirs } => {
+                write!(f, "system: {:?} != {:?}", ours, theirs)
+            }
+            Difference::Environment { key, ours, theirs } => write!(
+                f,
+                "env.{}: {} != {}",
+                key,
+                opt(&ours.as_ref().map(|v| format!("{:?}", v))),
+                opt(&theirs.as_ref().map(|v| format!("{:?}", v)))
+            ),
+            Difference::InputSource { ours, theirs } => {
+                write!(f, "input source: {} != {}", opt(ours), opt(theirs))
+            }
+            Difference::InputDerivation { ours, theirs } => {
+                write!(f, "input derivation: {} != {}", opt(ours), opt(theirs))
+            }
+            Difference::InputDerivationOutputs {
+                ours,
+                theirs,
+                ours_outputs,
+                theirs_outputs,
+            } => write!(
+                f,
+                "outputs used from input derivation {} / {}: {:?} != {:?}",
+                ours, theirs, ours_outputs, theirs_outputs
+            ),
+            Difference::Output { name, ours, theirs } => {
+                let describe = |output: &Option<Output>| match output {
+                    None => "(missing)".to_string(),
+                    Some(Output { ca_hash: None, .. }) => "(not fixed-output)".to_string(),
+                    Some(Output {
+                        ca_hash: Some(ca_hash),
+                        ..
+                    }) => format!("{:?}", ca_hash),
+                };
+                write!(
+                    f,
+                    "output {}: {} != {}",
+                    name,
+                    describe(ours),
+                    describe(theirs)
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for DerivationDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} (ours) / {} (theirs):",
+            self.ours.to_absolute_path(),
+            self.theirs.to_absolute_path()
+        )?;
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{diff, Difference};
+    use crate::derivation::{Derivation, Output};
+    use crate::store_path::StorePath;
+
+    const FOO_OURS: &str = "00000000000000000000000000000000-foo";
+    const FOO_THEIRS: &str = "11111111111111111111111111111111-foo";
+
+    fn sp(s: &str) -> StorePath<String> {
+        StorePath::from_bytes(s.as_bytes()).expect("must parse")
+    }
+
+    /// Returns a derivation with the given output path and (optional) input
+    /// derivation, referring to the output path of the latter in its
+    /// environment.
+    fn drv(out: &str, input: Option<(&str, &str)>, extra_env: &[(&str, &str)]) -> Derivation {
+        let mut drv = Derivation {
+            builder: "/bin/sh".into(),
+            system: "x86_64-linux".into(),
+            ..Default::default()
+        };
+        drv.outputs.insert(
+            "out".into(),
+            Output {
+                path: Some(sp(out)),
+                ca_hash: None,
+            },
+        );
+        drv.environment
+            .insert("out".into(), format!("/nix/store/{out}").into());
+
+        if let Some((input_drv, input_out)) = input {
+            drv.input_derivations
+                .insert(sp(input_drv), ["out".to_string()].into());
+            drv.environment
+                .insert("input".into(), format!("/nix/store/{input_out}").into());
+        }
+
+        for (k, v) in extra_env {
+            drv.environment.insert(k.to_string(), v.to_string().into());
+        }
+
+        drv
+    }
+
+    #[test]
+    fn identical() {
+        let root = sp("00000000000000000000000000000000-root.drv");
+        let diffs = diff::<(), _, _>(&root, &root, |_| unreachable!(), |_| unreachable!())
+            .expect("must succeed");
+        assert!(diffs.is_empty());
+    }
+
+    /// A difference in an input derivation changes the store paths of the
+    /// derivations depending on it, which must not be reported.
+    #[test]
+    fn suppresses_cascade() {
+        let ours: BTreeMap<_, _> = [
+            (
+                sp("0000000000000000000000000000000a-foo.drv"),
+                drv(FOO_OURS, None, &[("foo", "bar")]),
+            ),
+            (
+                sp("0000000000000000000000000000000b-root.drv"),
+                drv(
+                    "0000000000000000000000000000000c-root",
+                    Some(("0000000000000000000000000000000a-foo.drv", FOO_OURS)),
+                    &[],
+                ),
+            ),
+        ]
+        .into();
+
+        let theirs: BTreeMap<_, _> = [
+            (
+                sp("1111111111111111111111111111111a-foo.drv"),
+                drv(FOO_THEIRS, None, &[("foo", "baz")]),
+            ),
+            (
+                sp("1111111111111111111111111111111b-root.drv"),
+                drv(
+                    "1111111111111111111111111111111c-root",
+                    Some(("1111111111111111111111111111111a-foo.drv", FOO_THEIRS)),
+                    &[],
+                ),
+            ),
+        ]
+        .into();
+
+        let diffs = diff::<(), _, _>(
+            &sp("0000000000000000000000000000000b-root.drv"),
+            &sp("1111111111111111111111111111111b-root.drv"),
+            |p| Ok(ours[p].clone()),
+            |p| Ok(theirs[p].clone()),
+        )
+        .expect("must succeed");
+
+        assert_eq!(1, diffs.len(), "only foo must be reported: {diffs:?}");
+        assert_eq!(
+            sp("0000000000000000000000000000000a-foo.drv"),
+            diffs[0].ours
+        );
+        assert_eq!(
+            vec![Difference::Environment {
+                key: "foo".into(),
+                ours: Some("bar".into()),
+                theirs: Some("baz".into()),
+            }],
+            diffs[0].differences
+        );
+    }
+
+    #[test]
+    fn missing_input() {
+        let ours: BTreeMap<_, _> = [
+            (
+                sp("0000000000000000000000000000000a-foo.drv"),
+                drv(FOO_OURS, None, &[]),
+            ),
+            (
+                sp("0000000000000000000000000000000b-root.drv"),
+                drv(
+                    "0000000000000000000000000000000c-root",
+                    Some(("0000000000000000000000000000000a-foo.drv", FOO_OURS)),
+                    &[],
+                ),
+            ),
+        ]
+        .into();
+
+        let theirs: BTreeMap<_, _> = [(
+            sp("1111111111111111111111111111111b-root.drv"),
+            drv("1111111111111111111111111111111c-root", None, &[]),
+        )]
+        .into();
+
+        let diffs = diff::<(), _, _>(
+            &sp("0000000000000000000000000000000b-root.drv"),
+            &sp("1111111111111111111111111111111b-root.drv"),
+            |p| Ok(ours[p].clone()),
+            |p| Ok(theirs[p].clone()),
+        )
+        .expect("must succeed");
+
+        assert_eq!(1, diffs.len(), "{diffs:?}");
+        assert_eq!(
+            vec![
+                Difference::InputDerivation {
+                    ours: Some(sp("0000000000000000000000000000000a-foo.drv")),
+                    theirs: None,
+                },
+                Difference::Environment {
+                    key: "input".into(),
+                    ours: Some(format!("/nix/store/{FOO_OURS}").into()),
+                    theirs: None,
+                }
+            ],
+            diffs[0].differences
+        );
+    }
+}
@@ -7,6 +7,7 @@ use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, BTreeSet};
 use std::io;
 
+pub mod diff;
 mod errors;
 mod output;
 mod parse_error;
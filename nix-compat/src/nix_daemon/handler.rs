@@ -5,7 +5,7 @@ use tokio::{
     io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf},
     sync::Mutex,
 };
-use tracing::{debug, warn};
+use tracing::{debug, instrument, warn};
 
 use super::{
     framing::{NixFramedReader, StderrReadFramedReader},
@@ -113,105 +113,114 @@ where
 
     /// Main client connection loop, reads client's requests and responds to them accordingly.
     pub async fn handle_client(&mut self) -> Result<(), std::io::Error> {
-        let io = self.io.clone();
         loop {
             let op_code = self.reader.read_number().await?;
             match TryInto::<Operation>::try_into(op_code) {
-                // Note: please keep operations sorted in ascending order of their numerical op number.
-                Ok(operation) => match operation {
-                    Operation::IsValidPath => {
-                        let path: StorePath<String> = self.reader.read_value().await?;
-                        Self::handle(&self.writer, io.is_valid_path(&path)).await?
-                    }
-                    // Note this operation does not currently delegate to NixDaemonIO,
-                    // The general idea is that we will pass relevant ClientSettings
-                    // into individual NixDaemonIO method calls if the need arises.
-                    // For now we just store the settings in the NixDaemon for future use.
-                    Operation::SetOptions => {
-                        self.client_settings = self.reader.read_value().await?;
-                        Self::handle(&self.writer, async { Ok(()) }).await?
-                    }
-                    Operation::QueryPathInfo => {
-                        let path: StorePath<String> = self.reader.read_value().await?;
-                        Self::handle(&self.writer, io.query_path_info(&path)).await?
-                    }
-                    Operation::QueryPathFromHashPart => {
-                        let hash: Bytes = self.reader.read_value().await?;
-                        Self::handle(&self.writer, io.query_path_from_hash_part(&hash)).await?
-                    }
-                    Operation::QueryValidPaths => {
-                        let query: QueryValidPaths = self.reader.read_value().await?;
-                        Self::handle(&self.writer, io.query_valid_paths(&query)).await?
-                    }
-                    Operation::QueryValidDerivers => {
-                        let path: StorePath<String> = self.reader.read_value().await?;
-                        Self::handle(&self.writer, io.query_valid_derivers(&path)).await?
+                Ok(operation) => self.handle_operation(operation).await?,
+                _ => {
+                    return Err(std::io::Error::other(format!(
+                        "Unknown operation code received: {op_code}"
+                    )));
+                }
+            }
+        }
+    }
+
+    /// The settings sent by the client, last updated by a SetOptions operation.
+    pub fn client_settings(&self) -> &ClientSettings {
+        &self.client_settings
+    }
+
+    /// Reads the arguments of a single operation, and handles it.
+    #[instrument(skip(self), level = "info", fields(protocol_version = %self.protocol_version))]
+    async fn handle_operation(&mut self, operation: Operation) -> Result<(), std::io::Error> {
+        // Note: please keep operations sorted in ascending order of their numerical op number.
+        match operation {
+            Operation::IsValidPath => {
+                let path: StorePath<String> = self.reader.read_value().await?;
+                Self::handle(&self.writer, self.io.is_valid_path(&path)).await?
+            }
+            // Note this operation does not currently delegate to NixDaemonIO,
+            // The general idea is that we will pass relevant ClientSettings
+            // into individual NixDaemonIO method calls if the need arises.
+            // For now we just store the settings in the NixDaemon for future use.
+            Operation::SetOptions => {
+                self.client_settings = self.reader.read_value().await?;
+                Self::handle(&self.writer, async { Ok(()) }).await?
+            }
+            Operation::QueryPathInfo => {
+                let path: StorePath<String> = self.reader.read_value().await?;
+                Self::handle(&self.writer, self.io.query_path_info(&path)).await?
+            }
+            Operation::QueryPathFromHashPart => {
+                let hash: Bytes = self.reader.read_value().await?;
+                Self::handle(&self.writer, self.io.query_path_from_hash_part(&hash)).await?
+            }
+            Operation::QueryValidPaths => {
+                let query: QueryValidPaths = self.reader.read_value().await?;
+                Self::handle(&self.writer, self.io.query_valid_paths(&query)).await?
+            }
+            Operation::QueryValidDerivers => {
+                let path: StorePath<String> = self.reader.read_value().await?;
+                Self::handle(&self.writer, self.io.query_valid_derivers(&path)).await?
+            }
+            // FUTUREWORK: These are just stubs that return an empty list.
+            // It's important not to return an error for the local-overlay:// store
+            // to work properly. While it will not see certain referrers and realizations
+            // it will not fail on various operations like gc and optimize store. At the
+            // same time, returning an empty list here shouldn't break any of local-overlay store's
+            // invariants.
+            Operation::QueryReferrers | Operation::QueryRealisation => {
+                let _: String = self.reader.read_value().await?;
+                Self::handle(&self.writer, async move {
+                    warn!(
+                        ?operation,
+                        "This operation is not implemented. Returning empty result..."
+                    );
+                    Ok(Vec::<StorePath<String>>::new())
+                })
+                .await?
+            }
+            Operation::AddToStoreNar => {
+                let request: AddToStoreNarRequest = self.reader.read_value().await?;
+                let minor_version = self.protocol_version.minor();
+                match minor_version {
+                    ..21 => {
+                        // Before protocol version 1.21, the nar is sent unframed, so we just
+                        // pass the reader directly to the operation.
+                        Self::handle(
+                            &self.writer,
+                            self.io.add_to_store_nar(request, &mut self.reader),
+                        )
+                        .await?
                     }
-                    // FUTUREWORK: These are just stubs that return an empty list.
-                    // It's important not to return an error for the local-overlay:// store
-                    // to work properly. While it will not see certain referrers and realizations
-                    // it will not fail on various operations like gc and optimize store. At the
-                    // same time, returning an empty list here shouldn't break any of local-overlay store's
-                    // invariants.
-                    Operation::QueryReferrers | Operation::QueryRealisation => {
-                        let _: String = self.reader.read_value().await?;
-                        Self::handle(&self.writer, async move {
-                            warn!(
-                                ?operation,
-                                "This operation is not implemented. Returning empty result..."
-                            );
-                            Ok(Vec::<StorePath<String>>::new())
+                    21..23 => {
+                        // Protocol versions 1.21 .. 1.23 use STDERR_READ protocol, see logging.md#stderr_read.
+                        Self::handle(&self.writer, async {
+                            let mut writer = self.writer.lock().await;
+                            let mut reader =
+                                StderrReadFramedReader::new(&mut self.reader, writer.deref_mut());
+                            self.io.add_to_store_nar(request, &mut reader).await
                         })
                         .await?
                     }
-                    Operation::AddToStoreNar => {
-                        let request: AddToStoreNarRequest = self.reader.read_value().await?;
-                        let minor_version = self.protocol_version.minor();
-                        match minor_version {
-                            ..21 => {
-                                // Before protocol version 1.21, the nar is sent unframed, so we just
-                                // pass the reader directly to the operation.
-                                Self::handle(
-                                    &self.writer,
-                                    self.io.add_to_store_nar(request, &mut self.reader),
-                                )
-                                .await?
-                            }
-                            21..23 => {
-                                // Protocol versions 1.21 .. 1.23 use STDERR_READ protocol, see logging.md#stderr_read.
-                                Self::handle(&self.writer, async {
-                                    let mut writer = self.writer.lock().await;
-                                    let mut reader = StderrReadFramedReader::new(
-                                        &mut self.reader,
-                                        writer.deref_mut(),
-                                    );
-                                    self.io.add_to_store_nar(request, &mut reader).await
-                                })
-                                .await?
-                            }
-                            23.. => {
-                                // Starting at protocol version 1.23, the framed protocol is used, see serialization.md#framed
-                                let mut framed = NixFramedReader::new(&mut self.reader);
-                                Self::handle(&self.writer, async {
-                                    self.io.add_to_store_nar(request, &mut framed).await
-                                })
-                                .await?
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(std::io::Error::other(format!(
-                            "Operation {operation:?} is not implemented"
-                        )));
+                    23.. => {
+                        // Starting at protocol version 1.23, the framed protocol is used, see serialization.md#framed
+                        let mut framed = NixFramedReader::new(&mut self.reader);
+                        Self::handle(&self.writer, async {
+                            self.io.add_to_store_nar(request, &mut framed).await
+                        })
+                        .await?
                     }
-                },
-                _ => {
-                    return Err(std::io::Error::other(format!(
-                        "Unknown operation code received: {op_code}"
-                    )));
                 }
             }
+            _ => {
+                return Err(std::io::Error::other(format!(
+                    "Operation {operation:?} is not implemented"
+                )));
+            }
         }
+        Ok(())
     }
 
     /// Handles the operation and sends the response or error to the client.
@@ -425,4 +434,81 @@ mod tests {
                 .kind()
         );
     }
+
+    /// Records the names and fields of all spans created.
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<std::sync::Mutex<Vec<(&'static str, Vec<(String, String)>)>>>);
+
+    impl tracing::field::Visit for SpanRecorder {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if let Some((_, fields)) = self.0.lock().unwrap().last_mut() {
+                fields.push((field.name().to_string(), format!("{:?}", value)));
+            }
+        }
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let id = {
+                let mut spans = self.0.lock().unwrap();
+                spans.push((span.metadata().name(), vec![]));
+                spans.len() as u64
+            };
+            span.record(&mut self.clone());
+            tracing::span::Id::from_u64(id)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+        fn event(&self, _event: &tracing::Event<'_>) {}
+        fn enter(&self, _span: &tracing::span::Id) {}
+        fn exit(&self, _span: &tracing::span::Id) {}
+    }
+
+    /// Each operation is handled in its own span, carrying the operation and
+    /// the protocol version.
+    #[tokio::test]
+    async fn test_handle_operation_span() {
+        let recorder = SpanRecorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let version = ProtocolVersion::from_parts(1, 37);
+        let (io, mut handle) = tokio_test::io::Builder::new().build_with_handle();
+        let mut mock = MockNixDaemonIO::new();
+        let (reader, writer) = split(io);
+        let path: StorePath<String> = StorePath::<String>::from_absolute_path(
+            "/nix/store/33l4p0pn0mybmqzaxfkpppyh7vx1c74p-hello-2.12.1".as_bytes(),
+        )
+        .unwrap();
+        mock.expect_is_valid_path()
+            .with(predicate::eq(path.clone()))
+            .times(1)
+            .returning(|_| Box::pin(async { Ok(true) }));
+
+        handle.read(&Into::<u64>::into(Operation::IsValidPath).to_le_bytes());
+        handle.read(&serialize(&path, version).await);
+        handle.write(&respond(&Ok(true), version).await);
+        drop(handle);
+
+        let mut daemon = NixDaemon::new(
+            Arc::new(mock),
+            version,
+            ClientSettings::default(),
+            NixReader::new(reader),
+            NixWriter::new(writer),
+        );
+        daemon.handle_client().await.expect_err("Expecting eof");
+
+        let spans = recorder.0.lock().unwrap().clone();
+        let (_, fields) = spans
+            .iter()
+            .find(|(name, _)| *name == "handle_operation")
+            .expect("must have a handle_operation span");
+        assert!(fields.contains(&("operation".to_string(), "IsValidPath".to_string())));
+        assert!(fields.contains(&("protocol_version".to_string(), "1.37".to_string())));
+    }
 }
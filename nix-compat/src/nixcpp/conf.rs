@@ -1,35 +1,109 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::narinfo::VerifyingKey;
+
+/// The maximum depth of nested `include` statements, which guards
+/// against include cycles.
+const MAX_INCLUDE_DEPTH: usize = 16;
 
 /// Represents configuration as stored in /etc/nix/nix.conf.
-/// This list is not exhaustive, feel free to add more.
+/// This list is not exhaustive, feel free to add more. Settings not
+/// known here are kept verbatim in [NixConfig::other].
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct NixConfig<'a> {
-    pub allowed_users: Option<Vec<&'a str>>,
+pub struct NixConfig {
+    pub allowed_users: Option<Vec<String>>,
     pub auto_optimise_store: Option<bool>,
     pub cores: Option<u64>,
     pub max_jobs: Option<u64>,
     pub require_sigs: Option<bool>,
     pub sandbox: Option<SandboxSetting>,
     pub sandbox_fallback: Option<bool>,
-    pub substituters: Option<Vec<&'a str>>,
-    pub system_features: Option<Vec<&'a str>>,
-    pub trusted_public_keys: Option<Vec<crate::narinfo::VerifyingKey>>,
-    pub trusted_substituters: Option<Vec<&'a str>>,
-    pub trusted_users: Option<Vec<&'a str>>,
-    pub extra_platforms: Option<Vec<&'a str>>,
-    pub extra_sandbox_paths: Option<Vec<&'a str>>,
-    pub experimental_features: Option<Vec<&'a str>>,
+    pub store: Option<String>,
+    pub substituters: Option<Vec<String>>,
+    pub system_features: Option<Vec<String>>,
+    pub trusted_public_keys: Option<Vec<VerifyingKey>>,
+    pub trusted_substituters: Option<Vec<String>>,
+    pub trusted_users: Option<Vec<String>>,
+    pub extra_platforms: Option<Vec<String>>,
+    pub extra_sandbox_paths: Option<Vec<String>>,
+    pub experimental_features: Option<Vec<String>>,
     pub builders_use_substitutes: Option<bool>,
+    pub netrc_file: Option<PathBuf>,
+    /// Number of seconds a lookup of a missing .narinfo is cached.
+    pub narinfo_cache_negative_ttl: Option<u64>,
+    /// Number of seconds a lookup of an existing .narinfo is cached.
+    pub narinfo_cache_positive_ttl: Option<u64>,
+    /// Timeout (in seconds) for connecting to binary caches.
+    pub connect_timeout: Option<u64>,
+    /// All other settings, with their (unparsed) values.
+    pub other: BTreeMap<String, String>,
 }
 
-impl<'a> NixConfig<'a> {
+impl NixConfig {
     /// Parses configuration from a file like `/etc/nix/nix.conf`, returning
     /// a [NixConfig] with all values contained in there.
-    /// It does not support parsing multiple config files, merging semantics,
-    /// and also does not understand `include` and `!include` statements.
-    pub fn parse(input: &'a str) -> Result<Self, Error> {
+    /// Relative paths in `include` statements are resolved relative to the
+    /// current working directory, use [NixConfig::apply_file] to resolve
+    /// them relative to a file.
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        let mut out = Self::default();
+        out.apply_str(input, None, 0)?;
+        Ok(out)
+    }
+
+    /// Loads the configuration the same way Nix does, from (in order of
+    /// increasing precedence):
+    ///
+    /// - `$NIX_CONF_DIR/nix.conf` (defaulting to `/etc/nix/nix.conf`),
+    /// - the files listed in `$NIX_USER_CONF_FILES`, or if unset,
+    ///   `nix/nix.conf` in the XDG config directories,
+    /// - the contents of `$NIX_CONFIG`.
+    ///
+    /// Files which do not exist are skipped.
+    pub fn load() -> Result<Self, Error> {
+        Self::load_with_env(|name| std::env::var(name).ok())
+    }
+
+    /// Like [NixConfig::load], but looks up environment variables with
+    /// the given function.
+    pub fn load_with_env(env: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
         let mut out = Self::default();
 
+        for path in config_files(&env) {
+            if path.exists() {
+                out.apply_file(&path)?;
+            }
+        }
+
+        if let Some(config) = env("NIX_CONFIG") {
+            out.apply_str(&config, None, 0)?;
+        }
+
+        Ok(out)
+    }
+
+    /// Applies the settings in the given file on top of the current ones.
+    /// Settings set again replace the current value, `extra-` settings
+    /// append to it.
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), Error> {
+        self.apply_file_at_depth(path, 0)
+    }
+
+    fn apply_file_at_depth(&mut self, path: &Path, depth: usize) -> Result<(), Error> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(Error::IncludeDepth(path.to_owned()));
+        }
+
+        let input = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        self.apply_str(&input, path.parent(), depth)
+    }
+
+    fn apply_str(&mut self, input: &str, dir: Option<&Path>, depth: usize) -> Result<(), Error> {
         for line in input.lines() {
             // strip comments at the end of the line
             let line = if let Some((line, _comment)) = line.split_once('#') {
@@ -43,75 +117,210 @@ impl<'a> NixConfig<'a> {
                 continue;
             }
 
-            let (tag, val) = line
-                .split_once('=')
-                .ok_or_else(|| Error::InvalidLine(line.to_string()))?;
+            let Some((tag, val)) = line.split_once('=') else {
+                let (optional, include) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["include", path] => (false, path),
+                    ["!include", path] => (true, path),
+                    _ => return Err(Error::InvalidLine(line.to_string())),
+                };
+
+                let path = match dir {
+                    Some(dir) => dir.join(include),
+                    None => PathBuf::from(include),
+                };
+
+                if optional && !path.exists() {
+                    continue;
+                }
+
+                self.apply_file_at_depth(&path, depth + 1)?;
+                continue;
+            };
 
             // trim whitespace
             let tag = tag.trim();
             let val = val.trim();
 
-            #[inline]
-            fn parse_val<'a>(this: &mut NixConfig<'a>, tag: &str, val: &'a str) -> Option<()> {
-                match tag {
-                    "allowed-users" => {
-                        this.allowed_users = Some(val.split_whitespace().collect());
-                    }
-                    "auto-optimise-store" => {
-                        this.auto_optimise_store = Some(val.parse::<bool>().ok()?);
-                    }
-                    "cores" => {
-                        this.cores = Some(val.parse().ok()?);
-                    }
-                    "max-jobs" => {
-                        this.max_jobs = Some(val.parse().ok()?);
-                    }
-                    "require-sigs" => {
-                        this.require_sigs = Some(val.parse().ok()?);
-                    }
-                    "sandbox" => this.sandbox = Some(val.parse().ok()?),
-                    "sandbox-fallback" => this.sandbox_fallback = Some(val.parse().ok()?),
-                    "substituters" => this.substituters = Some(val.split_whitespace().collect()),
-                    "system-features" => {
-                        this.system_features = Some(val.split_whitespace().collect())
-                    }
-                    "trusted-public-keys" => {
-                        this.trusted_public_keys = Some(
-                            val.split_whitespace()
-                                .map(crate::narinfo::VerifyingKey::parse)
-                                .collect::<Result<Vec<crate::narinfo::VerifyingKey>, _>>()
-                                .ok()?,
-                        )
-                    }
-                    "trusted-substituters" => {
-                        this.trusted_substituters = Some(val.split_whitespace().collect())
-                    }
-                    "trusted-users" => this.trusted_users = Some(val.split_whitespace().collect()),
-                    "extra-platforms" => {
-                        this.extra_platforms = Some(val.split_whitespace().collect())
-                    }
-                    "extra-sandbox-paths" => {
-                        this.extra_sandbox_paths = Some(val.split_whitespace().collect())
-                    }
-                    "experimental-features" => {
-                        this.experimental_features = Some(val.split_whitespace().collect())
-                    }
-                    "builders-use-substitutes" => {
-                        this.builders_use_substitutes = Some(val.parse().ok()?)
+            self.set(tag, val)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets a single setting. Unless a setting with the exact name is
+    /// known, `extra-foo` appends to the list in `foo`.
+    fn set(&mut self, tag: &str, val: &str) -> Result<(), Error> {
+        let result = match self.set_known(tag, val, false) {
+            Some(result) => result,
+            None => match tag
+                .strip_prefix("extra-")
+                .and_then(|base| self.set_known(base, val, true))
+            {
+                Some(result) => result,
+                None => {
+                    match tag
+                        .strip_prefix("extra-")
+                        .and_then(|base| self.other.get_mut(base))
+                    {
+                        Some(current) => {
+                            current.push(' ');
+                            current.push_str(val);
+                        }
+                        None => {
+                            self.other.insert(tag.to_string(), val.to_string());
+                        }
                     }
-                    _ => return None,
+                    Some(())
                 }
-                Some(())
+            },
+        };
+
+        result.ok_or_else(|| Error::InvalidValue(tag.to_string(), val.to_string()))
+    }
+
+    /// Sets the setting if it is known, returning [None] if it is not,
+    /// and `Some(None)` if the value is invalid (including appending to a
+    /// setting which is not a list).
+    fn set_known(&mut self, tag: &str, val: &str, append: bool) -> Option<Option<()>> {
+        #[inline]
+        fn list<T>(field: &mut Option<Vec<T>>, items: Vec<T>, append: bool) {
+            match field {
+                Some(current) if append => current.extend(items),
+                _ => *field = Some(items),
             }
+        }
 
-            parse_val(&mut out, tag, val)
-                .ok_or_else(|| Error::InvalidValue(tag.to_string(), val.to_string()))?
+        #[inline]
+        fn strings(val: &str) -> Vec<String> {
+            val.split_whitespace().map(ToString::to_string).collect()
         }
 
-        Ok(out)
+        #[inline]
+        fn parse_val(this: &mut NixConfig, tag: &str, val: &str, append: bool) -> Option<()> {
+            match (tag, append) {
+                ("allowed-users", _) => list(&mut this.allowed_users, strings(val), append),
+                ("substituters", _) => list(&mut this.substituters, strings(val), append),
+                ("system-features", _) => list(&mut this.system_features, strings(val), append),
+                ("trusted-public-keys", _) => list(
+                    &mut this.trusted_public_keys,
+                    val.split_whitespace()
+                        .map(VerifyingKey::parse)
+                        .collect::<Result<Vec<VerifyingKey>, _>>()
+                        .ok()?,
+                    append,
+                ),
+                ("trusted-substituters", _) => {
+                    list(&mut this.trusted_substituters, strings(val), append)
+                }
+                ("trusted-users", _) => list(&mut this.trusted_users, strings(val), append),
+                ("extra-platforms", _) => list(&mut this.extra_platforms, strings(val), append),
+                ("extra-sandbox-paths", _) => {
+                    list(&mut this.extra_sandbox_paths, strings(val), append)
+                }
+                ("experimental-features", _) => {
+                    list(&mut this.experimental_features, strings(val), append)
+                }
+
+                // Settings below are not lists, so can not be appended to.
+                (_, true) => return None,
+                ("auto-optimise-store", _) => {
+                    this.auto_optimise_store = Some(val.parse::<bool>().ok()?);
+                }
+                ("cores", _) => this.cores = Some(val.parse().ok()?),
+                ("max-jobs", _) => this.max_jobs = Some(val.parse().ok()?),
+                ("require-sigs", _) => this.require_sigs = Some(val.parse().ok()?),
+                ("sandbox", _) => this.sandbox = Some(val.parse().ok()?),
+                ("sandbox-fallback", _) => this.sandbox_fallback = Some(val.parse().ok()?),
+                ("store", _) => this.store = Some(val.to_string()),
+                ("builders-use-substitutes", _) => {
+                    this.builders_use_substitutes = Some(val.parse().ok()?)
+                }
+                ("netrc-file", _) => this.netrc_file = Some(PathBuf::from(val)),
+                ("narinfo-cache-negative-ttl", _) => {
+                    this.narinfo_cache_negative_ttl = Some(val.parse().ok()?)
+                }
+                ("narinfo-cache-positive-ttl", _) => {
+                    this.narinfo_cache_positive_ttl = Some(val.parse().ok()?)
+                }
+                ("connect-timeout", _) => this.connect_timeout = Some(val.parse().ok()?),
+                _ => unreachable!("unknown settings are filtered out before"),
+            }
+            Some(())
+        }
+
+        const KNOWN: &[&str] = &[
+            "allowed-users",
+            "auto-optimise-store",
+            "builders-use-substitutes",
+            "connect-timeout",
+            "cores",
+            "experimental-features",
+            "extra-platforms",
+            "extra-sandbox-paths",
+            "max-jobs",
+            "narinfo-cache-negative-ttl",
+            "narinfo-cache-positive-ttl",
+            "netrc-file",
+            "require-sigs",
+            "sandbox",
+            "sandbox-fallback",
+            "store",
+            "substituters",
+            "system-features",
+            "trusted-public-keys",
+            "trusted-substituters",
+            "trusted-users",
+        ];
+
+        if !KNOWN.contains(&tag) {
+            return None;
+        }
+
+        Some(parse_val(self, tag, val, append))
     }
 }
 
+/// Returns the configuration files read by [NixConfig::load], in the
+/// order they are applied.
+pub fn config_files(env: impl Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    let mut files =
+        vec![
+            PathBuf::from(env("NIX_CONF_DIR").unwrap_or_else(|| "/etc/nix".to_string()))
+                .join("nix.conf"),
+        ];
+
+    if let Some(user_files) = env("NIX_USER_CONF_FILES") {
+        files.extend(
+            user_files
+                .split(':')
+                .filter(|f| !f.is_empty())
+                .map(PathBuf::from),
+        );
+        return files;
+    }
+
+    // The first directory takes precedence, so it is applied last.
+    let mut dirs: Vec<PathBuf> = vec![];
+    match env("XDG_CONFIG_HOME") {
+        Some(dir) => dirs.push(dir.into()),
+        None => {
+            if let Some(home) = env("HOME") {
+                dirs.push(PathBuf::from(home).join(".config"));
+            }
+        }
+    }
+    dirs.extend(
+        env("XDG_CONFIG_DIRS")
+            .unwrap_or_else(|| "/etc/xdg".to_string())
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from),
+    );
+
+    files.extend(dirs.iter().rev().map(|dir| dir.join("nix/nix.conf")));
+    files
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Invalid line: {0}")]
@@ -120,6 +329,10 @@ pub enum Error {
     UnrecognizedKey(String),
     #[error("Invalid value '{1}' for key '{0}'")]
     InvalidValue(String, String),
+    #[error("Unable to read {}: {1}", .0.display())]
+    Io(PathBuf, std::io::Error),
+    #[error("Includes nested too deeply in {}", .0.display())]
+    IncludeDepth(PathBuf),
 }
 
 /// Valid values for the Nix 'sandbox' setting
@@ -155,9 +368,21 @@ impl FromStr for SandboxSetting {
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
     use crate::{narinfo::VerifyingKey, nixcpp::conf::SandboxSetting};
 
-    use super::NixConfig;
+    use super::{config_files, Error, NixConfig};
+
+    fn strings(s: &[&str]) -> Option<Vec<String>> {
+        Some(s.iter().map(ToString::to_string).collect())
+    }
+
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/nix.conf.d")
+            .join(name)
+    }
 
     #[test]
     pub fn test_parse() {
@@ -165,29 +390,30 @@ mod tests {
 
         assert_eq!(
             NixConfig {
-                allowed_users: Some(vec!["*"]),
+                allowed_users: strings(&["*"]),
                 auto_optimise_store: Some(false),
                 cores: Some(0),
                 max_jobs: Some(8),
                 require_sigs: Some(true),
                 sandbox: Some(SandboxSetting::True),
                 sandbox_fallback: Some(false),
-                substituters: Some(vec!["https://nix-community.cachix.org", "https://cache.nixos.org/"]),
-                system_features: Some(vec!["nixos-test", "benchmark", "big-parallel", "kvm"]),
+                substituters: strings(&["https://nix-community.cachix.org", "https://cache.nixos.org/"]),
+                system_features: strings(&["nixos-test", "benchmark", "big-parallel", "kvm"]),
                 trusted_public_keys: Some(vec![
                     VerifyingKey::parse("cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=")
                         .expect("failed to parse pubkey"),
                     VerifyingKey::parse("nix-community.cachix.org-1:mB9FSh9qf2dCimDSUo8Zy7bkq5CX+/rkCWyvRCYg3Fs=")
                         .expect("failed to parse pubkey")
                 ]),
-                trusted_substituters: Some(vec![]),
-                trusted_users: Some(vec!["flokli"]),
-                extra_platforms: Some(vec!["aarch64-linux", "i686-linux"]),
-                extra_sandbox_paths: Some(vec![
+                trusted_substituters: strings(&[]),
+                trusted_users: strings(&["flokli"]),
+                extra_platforms: strings(&["aarch64-linux", "i686-linux"]),
+                extra_sandbox_paths: strings(&[
                     "/run/binfmt", "/nix/store/swwyxyqpazzvbwx8bv40z7ih144q841f-qemu-aarch64-binfmt-P-x86_64-unknown-linux-musl"
                 ]),
-                experimental_features: Some(vec!["nix-command"]),
-                builders_use_substitutes: Some(true)
+                experimental_features: strings(&["nix-command"]),
+                builders_use_substitutes: Some(true),
+                ..Default::default()
             },
             config
         );
@@ -199,4 +425,111 @@ mod tests {
 
         assert_eq!(config, other_config);
     }
+
+    #[test]
+    fn test_include_and_extra() {
+        let mut config = NixConfig::default();
+        config
+            .apply_file(&testdata("nix.conf"))
+            .expect("must parse");
+
+        assert_eq!(
+            NixConfig {
+                substituters: strings(&[
+                    "https://cache.nixos.org/",
+                    "https://nix-community.cachix.org"
+                ]),
+                connect_timeout: Some(5),
+                netrc_file: Some("/etc/nix/netrc".into()),
+                narinfo_cache_negative_ttl: Some(0),
+                narinfo_cache_positive_ttl: Some(2592000),
+                other: [("keep-outputs", "true"), ("allowed-uris", "github:")]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            },
+            config
+        );
+
+        // setting it again replaces the value
+        config
+            .apply_str("substituters = https://example.org", None, 0)
+            .unwrap();
+        assert_eq!(strings(&["https://example.org"]), config.substituters);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            NixConfig::parse("extra-cores = 4"),
+            Err(Error::InvalidValue(..))
+        ));
+        assert!(matches!(
+            NixConfig::parse("include"),
+            Err(Error::InvalidLine(..))
+        ));
+        assert!(matches!(
+            NixConfig::default().apply_file(&testdata("cycle.conf")),
+            Err(Error::IncludeDepth(..))
+        ));
+        assert!(matches!(
+            NixConfig::parse(&format!("include {}", testdata("missing.conf").display())),
+            Err(Error::Io(..))
+        ));
+    }
+
+    #[test]
+    fn test_load() {
+        let env = HashMap::from([
+            ("NIX_CONF_DIR", testdata("").to_string_lossy().into_owned()),
+            (
+                "NIX_USER_CONF_FILES",
+                format!(
+                    "{}:{}",
+                    testdata("missing.conf").display(),
+                    testdata("user.conf").display()
+                ),
+            ),
+            (
+                "NIX_CONFIG",
+                "connect-timeout = 10\nextra-substituters = https://example.com".to_string(),
+            ),
+        ]);
+
+        let config = NixConfig::load_with_env(|name| env.get(name).cloned()).expect("must load");
+
+        assert_eq!(
+            strings(&[
+                "https://cache.nixos.org/",
+                "https://nix-community.cachix.org",
+                "https://example.org",
+                "https://example.com"
+            ]),
+            config.substituters
+        );
+        assert_eq!(Some(10), config.connect_timeout);
+        assert_eq!(
+            Some(&"github: gitlab:".to_string()),
+            config.other.get("allowed-uris")
+        );
+    }
+
+    #[test]
+    fn test_config_files() {
+        let env = HashMap::from([
+            ("HOME", "/home/user"),
+            ("XDG_CONFIG_DIRS", "/etc/xdg:/run/xdg"),
+        ]);
+
+        assert_eq!(
+            vec![
+                PathBuf::from("/etc/nix/nix.conf"),
+                PathBuf::from("/run/xdg/nix/nix.conf"),
+                PathBuf::from("/etc/xdg/nix/nix.conf"),
+                PathBuf::from("/home/user/.config/nix/nix.conf"),
+            ],
+            config_files(|name| env.get(name).map(ToString::to_string))
+        );
+    }
 }
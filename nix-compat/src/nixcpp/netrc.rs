@@ -0,0 +1,122 @@
+//! Parses netrc files, as referred to by the `netrc-file` setting in
+//! nix.conf, which hold credentials for binary caches.
+
+/// A single `machine` (or `default`) entry in a netrc file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NetrcEntry {
+    /// The host name, or [None] for the `default` entry.
+    pub machine: Option<String>,
+    pub login: Option<String>,
+    pub password: Option<String>,
+}
+
+/// The entries of a netrc file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Netrc {
+    pub entries: Vec<NetrcEntry>,
+}
+
+impl Netrc {
+    /// Parses the contents of a netrc file.
+    /// `account` tokens are ignored, as are `macdef` definitions.
+    pub fn parse(input: &str) -> Self {
+        let mut entries: Vec<NetrcEntry> = vec![];
+        let mut lines = input.lines();
+
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+
+            while let Some(token) = tokens.next() {
+                match token {
+                    "machine" => entries.push(NetrcEntry {
+                        machine: tokens.next().map(ToString::to_string),
+                        ..Default::default()
+                    }),
+                    "default" => entries.push(NetrcEntry::default()),
+                    "login" | "password" | "account" => {
+                        let value = tokens.next().map(ToString::to_string);
+                        if let Some(entry) = entries.last_mut() {
+                            match token {
+                                "login" => entry.login = value,
+                                "password" => entry.password = value,
+                                _ => {}
+                            }
+                        }
+                    }
+                    "macdef" => {
+                        // macro definitions extend until the next empty line
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Self { entries }
+    }
+
+    /// Returns the entry for the given host, falling back to the
+    /// `default` entry.
+    pub fn find(&self, host: &str) -> Option<&NetrcEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.machine.as_deref() == Some(host))
+            .or_else(|| self.entries.iter().find(|entry| entry.machine.is_none()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Netrc, NetrcEntry};
+
+    #[test]
+    fn test_parse() {
+        let netrc = Netrc::parse(
+            "machine cache.example.org login alice password secret\n\
+             macdef init\n\
+             machine ignored.example.org\n\
+             \n\
+             machine other.example.org\n  login bob\n  account unused\n\
+             default login anonymous",
+        );
+
+        assert_eq!(
+            vec![
+                NetrcEntry {
+                    machine: Some("cache.example.org".into()),
+                    login: Some("alice".into()),
+                    password: Some("secret".into()),
+                },
+                NetrcEntry {
+                    machine: Some("other.example.org".into()),
+                    login: Some("bob".into()),
+                    password: None,
+                },
+                NetrcEntry {
+                    machine: None,
+                    login: Some("anonymous".into()),
+                    password: None,
+                },
+            ],
+            netrc.entries
+        );
+
+        assert_eq!(
+            Some("bob"),
+            netrc
+                .find("other.example.org")
+                .and_then(|e| e.login.as_deref())
+        );
+        assert_eq!(
+            Some("anonymous"),
+            netrc
+                .find("cache.nixos.org")
+                .and_then(|e| e.login.as_deref())
+        );
+    }
+}
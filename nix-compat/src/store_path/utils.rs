@@ -73,7 +73,7 @@ where
     I: IntoIterator<Item = S>,
 {
     // self references are only allowed for CAHash::Nar(NixHash::Sha256(_)).
-    if self_reference && matches!(ca_hash, CAHash::Nar(NixHash::Sha256(_))) {
+    if self_reference && !matches!(ca_hash, CAHash::Nar(NixHash::Sha256(_))) {
         return Err(BuildStorePathError::InvalidReference());
     }
 
@@ -299,4 +299,26 @@ mod test {
             "/nix/store/s89y431zzhmdn3k8r96rvakryddkpv2v-baz"
         );
     }
+
+    #[test]
+    fn build_store_path_with_self_reference() {
+        let ca_hash = CAHash::Nar(NixHash::Sha256(
+            nixbase32::decode(b"1xqkzcb3909fp07qngljr4wcdnrh1gdam1m2n29i6hhrxlmkgkv1")
+                .expect("nixbase32 should decode")
+                .try_into()
+                .expect("should have right len"),
+        ));
+
+        let without_self: StorePathRef =
+            build_ca_path("baz", &ca_hash, Vec::<String>::new(), false)
+                .expect("build_ca_path() should succeed");
+        let with_self: StorePathRef = build_ca_path("baz", &ca_hash, Vec::<String>::new(), true)
+            .expect("build_ca_path() should succeed");
+
+        assert_ne!(without_self, with_self);
+
+        // self references are only allowed for CAHash::Nar(NixHash::Sha256(_))
+        build_ca_path::<_, String, _>("baz", &CAHash::Text([0; 32]), Vec::<String>::new(), true)
+            .expect_err("must fail");
+    }
 }
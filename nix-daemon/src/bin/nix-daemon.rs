@@ -4,7 +4,7 @@ use nix_compat::nix_daemon::handler::NixDaemon;
 use nix_daemon::TvixDaemon;
 use std::{error::Error, sync::Arc};
 use tokio_listener::SystemOptions;
-use tracing::error;
+use tracing::{error, info_span, Instrument};
 use tvix_store::utils::{construct_services, ServiceUrlsGrpc};
 
 #[global_allocator]
@@ -24,6 +24,16 @@ struct Cli {
     /// Whether to configure OTLP. Set --otlp=false to disable.
     #[arg(long, default_missing_value = "true", default_value = "true", num_args(0..=1), require_equals(true), action(clap::ArgAction::Set))]
     otlp: bool,
+
+    #[cfg(feature = "prometheus")]
+    /// The address to serve Prometheus metrics on, at /metrics.
+    /// Metrics are not served if this is not set.
+    #[arg(long, env)]
+    metrics_listen_address: Option<std::net::SocketAddr>,
+
+    /// The format of log lines written to stderr, "text" or "json".
+    #[arg(long, env, default_value_t)]
+    log_format: tvix_tracing::LogFormat,
 }
 
 #[tokio::main]
@@ -31,7 +41,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let cli = Cli::parse();
 
     let tracing_handle = {
-        let mut builder = tvix_tracing::TracingBuilder::default();
+        let mut builder = tvix_tracing::TracingBuilder::default().log_format(cli.log_format);
         builder = builder.enable_progressbar();
         #[cfg(feature = "otlp")]
         {
@@ -39,6 +49,10 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                 builder = builder.enable_otlp("tvix.daemon");
             }
         }
+        #[cfg(feature = "prometheus")]
+        if let Some(metrics_listen_address) = cli.metrics_listen_address {
+            builder = builder.enable_prometheus(metrics_listen_address);
+        }
         builder.build()?
     };
 
@@ -84,26 +98,34 @@ async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     while let Ok((connection, _)) = listener.accept().await {
         let io = io.clone();
-        tokio::spawn(async move {
-            match NixDaemon::initialize(io.clone(), connection).await {
-                Ok(mut daemon) => {
-                    if let Err(error) = daemon.handle_client().await {
-                        match error.kind() {
-                            std::io::ErrorKind::UnexpectedEof => {
-                                // client disconnected, nothing to do
-                            }
-                            _ => {
-                                // otherwise log the error and disconnect
-                                error!(error=?error, "client error");
+        // All operations of a connection are traced as children of this span.
+        let span = info_span!("nix_daemon.connection");
+        tokio::spawn(
+            async move {
+                match NixDaemon::initialize(io.clone(), connection).await {
+                    Ok(mut daemon) => {
+                        tvix_tracing::propagate::nix_daemon::accept_trace(
+                            &daemon.client_settings().overrides,
+                        );
+                        if let Err(error) = daemon.handle_client().await {
+                            match error.kind() {
+                                std::io::ErrorKind::UnexpectedEof => {
+                                    // client disconnected, nothing to do
+                                }
+                                _ => {
+                                    // otherwise log the error and disconnect
+                                    error!(error=?error, "client error");
+                                }
                             }
                         }
                     }
-                }
-                Err(error) => {
-                    error!(error=?error, "nix-daemon handshake failed");
+                    Err(error) => {
+                        error!(error=?error, "nix-daemon handshake failed");
+                    }
                 }
             }
-        });
+            .instrument(span),
+        );
     }
     Ok(())
 }
@@ -1,24 +1,112 @@
 //! Deserialisation from Nix to Rust values.
 
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
 use bstr::ByteSlice;
+use rnix::ast;
+use rowan::ast::AstNode;
 use serde::de::value::{MapDeserializer, SeqDeserializer};
-use serde::de::{self, EnumAccess, VariantAccess};
-use tvix_eval::{EvalIO, EvalMode, EvaluationBuilder, Value};
+use serde::de::{self, DeserializeOwned, EnumAccess, VariantAccess};
+use tvix_eval::{EvalIO, EvalMode, EvaluationBuilder, EvaluationResult, Value};
 
-use crate::error::Error;
+use crate::error::{Error, Location, PathSegment};
+use crate::io::SandboxedIO;
+use crate::ser::to_value;
 
 struct NixDeserializer {
     value: tvix_eval::Value,
+
+    /// Path to the value from the root of the deserialised value, used
+    /// to locate errors.
+    path: Vec<PathSegment>,
 }
 
 impl NixDeserializer {
     fn new(value: Value) -> Self {
+        Self::at(value, vec![])
+    }
+
+    fn at(value: Value, path: Vec<PathSegment>) -> Self {
         if let Value::Thunk(thunk) = value {
-            Self::new(thunk.value().clone())
+            Self::at(thunk.value().clone(), path)
         } else {
-            Self { value }
+            Self { value, path }
         }
     }
+
+    fn unexpected(&self, expected: &'static str) -> Error {
+        locate(
+            &self.path,
+            Error::UnexpectedType {
+                expected,
+                got: self.value.type_of(),
+            },
+        )
+    }
+
+    fn integer<I: TryFrom<i64>>(&self) -> Result<I, Error> {
+        match self.value {
+            Value::Integer(i) => I::try_from(i).map_err(|_| {
+                locate(
+                    &self.path,
+                    Error::IntegerConversion {
+                        got: i,
+                        need: std::any::type_name::<I>(),
+                    },
+                )
+            }),
+
+            _ => Err(self.unexpected("integer")),
+        }
+    }
+
+    /// Return the contents of a string or path, if they are valid UTF-8.
+    fn str(&self) -> Option<&str> {
+        match &self.value {
+            Value::String(s) => s.to_str().ok(),
+            Value::Path(p) => p.to_str(),
+            _ => None,
+        }
+    }
+
+    /// Return the bytes of a string, or a list of integers as produced by
+    /// the serialisation of byte strings.
+    fn bytes(&self) -> Result<Vec<u8>, Error> {
+        match &self.value {
+            Value::String(s) => Ok(s.as_bytes().to_vec()),
+            Value::List(list) => list
+                .iter()
+                .enumerate()
+                .map(|(idx, elem)| {
+                    nested(&self.path, PathSegment::Index(idx), elem.clone()).integer::<u8>()
+                })
+                .collect(),
+            _ => Err(self.unexpected("bytes")),
+        }
+    }
+}
+
+/// Create a deserializer for a value nested in the value at `path`.
+fn nested(path: &[PathSegment], segment: PathSegment, value: Value) -> NixDeserializer {
+    let mut path = path.to_vec();
+    path.push(segment);
+    NixDeserializer::at(value, path)
+}
+
+/// Attach the path of the value being deserialised to an error, unless
+/// it already has the (more precise) path of a nested value.
+fn locate(path: &[PathSegment], error: Error) -> Error {
+    if path.is_empty() || matches!(error, Error::Located { .. }) {
+        return error;
+    }
+
+    Error::Located {
+        path: path.to_vec(),
+        location: None,
+        error: Box::new(error),
+    }
 }
 
 impl de::IntoDeserializer<'_, Error> for NixDeserializer {
@@ -50,33 +138,232 @@ where
     // First step is to evaluate the Nix code ...
     let eval = config(EvaluationBuilder::new_pure().mode(EvalMode::Strict)).build();
     let result = eval.evaluate(src, None);
+    let expr = result.expr.clone();
+    let value = evaluation_value(result)?;
+
+    deserialize(value, src, "[code]", expr)
+}
+
+/// Evaluate the Nix code in `src`, which must evaluate to a function,
+/// call it with `args` (serialised to a Nix value, see
+/// [`crate::to_value`]) and attempt to deserialise the value it returns
+/// to `T`.
+pub fn from_str_with_args<'code, T, A>(src: &'code str, args: &A) -> Result<T, Error>
+where
+    T: serde::Deserialize<'code>,
+    A: serde::Serialize + ?Sized,
+{
+    let (value, expr) = evaluate_with_args(src, None, to_value(args)?, None)?;
+    deserialize(value, src, "[code]", expr)
+}
+
+/// Evaluate the Nix file at `path` and attempt to deserialise the value
+/// it returns to `T`.
+///
+/// The code can `import` other files and read them with builtins such
+/// as `builtins.readFile`, but all file system access is confined to
+/// the directory containing `path` (see [`SandboxedIO`]).
+pub fn from_path<T>(path: impl AsRef<Path>) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let (path, code, io) = read_file(path.as_ref())?;
+    let eval = builder(Some(io)).mode(EvalMode::Strict).build();
+    let result = eval.evaluate(&code, Some(path.clone()));
+    let expr = result.expr.clone();
+    let value = evaluation_value(result)?;
+
+    deserialize(value, &code, &path.to_string_lossy(), expr)
+}
+
+/// Evaluate the Nix file at `path`, which must evaluate to a function,
+/// call it with `args` and attempt to deserialise the value it returns
+/// to `T`. File system access is confined like in [`from_path`].
+pub fn from_path_with_args<T, A>(path: impl AsRef<Path>, args: &A) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+    A: serde::Serialize + ?Sized,
+{
+    let (path, code, io) = read_file(path.as_ref())?;
+    let (value, expr) = evaluate_with_args(&code, Some(path.clone()), to_value(args)?, Some(io))?;
+    deserialize(value, &code, &path.to_string_lossy(), expr)
+}
 
+/// Read the Nix file at `path`, returning its absolute path, its
+/// contents and a sandbox rooted at the directory containing it.
+fn read_file(path: &Path) -> Result<(PathBuf, String, SandboxedIO), Error> {
+    let io_error = |error| Error::IO {
+        path: path.to_path_buf(),
+        error: Rc::new(error),
+    };
+
+    let path = path.canonicalize().map_err(io_error)?;
+    let code = std::fs::read_to_string(&path).map_err(io_error)?;
+    let root = path.parent().expect("canonical file paths have a parent");
+    let io = SandboxedIO::new(root).map_err(io_error)?;
+
+    Ok((path, code, io))
+}
+
+/// Create the builder for an evaluation, which is pure unless an I/O
+/// implementation is supplied. In that case `import` and the impure
+/// builtins are available, but the `NIX_PATH` is not used.
+fn builder<'co, 'ro, 'env>(
+    io: Option<SandboxedIO>,
+) -> EvaluationBuilder<'co, 'ro, 'env, Box<dyn EvalIO>> {
+    let builder = EvaluationBuilder::new_pure();
+
+    match io {
+        None => builder,
+        Some(io) => builder.enable_impure(Some(Box::new(io))).nix_path(None),
+    }
+}
+
+/// Evaluate the code, and call the (function) value it returns with the
+/// given argument. The AST of the code is returned along with the result.
+fn evaluate_with_args(
+    src: &str,
+    location: Option<PathBuf>,
+    args: Value,
+    io: Option<SandboxedIO>,
+) -> Result<(Value, Option<ast::Expr>), Error> {
+    // The code itself is evaluated lazily, as only the result of the
+    // call should be forced.
+    let mut root_builder = builder(io.clone());
+    let source_map = root_builder.source_map().clone();
+    let root_eval = root_builder.build();
+    let globals = root_eval.globals();
+    let root = root_eval.evaluate(src, location);
+    let expr = root.expr.clone();
+    let function = evaluation_value(root)?;
+
+    let env = HashMap::from_iter([("function".into(), function), ("args".into(), args)]);
+
+    let eval = builder(io)
+        .mode(EvalMode::Strict)
+        .with_globals(globals)
+        .with_source_map(source_map)
+        .env(Some(&env))
+        .build();
+
+    let value = evaluation_value(eval.evaluate("function args", None))?;
+    Ok((value, expr))
+}
+
+/// Return the value of a successful evaluation, or its errors.
+fn evaluation_value(result: EvaluationResult) -> Result<Value, Error> {
     if !result.errors.is_empty() {
         return Err(Error::NixErrors {
             errors: result.errors,
         });
     }
 
-    let de = NixDeserializer::new(result.value.expect("value should be present on success"));
+    Ok(result.value.expect("value should be present on success"))
+}
 
-    T::deserialize(de)
+/// Deserialise the value that the code `src` in `file` (with the given
+/// AST) evaluated to. Errors in nested values get the position of the
+/// value in the code attached, if it can be determined.
+fn deserialize<'de, T>(
+    value: Value,
+    src: &str,
+    file: &str,
+    expr: Option<ast::Expr>,
+) -> Result<T, Error>
+where
+    T: serde::Deserialize<'de>,
+{
+    T::deserialize(NixDeserializer::new(value)).map_err(|error| match error {
+        Error::Located {
+            path,
+            location: None,
+            error,
+        } => {
+            let location = expr.and_then(|expr| find_value(expr, &path)).map(|offset| {
+                let before = &src[..offset];
+                let line_start = before.rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+                Location {
+                    file: file.to_string(),
+                    line: before.matches('\n').count() + 1,
+                    column: before[line_start..].chars().count() + 1,
+                }
+            });
+
+            Error::Located {
+                path,
+                location,
+                error,
+            }
+        }
+        error => error,
+    })
 }
 
-fn unexpected(expected: &'static str, got: &Value) -> Error {
-    Error::UnexpectedType {
-        expected,
-        got: got.type_of(),
+/// Find the offset of the expression defining the value at `path` in
+/// the value of `expr`, if it is written out literally (i.e. as nested
+/// attribute sets and lists, optionally in the body of functions and
+/// `let`-expressions).
+fn find_value(expr: ast::Expr, path: &[PathSegment]) -> Option<usize> {
+    let expr = match expr {
+        ast::Expr::Paren(paren) => return find_value(paren.expr()?, path),
+        ast::Expr::LetIn(let_in) => return find_value(let_in.body()?, path),
+        ast::Expr::Lambda(lambda) => return find_value(lambda.body()?, path),
+        ast::Expr::With(with) => return find_value(with.body()?, path),
+        ast::Expr::Assert(assert) => return find_value(assert.body()?, path),
+        expr => expr,
+    };
+
+    let Some((segment, rest)) = path.split_first() else {
+        return Some(expr.syntax().text_range().start().into());
+    };
+
+    match (expr, segment) {
+        (ast::Expr::List(list), PathSegment::Index(idx)) => {
+            find_value(list.items().nth(*idx)?, rest)
+        }
+
+        // Attributes can be defined with nested paths (`a.b = ...;`),
+        // possibly spread over several entries.
+        (ast::Expr::AttrSet(attrs), PathSegment::Attr(_)) => ast::HasEntry::attrpath_values(&attrs)
+            .find_map(|entry| {
+                let mut path = path;
+                let mut attrs = entry.attrpath()?.attrs();
+
+                loop {
+                    match (attrs.next(), path.split_first()) {
+                        (None, _) => return find_value(entry.value()?, path),
+                        (Some(attr), None) => {
+                            return Some(attr.syntax().text_range().start().into())
+                        }
+                        (Some(attr), Some((PathSegment::Attr(name), rest))) => {
+                            if static_attr_name(&attr)? != *name {
+                                return None;
+                            }
+                            path = rest;
+                        }
+                        (Some(_), Some((PathSegment::Index(_), _))) => return None,
+                    }
+                }
+            }),
+
+        _ => None,
     }
 }
 
-fn visit_integer<I: TryFrom<i64>>(v: &Value) -> Result<I, Error> {
-    match v {
-        Value::Integer(i) => I::try_from(*i).map_err(|_| Error::IntegerConversion {
-            got: *i,
-            need: std::any::type_name::<I>(),
-        }),
-
-        _ => Err(unexpected("integer", v)),
+/// Return the name of an attribute, if it is not dynamic.
+fn static_attr_name(attr: &ast::Attr) -> Option<String> {
+    let string = match attr {
+        ast::Attr::Ident(ident) => return Some(ident.ident_token()?.text().to_string()),
+        ast::Attr::Str(s) => s.clone(),
+        ast::Attr::Dynamic(dynamic) => match dynamic.expr()? {
+            ast::Expr::Str(s) => s,
+            _ => return None,
+        },
+    };
+
+    match string.normalized_parts().as_slice() {
+        [ast::InterpolPart::Literal(lit)] => Some(lit.clone()),
+        _ => None,
     }
 }
 
@@ -87,13 +374,20 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
     where
         V: de::Visitor<'de>,
     {
-        match self.value {
+        let path = self.path.clone();
+        let result = match self.value {
             Value::Null => visitor.visit_unit(),
             Value::Bool(b) => visitor.visit_bool(b),
             Value::Integer(i) => visitor.visit_i64(i),
             Value::Float(f) => visitor.visit_f64(f),
-            Value::String(s) => visitor.visit_string(s.to_string()),
-            Value::Path(p) => visitor.visit_string(p.to_string_lossy().into()), // TODO: hmm
+            Value::String(ref s) => match s.to_str() {
+                Ok(s) => visitor.visit_str(s),
+                Err(_) => visitor.visit_bytes(s.as_bytes()),
+            },
+            Value::Path(ref p) => match p.to_str() {
+                Some(s) => visitor.visit_str(s),
+                None => visitor.visit_bytes(p.as_os_str().as_encoded_bytes()),
+            },
             Value::Attrs(_) => self.deserialize_map(visitor),
             Value::List(_) => self.deserialize_seq(visitor),
 
@@ -109,7 +403,9 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
             | Value::FinaliseRequest(_) => Err(Error::Unserializable {
                 value_type: self.value.type_of(),
             }),
-        }
+        };
+
+        result.map_err(|error| locate(&path, error))
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -118,7 +414,7 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
     {
         match self.value {
             Value::Bool(b) => visitor.visit_bool(b),
-            _ => Err(unexpected("bool", &self.value)),
+            _ => Err(self.unexpected("bool")),
         }
     }
 
@@ -126,56 +422,56 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_i8(visit_integer(&self.value)?)
+        visitor.visit_i8(self.integer()?)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_i16(visit_integer(&self.value)?)
+        visitor.visit_i16(self.integer()?)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_i32(visit_integer(&self.value)?)
+        visitor.visit_i32(self.integer()?)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_i64(visit_integer(&self.value)?)
+        visitor.visit_i64(self.integer()?)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_u8(visit_integer(&self.value)?)
+        visitor.visit_u8(self.integer()?)
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_u16(visit_integer(&self.value)?)
+        visitor.visit_u16(self.integer()?)
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_u32(visit_integer(&self.value)?)
+        visitor.visit_u32(self.integer()?)
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_u64(visit_integer(&self.value)?)
+        visitor.visit_u64(self.integer()?)
     }
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -186,7 +482,7 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
             return visitor.visit_f32(f as f32);
         }
 
-        Err(unexpected("float", &self.value))
+        Err(self.unexpected("float"))
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -197,7 +493,7 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
             return visitor.visit_f64(f);
         }
 
-        Err(unexpected("float", &self.value))
+        Err(self.unexpected("float"))
     }
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -211,47 +507,41 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
             }
         }
 
-        Err(unexpected("char", &self.value))
+        Err(self.unexpected("char"))
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        if let Value::String(s) = &self.value {
-            if let Ok(s) = s.to_str() {
-                return visitor.visit_str(s);
-            }
+        match self.str() {
+            Some(s) => visitor.visit_str(s),
+            None => Err(self.unexpected("string")),
         }
-
-        Err(unexpected("string", &self.value))
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        if let Value::String(s) = &self.value {
-            if let Ok(s) = s.to_str() {
-                return visitor.visit_str(s);
-            }
+        match self.str() {
+            Some(s) => visitor.visit_str(s),
+            None => Err(self.unexpected("string")),
         }
-
-        Err(unexpected("string", &self.value))
     }
 
-    fn deserialize_bytes<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_byte_buf(self.bytes()?)
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        unimplemented!()
+        visitor.visit_byte_buf(self.bytes()?)
     }
 
     // Note that this can not distinguish between a serialisation of
@@ -275,7 +565,7 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
             return visitor.visit_unit();
         }
 
-        Err(unexpected("null", &self.value))
+        Err(self.unexpected("null"))
     }
 
     fn deserialize_unit_struct<V>(
@@ -305,13 +595,19 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
         V: de::Visitor<'de>,
     {
         if let Value::List(list) = self.value {
-            let mut seq = SeqDeserializer::new(list.into_iter().map(NixDeserializer::new));
-            let result = visitor.visit_seq(&mut seq)?;
-            seq.end()?;
-            return Ok(result);
+            let path = self.path;
+            let mut seq = SeqDeserializer::new(
+                list.into_iter()
+                    .enumerate()
+                    .map(|(idx, elem)| nested(&path, PathSegment::Index(idx), elem)),
+            );
+            return visitor
+                .visit_seq(&mut seq)
+                .and_then(|result| seq.end().map(|_| result))
+                .map_err(|error| locate(&path, error));
         }
 
-        Err(unexpected("list", &self.value))
+        Err(self.unexpected("list"))
     }
 
     fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
@@ -340,18 +636,21 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
         V: de::Visitor<'de>,
     {
         if let Value::Attrs(attrs) = self.value {
+            let path = self.path;
             let mut map = MapDeserializer::new(attrs.into_iter().map(|(k, v)| {
+                let segment = PathSegment::Attr(k.to_str_lossy().into_owned());
                 (
-                    NixDeserializer::new(Value::from(k)),
-                    NixDeserializer::new(v),
+                    NixDeserializer::at(Value::from(k), path.clone()),
+                    nested(&path, segment, v),
                 )
             }));
-            let result = visitor.visit_map(&mut map)?;
-            map.end()?;
-            return Ok(result);
+            return visitor
+                .visit_map(&mut map)
+                .and_then(|result| map.end().map(|_| result))
+                .map_err(|error| locate(&path, error));
         }
 
-        Err(unexpected("map", &self.value))
+        Err(self.unexpected("map"))
     }
 
     fn deserialize_struct<V>(
@@ -367,7 +666,11 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
     }
 
     // This method is responsible for deserializing the externally
-    // tagged enum variant serialisation.
+    // tagged enum variant serialisation. Internally and adjacently
+    // tagged enums are deserialised by serde through `deserialize_any`.
+    //
+    // Variant names are checked by the visitor, which also takes care
+    // of aliases and `#[serde(other)]` variants.
     fn deserialize_enum<V>(
         self,
         name: &'static str,
@@ -377,22 +680,25 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
     where
         V: de::Visitor<'de>,
     {
-        match self.value {
+        let path = self.path.clone();
+        let result = match self.value {
             // a string represents a unit variant
-            Value::String(ref s) => {
-                if let Ok(s) = s.to_str() {
-                    visitor.visit_enum(de::value::StrDeserializer::new(s))
-                } else {
-                    Err(unexpected(name, &self.value))
-                }
-            }
+            Value::String(ref s) => match s.to_str() {
+                Ok(s) => visitor.visit_enum(de::value::StrDeserializer::new(s)),
+                Err(_) => Err(self.unexpected(name)),
+            },
 
             // an attribute set however represents an externally
             // tagged enum with content
-            Value::Attrs(attrs) => visitor.visit_enum(Enum(*attrs)),
+            Value::Attrs(attrs) => visitor.visit_enum(Enum {
+                attrs: *attrs,
+                path: path.clone(),
+            }),
 
-            _ => Err(unexpected(name, &self.value)),
-        }
+            _ => Err(self.unexpected(name)),
+        };
+
+        result.map_err(|error| locate(&path, error))
     }
 
     fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -410,27 +716,36 @@ impl<'de> de::Deserializer<'de> for NixDeserializer {
     }
 }
 
-struct Enum(tvix_eval::NixAttrs);
+struct Enum {
+    attrs: tvix_eval::NixAttrs,
+    path: Vec<PathSegment>,
+}
 
 impl<'de> EnumAccess<'de> for Enum {
     type Error = Error;
     type Variant = NixDeserializer;
 
-    // TODO: pass the known variants down here and check against them
     fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
     where
         V: de::DeserializeSeed<'de>,
     {
-        if self.0.len() != 1 {
+        if self.attrs.len() != 1 {
             return Err(Error::AmbiguousEnum);
         }
 
-        let (key, value) = self.0.into_iter().next().expect("length asserted above");
-        if let Ok(k) = key.to_str() {
-            let val = seed.deserialize(de::value::StrDeserializer::<Error>::new(k))?;
-            Ok((val, NixDeserializer::new(value)))
-        } else {
-            Err(unexpected("string", &key.clone().into()))
+        let (key, value) = self
+            .attrs
+            .into_iter()
+            .next()
+            .expect("length asserted above");
+
+        match key.to_str() {
+            Ok(k) => {
+                let val = seed.deserialize(de::value::StrDeserializer::<Error>::new(k))?;
+                let segment = PathSegment::Attr(k.to_string());
+                Ok((val, nested(&self.path, segment, value)))
+            }
+            Err(_) => Err(NixDeserializer::at(key.clone().into(), self.path).unexpected("string")),
         }
     }
 }
@@ -2,7 +2,10 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use tvix_eval::builtin_macros::builtins;
 
-use crate::de::{from_str, from_str_with_config};
+use crate::de::{
+    from_path, from_path_with_args, from_str, from_str_with_args, from_str_with_config,
+};
+use crate::error::{Error, Location, PathSegment};
 
 #[test]
 fn deserialize_none() {
@@ -200,6 +203,197 @@ fn deserialize_enum_all() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn deserialize_tagged_enums() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(tag = "type")]
+    enum Internal {
+        Unit,
+        Struct { name: String },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(tag = "t", content = "c")]
+    enum Adjacent {
+        Unit,
+        Tuple(String, usize),
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(untagged)]
+    enum Untagged {
+        Number(usize),
+        Text(String),
+    }
+
+    let result: Vec<Internal> =
+        from_str(r#"[ { type = "Unit"; } { type = "Struct"; name = "Slartibartfast"; } ]"#)
+            .expect("should deserialize");
+    assert_eq!(
+        result,
+        vec![
+            Internal::Unit,
+            Internal::Struct {
+                name: "Slartibartfast".into()
+            }
+        ]
+    );
+
+    let result: Vec<Adjacent> =
+        from_str(r#"[ { t = "Unit"; } { t = "Tuple"; c = [ "Ford" 42 ]; } ]"#)
+            .expect("should deserialize");
+    assert_eq!(
+        result,
+        vec![Adjacent::Unit, Adjacent::Tuple("Ford".into(), 42)]
+    );
+
+    let result: Vec<Untagged> = from_str(r#"[ 42 "Arthur" ]"#).expect("should deserialize");
+    assert_eq!(
+        result,
+        vec![Untagged::Number(42), Untagged::Text("Arthur".into())]
+    );
+}
+
+#[test]
+fn deserialize_unknown_variant() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Foo {
+        Bar,
+    }
+
+    let result: Result<Foo, _> = from_str("\"Baz\"");
+    assert!(
+        matches!(&result, Err(Error::Deserialization(msg)) if msg.contains("unknown variant")),
+        "{result:?}"
+    );
+
+    let result: Result<Foo, _> = from_str("{ Baz = 42; }");
+    assert!(result.is_err(), "{result:?}");
+}
+
+#[test]
+fn deserialize_other_variant() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Foo {
+        #[serde(alias = "Qux")]
+        Bar,
+        #[serde(other)]
+        Unknown,
+    }
+
+    let result: Foo = from_str("\"Qux\"").expect("should deserialize");
+    assert_eq!(result, Foo::Bar);
+
+    let result: Foo = from_str("\"Baz\"").expect("should deserialize");
+    assert_eq!(result, Foo::Unknown);
+}
+
+#[test]
+fn deserialize_bytes() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Data {
+        #[serde(with = "serde_bytes_buf")]
+        text: Vec<u8>,
+        #[serde(with = "serde_bytes_buf")]
+        list: Vec<u8>,
+    }
+
+    mod serde_bytes_buf {
+        pub fn deserialize<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Vec<u8>, D::Error> {
+            struct Visitor;
+            impl<'de> serde::de::Visitor<'de> for Visitor {
+                type Value = Vec<u8>;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("bytes")
+                }
+
+                fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                    Ok(v.to_vec())
+                }
+
+                fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+                    Ok(v)
+                }
+            }
+
+            de.deserialize_byte_buf(Visitor)
+        }
+    }
+
+    let result: Data =
+        from_str(r#"{ text = "hi"; list = [ 104 105 ]; }"#).expect("should deserialize");
+    assert_eq!(
+        result,
+        Data {
+            text: b"hi".to_vec(),
+            list: b"hi".to_vec()
+        }
+    );
+
+    let result: Result<Data, _> = from_str(r#"{ text = "hi"; list = [ 256 ]; }"#);
+    assert!(
+        matches!(&result, Err(Error::Located { path, .. })
+            if path == &[PathSegment::Attr("list".into()), PathSegment::Index(0)]),
+        "{result:?}"
+    );
+}
+
+#[test]
+fn deserialize_path() {
+    let result: std::path::PathBuf = from_str("/etc/foo").expect("should deserialize");
+    assert_eq!(result, std::path::PathBuf::from("/etc/foo"));
+
+    let result: String = from_str("/etc/foo").expect("should deserialize");
+    assert_eq!(result, "/etc/foo");
+}
+
+#[test]
+fn deserialize_error_location() {
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Outer {
+        a: Inner,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Inner {
+        b: u16,
+    }
+
+    let result: Result<Outer, _> = from_str("{\n  a.b = \"x\";\n}");
+    match result {
+        Err(Error::Located {
+            path,
+            location,
+            error,
+        }) => {
+            assert_eq!(
+                path,
+                vec![PathSegment::Attr("a".into()), PathSegment::Attr("b".into())]
+            );
+            assert_eq!(
+                location,
+                Some(Location {
+                    file: "[code]".into(),
+                    line: 2,
+                    column: 9,
+                })
+            );
+            assert!(matches!(*error, Error::UnexpectedType { .. }), "{error}");
+        }
+        other => panic!("expected a located error, got {other:?}"),
+    }
+
+    // Computed values have no location, but still have a path.
+    let result: Result<Outer, _> = from_str("{ a = builtins.fromJSON ''{\"b\": true}''; }");
+    assert!(
+        matches!(&result, Err(Error::Located { path, location: None, .. }) if path.len() == 2),
+        "{result:?}"
+    );
+}
+
 #[test]
 fn deserialize_with_config() {
     let result: String = from_str_with_config("builtins.testWithConfig", |eval| {
@@ -242,3 +436,100 @@ fn deserialize_with_extra_builtin() {
 
     assert_eq!(result, "hello world");
 }
+
+#[test]
+fn deserialize_with_args() {
+    #[derive(serde::Serialize)]
+    struct Args {
+        name: &'static str,
+        ports: Vec<u16>,
+    }
+
+    let result: Vec<String> = from_str_with_args(
+        r#"{ name, ports }: map (port: "${name}:${toString port}") ports"#,
+        &Args {
+            name: "localhost",
+            ports: vec![80, 443],
+        },
+    )
+    .expect("should deserialize");
+
+    assert_eq!(result, vec!["localhost:80", "localhost:443"]);
+}
+
+/// Create a directory named after the test, containing the given files.
+fn config_dir(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("tvix-serde-test-{}-{}", std::process::id(), name));
+
+    for (file, content) in files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    dir
+}
+
+#[test]
+fn deserialize_from_path() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        port: u16,
+        motd: String,
+    }
+
+    let dir = config_dir(
+        "path",
+        &[
+            (
+                "config.nix",
+                "{ port = import ./lib/port.nix; motd = builtins.readFile ./motd; }",
+            ),
+            ("lib/port.nix", "8080"),
+            ("motd", "hello"),
+        ],
+    );
+
+    let result: Config = from_path(dir.join("config.nix")).expect("should deserialize");
+    assert_eq!(
+        result,
+        Config {
+            port: 8080,
+            motd: "hello".into()
+        }
+    );
+}
+
+#[test]
+fn deserialize_from_path_with_args() {
+    let dir = config_dir(
+        "path-args",
+        &[
+            ("config.nix", "{ factor }: (import ./lib.nix) * factor"),
+            ("lib.nix", "21"),
+        ],
+    );
+
+    let result: usize =
+        from_path_with_args(dir.join("config.nix"), &HashMap::from([("factor", 2)]))
+            .expect("should deserialize");
+    assert_eq!(result, 42);
+}
+
+#[test]
+fn deserialize_from_path_is_sandboxed() {
+    let outside = config_dir("sandbox-outside", &[("secret.nix", "42")]);
+    let dir = config_dir(
+        "sandbox",
+        &[(
+            "config.nix",
+            &format!("import {}", outside.join("secret.nix").display()),
+        )],
+    );
+
+    let result: Result<usize, _> = from_path(dir.join("config.nix"));
+    assert!(
+        matches!(result, Err(Error::NixErrors { .. })),
+        "access outside of the sandbox should fail: {result:?}"
+    );
+}
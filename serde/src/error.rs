@@ -2,6 +2,10 @@
 
 use std::error;
 use std::fmt::Display;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use tvix_eval::NixString;
 
 #[derive(Clone, Debug)]
 pub enum Error {
@@ -34,6 +38,60 @@ pub enum Error {
 
     /// Attempted to provide content to a unit enum.
     UnitEnumContent,
+
+    /// Deserialising a value nested in the evaluated Nix value failed.
+    Located {
+        /// Attribute names and list indices leading to the value.
+        path: Vec<PathSegment>,
+
+        /// Position of the value in the Nix code, if it could be
+        /// determined (values do not carry their position, so it is
+        /// only known for values written out literally in the code).
+        location: Option<Location>,
+
+        error: Box<Error>,
+    },
+
+    /// The Nix file to evaluate could not be read.
+    IO {
+        path: PathBuf,
+        error: Rc<std::io::Error>,
+    },
+
+    /// Serialisation error returned from `serde::ser`.
+    Serialization(String),
+
+    /// Serialized integer does not fit into a Nix integer.
+    IntegerOverflow { got: u64 },
+
+    /// Attempted to serialise a map with keys that can not be used as
+    /// Nix attribute names.
+    NonStringKey { got: &'static str },
+
+    /// Attempted to serialise a float that has no literal representation
+    /// in Nix code (i.e. infinity or NaN).
+    NonFiniteFloat(f64),
+}
+
+/// Attribute name or list index selecting a value nested in another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    Attr(String),
+    Index(usize),
+}
+
+/// Position of a value in Nix code, with 1-indexed line and column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
 }
 
 impl Display for Error {
@@ -76,6 +134,48 @@ impl Display for Error {
             Error::AmbiguousEnum => write!(f, "could not determine enum variant: ambiguous keys"),
 
             Error::UnitEnumContent => write!(f, "provided content for unit enum variant"),
+
+            Error::Located {
+                path,
+                location,
+                error,
+            } => {
+                if let Some(location) = location {
+                    write!(f, "{}: ", location)?;
+                }
+
+                write!(f, "in '")?;
+                for (idx, segment) in path.iter().enumerate() {
+                    match segment {
+                        PathSegment::Attr(name) => {
+                            if idx > 0 {
+                                write!(f, ".")?;
+                            }
+                            write!(f, "{}", NixString::from(name.as_str()).ident_str())?;
+                        }
+                        PathSegment::Index(index) => write!(f, "[{}]", index)?,
+                    }
+                }
+                write!(f, "': {}", error)
+            }
+
+            Error::IO { path, error } => {
+                write!(f, "could not read Nix file {}: {}", path.display(), error)
+            }
+
+            Error::Serialization(err) => write!(f, "serialisation error occured: {}", err),
+
+            Error::IntegerOverflow { got } => {
+                write!(f, "u64({}) does not fit in a Nix integer", got)
+            }
+
+            Error::NonStringKey { got } => {
+                write!(f, "can not use a Nix '{}' as the name of an attribute", got)
+            }
+
+            Error::NonFiniteFloat(float) => {
+                write!(f, "can not represent the float {} in Nix code", float)
+            }
         }
     }
 }
@@ -84,11 +184,22 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::NixErrors { errors, .. } => errors.first().map(|e| e as &dyn error::Error),
+            Self::Located { error, .. } => Some(error.as_ref()),
+            Self::IO { error, .. } => Some(error.as_ref()),
             _ => None,
         }
     }
 }
 
+impl serde::ser::Error for Error {
+    fn custom<T>(err: T) -> Self
+    where
+        T: Display,
+    {
+        Self::Serialization(err.to_string())
+    }
+}
+
 impl serde::de::Error for Error {
     fn custom<T>(err: T) -> Self
     where
@@ -0,0 +1,97 @@
+//! Sandboxed file system access for evaluating configuration files.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tvix_eval::{EvalIO, FileType, StdIO};
+
+/// Implementation of [`EvalIO`] which only allows access to the files
+/// in (and below) a root directory, e.g. the directory containing a
+/// configuration file. Accessing any other path, including through
+/// symlinks pointing out of the root directory, is an error.
+#[derive(Clone, Debug)]
+pub struct SandboxedIO {
+    root: PathBuf,
+}
+
+impl SandboxedIO {
+    /// Create a sandbox rooted at the given directory, which must
+    /// exist.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            root: root.as_ref().canonicalize()?,
+        })
+    }
+
+    /// The directory to which access is confined.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn outside_of_root(&self, path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "access to '{}' is not allowed, as it is outside of '{}'",
+                path.display(),
+                self.root.display()
+            ),
+        )
+    }
+
+    /// Check that the path is (lexically) inside the root directory,
+    /// without touching the file system. Paths passed in by the
+    /// evaluator are already absolute and normalised.
+    fn check_lexically(&self, path: &Path) -> io::Result<()> {
+        if path.starts_with(&self.root) {
+            Ok(())
+        } else {
+            Err(self.outside_of_root(path))
+        }
+    }
+
+    /// Check that the path is inside the root directory after resolving
+    /// all symlinks in it.
+    fn check(&self, path: &Path) -> io::Result<()> {
+        self.check_lexically(path)?;
+
+        if path.canonicalize()?.starts_with(&self.root) {
+            Ok(())
+        } else {
+            Err(self.outside_of_root(path))
+        }
+    }
+}
+
+impl EvalIO for SandboxedIO {
+    fn path_exists(&self, path: &Path) -> io::Result<bool> {
+        self.check_lexically(path)?;
+
+        match self.check(path) {
+            Ok(()) => StdIO.path_exists(path),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read>> {
+        self.check(path)?;
+        StdIO.open(path)
+    }
+
+    // Does not follow symlinks, so checking the path itself suffices.
+    fn file_type(&self, path: &Path) -> io::Result<FileType> {
+        self.check_lexically(path)?;
+        StdIO.file_type(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(bytes::Bytes, FileType)>> {
+        self.check(path)?;
+        StdIO.read_dir(path)
+    }
+
+    fn import_path(&self, path: &Path) -> io::Result<PathBuf> {
+        self.check(path)?;
+        StdIO.import_path(path)
+    }
+}
@@ -4,9 +4,22 @@
 
 mod de;
 mod error;
+mod io;
+mod ser;
 
+pub use de::from_path;
+pub use de::from_path_with_args;
 pub use de::from_str;
+pub use de::from_str_with_args;
 pub use de::from_str_with_config;
+pub use error::Error;
+pub use error::Location;
+pub use error::PathSegment;
+pub use io::SandboxedIO;
+pub use ser::to_string;
+pub use ser::to_value;
 
 #[cfg(test)]
 mod de_tests;
+#[cfg(test)]
+mod ser_tests;
@@ -0,0 +1,465 @@
+//! Serialisation from Rust to Nix values.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::ser::{self, Serialize};
+use tvix_eval::{NixAttrs, NixString, Value};
+
+use crate::error::Error;
+
+/// Serialise `value` to a Nix value, which can for example be passed
+/// into an evaluation.
+pub fn to_value<T>(value: &T) -> Result<Value, Error>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(NixSerializer)
+}
+
+/// Serialise `value` to pretty-printed Nix code, which evaluates to a
+/// value that deserialises to `value` again.
+pub fn to_string<T>(value: &T) -> Result<String, Error>
+where
+    T: Serialize + ?Sized,
+{
+    let mut out = String::new();
+    print_value(&mut out, &to_value(value)?, 0, false)?;
+    Ok(out)
+}
+
+/// Print the Nix code for `value` (which must be in normal form) to `out`,
+/// indenting nested lines by `indent` levels. Negative numbers need to be
+/// parenthesised if the value is an element of a list.
+fn print_value(out: &mut String, value: &Value, indent: usize, in_list: bool) -> Result<(), Error> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => write!(out, "{}", b).unwrap(),
+
+        // The literal of the smallest integer is out of range without its
+        // sign, which is applied to the literal by the parser.
+        Value::Integer(i64::MIN) => write!(out, "({} - 1)", i64::MIN + 1).unwrap(),
+        Value::Integer(i) if *i < 0 && in_list => write!(out, "({})", i).unwrap(),
+        Value::Integer(i) => write!(out, "{}", i).unwrap(),
+
+        Value::Float(f) => {
+            if !f.is_finite() {
+                return Err(Error::NonFiniteFloat(*f));
+            }
+
+            // Nix float literals always contain a decimal point, which is
+            // omitted by Rust for e.g. `1e20`.
+            let mut literal = format!("{:?}", f);
+            if !literal.contains('.') {
+                let exponent = literal.find('e').unwrap_or(literal.len());
+                literal.insert_str(exponent, ".0");
+            }
+
+            if f.is_sign_negative() && in_list {
+                write!(out, "({})", literal).unwrap();
+            } else {
+                out.push_str(&literal);
+            }
+        }
+
+        // The `Display` implementation of strings escapes them.
+        Value::String(s) => write!(out, "{}", s).unwrap(),
+
+        Value::List(list) if list.is_empty() => out.push_str("[ ]"),
+        Value::List(list) => {
+            out.push_str("[\n");
+            for elem in list.iter() {
+                push_indent(out, indent + 1);
+                print_value(out, elem, indent + 1, true)?;
+                out.push('\n');
+            }
+            push_indent(out, indent);
+            out.push(']');
+        }
+
+        Value::Attrs(attrs) if attrs.is_empty() => out.push_str("{ }"),
+        Value::Attrs(attrs) => {
+            out.push_str("{\n");
+            for (key, value) in attrs.iter_sorted() {
+                push_indent(out, indent + 1);
+                write!(out, "{} = ", key.ident_str()).unwrap();
+                print_value(out, value, indent + 1, false)?;
+                out.push_str(";\n");
+            }
+            push_indent(out, indent);
+            out.push('}');
+        }
+
+        // Values which are never produced by the serialiser.
+        _ => {
+            return Err(Error::Unserializable {
+                value_type: value.type_of(),
+            })
+        }
+    }
+
+    Ok(())
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+/// Build an attribute set with a single attribute, which is how enum
+/// variants with content are represented (i.e. externally tagged).
+fn variant(name: &'static str, value: Value) -> Value {
+    Value::attrs(NixAttrs::from_iter([(name, value)]))
+}
+
+struct NixSerializer;
+
+impl ser::Serializer for NixSerializer {
+    type Ok = Value;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeAttrs;
+    type SerializeStruct = SerializeAttrs;
+    type SerializeStructVariant = SerializeAttrs;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        Ok(Value::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        let i = i64::try_from(v).map_err(|_| Error::IntegerOverflow { got: v })?;
+        self.serialize_i64(i)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        Ok(Value::from(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::from(v))
+    }
+
+    // Byte strings can not be represented in Nix code if they are not
+    // valid UTF-8, so they are serialised as a list of integers instead.
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        Ok(Value::List(
+            v.iter()
+                .map(|b| Value::Integer((*b).into()))
+                .collect::<Vec<_>>()
+                .into(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        self.serialize_unit()
+    }
+
+    // A unit variant is represented by its name.
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant_name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Ok(variant(variant_name, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            variant: None,
+            elems: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    // just represent tuples as lists ...
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            variant: Some(variant),
+            elems: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeAttrs, Error> {
+        Ok(SerializeAttrs {
+            variant: None,
+            attrs: BTreeMap::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeAttrs, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeAttrs, Error> {
+        Ok(SerializeAttrs {
+            variant: Some(variant),
+            attrs: BTreeMap::new(),
+            next_key: None,
+        })
+    }
+}
+
+/// Serialiser for all types represented as Nix lists, optionally
+/// wrapped in an enum variant.
+struct SerializeList {
+    variant: Option<&'static str>,
+    elems: Vec<Value>,
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.elems.push(value.serialize(NixSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, Error> {
+        let list = Value::List(self.elems.into());
+        Ok(match self.variant {
+            Some(name) => variant(name, list),
+            None => list,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+/// Serialiser for all types represented as Nix attribute sets,
+/// optionally wrapped in an enum variant.
+struct SerializeAttrs {
+    variant: Option<&'static str>,
+    attrs: BTreeMap<NixString, Value>,
+    next_key: Option<NixString>,
+}
+
+impl SerializeAttrs {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: NixString, value: &T) -> Result<(), Error> {
+        self.attrs.insert(key, value.serialize(NixSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, Error> {
+        let attrs = Value::attrs(NixAttrs::from(self.attrs));
+        Ok(match self.variant {
+            Some(name) => variant(name, attrs),
+            None => attrs,
+        })
+    }
+}
+
+impl ser::SerializeMap for SerializeAttrs {
+    type Ok = Value;
+    type Error = Error;
+
+    // Attribute names must be strings, but integer keys are converted
+    // to strings (like e.g. `serde_json` does).
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.next_key = Some(match key.serialize(NixSerializer)? {
+            Value::String(s) => s,
+            Value::Integer(i) => i.to_string().into(),
+            other => {
+                return Err(Error::NonStringKey {
+                    got: other.type_of(),
+                })
+            }
+        });
+
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .next_key
+            .take()
+            .expect("serialize_value should only be called after serialize_key");
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeAttrs {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.into(), value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeAttrs {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.insert(key.into(), value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        self.finish()
+    }
+}
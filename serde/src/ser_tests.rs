@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use tvix_eval::Value;
+
+use crate::de::from_str;
+use crate::error::Error;
+use crate::ser::{to_string, to_value};
+
+/// Serialise the given value to Nix code, and assert that it
+/// deserialises to the same value again.
+fn round_trip<T>(value: T) -> String
+where
+    T: Serialize + for<'de> Deserialize<'de> + PartialEq + Debug,
+{
+    let code = to_string(&value).expect("should serialize");
+    let result: T = from_str(&code)
+        .unwrap_or_else(|err| panic!("should deserialize the serialized code {code}: {err}"));
+    assert_eq!(value, result, "round-trip through {code}");
+    code
+}
+
+#[test]
+fn serialize_to_value() {
+    assert!(matches!(to_value(&42u8).unwrap(), Value::Integer(42)));
+    assert!(matches!(to_value(&None::<usize>).unwrap(), Value::Null));
+
+    match to_value(&vec!["foo"]).unwrap() {
+        Value::List(list) => {
+            assert_eq!(list.len(), 1);
+            assert!(matches!(list.iter().next(), Some(Value::String(s)) if s.as_bytes() == b"foo"));
+        }
+        other => panic!("expected a list, got {other}"),
+    }
+}
+
+#[test]
+fn serialize_struct() {
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Person {
+        name: String,
+        age: usize,
+        #[serde(rename = "favourite colour")]
+        favourite_colour: Option<String>,
+        friends: Vec<String>,
+    }
+
+    let code = round_trip(Person {
+        name: "Slartibartfast".into(),
+        age: 42,
+        favourite_colour: None,
+        friends: vec!["Arthur".into(), "Ford".into()],
+    });
+
+    assert_eq!(
+        code,
+        r#"{
+  age = 42;
+  "favourite colour" = null;
+  friends = [
+    "Arthur"
+    "Ford"
+  ];
+  name = "Slartibartfast";
+}"#
+    );
+}
+
+#[test]
+fn serialize_escaped_strings() {
+    let code = round_trip(String::from("\"quoted\" ${interpolation}\n\\"));
+    assert_eq!(code, r#""\"quoted\" \${interpolation}\n\\""#);
+
+    let code = round_trip(BTreeMap::from([
+        ("in".to_string(), 1),
+        ("with space".to_string(), 2),
+        ("${x}".to_string(), 3),
+        ("valid-ident'".to_string(), 4),
+    ]));
+    assert!(code.contains("\"in\" = 1;"), "{code}");
+    assert!(code.contains("\"with space\" = 2;"), "{code}");
+    assert!(code.contains("\"\\${x}\" = 3;"), "{code}");
+    assert!(code.contains("valid-ident' = 4;"), "{code}");
+}
+
+#[test]
+fn serialize_numbers() {
+    round_trip(vec![-1, 0, i64::MAX, i64::MIN]);
+    round_trip(vec![-1.5, 0.0, 1.0, 1e20, 2.5e-10]);
+    assert_eq!(to_string(&1e20).unwrap(), "1.0e20");
+
+    assert!(matches!(
+        to_string(&u64::MAX),
+        Err(Error::IntegerOverflow { got: u64::MAX })
+    ));
+    assert!(matches!(
+        to_string(&f64::NAN),
+        Err(Error::NonFiniteFloat(_))
+    ));
+}
+
+#[test]
+fn serialize_enums() {
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Food {
+        Pizza,
+        Spaghetti(String),
+        Sandwich { bread: String, filling: String },
+        Soup(String, usize),
+    }
+
+    let code = round_trip(vec![
+        Food::Pizza,
+        Food::Spaghetti("carbonara".into()),
+        Food::Sandwich {
+            bread: "rye".into(),
+            filling: "cheese".into(),
+        },
+        Food::Soup("tomato".into(), 2),
+    ]);
+
+    assert!(code.contains("\"Pizza\""), "{code}");
+    assert!(code.contains("Spaghetti = \"carbonara\";"), "{code}");
+}
+
+#[test]
+fn serialize_map_keys() {
+    round_trip(HashMap::from([("answer".to_string(), 42)]));
+
+    let code = to_string(&HashMap::from([(42, "answer")])).unwrap();
+    assert_eq!(code, "{\n  \"42\" = \"answer\";\n}");
+
+    assert!(matches!(
+        to_string(&HashMap::from([(true, 1)])),
+        Err(Error::NonStringKey { got: "bool" })
+    ));
+}
@@ -47,6 +47,7 @@ pkgs.mkShell {
     pkgs.mdbook-admonish
     pkgs.mdbook-d2
     pkgs.mdbook-plantuml
+    pkgs.mercurial # fetchMercurial tests
     pkgs.nix_2_3 # b/313
     pkgs.pkg-config
     pkgs.rust-analyzer
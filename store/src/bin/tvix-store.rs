@@ -56,6 +56,16 @@ struct Cli {
     #[arg(long, default_missing_value = "true", default_value = "true", num_args(0..=1), require_equals(true), action(clap::ArgAction::Set))]
     otlp: bool,
 
+    #[cfg(feature = "prometheus")]
+    /// The address to serve Prometheus metrics on, at /metrics.
+    /// Metrics are not served if this is not set.
+    #[arg(long, env)]
+    metrics_listen_address: Option<std::net::SocketAddr>,
+
+    /// The format of log lines written to stderr, "text" or "json".
+    #[arg(long, env, default_value_t)]
+    log_format: tvix_tracing::LogFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -105,6 +115,11 @@ enum Commands {
         #[arg(value_name = "NIX_ATTRS_JSON_FILE", env = "NIX_ATTRS_JSON_FILE")]
         reference_graph_path: PathBuf,
     },
+    /// Inspects the store configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
     /// Mounts a tvix-store at the given mountpoint
     #[cfg(feature = "fuse")]
     Mount {
@@ -156,6 +171,16 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Instantiates every configured service (not only the ones in use), and
+    /// checks each of them responds to requests.
+    Check {
+        #[clap(flatten)]
+        service_addrs: ServiceUrls,
+    },
+}
+
 #[cfg(feature = "fuse")]
 fn default_threads() -> usize {
     std::thread::available_parallelism()
@@ -451,6 +476,27 @@ async fn run_cli(
                 path_info_service.put(path_info).await?;
             }
         }
+        Commands::Config {
+            command: ConfigCommands::Check { service_addrs },
+        } => {
+            let configs = tvix_store::utils::addrs_to_configs(service_addrs).await?;
+            let comp = tvix_store::utils::composition_from_configs(configs)?;
+
+            let mut failed = 0;
+            for check in tvix_store::utils::check_services(&comp).await {
+                match check.result {
+                    Ok(()) => println!("{}.{}: ok", check.kind, check.name),
+                    Err(e) => {
+                        failed += 1;
+                        println!("{}.{}: {}", check.kind, check.name, e);
+                    }
+                }
+            }
+
+            if failed > 0 {
+                return Err(format!("{failed} service(s) failed the check").into());
+            }
+        }
         #[cfg(feature = "fuse")]
         Commands::Mount {
             dest,
@@ -527,7 +573,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let cli = Cli::parse();
 
     let tracing_handle = {
-        let mut builder = tvix_tracing::TracingBuilder::default();
+        let mut builder = tvix_tracing::TracingBuilder::default().log_format(cli.log_format);
         builder = builder.enable_progressbar();
         #[cfg(feature = "otlp")]
         {
@@ -535,6 +581,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 builder = builder.enable_otlp("tvix.store");
             }
         }
+        #[cfg(feature = "prometheus")]
+        if let Some(metrics_listen_address) = cli.metrics_listen_address {
+            builder = builder.enable_prometheus(metrics_listen_address);
+        }
         builder.build()?
     };
 
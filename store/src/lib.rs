@@ -5,6 +5,7 @@ pub mod nar;
 pub mod path_info;
 pub mod pathinfoservice;
 pub mod proto;
+pub mod substituters;
 pub mod utils;
 
 #[cfg(test)]
@@ -1,33 +1,85 @@
-use std::sync::Arc;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use futures::stream::BoxStream;
+use lru::LruCache;
 use nix_compat::nixbase32;
 use tonic::async_trait;
 use tracing::{debug, instrument};
-use tvix_castore::composition::{CompositionContext, ServiceBuilder};
+use tvix_castore::composition::{CompositionContext, Dependency, ServiceBuilder};
 use tvix_castore::Error;
+use tvix_tracing::metrics::InstanceMetrics;
 
 use super::{PathInfo, PathInfoService};
 
 /// Asks near first, if not found, asks far.
 /// If found in there, returns it, and *inserts* it into
 /// near.
-/// There is no negative cache.
-/// Inserts and listings are not implemented for now.
+/// Optionally, PathInfo not found in far are remembered for a negative TTL,
+/// during which far is not asked for them again.
+/// Inserts and listings go to near, as far is usually a read-only binary
+/// cache. With write-through enabled, inserts go to far first and then to
+/// near, and listings to far, for using near as a (volatile) cache in front
+/// of a persistent far.
 pub struct Cache<PS1, PS2> {
     instance_name: String,
+    metrics: InstanceMetrics,
     near: PS1,
     far: PS2,
+    write_through: bool,
+    negative_ttl: Option<Duration>,
+    /// Digests of PathInfo far did not know, and when it was asked.
+    negative: Mutex<LruCache<[u8; 20], Instant>>,
 }
 
+/// Number of PathInfo not found in far that are remembered at most.
+const NEGATIVE_CACHE_CAPACITY: usize = 10_000;
+
 impl<PS1, PS2> Cache<PS1, PS2> {
     pub fn new(instance_name: String, near: PS1, far: PS2) -> Self {
         Self {
+            metrics: InstanceMetrics::new("pathinfoservice", &instance_name),
             instance_name,
             near,
             far,
+            write_through: false,
+            negative_ttl: None,
+            negative: Mutex::new(LruCache::new(
+                NonZeroUsize::new(NEGATIVE_CACHE_CAPACITY).unwrap(),
+            )),
         }
     }
+
+    /// Configures [Self] to also insert into far, see [Cache].
+    pub fn set_write_through(&mut self, write_through: bool) {
+        self.write_through = write_through;
+    }
+
+    /// Configures [Self] to remember PathInfo not found in far for
+    /// `negative_ttl`, see [Cache].
+    pub fn set_negative_ttl(&mut self, negative_ttl: Option<Duration>) {
+        self.negative_ttl = negative_ttl;
+    }
+
+    /// Returns true if far recently did not know the PathInfo.
+    fn is_known_missing(&self, digest: &[u8; 20]) -> bool {
+        let Some(negative_ttl) = self.negative_ttl else {
+            return false;
+        };
+
+        let mut negative = self.negative.lock().unwrap();
+        let expired = match negative.peek(digest) {
+            Some(asked) => asked.elapsed() >= negative_ttl,
+            None => return false,
+        };
+
+        if expired {
+            negative.pop(digest);
+        }
+
+        !expired
+    }
 }
 
 #[async_trait]
@@ -41,12 +93,24 @@ where
         match self.near.get(digest).await? {
             Some(path_info) => {
                 debug!("serving from cache");
+                self.metrics.record_cache("get", true);
                 Ok(Some(path_info))
             }
             None => {
+                self.metrics.record_cache("get", false);
+                if self.is_known_missing(&digest) {
+                    debug!("not found in near, and recently not found in remote");
+                    return Ok(None);
+                }
+
                 debug!("not found in near, asking remote…");
                 match self.far.get(digest).await? {
-                    None => Ok(None),
+                    None => {
+                        if self.negative_ttl.is_some() {
+                            self.negative.lock().unwrap().put(digest, Instant::now());
+                        }
+                        Ok(None)
+                    }
                     Some(path_info) => {
                         debug!("found in remote, adding to cache");
                         self.near.put(path_info.clone()).await?;
@@ -57,21 +121,43 @@ where
         }
     }
 
-    async fn put(&self, _path_info: PathInfo) -> Result<PathInfo, Error> {
-        Err(Error::StorageError("unimplemented".to_string()))
+    #[instrument(level = "trace", skip_all, fields(path_info.root_node = ?path_info.node, instance_name = %self.instance_name))]
+    async fn put(&self, path_info: PathInfo) -> Result<PathInfo, Error> {
+        self.negative
+            .lock()
+            .unwrap()
+            .pop(path_info.store_path.digest());
+
+        let path_info = if self.write_through {
+            self.far.put(path_info).await?
+        } else {
+            path_info
+        };
+
+        self.near.put(path_info).await
     }
 
     fn list(&self) -> BoxStream<'static, Result<PathInfo, Error>> {
-        Box::pin(tokio_stream::once(Err(Error::StorageError(
-            "unimplemented".to_string(),
-        ))))
+        if self.write_through {
+            self.far.list()
+        } else {
+            self.near.list()
+        }
     }
 }
 
 #[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CacheConfig {
     pub near: String,
     pub far: String,
+    /// Whether inserts also go to far, see [Cache].
+    #[serde(default)]
+    pub write_through: bool,
+    /// Number of seconds PathInfo not found in far are remembered, see
+    /// [Cache].
+    #[serde(default)]
+    pub negative_ttl: Option<u64>,
 }
 
 impl TryFrom<url::Url> for CacheConfig {
@@ -96,17 +182,24 @@ impl ServiceBuilder for CacheConfig {
             context.resolve::<Self::Output>(self.near.clone()),
             context.resolve::<Self::Output>(self.far.clone())
         );
-        Ok(Arc::new(Cache {
-            instance_name: instance_name.to_string(),
-            near: near?,
-            far: far?,
-        }))
+        let mut svc = Cache::new(instance_name.to_string(), near?, far?);
+        svc.set_write_through(self.write_through);
+        svc.set_negative_ttl(self.negative_ttl.map(Duration::from_secs));
+        Ok(Arc::new(svc))
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::new::<dyn PathInfoService>(&self.near),
+            Dependency::new::<dyn PathInfoService>(&self.far),
+        ]
     }
 }
 
 #[cfg(test)]
 mod test {
     use std::num::NonZeroUsize;
+    use std::time::Duration;
 
     use crate::{
         fixtures::PATH_INFO,
@@ -153,4 +246,33 @@ mod test {
             svc.near.get(*PATH_INFO.store_path.digest()).await.unwrap()
         );
     }
+
+    /// With a negative TTL, far is not asked again for PathInfo it recently
+    /// did not know.
+    #[tokio::test]
+    async fn test_negative_cache() {
+        let mut svc = create_pathinfoservice().await;
+        svc.set_negative_ttl(Some(Duration::from_secs(3600)));
+
+        assert!(svc
+            .get(*PATH_INFO.store_path.digest())
+            .await
+            .unwrap()
+            .is_none());
+
+        // insert it into the far one, it's still not found.
+        svc.far.put(PATH_INFO.clone()).await.unwrap();
+        assert!(svc
+            .get(*PATH_INFO.store_path.digest())
+            .await
+            .unwrap()
+            .is_none());
+
+        // … until the entry expired.
+        svc.set_negative_ttl(Some(Duration::ZERO));
+        assert_eq!(
+            Some(PATH_INFO.clone()),
+            svc.get(*PATH_INFO.store_path.digest()).await.unwrap()
+        );
+    }
 }
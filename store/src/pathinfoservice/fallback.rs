@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use nix_compat::nixbase32;
+use tonic::async_trait;
+use tracing::{debug, instrument, warn};
+use tvix_castore::composition::{CompositionContext, Dependency, ServiceBuilder};
+use tvix_castore::Error;
+
+use super::{PathInfo, PathInfoService};
+
+/// Asks each of the services in order, returning the first PathInfo found.
+/// Errors of individual services are logged and the next one is asked,
+/// like Nix does with substituters. If none of them has the PathInfo and
+/// any of them failed, the last error is returned.
+/// Inserts and listings are not implemented.
+pub struct Fallback<PS> {
+    instance_name: String,
+    services: Vec<PS>,
+}
+
+impl<PS> Fallback<PS> {
+    pub fn new(instance_name: String, services: Vec<PS>) -> Self {
+        Self {
+            instance_name,
+            services,
+        }
+    }
+}
+
+#[async_trait]
+impl<PS> PathInfoService for Fallback<PS>
+where
+    PS: PathInfoService,
+{
+    #[instrument(level = "trace", skip_all, fields(path_info.digest = nixbase32::encode(&digest), instance_name = %self.instance_name))]
+    async fn get(&self, digest: [u8; 20]) -> Result<Option<PathInfo>, Error> {
+        let mut last_error = None;
+
+        for (idx, service) in self.services.iter().enumerate() {
+            match service.get(digest).await {
+                Ok(Some(path_info)) => {
+                    debug!(idx, "found");
+                    return Ok(Some(path_info));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(idx, err = %e, "unable to query service, trying the next one");
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, _path_info: PathInfo) -> Result<PathInfo, Error> {
+        Err(Error::StorageError("unimplemented".to_string()))
+    }
+
+    fn list(&self) -> BoxStream<'static, Result<PathInfo, Error>> {
+        Box::pin(tokio_stream::once(Err(Error::StorageError(
+            "unimplemented".to_string(),
+        ))))
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FallbackConfig {
+    /// The services to ask, in this order. They're not reordered, so this
+    /// list needs to be sorted by priority already.
+    pub services: Vec<String>,
+}
+
+impl TryFrom<url::Url> for FallbackConfig {
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    fn try_from(_url: url::Url) -> Result<Self, Self::Error> {
+        Err(Error::StorageError(
+            "Instantiating a FallbackPathInfoService from a url is not supported".into(),
+        )
+        .into())
+    }
+}
+
+#[async_trait]
+impl ServiceBuilder for FallbackConfig {
+    type Output = dyn PathInfoService;
+    async fn build<'a>(
+        &'a self,
+        instance_name: &str,
+        context: &CompositionContext,
+    ) -> Result<Arc<dyn PathInfoService>, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let services = futures::future::join_all(
+            self.services
+                .iter()
+                .map(|service| context.resolve::<Self::Output>(service.clone())),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Arc::new(Fallback {
+            instance_name: instance_name.to_string(),
+            services,
+        }))
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        self.services
+            .iter()
+            .map(Dependency::new::<dyn PathInfoService>)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        fixtures::PATH_INFO,
+        pathinfoservice::{MemoryPathInfoService, PathInfoService},
+    };
+
+    /// The first service having the PathInfo answers.
+    #[tokio::test]
+    async fn test_fallback() {
+        let svc = super::Fallback::new(
+            "test".into(),
+            vec![
+                MemoryPathInfoService::default(),
+                MemoryPathInfoService::default(),
+            ],
+        );
+
+        // query the PathInfo, things should not be there.
+        assert!(svc
+            .get(*PATH_INFO.store_path.digest())
+            .await
+            .unwrap()
+            .is_none());
+
+        // insert it into the second one.
+        svc.services[1].put(PATH_INFO.clone()).await.unwrap();
+
+        // now try getting it again, it should succeed.
+        assert_eq!(
+            Some(PATH_INFO.clone()),
+            svc.get(*PATH_INFO.store_path.digest()).await.unwrap()
+        );
+    }
+}
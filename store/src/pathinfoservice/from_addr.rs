@@ -101,6 +101,16 @@ mod tests {
     #[case::correct_nix_https_with_trusted_public_key("nix+https://cache.nixos.org?trusted-public-keys=cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=", true)]
     /// Correct Scheme for the cache.nixos.org binary cache, and two correct trusted public keys set
     #[case::correct_nix_https_with_two_trusted_public_keys("nix+https://cache.nixos.org?trusted-public-keys=cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=%20foo:jp4fCEx9tBEId/L0ZsVJ26k0wC0fu7vJqLjjIGFkup8=", true)]
+    /// Correct Scheme for the cache.nixos.org binary cache, with a connect timeout
+    #[case::correct_nix_https_with_connect_timeout(
+        "nix+https://cache.nixos.org?connect-timeout=5",
+        true
+    )]
+    /// Correct Scheme for the cache.nixos.org binary cache, but an invalid connect timeout
+    #[case::nix_https_with_invalid_connect_timeout(
+        "nix+https://cache.nixos.org?connect-timeout=soon",
+        false
+    )]
     /// Correct scheme to connect to a unix socket.
     #[case::grpc_valid_unix_socket("grpc+unix:///path/to/somewhere", true)]
     /// Correct scheme for unix socket, but setting a host too, which is invalid.
@@ -4,53 +4,85 @@ use lru::LruCache;
 use nix_compat::nixbase32;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tonic::async_trait;
 use tracing::instrument;
 
 use tvix_castore::composition::{CompositionContext, ServiceBuilder};
 use tvix_castore::Error;
+use tvix_tracing::metrics::InstanceMetrics;
 
 use super::{PathInfo, PathInfoService};
 
+/// Keeps the most recently used PathInfo in memory.
+/// Optionally, entries expire after a TTL, after which they're treated as if
+/// they were evicted.
 pub struct LruPathInfoService {
     instance_name: String,
-    lru: Arc<RwLock<LruCache<[u8; 20], PathInfo>>>,
+    metrics: InstanceMetrics,
+    /// PathInfo, and when they were inserted.
+    lru: Arc<RwLock<LruCache<[u8; 20], (Instant, PathInfo)>>>,
+    ttl: Option<Duration>,
 }
 
 impl LruPathInfoService {
     pub fn with_capacity(instance_name: String, capacity: NonZeroUsize) -> Self {
         Self {
+            metrics: InstanceMetrics::new("pathinfoservice", &instance_name),
             instance_name,
             lru: Arc::new(RwLock::new(LruCache::new(capacity))),
+            ttl: None,
         }
     }
+
+    /// Configures [Self] to expire entries once they're older than `ttl`.
+    pub fn set_ttl(&mut self, ttl: Option<Duration>) {
+        self.ttl = ttl;
+    }
+}
+
+/// Returns true if an entry inserted at `inserted` is older than `ttl`.
+fn is_expired(inserted: &Instant, ttl: Option<Duration>) -> bool {
+    ttl.is_some_and(|ttl| inserted.elapsed() >= ttl)
 }
 
 #[async_trait]
 impl PathInfoService for LruPathInfoService {
     #[instrument(level = "trace", skip_all, fields(path_info.digest = nixbase32::encode(&digest), instance_name = %self.instance_name))]
     async fn get(&self, digest: [u8; 20]) -> Result<Option<PathInfo>, Error> {
-        Ok(self.lru.write().await.get(&digest).cloned())
+        let mut lru = self.lru.write().await;
+        if lru
+            .peek(&digest)
+            .is_some_and(|(inserted, _)| is_expired(inserted, self.ttl))
+        {
+            lru.pop(&digest);
+        }
+        let path_info = lru.get(&digest).map(|(_, path_info)| path_info.clone());
+        drop(lru);
+        self.metrics.record_cache("get", path_info.is_some());
+
+        Ok(path_info)
     }
 
     #[instrument(level = "trace", skip_all, fields(path_info.root_node = ?path_info.node, instance_name = %self.instance_name))]
     async fn put(&self, path_info: PathInfo) -> Result<PathInfo, Error> {
-        self.lru
-            .write()
-            .await
-            .put(*path_info.store_path.digest(), path_info.clone());
+        self.lru.write().await.put(
+            *path_info.store_path.digest(),
+            (Instant::now(), path_info.clone()),
+        );
 
         Ok(path_info)
     }
 
     fn list(&self) -> BoxStream<'static, Result<PathInfo, Error>> {
         let lru = self.lru.clone();
+        let ttl = self.ttl;
         Box::pin(try_stream! {
             let lru = lru.read().await;
-            let it = lru.iter();
+            let it = lru.iter().filter(|(_k, (inserted, _))| !is_expired(inserted, ttl));
 
-            for (_k,v) in it {
+            for (_k, (_inserted, v)) in it {
                 yield v.clone()
             }
         })
@@ -60,7 +92,10 @@ impl PathInfoService for LruPathInfoService {
 #[derive(serde::Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct LruPathInfoServiceConfig {
-    capacity: NonZeroUsize,
+    pub capacity: NonZeroUsize,
+    /// Number of seconds after which entries expire, see [LruPathInfoService].
+    #[serde(default)]
+    pub ttl: Option<u64>,
 }
 
 impl TryFrom<url::Url> for LruPathInfoServiceConfig {
@@ -81,17 +116,17 @@ impl ServiceBuilder for LruPathInfoServiceConfig {
         instance_name: &str,
         _context: &CompositionContext,
     ) -> Result<Arc<dyn PathInfoService>, Box<dyn std::error::Error + Send + Sync + 'static>> {
-        Ok(Arc::new(LruPathInfoService::with_capacity(
-            instance_name.to_string(),
-            self.capacity,
-        )))
+        let mut svc = LruPathInfoService::with_capacity(instance_name.to_string(), self.capacity);
+        svc.set_ttl(self.ttl.map(Duration::from_secs));
+        Ok(Arc::new(svc))
     }
 }
 
 #[cfg(test)]
 mod test {
+    use futures::TryStreamExt;
     use nix_compat::store_path::StorePath;
-    use std::{num::NonZeroUsize, sync::LazyLock};
+    use std::{num::NonZeroUsize, sync::LazyLock, time::Duration};
 
     use crate::{
         fixtures::PATH_INFO,
@@ -144,4 +179,26 @@ mod test {
             .expect("no error")
             .is_none());
     }
+
+    #[tokio::test]
+    async fn expire() {
+        let mut svc =
+            LruPathInfoService::with_capacity("test".into(), NonZeroUsize::new(1).unwrap());
+        svc.set_ttl(Some(Duration::ZERO));
+
+        svc.put(PATH_INFO.clone()).await.expect("no error");
+
+        // The entry expired immediately.
+        assert!(svc
+            .list()
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("no error")
+            .is_empty());
+        assert!(svc
+            .get(*PATH_INFO.store_path.digest())
+            .await
+            .expect("no error")
+            .is_none());
+    }
 }
@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use tonic::async_trait;
+use tvix_castore::Error;
+use tvix_tracing::metrics::InstanceMetrics;
+
+use super::{PathInfo, PathInfoService};
+use crate::nar::NarCalculationService;
+
+/// Wraps a [PathInfoService], recording all requests sent to it in
+/// [InstanceMetrics].
+/// [crate::composition::add_default_services] registers this to be applied to
+/// every PathInfoService in a composition.
+pub struct MetricsPathInfoService<PS> {
+    metrics: InstanceMetrics,
+    inner: PS,
+}
+
+impl<PS> MetricsPathInfoService<PS> {
+    pub fn new(instance_name: &str, inner: PS) -> Self {
+        Self {
+            metrics: InstanceMetrics::new("pathinfoservice", instance_name),
+            inner,
+        }
+    }
+}
+
+#[async_trait]
+impl<PS> PathInfoService for MetricsPathInfoService<PS>
+where
+    PS: PathInfoService,
+{
+    async fn get(&self, digest: [u8; 20]) -> Result<Option<PathInfo>, Error> {
+        self.metrics.observe("get", self.inner.get(digest)).await
+    }
+
+    async fn put(&self, path_info: PathInfo) -> Result<PathInfo, Error> {
+        self.metrics.observe("put", self.inner.put(path_info)).await
+    }
+
+    /// The request is recorded once the stream is exhausted, as failed if any
+    /// of its elements was an error.
+    fn list(&self) -> BoxStream<'static, Result<PathInfo, Error>> {
+        let metrics = self.metrics.clone();
+        let mut stream = self.inner.list();
+
+        Box::pin(async_stream::stream! {
+            let start = Instant::now();
+            let mut failed = false;
+            while let Some(item) = stream.next().await {
+                failed |= item.is_err();
+                yield item;
+            }
+            metrics.record("list", start, failed);
+        })
+    }
+
+    fn nar_calculation_service(&self) -> Option<Box<dyn NarCalculationService>> {
+        self.inner.nar_calculation_service()
+    }
+}
@@ -1,8 +1,10 @@
 mod cache;
+mod fallback;
 mod from_addr;
 mod grpc;
 mod lru;
 mod memory;
+mod metrics;
 mod nix_http;
 mod redb;
 mod signing_wrapper;
@@ -13,6 +15,8 @@ mod fs;
 #[cfg(test)]
 mod tests;
 
+use std::sync::Arc;
+
 use auto_impl::auto_impl;
 use futures::stream::BoxStream;
 use tonic::async_trait;
@@ -23,11 +27,17 @@ use crate::nar::NarCalculationService;
 pub use crate::path_info::PathInfo;
 
 pub use self::cache::{Cache as CachePathInfoService, CacheConfig as CachePathInfoServiceConfig};
+pub use self::fallback::{
+    Fallback as FallbackPathInfoService, FallbackConfig as FallbackPathInfoServiceConfig,
+};
 pub use self::from_addr::from_addr;
 pub use self::grpc::{GRPCPathInfoService, GRPCPathInfoServiceConfig};
 pub use self::lru::{LruPathInfoService, LruPathInfoServiceConfig};
 pub use self::memory::{MemoryPathInfoService, MemoryPathInfoServiceConfig};
-pub use self::nix_http::{NixHTTPPathInfoService, NixHTTPPathInfoServiceConfig};
+pub use self::metrics::MetricsPathInfoService;
+pub use self::nix_http::{
+    fetch_cache_priority, NixHTTPPathInfoService, NixHTTPPathInfoServiceConfig,
+};
 pub use self::redb::{RedbPathInfoService, RedbPathInfoServiceConfig};
 pub use self::signing_wrapper::{KeyFileSigningPathInfoServiceConfig, SigningPathInfoService};
 
@@ -69,9 +79,11 @@ pub trait PathInfoService: Send + Sync {
     }
 }
 
-/// Registers the builtin PathInfoService implementations with the registry
+/// Registers the builtin PathInfoService implementations with the registry,
+/// and records metrics of each of them.
 pub(crate) fn register_pathinfo_services(reg: &mut Registry) {
     reg.register::<Box<dyn ServiceBuilder<Output = dyn PathInfoService>>, CachePathInfoServiceConfig>("cache");
+    reg.register::<Box<dyn ServiceBuilder<Output = dyn PathInfoService>>, FallbackPathInfoServiceConfig>("fallback");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn PathInfoService>>, GRPCPathInfoServiceConfig>("grpc");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn PathInfoService>>, LruPathInfoServiceConfig>("lru");
     reg.register::<Box<dyn ServiceBuilder<Output = dyn PathInfoService>>, MemoryPathInfoServiceConfig>("memory");
@@ -84,4 +96,7 @@ pub(crate) fn register_pathinfo_services(reg: &mut Registry) {
             "bigtable",
         );
     }
+    reg.register_wrapper::<dyn PathInfoService>(|instance_name, service| {
+        Arc::new(MetricsPathInfoService::new(instance_name, service))
+    });
 }
@@ -4,16 +4,19 @@ use futures::{stream::BoxStream, TryStreamExt};
 use nix_compat::{
     narinfo::{self, NarInfo, Signature},
     nixbase32,
+    nixcpp::netrc::Netrc,
     nixhash::NixHash,
     store_path::StorePath,
 };
 use reqwest::StatusCode;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{self, AsyncRead};
 use tonic::async_trait;
 use tracing::{debug, instrument, warn};
-use tvix_castore::composition::{CompositionContext, ServiceBuilder};
+use tvix_castore::composition::{CompositionContext, Dependency, ServiceBuilder};
 use tvix_castore::{blobservice::BlobService, directoryservice::DirectoryService, Error};
+use tvix_tracing::metrics::InstanceMetrics;
 use url::Url;
 
 /// NixHTTPPathInfoService acts as a bridge in between the Nix HTTP Binary cache
@@ -30,9 +33,11 @@ use url::Url;
 /// The client is expected to be (indirectly) using the same [BlobService] and
 /// [DirectoryService], so able to fetch referred Directories and Blobs.
 /// [PathInfoService::put] is not implemented and returns an error if called.
-/// TODO: what about reading from nix-cache-info?
+/// The `nix-cache-info` is not read by this service, see
+/// [fetch_cache_priority] for the priority announced there.
 pub struct NixHTTPPathInfoService<BS, DS> {
     instance_name: String,
+    metrics: InstanceMetrics,
     base_url: url::Url,
     http_client: reqwest_middleware::ClientWithMiddleware,
 
@@ -42,6 +47,66 @@ pub struct NixHTTPPathInfoService<BS, DS> {
     /// An optional list of [narinfo::PubKey].
     /// If set, the .narinfo files received need to have correct signature by at least one of these.
     public_keys: Option<Vec<narinfo::VerifyingKey>>,
+
+    /// Optional credentials, sent with requests to the hosts they are
+    /// configured for.
+    netrc: Option<Netrc>,
+}
+
+fn http_client(connect_timeout: Option<Duration>) -> reqwest_middleware::ClientWithMiddleware {
+    let mut builder = reqwest::Client::builder().user_agent(crate::USER_AGENT);
+    if let Some(connect_timeout) = connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+
+    reqwest_middleware::ClientBuilder::new(builder.build().expect("Client::new()"))
+        .with(tvix_tracing::propagate::reqwest::tracing_middleware())
+        .build()
+}
+
+/// Fetches the `nix-cache-info` of the binary cache at `base_url`, and returns
+/// the `Priority` announced in there, if any.
+/// Credentials for the binary cache are taken from `netrc`, if set.
+pub async fn fetch_cache_priority(
+    base_url: &Url,
+    connect_timeout: Option<Duration>,
+    netrc: Option<&Netrc>,
+) -> Result<Option<u64>, Error> {
+    let url = base_url
+        .join("nix-cache-info")
+        .map_err(|e| Error::StorageError(format!("unable to join url: {e}")))?;
+
+    let mut request = http_client(connect_timeout).get(url);
+    if let Some(entry) = netrc
+        .zip(base_url.host_str())
+        .and_then(|(netrc, host)| netrc.find(host))
+    {
+        if let Some(login) = &entry.login {
+            request = request.basic_auth(login, entry.password.clone());
+        }
+    }
+
+    let resp = request
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status().map_err(Into::into))
+        .map_err(|e| Error::StorageError(format!("unable to fetch nix-cache-info: {e}")))?;
+    let cache_info = resp
+        .text()
+        .await
+        .map_err(|e| Error::StorageError(format!("unable to read nix-cache-info: {e}")))?;
+
+    Ok(parse_priority(&cache_info))
+}
+
+/// Returns the value of the `Priority` field of a `nix-cache-info` file.
+fn parse_priority(cache_info: &str) -> Option<u64> {
+    cache_info.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "Priority")
+            .then(|| value.trim().parse().ok())
+            .flatten()
+    })
 }
 
 impl<BS, DS> NixHTTPPathInfoService<BS, DS> {
@@ -52,20 +117,15 @@ impl<BS, DS> NixHTTPPathInfoService<BS, DS> {
         directory_service: DS,
     ) -> Self {
         Self {
+            metrics: InstanceMetrics::new("pathinfoservice", &instance_name),
             instance_name,
             base_url,
-            http_client: reqwest_middleware::ClientBuilder::new(
-                reqwest::Client::builder()
-                    .user_agent(crate::USER_AGENT)
-                    .build()
-                    .expect("Client::new()"),
-            )
-            .with(tvix_tracing::propagate::reqwest::tracing_middleware())
-            .build(),
+            http_client: http_client(None),
             blob_service,
             directory_service,
 
             public_keys: None,
+            netrc: None,
         }
     }
 
@@ -73,6 +133,40 @@ impl<BS, DS> NixHTTPPathInfoService<BS, DS> {
     pub fn set_public_keys(&mut self, public_keys: Vec<narinfo::VerifyingKey>) {
         self.public_keys = Some(public_keys);
     }
+
+    /// Configures [Self] to give up connecting to the binary cache after the
+    /// given duration.
+    pub fn set_connect_timeout(&mut self, connect_timeout: Duration) {
+        self.http_client = http_client(Some(connect_timeout));
+    }
+
+    /// Configures [Self] to authenticate with the credentials from the given
+    /// netrc file, using HTTP basic authentication.
+    pub fn set_netrc(&mut self, netrc: Netrc) {
+        self.netrc = Some(netrc);
+    }
+
+    fn get_request(&self, url: Url) -> reqwest_middleware::RequestBuilder {
+        // NAR URLs may point to other hosts than the binary cache, which
+        // must only receive the credentials of their own machine entry.
+        // The `default` entry only applies to the binary cache itself.
+        let entry = match (&self.netrc, url.host_str()) {
+            (Some(netrc), Some(host)) if Some(host) == self.base_url.host_str() => netrc.find(host),
+            (Some(netrc), Some(host)) => netrc
+                .entries
+                .iter()
+                .find(|entry| entry.machine.as_deref() == Some(host)),
+            _ => None,
+        };
+        let credentials =
+            entry.and_then(|entry| Some((entry.login.clone()?, entry.password.clone())));
+
+        let request = self.http_client.get(url);
+        match credentials {
+            Some((username, password)) => request.basic_auth(username, password),
+            None => request,
+        }
+    }
 }
 
 #[async_trait]
@@ -93,18 +187,13 @@ where
 
         debug!(narinfo_url= %narinfo_url, "constructed NARInfo url");
 
-        let resp = self
-            .http_client
-            .get(narinfo_url)
-            .send()
-            .await
-            .map_err(|e| {
-                warn!(e=%e,"unable to send NARInfo request");
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "unable to send NARInfo request",
-                )
-            })?;
+        let resp = self.get_request(narinfo_url).send().await.map_err(|e| {
+            warn!(e=%e,"unable to send NARInfo request");
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unable to send NARInfo request",
+            )
+        })?;
 
         // In the case of a 404, return a NotFound.
         // We also return a NotFound in case of a 403 - this is to match the behaviour as Nix,
@@ -120,6 +209,7 @@ where
                 "unable to decode response as string",
             )
         })?;
+        self.metrics.record_bytes("get", narinfo_str.len() as u64);
 
         // parse the received narinfo
         let narinfo = NarInfo::parse(&narinfo_str).map_err(|e| {
@@ -162,8 +252,7 @@ where
         debug!(nar_url= %nar_url, "constructed NAR url");
 
         let resp = self
-            .http_client
-            .get(nar_url.clone())
+            .get_request(nar_url.clone())
             .send()
             .await
             .map_err(|e| {
@@ -180,12 +269,17 @@ where
             )));
         }
 
-        // get a reader of the response body.
-        let r = tokio_util::io::StreamReader::new(resp.bytes_stream().map_err(|e| {
-            let e = e.without_url();
-            warn!(e=%e, "failed to get response body");
-            io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())
-        }));
+        // get a reader of the response body, counting the bytes received.
+        let metrics = self.metrics.clone();
+        let r = tokio_util::io::StreamReader::new(
+            resp.bytes_stream()
+                .inspect_ok(move |chunk| metrics.record_bytes("get", chunk.len() as u64))
+                .map_err(|e| {
+                    let e = e.without_url();
+                    warn!(e=%e, "failed to get response body");
+                    io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())
+                }),
+        );
 
         // handle decompression, depending on the compression field.
         let mut r: Box<dyn AsyncRead + Send + Unpin> = match narinfo.compression {
@@ -271,14 +365,21 @@ where
 }
 
 #[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct NixHTTPPathInfoServiceConfig {
-    base_url: String,
-    blob_service: String,
-    directory_service: String,
+    pub base_url: String,
+    pub blob_service: String,
+    pub directory_service: String,
     #[serde(default)]
     /// An optional list of [narinfo::PubKey].
     /// If set, the .narinfo files received need to have correct signature by at least one of these.
-    public_keys: Option<Vec<String>>,
+    pub public_keys: Option<Vec<String>>,
+    /// Timeout (in seconds) for connecting to the binary cache.
+    #[serde(default)]
+    pub connect_timeout: Option<u64>,
+    /// Path to a netrc file containing credentials for the binary cache.
+    #[serde(default)]
+    pub netrc_file: Option<String>,
 }
 
 impl TryFrom<Url> for NixHTTPPathInfoServiceConfig {
@@ -309,6 +410,17 @@ impl TryFrom<Url> for NixHTTPPathInfoServiceConfig {
             .find(|(k, _)| k == "directory_service")
             .map(|(_, v)| v.to_string())
             .unwrap_or("root".to_string());
+        let connect_timeout = url
+            .query_pairs()
+            .into_iter()
+            .find(|(k, _)| k == "connect-timeout")
+            .map(|(_, v)| v.parse())
+            .transpose()?;
+        let netrc_file = url
+            .query_pairs()
+            .into_iter()
+            .find(|(k, _)| k == "netrc-file")
+            .map(|(_, v)| v.to_string());
 
         Ok(NixHTTPPathInfoServiceConfig {
             // Stringify the URL and remove the nix+ prefix.
@@ -318,6 +430,8 @@ impl TryFrom<Url> for NixHTTPPathInfoServiceConfig {
             blob_service,
             directory_service,
             public_keys,
+            connect_timeout,
+            netrc_file,
         })
     }
 }
@@ -351,6 +465,83 @@ impl ServiceBuilder for NixHTTPPathInfoServiceConfig {
                     .collect::<Result<Vec<_>, Error>>()?,
             );
         }
+        if let Some(connect_timeout) = self.connect_timeout {
+            svc.set_connect_timeout(Duration::from_secs(connect_timeout));
+        }
+        if let Some(netrc_file) = &self.netrc_file {
+            // Like Nix, a missing netrc file is not an error.
+            match tokio::fs::read_to_string(netrc_file).await {
+                Ok(netrc) => svc.set_netrc(Netrc::parse(&netrc)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => Err(Error::StorageError(format!(
+                    "unable to read netrc file {netrc_file}: {e}"
+                )))?,
+            }
+        }
         Ok(Arc::new(svc))
     }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::new::<dyn BlobService>(&self.blob_service),
+            Dependency::new::<dyn DirectoryService>(&self.directory_service),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix_compat::nixcpp::netrc::Netrc;
+    use reqwest::header::AUTHORIZATION;
+    use url::Url;
+
+    use super::{parse_priority, NixHTTPPathInfoService};
+
+    #[test]
+    fn priority() {
+        assert_eq!(
+            Some(40),
+            parse_priority("StoreDir: /nix/store\nWantMassQuery: 1\nPriority: 40\n")
+        );
+        assert_eq!(None, parse_priority("StoreDir: /nix/store\n"));
+        assert_eq!(None, parse_priority("Priority: high\n"));
+    }
+
+    /// Credentials are only sent to the hosts they are configured for.
+    #[test]
+    fn credentials_scoped_to_host() {
+        let mut svc = NixHTTPPathInfoService::new(
+            "test".into(),
+            Url::parse("https://cache.example.org/").unwrap(),
+            (),
+            (),
+        );
+        svc.set_netrc(Netrc::parse(
+            "machine cache.example.org login alice password secret\n\
+             machine nars.example.org login bob password hunter2\n\
+             default login anonymous password none",
+        ));
+
+        let authorization = |url: &str| {
+            svc.get_request(Url::parse(url).unwrap())
+                .build()
+                .unwrap()
+                .headers()
+                .get(AUTHORIZATION)
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        // "alice:secret", "bob:hunter2"
+        assert_eq!(
+            Some("Basic YWxpY2U6c2VjcmV0".to_string()),
+            authorization("https://cache.example.org/foo.narinfo")
+        );
+        assert_eq!(
+            Some("Basic Ym9iOmh1bnRlcjI=".to_string()),
+            authorization("https://nars.example.org/nar/foo.nar")
+        );
+
+        // The default entry is not sent to other hosts.
+        assert_eq!(None, authorization("https://evil.example.com/nar/foo.nar"));
+    }
 }
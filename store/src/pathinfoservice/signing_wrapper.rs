@@ -6,7 +6,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use tonic::async_trait;
 
-use tvix_castore::composition::{CompositionContext, ServiceBuilder};
+use tvix_castore::composition::{CompositionContext, Dependency, ServiceBuilder};
 
 use tvix_castore::Error;
 
@@ -84,6 +84,7 @@ where
 /// a keyfile. The keyfile is parsed using [parse_keypair], the expected format is the nix one
 /// (`nix-store --generate-binary-cache-key` for more informations).
 #[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct KeyFileSigningPathInfoServiceConfig {
     /// Inner [PathInfoService], will be resolved using a [CompositionContext].
     pub inner: String,
@@ -121,6 +122,10 @@ impl ServiceBuilder for KeyFileSigningPathInfoServiceConfig {
             signing_key,
         }))
     }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![Dependency::new::<dyn PathInfoService>(&self.inner)]
+    }
 }
 
 #[cfg(test)]
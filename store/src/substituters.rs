@@ -0,0 +1,464 @@
+//! Translates Nix-style substituter settings into a store composition, so
+//! the common case of fetching from binary caches doesn't require writing
+//! a composition config by hand.
+
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::time::Duration;
+
+use nix_compat::nixcpp::conf::NixConfig;
+use nix_compat::nixcpp::netrc::Netrc;
+use tracing::warn;
+use tvix_castore::composition::{with_registry, DeserializeWithRegistry};
+use tvix_castore::Error;
+use url::Url;
+
+use crate::composition::REG;
+use crate::pathinfoservice::{
+    fetch_cache_priority, CachePathInfoServiceConfig, FallbackPathInfoServiceConfig,
+    LruPathInfoServiceConfig, NixHTTPPathInfoServiceConfig,
+};
+use crate::utils::CompositionConfigs;
+
+/// Number of PathInfo kept in memory in front of the substituters.
+const LRU_CAPACITY: usize = 1000;
+
+/// Priority of binary caches not specifying one, like in Nix.
+const DEFAULT_PRIORITY: u64 = 50;
+
+/// Default number of seconds a lookup of a missing store path is cached, like
+/// Nix' `narinfo-cache-negative-ttl`.
+const NARINFO_CACHE_NEGATIVE_TTL: u64 = 3600;
+
+/// Default number of seconds a lookup of an existing store path is cached,
+/// like Nix' `narinfo-cache-positive-ttl`.
+const NARINFO_CACHE_POSITIVE_TTL: u64 = 30 * 24 * 3600;
+
+/// Nix binary caches to substitute store paths from, configured like the
+/// `substituters` and related settings in nix.conf.
+#[derive(Clone, Debug, Default)]
+pub struct Substituters {
+    /// URLs of the binary caches. Like in Nix, they are asked in order of
+    /// their priority (lower is asked first), which is taken from the
+    /// `priority` URL parameter, and defaults to 50. Binary caches with the
+    /// same priority are asked in the order they are listed.
+    /// [Self::fetch_priorities] sets the parameter to the priority announced
+    /// in the `nix-cache-info` of each binary cache.
+    pub urls: Vec<String>,
+    /// Public keys of which at least one needs to have signed the
+    /// substituted store paths. If empty, signatures are not checked.
+    pub public_keys: Vec<String>,
+    /// Timeout (in seconds) for connecting to the binary caches.
+    pub connect_timeout: Option<u64>,
+    /// Path to a netrc file containing credentials for the binary caches.
+    pub netrc_file: Option<String>,
+    /// Number of seconds a lookup of a store path missing in all binary
+    /// caches is cached. Defaults to one hour, like in Nix.
+    pub narinfo_cache_negative_ttl: Option<u64>,
+    /// Number of seconds a PathInfo is kept in the in-memory cache. Defaults
+    /// to 30 days, like in Nix.
+    pub narinfo_cache_positive_ttl: Option<u64>,
+}
+
+impl Substituters {
+    /// Takes the substituters and related settings from a Nix config.
+    pub fn from_nix_config(config: &NixConfig) -> Self {
+        Self {
+            urls: config.substituters.clone().unwrap_or_default(),
+            public_keys: config
+                .trusted_public_keys
+                .iter()
+                .flatten()
+                .map(ToString::to_string)
+                .collect(),
+            connect_timeout: config.connect_timeout,
+            netrc_file: config
+                .netrc_file
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+            narinfo_cache_negative_ttl: config.narinfo_cache_negative_ttl,
+            narinfo_cache_positive_ttl: config.narinfo_cache_positive_ttl,
+        }
+    }
+
+    /// For all binary caches without a `priority` URL parameter, fetches
+    /// their `nix-cache-info`, and sets the parameter to the priority
+    /// announced in there, if any.
+    ///
+    /// Binary caches that can not be reached are logged, and keep the
+    /// default priority.
+    pub async fn fetch_priorities(&mut self) -> Result<(), Error> {
+        let netrc = match &self.netrc_file {
+            // Like Nix, a missing netrc file is not an error.
+            Some(netrc_file) => match tokio::fs::read_to_string(netrc_file).await {
+                Ok(netrc) => Some(Netrc::parse(&netrc)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(Error::StorageError(format!(
+                        "unable to read netrc file {netrc_file}: {e}"
+                    )))
+                }
+            },
+            None => None,
+        };
+        let connect_timeout = self.connect_timeout.map(Duration::from_secs);
+
+        let priorities = futures::future::join_all(self.urls.iter().map(|url| {
+            let netrc = netrc.as_ref();
+            async move {
+                let (url, priority) = match split_priority(url) {
+                    Ok(split) => split,
+                    Err(e) => return Err(e),
+                };
+                if priority.is_some() {
+                    return Ok(None);
+                }
+
+                match fetch_cache_priority(&url, connect_timeout, netrc).await {
+                    Ok(priority) => Ok(priority),
+                    Err(e) => {
+                        warn!(%url, err = %e, "unable to fetch priority of substituter");
+                        Ok(None)
+                    }
+                }
+            }
+        }))
+        .await;
+
+        for (url, priority) in self.urls.iter_mut().zip(priorities) {
+            if let Some(priority) = priority? {
+                let mut parsed = Url::parse(url).map_err(|e| Error::StorageError(e.to_string()))?;
+                parsed
+                    .query_pairs_mut()
+                    .append_pair("priority", &priority.to_string());
+                *url = parsed.to_string();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the URLs of the binary caches (without their `priority`
+    /// parameter), in the order they are asked, see [Self::urls].
+    fn urls_by_priority(&self) -> Result<Vec<Url>, Error> {
+        let mut urls = self
+            .urls
+            .iter()
+            .map(|url| split_priority(url))
+            .collect::<Result<Vec<_>, _>>()?;
+        // This is a stable sort, keeping the order of binary caches with the
+        // same priority.
+        urls.sort_by_key(|(_, priority)| priority.unwrap_or(DEFAULT_PRIORITY));
+
+        Ok(urls.into_iter().map(|(url, _)| url).collect())
+    }
+
+    /// Puts the substituters behind the "root" PathInfoService in `configs`.
+    ///
+    /// The resulting "root" PathInfoService asks an in-memory LRU cache
+    /// first, then the previous "root" PathInfoService (which acts as local
+    /// cache), and then each substituter in order of priority, see
+    /// [Self::urls]. PathInfo found in a substituter are inserted into both
+    /// caches. NARs are ingested into the "root" BlobService and
+    /// DirectoryService.
+    ///
+    /// Like Nix' narinfo cache, entries of the LRU cache expire after
+    /// [Self::narinfo_cache_positive_ttl], and store paths none of the
+    /// substituters know are not looked up again for
+    /// [Self::narinfo_cache_negative_ttl].
+    ///
+    /// PathInfo inserted into the "root" PathInfoService go to the previous
+    /// "root" PathInfoService, and the LRU cache.
+    ///
+    /// Does nothing if there are no substituters.
+    pub fn add_to_configs(
+        &self,
+        configs: &mut CompositionConfigs,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.urls.is_empty() {
+            return Ok(());
+        }
+
+        let local = configs
+            .pathinfoservices
+            .remove("root")
+            .ok_or_else(|| Error::StorageError("no root PathInfoService configured".into()))?;
+        configs
+            .pathinfoservices
+            .insert("substituters-local".into(), local);
+
+        let mut services = vec![];
+        for (idx, url) in self.urls_by_priority()?.iter().enumerate() {
+            // Nix supports more kinds of stores as substituters, we only
+            // support HTTP binary caches.
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(Error::StorageError(format!(
+                    "unsupported substituter {url}, only http(s) binary caches are supported"
+                ))
+                .into());
+            }
+
+            let name = format!("substituter-{idx}");
+            configs.pathinfoservices.insert(
+                name.clone(),
+                DeserializeWithRegistry(Box::new(NixHTTPPathInfoServiceConfig {
+                    base_url: url.to_string(),
+                    blob_service: "root".into(),
+                    directory_service: "root".into(),
+                    public_keys: (!self.public_keys.is_empty()).then(|| self.public_keys.clone()),
+                    connect_timeout: self.connect_timeout,
+                    netrc_file: self.netrc_file.clone(),
+                })),
+            );
+            services.push(name);
+        }
+
+        configs.pathinfoservices.insert(
+            "substituters".into(),
+            DeserializeWithRegistry(Box::new(FallbackPathInfoServiceConfig { services })),
+        );
+        configs.pathinfoservices.insert(
+            "substituters-cache".into(),
+            DeserializeWithRegistry(Box::new(CachePathInfoServiceConfig {
+                near: "substituters-local".into(),
+                far: "substituters".into(),
+                write_through: false,
+                negative_ttl: Some(
+                    self.narinfo_cache_negative_ttl
+                        .unwrap_or(NARINFO_CACHE_NEGATIVE_TTL),
+                ),
+            })),
+        );
+        configs.pathinfoservices.insert(
+            "substituters-lru".into(),
+            DeserializeWithRegistry(Box::new(LruPathInfoServiceConfig {
+                capacity: NonZeroUsize::new(LRU_CAPACITY).unwrap(),
+                ttl: Some(
+                    self.narinfo_cache_positive_ttl
+                        .unwrap_or(NARINFO_CACHE_POSITIVE_TTL),
+                ),
+            })),
+        );
+        configs.pathinfoservices.insert(
+            "root".into(),
+            DeserializeWithRegistry(Box::new(CachePathInfoServiceConfig {
+                near: "substituters-lru".into(),
+                far: "substituters-cache".into(),
+                write_through: true,
+                negative_ttl: None,
+            })),
+        );
+
+        Ok(())
+    }
+
+    /// Returns a full composition substituting from these substituters.
+    /// Blobs, directories and PathInfo are kept in `cache_dir` (using
+    /// object_store and redb), or in memory if it is not specified.
+    pub fn to_configs(
+        &self,
+        cache_dir: Option<&Path>,
+    ) -> Result<CompositionConfigs, Box<dyn std::error::Error + Send + Sync>> {
+        let (blob_service_addr, directory_service_addr, path_info_service_addr) = match cache_dir {
+            Some(dir) => (
+                format!(
+                    "objectstore+file://{}",
+                    dir.join("blobs.object_store").display()
+                ),
+                format!("redb://{}", dir.join("directories.redb").display()),
+                format!("redb://{}", dir.join("pathinfo.redb").display()),
+            ),
+            None => ("memory://".into(), "memory://".into(), "redb://".into()),
+        };
+
+        let blob_service_url = Url::parse(&blob_service_addr)?;
+        let directory_service_url = Url::parse(&directory_service_addr)?;
+        let path_info_service_url = Url::parse(&path_info_service_addr)?;
+
+        let mut configs: CompositionConfigs = Default::default();
+        configs.blobservices.insert(
+            "root".into(),
+            with_registry(&REG, || blob_service_url.try_into())?,
+        );
+        configs.directoryservices.insert(
+            "root".into(),
+            with_registry(&REG, || directory_service_url.try_into())?,
+        );
+        configs.pathinfoservices.insert(
+            "root".into(),
+            with_registry(&REG, || path_info_service_url.try_into())?,
+        );
+
+        self.add_to_configs(&mut configs)?;
+
+        Ok(configs)
+    }
+}
+
+/// Parses the URL of a binary cache, and splits off its `priority` parameter.
+fn split_priority(url: &str) -> Result<(Url, Option<u64>), Error> {
+    let mut url = Url::parse(url).map_err(|e| Error::StorageError(e.to_string()))?;
+
+    let mut priority = None;
+    let mut other = vec![];
+    for (k, v) in url.query_pairs() {
+        if k == "priority" {
+            priority = Some(v.parse().map_err(|_| {
+                Error::StorageError(format!("invalid priority of substituter {url}: {v}"))
+            })?);
+        } else {
+            other.push((k.into_owned(), v.into_owned()));
+        }
+    }
+
+    if other.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(other);
+    }
+
+    Ok((url, priority))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::TryStreamExt;
+    use nix_compat::nixcpp::conf::NixConfig;
+
+    use super::Substituters;
+    use crate::fixtures::PATH_INFO;
+    use crate::pathinfoservice::PathInfoService;
+    use crate::utils::{composition_from_configs, construct_services_from_configs};
+
+    #[test]
+    fn from_nix_config() {
+        let config = NixConfig::parse(
+            "substituters = https://cache.nixos.org\n\
+             extra-substituters = https://nix-community.cachix.org\n\
+             trusted-public-keys = cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=\n\
+             connect-timeout = 5\n\
+             narinfo-cache-negative-ttl = 0\n\
+             narinfo-cache-positive-ttl = 60",
+        )
+        .unwrap();
+
+        let substituters = Substituters::from_nix_config(&config);
+        assert_eq!(
+            vec![
+                "https://cache.nixos.org".to_string(),
+                "https://nix-community.cachix.org".to_string()
+            ],
+            substituters.urls
+        );
+        assert_eq!(
+            vec!["cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=".to_string()],
+            substituters.public_keys
+        );
+        assert_eq!(Some(5), substituters.connect_timeout);
+        assert_eq!(Some(0), substituters.narinfo_cache_negative_ttl);
+        assert_eq!(Some(60), substituters.narinfo_cache_positive_ttl);
+    }
+
+    /// The composition can be instantiated, without talking to the
+    /// substituters yet.
+    #[tokio::test]
+    async fn to_configs() {
+        let substituters = Substituters {
+            urls: vec![
+                "https://cache.nixos.org".into(),
+                "http://[::1]:8080/foo".into(),
+            ],
+            public_keys: vec![
+                "cache.nixos.org-1:6NCHdD59X431o0gWypbMrAURkbJ16ZPMQFGspcDShjY=".into(),
+            ],
+            ..Default::default()
+        };
+
+        let configs = substituters.to_configs(None).expect("must succeed");
+        for name in [
+            "root",
+            "substituters-lru",
+            "substituters-cache",
+            "substituters-local",
+            "substituters",
+            "substituter-0",
+            "substituter-1",
+        ] {
+            assert!(configs.pathinfoservices.contains_key(name), "{name}");
+        }
+
+        construct_services_from_configs(configs)
+            .await
+            .expect("must build");
+    }
+
+    /// PathInfo can be inserted through the composed "root" PathInfoService,
+    /// without asking the substituters, and end up in the local one.
+    #[tokio::test]
+    async fn put_and_get() {
+        let substituters = Substituters {
+            urls: vec!["http://[::1]:1/".into()],
+            ..Default::default()
+        };
+
+        let comp = composition_from_configs(substituters.to_configs(None).unwrap()).unwrap();
+        let root: Arc<dyn PathInfoService> = comp.build("root").await.unwrap();
+        let local: Arc<dyn PathInfoService> = comp.build("substituters-local").await.unwrap();
+
+        let path_info = root.put(PATH_INFO.clone()).await.expect("put must succeed");
+        let digest = *PATH_INFO.store_path.digest();
+
+        assert_eq!(Some(path_info.clone()), root.get(digest).await.unwrap());
+        assert_eq!(Some(path_info.clone()), local.get(digest).await.unwrap());
+        assert_eq!(
+            vec![path_info],
+            root.list().try_collect::<Vec<_>>().await.unwrap()
+        );
+    }
+
+    #[test]
+    fn urls_by_priority() {
+        let substituters = Substituters {
+            urls: vec![
+                "https://a.example.org?priority=60".into(),
+                "https://b.example.org".into(),
+                "https://c.example.org/?priority=10&trusted=1".into(),
+                "https://d.example.org?priority=50".into(),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![
+                "https://c.example.org/?trusted=1",
+                "https://b.example.org/",
+                "https://d.example.org/",
+                "https://a.example.org/",
+            ],
+            substituters
+                .urls_by_priority()
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        );
+
+        let invalid = Substituters {
+            urls: vec!["https://a.example.org?priority=high".into()],
+            ..Default::default()
+        };
+        assert!(invalid.urls_by_priority().is_err());
+    }
+
+    #[test]
+    fn unsupported_substituter() {
+        let substituters = Substituters {
+            urls: vec!["s3://nix-cache".into()],
+            ..Default::default()
+        };
+
+        assert!(substituters.to_configs(None).is_err());
+    }
+}
@@ -1,22 +1,26 @@
 use std::{
     collections::HashMap,
+    path::{Path, PathBuf},
     pin::Pin,
     sync::Arc,
     task::{self, Poll},
 };
 use tokio::io::{self, AsyncWrite};
 
-use tvix_castore::{blobservice::BlobService, directoryservice::DirectoryService};
+use tvix_castore::{blobservice::BlobService, directoryservice::DirectoryService, B3Digest};
 use url::Url;
 
 use crate::composition::REG;
 use crate::nar::{NarCalculationService, SimpleRenderer};
 use crate::pathinfoservice::PathInfoService;
+use crate::substituters::Substituters;
 use tvix_castore::composition::{
-    with_registry, Composition, DeserializeWithRegistry, ServiceBuilder,
+    with_registry, Composition, CompositionError, DeserializeWithRegistry, ServiceBuilder,
 };
 
+/// Configuration of all services, as read from a `--store-config` file.
 #[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct CompositionConfigs {
     pub blobservices:
         HashMap<String, DeserializeWithRegistry<Box<dyn ServiceBuilder<Output = dyn BlobService>>>>,
@@ -53,12 +57,21 @@ pub struct ServiceUrls {
     #[arg(long, env, default_value = "redb:///var/lib/tvix-store/pathinfo.redb")]
     path_info_service_addr: String,
 
-    /// Path to a TOML file describing the way the services should be composed
-    /// Experimental because the format is not final.
-    /// If specified, the other service addrs are ignored.
-    #[cfg(feature = "xp-composition-cli")]
-    #[arg(long, env)]
-    experimental_store_composition: Option<String>,
+    /// Nix binary caches to substitute store paths from, in order of
+    /// priority. The PathInfoService acts as a local cache in front of them.
+    #[arg(long, env, value_delimiter = ' ')]
+    substituters: Vec<String>,
+
+    /// Public keys of which at least one needs to have signed store paths
+    /// fetched from substituters.
+    #[arg(long, env, value_delimiter = ' ')]
+    trusted_public_keys: Vec<String>,
+
+    /// Path to a TOML file describing the way the services should be
+    /// composed. If specified, the other service addrs and substituters are
+    /// ignored.
+    #[arg(long, env, alias = "experimental-store-composition")]
+    store_config: Option<PathBuf>,
 }
 
 /// Provides a set clap arguments to configure tvix-[ca]store services.
@@ -76,9 +89,21 @@ pub struct ServiceUrlsGrpc {
     #[arg(long, env, default_value = "grpc+http://[::1]:8000")]
     path_info_service_addr: String,
 
-    #[cfg(feature = "xp-composition-cli")]
-    #[arg(long, env)]
-    experimental_store_composition: Option<String>,
+    /// Nix binary caches to substitute store paths from, in order of
+    /// priority. The PathInfoService acts as a local cache in front of them.
+    #[arg(long, env, value_delimiter = ' ')]
+    substituters: Vec<String>,
+
+    /// Public keys of which at least one needs to have signed store paths
+    /// fetched from substituters.
+    #[arg(long, env, value_delimiter = ' ')]
+    trusted_public_keys: Vec<String>,
+
+    /// Path to a TOML file describing the way the services should be
+    /// composed. If specified, the other service addrs and substituters are
+    /// ignored.
+    #[arg(long, env, alias = "experimental-store-composition")]
+    store_config: Option<PathBuf>,
 }
 
 /// Provides a set clap arguments to configure tvix-[ca]store services.
@@ -99,9 +124,21 @@ pub struct ServiceUrlsMemory {
     #[arg(long, env, default_value = "memory://")]
     path_info_service_addr: String,
 
-    #[cfg(feature = "xp-composition-cli")]
-    #[arg(long, env)]
-    experimental_store_composition: Option<String>,
+    /// Nix binary caches to substitute store paths from, in order of
+    /// priority. The PathInfoService acts as a local cache in front of them.
+    #[arg(long, env, value_delimiter = ' ')]
+    substituters: Vec<String>,
+
+    /// Public keys of which at least one needs to have signed store paths
+    /// fetched from substituters.
+    #[arg(long, env, value_delimiter = ' ')]
+    trusted_public_keys: Vec<String>,
+
+    /// Path to a TOML file describing the way the services should be
+    /// composed. If specified, the other service addrs and substituters are
+    /// ignored.
+    #[arg(long, env, alias = "experimental-store-composition")]
+    store_config: Option<PathBuf>,
 }
 
 impl From<ServiceUrlsGrpc> for ServiceUrls {
@@ -110,8 +147,9 @@ impl From<ServiceUrlsGrpc> for ServiceUrls {
             blob_service_addr: urls.blob_service_addr,
             directory_service_addr: urls.directory_service_addr,
             path_info_service_addr: urls.path_info_service_addr,
-            #[cfg(feature = "xp-composition-cli")]
-            experimental_store_composition: urls.experimental_store_composition,
+            substituters: urls.substituters,
+            trusted_public_keys: urls.trusted_public_keys,
+            store_config: urls.store_config,
         }
     }
 }
@@ -122,25 +160,39 @@ impl From<ServiceUrlsMemory> for ServiceUrls {
             blob_service_addr: urls.blob_service_addr,
             directory_service_addr: urls.directory_service_addr,
             path_info_service_addr: urls.path_info_service_addr,
-            #[cfg(feature = "xp-composition-cli")]
-            experimental_store_composition: urls.experimental_store_composition,
+            substituters: urls.substituters,
+            trusted_public_keys: urls.trusted_public_keys,
+            store_config: urls.store_config,
         }
     }
 }
 
+/// Reads the configuration of all services from a TOML file, rejecting
+/// unknown keys.
+pub async fn load_store_config(
+    path: &Path,
+) -> Result<CompositionConfigs, Box<dyn std::error::Error + Send + Sync>> {
+    let conf_text = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+
+    Ok(with_registry(&REG, || toml::from_str(&conf_text))
+        .map_err(|e| format!("invalid store config {}: {e}", path.display()))?)
+}
+
 /// Deserializes service addresses into composition config, configuring each
 /// service as the single "root".
-/// If the `xp-composition-cli` feature is enabled, and a file specified in the
-/// `--experimental-store-composition` parameter, this is used instead.
+/// If substituters are specified, they're put behind the "root"
+/// PathInfoService, see [Substituters::add_to_configs].
+/// If a file is specified in the `--store-config` parameter, it is used
+/// instead.
 pub async fn addrs_to_configs(
     urls: impl Into<ServiceUrls>,
 ) -> Result<CompositionConfigs, Box<dyn std::error::Error + Send + Sync>> {
     let urls: ServiceUrls = urls.into();
 
-    #[cfg(feature = "xp-composition-cli")]
-    if let Some(conf_path) = urls.experimental_store_composition {
-        let conf_text = tokio::fs::read_to_string(conf_path).await?;
-        return Ok(with_registry(&REG, || toml::from_str(&conf_text))?);
+    if let Some(conf_path) = urls.store_config {
+        return load_store_config(&conf_path).await;
     }
 
     let mut configs: CompositionConfigs = Default::default();
@@ -162,6 +214,14 @@ pub async fn addrs_to_configs(
         with_registry(&REG, || path_info_service_url.try_into())?,
     );
 
+    let mut substituters = Substituters {
+        urls: urls.substituters,
+        public_keys: urls.trusted_public_keys,
+        ..Default::default()
+    };
+    substituters.fetch_priorities().await?;
+    substituters.add_to_configs(&mut configs)?;
+
     Ok(configs)
 }
 
@@ -193,11 +253,7 @@ pub async fn construct_services_from_configs(
     ),
     Box<dyn std::error::Error + Send + Sync>,
 > {
-    let mut comp = Composition::new(&REG);
-
-    comp.extend(configs.blobservices);
-    comp.extend(configs.directoryservices);
-    comp.extend(configs.pathinfoservices);
+    let comp = composition_from_configs(configs)?;
 
     let blob_service: Arc<dyn BlobService> = comp.build("root").await?;
     let directory_service: Arc<dyn DirectoryService> = comp.build("root").await?;
@@ -223,6 +279,82 @@ pub async fn construct_services_from_configs(
     ))
 }
 
+/// Puts all configs into a [Composition], checking that services only refer
+/// to services defined in it.
+pub fn composition_from_configs(
+    configs: CompositionConfigs,
+) -> Result<Composition, CompositionError> {
+    let mut comp = Composition::new(&REG);
+
+    comp.extend(configs.blobservices);
+    comp.extend(configs.directoryservices);
+    comp.extend(configs.pathinfoservices);
+
+    comp.validate()?;
+    Ok(comp)
+}
+
+/// Result of checking a single service, see [check_services].
+pub struct ServiceCheck {
+    /// The kind of service, e.g. "blobservices".
+    pub kind: &'static str,
+    pub name: String,
+    pub result: Result<(), Box<dyn std::error::Error + Send + Sync>>,
+}
+
+/// Instantiates every service in the composition (not only the ones
+/// reachable from "root"), and checks whether it responds to a request for
+/// an object which doesn't exist.
+pub async fn check_services(comp: &Composition) -> Vec<ServiceCheck> {
+    let mut checks = vec![];
+
+    for name in comp.names::<dyn BlobService>() {
+        let result = match comp.build::<dyn BlobService>(&name).await {
+            Ok(svc) => svc
+                .has(&B3Digest::from(&[0; 32]))
+                .await
+                .map(|_| ())
+                .map_err(Into::into),
+            Err(e) => Err(e.into()),
+        };
+        checks.push(ServiceCheck {
+            kind: "blobservices",
+            name,
+            result,
+        });
+    }
+
+    for name in comp.names::<dyn DirectoryService>() {
+        let result = match comp.build::<dyn DirectoryService>(&name).await {
+            Ok(svc) => svc
+                .get(&B3Digest::from(&[0; 32]))
+                .await
+                .map(|_| ())
+                .map_err(Into::into),
+            Err(e) => Err(e.into()),
+        };
+        checks.push(ServiceCheck {
+            kind: "directoryservices",
+            name,
+            result,
+        });
+    }
+
+    for name in comp.names::<dyn PathInfoService>() {
+        let result = match comp.build::<dyn PathInfoService>(&name).await {
+            Ok(svc) => svc.get([0; 20]).await.map(|_| ()).map_err(Into::into),
+            Err(e) => Err(e.into()),
+        };
+        checks.push(ServiceCheck {
+            kind: "pathinfoservices",
+            name,
+            result,
+        });
+    }
+
+    checks
+}
+
 /// The inverse of [tokio_util::io::SyncIoBridge].
 /// Don't use this with anything that actually does blocking I/O.
 pub struct AsyncIoBridge<T>(pub T);
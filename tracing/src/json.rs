@@ -0,0 +1,226 @@
+//! A layer writing events as JSON lines, for log pipelines.
+//!
+//! Each line contains the event fields, the fields of all spans the event
+//! happened in, and (if otlp is enabled) the trace and span ID, to correlate
+//! it with the exported traces.
+
+use std::fmt;
+use std::io::Write;
+
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        MakeWriter,
+    },
+    layer::Context,
+    registry::LookupSpan,
+    Layer,
+};
+
+pub(crate) struct JsonLayer<W> {
+    make_writer: W,
+}
+
+impl<W> JsonLayer<W> {
+    pub(crate) fn new(make_writer: W) -> Self {
+        Self { make_writer }
+    }
+}
+
+/// The fields recorded on a span so far, stored in its extensions.
+struct SpanFields(Map<String, Value>);
+
+/// Records fields into a JSON object.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl JsonVisitor<'_> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // only used to control progress bars.
+        if field.name() != "indicatif.pb_show" {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::String(format!("{value:?}")));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + 'static,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist");
+
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span must exist");
+
+        if let Some(SpanFields(fields)) = span.extensions_mut().get_mut::<SpanFields>() {
+            values.record(&mut JsonVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+
+        let mut timestamp = String::new();
+        if SystemTime
+            .format_time(&mut Writer::new(&mut timestamp))
+            .is_ok()
+        {
+            line.insert("timestamp".into(), timestamp.into());
+        }
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+        line.insert("fields".into(), fields.into());
+
+        if let Some(scope) = ctx.event_scope(event) {
+            let mut spans = vec![];
+            for span in scope.from_root() {
+                let mut entry = Map::new();
+                entry.insert("name".into(), span.name().into());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    entry.extend(fields.clone());
+                }
+                spans.push(Value::Object(entry));
+
+                #[cfg(feature = "otlp")]
+                if let Some((trace_id, span_id)) = otel_ids(&span) {
+                    // the innermost span comes last, and wins.
+                    line.insert("trace_id".into(), trace_id.to_string().into());
+                    line.insert("span_id".into(), span_id.to_string().into());
+                }
+            }
+            line.insert("spans".into(), spans.into());
+        }
+
+        let mut buf = serde_json::to_vec(&line).expect("serializing a json map can't fail");
+        buf.push(b'\n');
+        // there's nowhere to report failing to write logs to.
+        let _ = self.make_writer.make_writer_for(metadata).write_all(&buf);
+    }
+}
+
+/// Returns the OpenTelemetry trace and span ID of a span, if it is sampled
+/// by the OpenTelemetry layer.
+#[cfg(feature = "otlp")]
+fn otel_ids<S>(
+    span: &tracing_subscriber::registry::SpanRef<'_, S>,
+) -> Option<(opentelemetry::trace::TraceId, opentelemetry::trace::SpanId)>
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::trace::{TraceContextExt, TraceId};
+
+    let extensions = span.extensions();
+    let data = extensions.get::<tracing_opentelemetry::OtelData>()?;
+
+    // only root spans have a trace ID set, others inherit it from the parent.
+    let trace_id = data
+        .builder
+        .trace_id
+        .unwrap_or_else(|| data.parent_cx.span().span_context().trace_id());
+    let span_id = data.builder.span_id?;
+
+    (trace_id != TraceId::INVALID).then_some((trace_id, span_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value;
+    use tracing::{info, info_span};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::JsonLayer;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::registry().with(JsonLayer::new({
+            let buffer = buffer.clone();
+            move || buffer.clone()
+        }));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "outer",
+                blob.digest = "b3:abc",
+                path_info.name = tracing::field::Empty
+            );
+            let _guard = span.enter();
+            span.record("path_info.name", "hello-2.12");
+
+            info!(size = 42, "fetched");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(1, lines.len(), "one line per event");
+
+        let line: Value = serde_json::from_str(lines[0]).expect("must be json");
+        assert_eq!("INFO", line["level"]);
+        assert_eq!("fetched", line["fields"]["message"]);
+        assert_eq!(42, line["fields"]["size"]);
+        assert!(line["timestamp"].is_string());
+
+        let span = &line["spans"][0];
+        assert_eq!("outer", span["name"]);
+        assert_eq!("b3:abc", span["blob.digest"]);
+        assert_eq!("hello-2.12", span["path_info.name"]);
+    }
+}
@@ -1,4 +1,6 @@
 use indicatif::ProgressStyle;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::LazyLock;
 use tokio::sync::{mpsc, oneshot};
 use tracing::level_filters::LevelFilter;
@@ -22,6 +24,10 @@ use opentelemetry_sdk::{
 #[cfg(feature = "tracy")]
 use tracing_tracy::TracyLayer;
 
+mod json;
+pub mod metrics;
+#[cfg(feature = "prometheus")]
+mod prometheus;
 pub mod propagate;
 
 pub static PB_PROGRESS_STYLE: LazyLock<ProgressStyle> = LazyLock::new(|| {
@@ -53,6 +59,10 @@ pub enum Error {
 
     #[error(transparent)]
     OneshotRecv(#[from] oneshot::error::RecvError),
+
+    #[cfg(feature = "prometheus")]
+    #[error("unable to listen for metrics requests: {0}")]
+    MetricsListener(std::io::Error),
 }
 
 #[derive(Clone)]
@@ -143,13 +153,48 @@ impl TracingHandle {
     }
 }
 
+/// The format of the log lines written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Compact, human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, see [TracingBuilder::enable_json_logs].
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {s}, expected text or json")),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => f.write_str("text"),
+            LogFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
 #[must_use = "Don't forget to call build() to enable tracing."]
 #[derive(Default)]
 pub struct TracingBuilder {
     progess_bar: bool,
+    json_logs: bool,
 
     #[cfg(feature = "otlp")]
     service_name: Option<&'static str>,
+
+    #[cfg(feature = "prometheus")]
+    prometheus_listen_address: Option<std::net::SocketAddr>,
 }
 
 impl TracingBuilder {
@@ -160,6 +205,30 @@ impl TracingBuilder {
         self
     }
 
+    #[cfg(feature = "prometheus")]
+    /// Serve metrics for Prometheus to scrape at /metrics on the given address,
+    /// including process and tokio runtime metrics.
+    pub fn enable_prometheus(mut self, listen_address: std::net::SocketAddr) -> TracingBuilder {
+        self.prometheus_listen_address = Some(listen_address);
+        self
+    }
+
+    /// Write logs as JSON lines instead of human-readable ones. Each line
+    /// contains the event fields, the fields of the spans it happened in and,
+    /// with otlp enabled, the trace and span ID.
+    pub fn enable_json_logs(mut self) -> TracingBuilder {
+        self.json_logs = true;
+        self
+    }
+
+    /// Write logs in the given format, see [LogFormat].
+    pub fn log_format(self, log_format: LogFormat) -> TracingBuilder {
+        match log_format {
+            LogFormat::Text => self,
+            LogFormat::Json => self.enable_json_logs(),
+        }
+    }
+
     /// Enable progress bar layer, default is disabled
     pub fn enable_progressbar(mut self) -> TracingBuilder {
         self.progess_bar = true;
@@ -167,7 +236,8 @@ impl TracingBuilder {
     }
 
     /// This will setup tracing based on the configuration passed in.
-    /// It will setup a stderr writer output layer and configure EnvFilter to honor RUST_LOG.
+    /// It will setup a stderr writer output layer (human-readable, or JSON if
+    /// enabled) and configure EnvFilter to honor RUST_LOG.
     /// The EnvFilter will be applied to all configured layers, also otlp.
     ///
     /// It will also configure otlp if the feature is enabled and a service_name was provided. It
@@ -199,14 +269,22 @@ impl TracingBuilder {
         let stdout_writer = indicatif_layer.get_stdout_writer();
         let stderr_writer = indicatif_layer.get_stderr_writer();
 
-        let layered = tracing_subscriber::fmt::Layer::new()
-            .fmt_fields(FilteredFormatFields::new(
-                tracing_subscriber::fmt::format::DefaultFields::new(),
-                |field| field.name() != "indicatif.pb_show",
-            ))
-            .with_writer(indicatif_layer.get_stderr_writer())
-            .compact()
-            .and_then((self.progess_bar).then(|| {
+        let fmt_layer = (!self.json_logs).then(|| {
+            tracing_subscriber::fmt::Layer::new()
+                .fmt_fields(FilteredFormatFields::new(
+                    tracing_subscriber::fmt::format::DefaultFields::new(),
+                    |field| field.name() != "indicatif.pb_show",
+                ))
+                .with_writer(indicatif_layer.get_stderr_writer())
+                .compact()
+        });
+        let json_layer = self
+            .json_logs
+            .then(|| json::JsonLayer::new(indicatif_layer.get_stderr_writer()));
+
+        // Option has an and_then method on its own, so call Layer's one explicitly.
+        let layered =
+            Layer::and_then(fmt_layer, json_layer).and_then((self.progess_bar).then(|| {
                 indicatif_layer.with_filter(
                     // only show progress for spans with indicatif.pb_show field being set
                     IndicatifFilter::new(false),
@@ -218,6 +296,22 @@ impl TracingBuilder {
         #[cfg(feature = "otlp")]
         let mut flush_tx: Option<mpsc::Sender<oneshot::Sender<()>>> = None;
 
+        // Bind the Prometheus endpoint if configured, and feed it from the
+        // meter provider.
+        #[cfg(feature = "prometheus")]
+        let prometheus_endpoint = self
+            .prometheus_listen_address
+            .map(prometheus::Endpoint::bind)
+            .transpose()?;
+
+        #[cfg(feature = "otlp")]
+        let meter_provider_builder = opentelemetry_sdk::metrics::SdkMeterProvider::builder();
+        #[cfg(feature = "prometheus")]
+        let meter_provider_builder = match &prometheus_endpoint {
+            Some(endpoint) => meter_provider_builder.with_reader(endpoint.reader()),
+            None => meter_provider_builder,
+        };
+
         // Setup otlp if a service_name is configured
         #[cfg(feature = "otlp")]
         let layered = layered.and_then({
@@ -225,8 +319,10 @@ impl TracingBuilder {
                 // register a text map propagator for trace propagation
                 opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
 
-                let (tracer, meter_provider, sender) =
-                    gen_otlp_tracer_meter_provider(service_name.to_string());
+                let (tracer, meter_provider, sender) = gen_otlp_tracer_meter_provider(
+                    service_name.to_string(),
+                    meter_provider_builder,
+                );
 
                 flush_tx = Some(sender);
 
@@ -237,6 +333,12 @@ impl TracingBuilder {
                 // Create a tracing layer with the configured tracer
                 Some(tracing_opentelemetry::layer().with_tracer(tracer))
             } else {
+                // metrics might still be scraped, without OTLP.
+                #[cfg(feature = "prometheus")]
+                if prometheus_endpoint.is_some() {
+                    opentelemetry::global::set_meter_provider(meter_provider_builder.build());
+                }
+
                 None
             }
         });
@@ -255,6 +357,11 @@ impl TracingBuilder {
             .with(layered)
             .try_init()?;
 
+        #[cfg(feature = "prometheus")]
+        if let Some(prometheus_endpoint) = prometheus_endpoint {
+            prometheus_endpoint.serve()?;
+        }
+
         Ok(TracingHandle {
             #[cfg(feature = "otlp")]
             flush_tx,
@@ -321,23 +428,22 @@ fn gen_tracer_provider(
     Ok(tracer_provider)
 }
 
+/// Adds an OTLP exporter to the meter provider being built.
 #[cfg(feature = "otlp")]
 fn gen_meter_provider(
     service_name: String,
+    meter_provider_builder: opentelemetry_sdk::metrics::MeterProviderBuilder,
 ) -> Result<opentelemetry_sdk::metrics::SdkMeterProvider, opentelemetry_sdk::metrics::MetricError> {
     use std::time::Duration;
 
     use opentelemetry_otlp::WithExportConfig;
-    use opentelemetry_sdk::{
-        metrics::{PeriodicReader, SdkMeterProvider},
-        runtime,
-    };
+    use opentelemetry_sdk::{metrics::PeriodicReader, runtime};
     let exporter = opentelemetry_otlp::MetricExporter::builder()
         .with_tonic()
         .with_timeout(Duration::from_secs(10))
         .build()?;
 
-    Ok(SdkMeterProvider::builder()
+    Ok(meter_provider_builder
         .with_reader(
             PeriodicReader::builder(exporter, runtime::Tokio)
                 .with_interval(Duration::from_secs(3))
@@ -354,6 +460,7 @@ fn gen_meter_provider(
 #[cfg(feature = "otlp")]
 fn gen_otlp_tracer_meter_provider(
     service_name: String,
+    meter_provider_builder: opentelemetry_sdk::metrics::MeterProviderBuilder,
 ) -> (
     impl Tracer + tracing_opentelemetry::PreSampledTracer,
     opentelemetry_sdk::metrics::SdkMeterProvider,
@@ -362,8 +469,8 @@ fn gen_otlp_tracer_meter_provider(
     use opentelemetry::trace::TracerProvider;
     let tracer_provider =
         gen_tracer_provider(service_name.clone()).expect("Unable to configure trace provider");
-    let meter_provider =
-        gen_meter_provider(service_name).expect("Unable to configure meter provider");
+    let meter_provider = gen_meter_provider(service_name, meter_provider_builder)
+        .expect("Unable to configure meter provider");
 
     // tracer_provider needs to be kept around so we can request flushes later.
     let tracer = tracer_provider.tracer("tvix");
@@ -0,0 +1,251 @@
+//! Per-instance metrics for the services in a store composition.
+//!
+//! Instruments are created on the global meter provider, which is set up by
+//! [crate::TracingBuilder] if otlp is enabled. If no meter provider is set up
+//! (or it was set up after the instruments were created), recording is a no-op.
+
+use std::future::Future;
+use std::time::Instant;
+
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+
+/// Name of the meter all store metrics are reported on.
+pub const METER_NAME: &str = "tvix-store";
+
+/// Request counts, errors, latencies, bytes transferred and cache hits/misses
+/// of a single service instance, labelled by the kind of service
+/// (blobservice, directoryservice, pathinfoservice), its instance name and
+/// the operation.
+#[derive(Clone)]
+pub struct InstanceMetrics {
+    service: &'static str,
+    instance_name: String,
+
+    requests: Counter<u64>,
+    errors: Counter<u64>,
+    duration: Histogram<f64>,
+    bytes: Counter<u64>,
+    cache_hits: Counter<u64>,
+    cache_misses: Counter<u64>,
+}
+
+impl InstanceMetrics {
+    /// Creates the instruments on the global meter provider.
+    pub fn new(service: &'static str, instance_name: &str) -> Self {
+        Self::with_meter(&global::meter(METER_NAME), service, instance_name)
+    }
+
+    /// Creates the instruments on the given [Meter].
+    pub fn with_meter(meter: &Meter, service: &'static str, instance_name: &str) -> Self {
+        Self {
+            service,
+            instance_name: instance_name.to_string(),
+            requests: meter
+                .u64_counter("tvix.store.requests")
+                .with_description("Number of requests")
+                .build(),
+            errors: meter
+                .u64_counter("tvix.store.errors")
+                .with_description("Number of requests that failed")
+                .build(),
+            duration: meter
+                .f64_histogram("tvix.store.request.duration")
+                .with_description("Duration of requests")
+                .with_unit("s")
+                .build(),
+            bytes: meter
+                .u64_counter("tvix.store.bytes")
+                .with_description("Number of bytes transferred")
+                .with_unit("By")
+                .build(),
+            cache_hits: meter
+                .u64_counter("tvix.store.cache.hits")
+                .with_description("Number of lookups answered by the near/cache side")
+                .build(),
+            cache_misses: meter
+                .u64_counter("tvix.store.cache.misses")
+                .with_description("Number of lookups not answered by the near/cache side")
+                .build(),
+        }
+    }
+
+    fn attributes(&self, operation: &'static str) -> [KeyValue; 3] {
+        [
+            KeyValue::new("service", self.service),
+            KeyValue::new("instance_name", self.instance_name.clone()),
+            KeyValue::new("operation", operation),
+        ]
+    }
+
+    /// Records a finished request, which was started at `start`.
+    pub fn record_request<T, E>(
+        &self,
+        operation: &'static str,
+        start: Instant,
+        result: &Result<T, E>,
+    ) {
+        self.record(operation, start, result.is_err())
+    }
+
+    /// Records a finished request, which was started at `start`, and whether
+    /// it failed. This is for requests not returning a single [Result], like
+    /// streams.
+    pub fn record(&self, operation: &'static str, start: Instant, failed: bool) {
+        let attributes = self.attributes(operation);
+
+        self.requests.add(1, &attributes);
+        if failed {
+            self.errors.add(1, &attributes);
+        }
+        self.duration
+            .record(start.elapsed().as_secs_f64(), &attributes);
+    }
+
+    /// Runs the future, recording it as a request.
+    pub async fn observe<T, E>(
+        &self,
+        operation: &'static str,
+        fut: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let start = Instant::now();
+        let result = fut.await;
+        self.record_request(operation, start, &result);
+        result
+    }
+
+    /// Records a lookup in the near/cache side, and whether it was answered
+    /// from there.
+    pub fn record_cache(&self, operation: &'static str, hit: bool) {
+        let attributes = self.attributes(operation);
+
+        if hit {
+            self.cache_hits.add(1, &attributes);
+        } else {
+            self.cache_misses.add(1, &attributes);
+        }
+    }
+
+    /// Records bytes received or sent.
+    pub fn record_bytes(&self, operation: &'static str, bytes: u64) {
+        self.bytes.add(bytes, &self.attributes(operation));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::metrics::MeterProvider;
+    use opentelemetry_sdk::{
+        metrics::{
+            data::{Histogram, ResourceMetrics, Sum},
+            PeriodicReader, SdkMeterProvider,
+        },
+        runtime,
+        testing::metrics::InMemoryMetricExporter,
+    };
+
+    use super::{InstanceMetrics, METER_NAME};
+
+    /// Returns the data points of the metric with the given name, as
+    /// (instance_name, operation, value) tuples.
+    fn sum_points(metrics: &ResourceMetrics, name: &str) -> Vec<(String, String, u64)> {
+        let mut points: Vec<_> = metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+            .filter(|metric| metric.name == name)
+            .flat_map(|metric| {
+                metric
+                    .data
+                    .as_any()
+                    .downcast_ref::<Sum<u64>>()
+                    .expect("must be a u64 sum")
+                    .data_points
+                    .iter()
+            })
+            .map(|point| {
+                let attr = |key: &str| {
+                    point
+                        .attributes
+                        .iter()
+                        .find(|kv| kv.key.as_str() == key)
+                        .map(|kv| kv.value.to_string())
+                        .unwrap_or_default()
+                };
+                (attr("instance_name"), attr("operation"), point.value)
+            })
+            .collect();
+        points.sort();
+        points
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn records_per_instance() {
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone(), runtime::Tokio).build())
+            .build();
+        let meter = provider.meter(METER_NAME);
+
+        let near = InstanceMetrics::with_meter(&meter, "blobservice", "near");
+        let far = InstanceMetrics::with_meter(&meter, "blobservice", "far");
+
+        near.record_cache("has", true);
+        near.record_cache("has", true);
+        near.record_cache("has", false);
+        far.record_bytes("open_read", 42);
+
+        let _: Result<(), ()> = near.observe("has", async { Ok(()) }).await;
+        let _: Result<(), ()> = far.observe("has", async { Err(()) }).await;
+
+        provider.force_flush().expect("flush must succeed");
+        let finished = exporter.get_finished_metrics().expect("must have metrics");
+        let metrics = finished.last().expect("must have metrics");
+
+        let point = |instance_name: &str, operation: &str, value| {
+            (instance_name.to_string(), operation.to_string(), value)
+        };
+
+        assert_eq!(
+            vec![point("near", "has", 2)],
+            sum_points(metrics, "tvix.store.cache.hits")
+        );
+        assert_eq!(
+            vec![point("near", "has", 1)],
+            sum_points(metrics, "tvix.store.cache.misses")
+        );
+        assert_eq!(
+            vec![point("far", "open_read", 42)],
+            sum_points(metrics, "tvix.store.bytes")
+        );
+        assert_eq!(
+            vec![point("far", "has", 1), point("near", "has", 1)],
+            sum_points(metrics, "tvix.store.requests")
+        );
+        assert_eq!(
+            vec![point("far", "has", 1)],
+            sum_points(metrics, "tvix.store.errors")
+        );
+
+        // durations are recorded for both requests.
+        let durations: usize = metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+            .filter(|metric| metric.name == "tvix.store.request.duration")
+            .map(|metric| {
+                metric
+                    .data
+                    .as_any()
+                    .downcast_ref::<Histogram<f64>>()
+                    .expect("must be a f64 histogram")
+                    .data_points
+                    .len()
+            })
+            .sum();
+        assert_eq!(2, durations);
+    }
+}
@@ -0,0 +1,519 @@
+//! Exposes the metrics of the global meter provider for Prometheus to scrape,
+//! for daemons running without an OTLP collector around.
+//!
+//! Metrics are collected periodically, and the most recent collection is
+//! served in the Prometheus text format at `/metrics`.
+
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use opentelemetry::{
+    metrics::{Meter, ObservableCounter, ObservableGauge},
+    KeyValue,
+};
+use opentelemetry_sdk::{
+    metrics::{
+        data::{Gauge, Histogram, Metric, ResourceMetrics, Sum},
+        exporter::PushMetricExporter,
+        MetricError, PeriodicReader, Temporality,
+    },
+    runtime,
+};
+use tracing::error;
+
+use crate::Error;
+
+/// How often metrics are collected.
+const COLLECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The most recent collection, in the Prometheus text format.
+type Rendered = Arc<RwLock<String>>;
+
+/// A bound, but not yet served, metrics endpoint.
+pub(crate) struct Endpoint {
+    listener: std::net::TcpListener,
+    rendered: Rendered,
+}
+
+impl Endpoint {
+    /// Binds to the listen address, so errors are reported while setting up
+    /// tracing, not later on.
+    pub(crate) fn bind(listen_address: SocketAddr) -> Result<Self, Error> {
+        let listener =
+            std::net::TcpListener::bind(listen_address).map_err(Error::MetricsListener)?;
+        listener
+            .set_nonblocking(true)
+            .map_err(Error::MetricsListener)?;
+
+        Ok(Self {
+            listener,
+            rendered: Default::default(),
+        })
+    }
+
+    /// Returns the reader to add to the meter provider, which feeds the
+    /// endpoint.
+    pub(crate) fn reader(&self) -> PeriodicReader {
+        PeriodicReader::builder(
+            TextExporter {
+                rendered: self.rendered.clone(),
+            },
+            runtime::Tokio,
+        )
+        .with_interval(COLLECT_INTERVAL)
+        .build()
+    }
+
+    /// Registers process and tokio runtime metrics on the global meter
+    /// provider, and starts serving the endpoint in the background.
+    /// Needs to be called from within a tokio runtime.
+    pub(crate) fn serve(self) -> Result<(), Error> {
+        let listener =
+            tokio::net::TcpListener::from_std(self.listener).map_err(Error::MetricsListener)?;
+        let instruments = RuntimeInstruments::new(&opentelemetry::global::meter("tvix-runtime"));
+
+        let app = Router::new()
+            .route("/metrics", get(metrics))
+            .with_state(self.rendered);
+
+        tokio::spawn(async move {
+            // the instruments are only observed as long as they're alive.
+            let _instruments = instruments;
+
+            if let Err(e) = axum::serve(listener, app).await {
+                error!(err = %e, "failed to serve metrics");
+            }
+        });
+
+        Ok(())
+    }
+}
+
+async fn metrics(State(rendered): State<Rendered>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        rendered.read().expect("poisoned lock").clone(),
+    )
+}
+
+/// Renders each collection, and keeps the result around to be served.
+#[derive(Debug)]
+struct TextExporter {
+    rendered: Rendered,
+}
+
+#[async_trait]
+impl PushMetricExporter for TextExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> Result<(), MetricError> {
+        *self.rendered.write().expect("poisoned lock") = render(metrics);
+        Ok(())
+    }
+
+    async fn force_flush(&self) -> Result<(), MetricError> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<(), MetricError> {
+        Ok(())
+    }
+
+    fn temporality(&self) -> Temporality {
+        // Prometheus expects counters to be totals.
+        Temporality::Cumulative
+    }
+}
+
+/// Process and tokio runtime metrics, which are observed for as long as
+/// this is alive.
+struct RuntimeInstruments {
+    _gauges: Vec<ObservableGauge<u64>>,
+    _cpu: ObservableCounter<f64>,
+}
+
+impl RuntimeInstruments {
+    fn new(meter: &Meter) -> Self {
+        let runtime = tokio::runtime::Handle::current();
+
+        let gauge = |name: &'static str,
+                     description: &'static str,
+                     unit: &'static str,
+                     read: Box<dyn Fn() -> Option<u64> + Send + Sync>| {
+            meter
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |observer| {
+                    if let Some(value) = read() {
+                        observer.observe(value, &[]);
+                    }
+                })
+                .build()
+        };
+
+        let gauges = vec![
+            gauge(
+                "process.resident_memory",
+                "Resident memory size",
+                "By",
+                Box::new(|| proc_status("VmRSS").map(|kb| kb * 1024)),
+            ),
+            gauge(
+                "process.virtual_memory",
+                "Virtual memory size",
+                "By",
+                Box::new(|| proc_status("VmSize").map(|kb| kb * 1024)),
+            ),
+            gauge(
+                "process.threads",
+                "Number of OS threads",
+                "",
+                Box::new(|| proc_status("Threads")),
+            ),
+            gauge(
+                "process.open_fds",
+                "Number of open file descriptors",
+                "",
+                Box::new(|| Some(std::fs::read_dir("/proc/self/fd").ok()?.count() as u64)),
+            ),
+            gauge("tokio.workers", "Number of tokio worker threads", "", {
+                let runtime = runtime.clone();
+                Box::new(move || Some(runtime.metrics().num_workers() as u64))
+            }),
+            gauge("tokio.alive_tasks", "Number of alive tokio tasks", "", {
+                let runtime = runtime.clone();
+                Box::new(move || Some(runtime.metrics().num_alive_tasks() as u64))
+            }),
+            gauge(
+                "tokio.global_queue_depth",
+                "Number of tasks in the tokio global queue",
+                "",
+                Box::new(move || Some(runtime.metrics().global_queue_depth() as u64)),
+            ),
+        ];
+
+        let cpu = meter
+            .f64_observable_counter("process.cpu")
+            .with_description("CPU time spent")
+            .with_unit("s")
+            .with_callback(|observer| {
+                if let Some(seconds) = proc_cpu_seconds() {
+                    observer.observe(seconds, &[]);
+                }
+            })
+            .build();
+
+        Self {
+            _gauges: gauges,
+            _cpu: cpu,
+        }
+    }
+}
+
+/// Reads a numeric field from `/proc/self/status`, e.g. `VmRSS` (in kB).
+/// Returns [None] where this is not available.
+fn proc_status(key: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status.lines().find_map(|line| {
+        line.strip_prefix(key)?
+            .strip_prefix(':')?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    })
+}
+
+/// Returns the CPU time (user and system) spent by all threads of the
+/// process, using `getrusage(2)`.
+/// Returns [None] where this is not available.
+fn proc_cpu_seconds() -> Option<f64> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage only writes to the passed struct.
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: getrusage succeeded, so it initialized the struct.
+    let usage = unsafe { usage.assume_init() };
+
+    let seconds = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1_000_000.0;
+    Some(seconds(usage.ru_utime) + seconds(usage.ru_stime))
+}
+
+/// Renders metrics in the Prometheus text format.
+fn render(metrics: &ResourceMetrics) -> String {
+    let mut out = String::new();
+    for metric in metrics
+        .scope_metrics
+        .iter()
+        .flat_map(|scope| scope.metrics.iter())
+    {
+        render_metric(&mut out, metric);
+    }
+    out
+}
+
+/// Converts data points of any number type to (attributes, value) pairs.
+macro_rules! points {
+    ($data:expr) => {
+        $data
+            .data_points
+            .iter()
+            .map(|p| (p.attributes.as_slice(), p.value as f64))
+    };
+}
+
+/// Converts histogram data points of any number type to
+/// (attributes, bounds, bucket counts, sum, count) tuples.
+macro_rules! histogram_points {
+    ($data:expr) => {
+        $data.data_points.iter().map(|p| {
+            (
+                p.attributes.as_slice(),
+                p.bounds.as_slice(),
+                p.bucket_counts.as_slice(),
+                p.sum as f64,
+                p.count,
+            )
+        })
+    };
+}
+
+fn render_metric(out: &mut String, metric: &Metric) {
+    let name = metric_name(&metric.name, &metric.unit);
+    let data = metric.data.as_any();
+
+    if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+        write_sum(
+            out,
+            &name,
+            &metric.description,
+            sum.is_monotonic,
+            points!(sum),
+        );
+    } else if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+        write_sum(
+            out,
+            &name,
+            &metric.description,
+            sum.is_monotonic,
+            points!(sum),
+        );
+    } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+        write_sum(
+            out,
+            &name,
+            &metric.description,
+            sum.is_monotonic,
+            points!(sum),
+        );
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<u64>>() {
+        write_family(out, &name, &metric.description, "gauge", points!(gauge));
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<i64>>() {
+        write_family(out, &name, &metric.description, "gauge", points!(gauge));
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<f64>>() {
+        write_family(out, &name, &metric.description, "gauge", points!(gauge));
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<u64>>() {
+        write_histogram(
+            out,
+            &name,
+            &metric.description,
+            histogram_points!(histogram),
+        );
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+        write_histogram(
+            out,
+            &name,
+            &metric.description,
+            histogram_points!(histogram),
+        );
+    }
+}
+
+/// Returns the Prometheus name for an OpenTelemetry instrument, e.g.
+/// `tvix_store_bytes` for `tvix.store.bytes` in `By`.
+fn metric_name(name: &str, unit: &str) -> String {
+    let mut name = sanitize(name);
+
+    let suffix = match unit {
+        "By" => "_bytes",
+        "s" => "_seconds",
+        _ => "",
+    };
+    if !name.ends_with(suffix) {
+        name.push_str(suffix);
+    }
+
+    name
+}
+
+/// Replaces characters not allowed in metric and label names.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Writes the labels for the given attributes, and optionally an extra one.
+fn write_labels(out: &mut String, attributes: &[KeyValue], extra: Option<(&str, &str)>) {
+    let labels: Vec<String> = attributes
+        .iter()
+        .map(|kv| (sanitize(kv.key.as_str()), kv.value.as_str().into_owned()))
+        .chain(extra.map(|(k, v)| (k.to_string(), v.to_string())))
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{v}\"")
+        })
+        .collect();
+
+    if !labels.is_empty() {
+        write!(out, "{{{}}}", labels.join(",")).unwrap();
+    }
+}
+
+fn write_sum<'a>(
+    out: &mut String,
+    name: &str,
+    description: &str,
+    is_monotonic: bool,
+    points: impl Iterator<Item = (&'a [KeyValue], f64)>,
+) {
+    if is_monotonic {
+        write_family(
+            out,
+            &format!("{name}_total"),
+            description,
+            "counter",
+            points,
+        );
+    } else {
+        write_family(out, name, description, "gauge", points);
+    }
+}
+
+fn write_family<'a>(
+    out: &mut String,
+    name: &str,
+    description: &str,
+    kind: &str,
+    points: impl Iterator<Item = (&'a [KeyValue], f64)>,
+) {
+    writeln!(out, "# HELP {name} {description}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+    for (attributes, value) in points {
+        out.push_str(name);
+        write_labels(out, attributes, None);
+        writeln!(out, " {value}").unwrap();
+    }
+}
+
+fn write_histogram<'a>(
+    out: &mut String,
+    name: &str,
+    description: &str,
+    points: impl Iterator<Item = (&'a [KeyValue], &'a [f64], &'a [u64], f64, u64)>,
+) {
+    writeln!(out, "# HELP {name} {description}").unwrap();
+    writeln!(out, "# TYPE {name} histogram").unwrap();
+    for (attributes, bounds, bucket_counts, sum, count) in points {
+        // Prometheus buckets are cumulative, OpenTelemetry ones are not.
+        let mut cumulative = 0;
+        for (bound, bucket_count) in bounds.iter().zip(bucket_counts) {
+            cumulative += bucket_count;
+            write!(out, "{name}_bucket").unwrap();
+            write_labels(out, attributes, Some(("le", &bound.to_string())));
+            writeln!(out, " {cumulative}").unwrap();
+        }
+        write!(out, "{name}_bucket").unwrap();
+        write_labels(out, attributes, Some(("le", "+Inf")));
+        writeln!(out, " {count}").unwrap();
+
+        write!(out, "{name}_sum").unwrap();
+        write_labels(out, attributes, None);
+        writeln!(out, " {sum}").unwrap();
+
+        write!(out, "{name}_count").unwrap();
+        write_labels(out, attributes, None);
+        writeln!(out, " {count}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use opentelemetry::{metrics::MeterProvider, KeyValue};
+    use opentelemetry_sdk::{
+        metrics::{PeriodicReader, SdkMeterProvider},
+        runtime,
+    };
+
+    use super::{metric_name, TextExporter};
+
+    #[test]
+    fn test_metric_name() {
+        assert_eq!("tvix_store_bytes", metric_name("tvix.store.bytes", "By"));
+        assert_eq!(
+            "tvix_store_request_duration_seconds",
+            metric_name("tvix.store.request.duration", "s")
+        );
+        assert_eq!("process_threads", metric_name("process.threads", ""));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_render() {
+        let rendered: Arc<RwLock<String>> = Default::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(
+                PeriodicReader::builder(
+                    TextExporter {
+                        rendered: rendered.clone(),
+                    },
+                    runtime::Tokio,
+                )
+                .build(),
+            )
+            .build();
+        let meter = provider.meter("test");
+
+        let requests = meter
+            .u64_counter("tvix.store.requests")
+            .with_description("Number of requests")
+            .build();
+        requests.add(3, &[KeyValue::new("instance_name", "ro\"ot")]);
+
+        let duration = meter
+            .f64_histogram("tvix.store.request.duration")
+            .with_unit("s")
+            .with_boundaries(vec![0.3, 1.0])
+            .build();
+        duration.record(0.25, &[]);
+        duration.record(0.5, &[]);
+        duration.record(4.0, &[]);
+
+        provider.force_flush().expect("flush must succeed");
+        let rendered = rendered.read().unwrap().clone();
+
+        for line in [
+            "# TYPE tvix_store_requests_total counter",
+            "tvix_store_requests_total{instance_name=\"ro\\\"ot\"} 3",
+            "# TYPE tvix_store_request_duration_seconds histogram",
+            "tvix_store_request_duration_seconds_bucket{le=\"0.3\"} 1",
+            "tvix_store_request_duration_seconds_bucket{le=\"1\"} 2",
+            "tvix_store_request_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "tvix_store_request_duration_seconds_sum 4.75",
+            "tvix_store_request_duration_seconds_count 3",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "{line} missing in:\n{rendered}"
+            );
+        }
+    }
+}
@@ -6,3 +6,5 @@ pub mod reqwest;
 
 #[cfg(feature = "axum")]
 pub mod axum;
+
+pub mod nix_daemon;
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "otlp")]
+use opentelemetry::{global, propagation::Extractor};
+#[cfg(feature = "otlp")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Trace context propagation: associate the current span with the otlp trace passed in the
+/// `traceparent` (and `tracestate`) settings a client sent with its SetOptions operation, if any
+/// and valid.
+/// The nix-daemon protocol has no headers, but clients can send arbitrary settings overrides. Note
+/// Nix itself only forwards settings it knows about, so this is only useful for other clients.
+/// This only sets the parent trace if the otlp feature is also enabled.
+#[allow(unused_variables)]
+pub fn accept_trace(overrides: &BTreeMap<String, String>) {
+    // we only extract and set a parent trace if otlp feature is enabled, otherwise this feature is
+    // an noop
+    #[cfg(feature = "otlp")]
+    {
+        // Current context, if no or invalid data is received.
+        let parent_context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&SettingsExtractor(overrides))
+        });
+        tracing::Span::current().set_parent(parent_context);
+    }
+}
+
+/// Helper for extracting the trace context from the settings overrides.
+#[cfg(feature = "otlp")]
+struct SettingsExtractor<'a>(&'a BTreeMap<String, String>);
+
+#[cfg(feature = "otlp")]
+impl Extractor for SettingsExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(String::as_str).collect()
+    }
+}
+
+#[cfg(all(test, feature = "otlp"))]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use opentelemetry::trace::{TraceContextExt, TraceId, TracerProvider as _};
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::TracerProvider};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::accept_trace;
+
+    /// Returns the otlp trace id of the span.
+    fn trace_id(span: &tracing::Span) -> TraceId {
+        span.context().span().span_context().trace_id()
+    }
+
+    /// The current span becomes part of the trace sent in the `traceparent`
+    /// setting, and is left alone if there is none.
+    #[test]
+    fn accepts_traceparent() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let provider = TracerProvider::builder().build();
+        let tracer = provider.tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let expected = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+            let overrides = BTreeMap::from([(
+                "traceparent".to_string(),
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+            )]);
+
+            let span = tracing::info_span!("nix_daemon.connection");
+            span.in_scope(|| accept_trace(&overrides));
+            assert_eq!(expected, trace_id(&span));
+
+            let span = tracing::info_span!("nix_daemon.connection");
+            span.in_scope(|| accept_trace(&BTreeMap::new()));
+            assert_ne!(expected, trace_id(&span));
+            assert_ne!(TraceId::INVALID, trace_id(&span));
+        });
+    }
+}
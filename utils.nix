@@ -125,6 +125,8 @@
         src = depot.tvix.utils.filterRustCrateSrc {
           root = prev.src.origSrc;
         };
+        # builtins.fetchMercurial shells out to hg, which its tests exercise.
+        nativeBuildInputs = [ pkgs.mercurial ];
       };
 
       tvix-serde = prev: {